@@ -0,0 +1,56 @@
+// Shared by the benchmarks, so not every bench uses every helper
+#![allow(dead_code)]
+
+use ndarray::Array2;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_neuralnet::parsing::{mnist, Dataset};
+use std::time::{Duration, Instant};
+
+/// Use the MNIST CSV at the path of this environment variable if it's set, and synthetic data otherwise
+const MNIST_ENV_VAR: &str = "MNIST_TRAIN_CSV";
+
+/// The first num_rows instances of MNIST, or a synthetic MNIST-shaped dataset: 784 features and 10 classes, where
+/// each instance is the prototype of its class plus noise
+pub fn mnist_or_synthetic(num_rows: usize, seed: u64) -> Dataset {
+    if let Ok(path) = std::env::var(MNIST_ENV_VAR) {
+        let dataset = mnist::parse_dataset(&path);
+        let num_rows = num_rows.min(dataset.data.nrows());
+
+        return Dataset {
+            data: dataset.data.slice(ndarray::s![..num_rows, ..]).to_owned(),
+            target: dataset.target.slice(ndarray::s![..num_rows, ..]).to_owned(),
+        };
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    // The prototypes are the same for every seed, so datasets with different seeds share the classes
+    let mut prototype_rng = StdRng::seed_from_u64(0);
+    let prototypes = Array2::from_shape_fn((10, 784), |_| prototype_rng.gen_range(0f64..1f64));
+    let mut data = Array2::zeros((num_rows, 784));
+    let mut target = Array2::zeros((num_rows, 10));
+
+    for row in 0..num_rows {
+        let class = rng.gen_range(0..10);
+
+        target[[row, class]] = 1f64;
+        for col in 0..784 {
+            data[[row, col]] = prototypes[[class, col]] + rng.gen_range(-4f64..4f64);
+        }
+    }
+
+    Dataset { data, target }
+}
+
+/// The mean time of a run of f over num_runs runs (after a warmup run)
+pub fn time_runs(num_runs: usize, mut f: impl FnMut()) -> Duration {
+    f();
+
+    let start = Instant::now();
+
+    for _ in 0..num_runs {
+        f();
+    }
+
+    start.elapsed() / num_runs as u32
+}
@@ -0,0 +1,63 @@
+//! Compares the test error of an ensemble to that of its members, and the cost of its predictions
+//! Run with cargo bench --bench ensemble (set MNIST_TRAIN_CSV to use MNIST instead of synthetic data)
+
+mod common;
+
+use rust_neuralnet::model::ensemble::Ensemble;
+use rust_neuralnet::model::metrics::accuracy;
+use rust_neuralnet::model::neural_net::{
+    ActivationFunction, InitMethod, NeuralNetBuilder, Verbosity,
+};
+use rust_neuralnet::model::Model;
+
+const NUM_MEMBERS: usize = 5;
+
+fn main() {
+    let dataset = common::mnist_or_synthetic(3000, 1);
+    let (train, test) = dataset.split(0.3, Some(0));
+    let builder = NeuralNetBuilder::new(vec![784, 64, 10])
+        .activation_function(ActivationFunction::ReLU)
+        .init_method(InitMethod::Xavier)
+        .num_epochs(Some(5))
+        .batch_size(32)
+        .learning_rate(0.01)
+        .verbosity(Verbosity::Silent);
+    let seeds: Vec<u64> = (0..NUM_MEMBERS as u64).collect();
+    let ensemble = Ensemble::train_members(&builder, &train, NUM_MEMBERS, &seeds);
+    let inputs = test.data.view();
+
+    for (idx, member) in ensemble.members.iter().enumerate() {
+        println!(
+            "member {}: test error {:.4}",
+            idx,
+            1f64 - accuracy(&member.predict(&inputs), &test.target)
+        );
+    }
+
+    println!(
+        "ensemble (mean): test error {:.4}",
+        1f64 - accuracy(&ensemble.predict(&inputs), &test.target)
+    );
+    println!(
+        "ensemble (vote): test error {:.4}",
+        1f64 - accuracy(&ensemble.predict_vote(&inputs), &test.target)
+    );
+
+    let single = common::time_runs(20, || {
+        ensemble.members[0].predict(&inputs);
+    });
+    let mean = common::time_runs(20, || {
+        ensemble.predict(&inputs);
+    });
+    let vote = common::time_runs(20, || {
+        ensemble.predict_vote(&inputs);
+    });
+
+    println!(
+        "predicting {} instances: single {:?}, ensemble mean {:?}, ensemble vote {:?}",
+        test.data.nrows(),
+        single,
+        mean,
+        vote
+    );
+}
@@ -0,0 +1,100 @@
+//! Compares training steps that reuse the gradient buffers with steps that allocate the gradients, by the time
+//! and the bytes allocated per step
+//! Run with cargo bench --bench gradient_buffers (set MNIST_TRAIN_CSV to use MNIST instead of synthetic data)
+
+mod common;
+
+use rust_neuralnet::model::neural_net::{
+    ActivationFunction, InitMethod, NeuralNetBuilder, Verbosity,
+};
+use rust_neuralnet::model::Model;
+use rust_neuralnet::parsing::Dataset;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const NUM_EPOCHS: usize = 5;
+
+/// The system allocator, counting the allocated bytes
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Train for NUM_EPOCHS epochs, and return the final training loss, the time per step in microseconds and the
+/// allocated bytes per step
+fn train(dataset: &Dataset, layers: &[usize], batch_size: usize, reuse: bool) -> (f64, f64, f64) {
+    let mut net = NeuralNetBuilder::new(layers.to_vec())
+        .activation_function(ActivationFunction::ReLU)
+        .init_method(InitMethod::Xavier)
+        .num_epochs(Some(NUM_EPOCHS))
+        .batch_size(batch_size)
+        .learning_rate(0.01)
+        .seed(0)
+        .verbosity(Verbosity::Silent)
+        .build()
+        .with_gradient_buffers(reuse);
+    let num_steps = (NUM_EPOCHS * dataset.data.nrows().div_ceil(batch_size)) as f64;
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    let history = net.fit(dataset, None);
+
+    let elapsed = start.elapsed();
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - allocated;
+
+    (
+        *history.train_losses.last().unwrap(),
+        elapsed.as_secs_f64() * 1e6 / num_steps,
+        allocated as f64 / num_steps,
+    )
+}
+
+fn main() {
+    let dataset = common::mnist_or_synthetic(600, 1);
+
+    for (layers, batch_size) in [
+        (vec![784, 64, 10], 8),
+        (vec![784, 128, 128, 128, 128, 10], 32),
+    ] {
+        // Warm up the caches and the allocator
+        train(&dataset, &layers, batch_size, true);
+
+        let (allocating_loss, allocating_time, allocating_bytes) =
+            train(&dataset, &layers, batch_size, false);
+        let (buffered_loss, buffered_time, buffered_bytes) =
+            train(&dataset, &layers, batch_size, true);
+
+        println!("{:?}, batch size {}:", layers, batch_size);
+        println!(
+            "  allocating: {:.0}us and {:.2}MB per step, final loss {}",
+            allocating_time,
+            allocating_bytes / 1e6,
+            allocating_loss
+        );
+        println!(
+            "  buffered:   {:.0}us and {:.2}MB per step, final loss {}",
+            buffered_time,
+            buffered_bytes / 1e6,
+            buffered_loss
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Compares the time of an epoch when the batches are read from a slow disk synchronously and with a DataLoader that
+//! reads them in the background. The slow disk is simulated by sleeping for a fixed time per batch read
+//! Run with cargo bench --bench loader (set MNIST_TRAIN_CSV to use MNIST instead of synthetic data)
+
+mod common;
+
+use ndarray::{s, Array2};
+use rust_neuralnet::model::neural_net::{
+    ActivationFunction, InitMethod, NeuralNetBuilder, Verbosity,
+};
+use rust_neuralnet::parsing::loader::{DataLoader, DataSource, LoadBatchFn};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const NUM_ROWS: usize = 2048;
+const BATCH_SIZE: usize = 64;
+const READ_LATENCY: Duration = Duration::from_millis(5);
+
+/// Reads every batch on the training thread, when it's needed
+struct SyncSource {
+    n_samples: usize,
+    load_batch: LoadBatchFn,
+    next_start: usize,
+}
+
+impl DataSource for SyncSource {
+    fn next_batch(&mut self) -> Option<(Array2<f64>, Array2<f64>)> {
+        if self.next_start >= self.n_samples {
+            return None;
+        }
+
+        let start = self.next_start;
+        self.next_start = (start + BATCH_SIZE).min(self.n_samples);
+
+        Some((self.load_batch)(start, self.next_start))
+    }
+
+    fn reset(&mut self) {
+        self.next_start = 0;
+    }
+}
+
+/// The time of an epoch of training on the batches of the source
+fn epoch_time(source: &mut impl DataSource) -> Duration {
+    let mut net = NeuralNetBuilder::new(vec![784, 64, 10])
+        .activation_function(ActivationFunction::ReLU)
+        .init_method(InitMethod::Xavier)
+        .num_epochs(Some(1))
+        .batch_size(BATCH_SIZE)
+        .learning_rate(0.01)
+        .seed(0)
+        .verbosity(Verbosity::Silent)
+        .build();
+    let start = Instant::now();
+
+    net.fit_source(source, None);
+
+    start.elapsed()
+}
+
+fn main() {
+    let dataset = Arc::new(common::mnist_or_synthetic(NUM_ROWS, 1));
+    let n_samples = dataset.data.nrows();
+    let load_batch: LoadBatchFn = Arc::new(move |start, end| {
+        thread::sleep(READ_LATENCY);
+
+        (
+            dataset.data.slice(s![start..end, ..]).to_owned(),
+            dataset.target.slice(s![start..end, ..]).to_owned(),
+        )
+    });
+
+    let mut sync_source = SyncSource {
+        n_samples,
+        load_batch: Arc::clone(&load_batch),
+        next_start: 0,
+    };
+    let baseline = epoch_time(&mut sync_source);
+
+    println!(
+        "{} instances, batch size {}, {:?} per batch read",
+        n_samples, BATCH_SIZE, READ_LATENCY
+    );
+    println!(
+        "synchronous reads: {:?} per epoch ({:.0} instances/s)",
+        baseline,
+        n_samples as f64 / baseline.as_secs_f64()
+    );
+
+    for (n_workers, prefetch_batches) in [(1, 4), (2, 8), (4, 8)] {
+        let mut loader = DataLoader::from_fn(
+            n_samples,
+            BATCH_SIZE,
+            n_workers,
+            prefetch_batches,
+            Arc::clone(&load_batch),
+        );
+        let time = epoch_time(&mut loader);
+
+        println!(
+            "DataLoader with {} workers, prefetching {} batches: {:?} per epoch ({:.0} instances/s, {:.2}x)",
+            n_workers,
+            prefetch_batches,
+            time,
+            n_samples as f64 / time.as_secs_f64(),
+            baseline.as_secs_f64() / time.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,12 @@
+// Generate the ONNX protobuf messages (used by src/parsing/onnx.rs) from proto/onnx.proto
+// protox compiles the .proto in Rust, so protoc doesn't need to be installed
+fn main() {
+    println!("cargo:rerun-if-changed=proto/onnx.proto");
+
+    let descriptors =
+        protox::compile(["proto/onnx.proto"], ["proto"]).expect("Failed to compile onnx.proto");
+
+    prost_build::Config::new()
+        .compile_fds(descriptors)
+        .expect("Failed to generate the ONNX messages");
+}
@@ -0,0 +1,52 @@
+"""Load a trained model through the C FFI bindings and predict an MNIST digit.
+
+Build the shared library with `cargo build --release`, then run:
+    python examples/python/predict_mnist.py weights.json mnist_test.csv
+"""
+import ctypes
+import sys
+
+NUM_FEATURES = 784
+NUM_CLASSES = 10
+
+lib = ctypes.CDLL(
+    {"darwin": "target/release/librust_neuralnet.dylib", "win32": "target/release/rust_neuralnet.dll"}.get(
+        sys.platform, "target/release/librust_neuralnet.so"
+    )
+)
+
+lib.neural_net_load.argtypes = [ctypes.c_char_p]
+lib.neural_net_load.restype = ctypes.c_void_p
+lib.neural_net_predict.argtypes = [
+    ctypes.c_void_p,
+    ctypes.POINTER(ctypes.c_double),
+    ctypes.c_size_t,
+    ctypes.POINTER(ctypes.c_double),
+    ctypes.c_size_t,
+]
+lib.neural_net_predict.restype = None
+lib.neural_net_free.argtypes = [ctypes.c_void_p]
+lib.neural_net_free.restype = None
+lib.neural_net_accuracy.argtypes = [ctypes.c_void_p, ctypes.c_char_p]
+lib.neural_net_accuracy.restype = ctypes.c_double
+
+weights_path, dataset_path = sys.argv[1], sys.argv[2]
+net = lib.neural_net_load(weights_path.encode())
+
+if not net:
+    sys.exit(f"Failed to load {weights_path}")
+
+# Predict the first instance of the dataset (skipping the header). Pixels are normalized like the Rust parser does
+with open(dataset_path) as f:
+    f.readline()
+    label, *pixels = map(float, f.readline().split(","))
+
+inputs = (ctypes.c_double * NUM_FEATURES)(*(p / 255 for p in pixels))
+outputs = (ctypes.c_double * NUM_CLASSES)()
+lib.neural_net_predict(net, inputs, NUM_FEATURES, outputs, NUM_CLASSES)
+
+prediction = max(range(NUM_CLASSES), key=lambda i: outputs[i])
+print(f"Predicted {prediction} (label {int(label)}) with probability {outputs[prediction]:.4f}")
+print(f"Accuracy on {dataset_path}: {lib.neural_net_accuracy(net, dataset_path.encode()):.4f}")
+
+lib.neural_net_free(net)
@@ -0,0 +1,53 @@
+use std::fmt;
+
+/// Errors returned by the fallible operations of the crate
+#[derive(Debug)]
+pub enum NeuralNetError {
+    Io(std::io::Error),
+    Parse(String), // A file was read successfully, but its contents are malformed
+    ShapeMismatch {
+        expected: Vec<usize>,
+        actual: Vec<usize>,
+    },
+    InvalidLayer {
+        index: usize,
+        num_layers: usize,
+    },
+    UnsupportedOp {
+        op: String, // An operation of an ONNX model that has no layer, or a part of a network that has no ONNX op
+    },
+    InvalidConfig(String), // The network was configured with options that can't be trained together
+    InvalidInput(String), // An input the first layer can't take, e.g. an embedding index outside the vocabulary
+}
+
+pub type Result<T> = std::result::Result<T, NeuralNetError>;
+
+impl fmt::Display for NeuralNetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NeuralNetError::Io(err) => write!(f, "I/O error: {}", err),
+            NeuralNetError::Parse(msg) => write!(f, "Parse error: {}", msg),
+            NeuralNetError::ShapeMismatch { expected, actual } => write!(
+                f,
+                "Shape mismatch: expected {:?}, got {:?}",
+                expected, actual
+            ),
+            NeuralNetError::InvalidLayer { index, num_layers } => write!(
+                f,
+                "Invalid layer index {} in a network with {} layers",
+                index, num_layers
+            ),
+            NeuralNetError::UnsupportedOp { op } => write!(f, "Unsupported operation {}", op),
+            NeuralNetError::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
+            NeuralNetError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for NeuralNetError {}
+
+impl From<std::io::Error> for NeuralNetError {
+    fn from(err: std::io::Error) -> NeuralNetError {
+        NeuralNetError::Io(err)
+    }
+}
@@ -0,0 +1,211 @@
+//! C bindings for running inference from other languages
+//! The header is generated with cbindgen (see cbindgen.toml): cbindgen --config cbindgen.toml --output neural_net.h
+
+use crate::model::metrics;
+use crate::model::neural_net::NeuralNet;
+use crate::model::Model;
+use crate::parsing::mnist;
+use ndarray::ArrayView2;
+use std::ffi::{c_char, CStr};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Load a model saved with NeuralNet::save. Returns NULL if the model can't be loaded
+/// The model must be freed with neural_net_free
+///
+/// # Safety
+/// path must be a valid NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn neural_net_load(path: *const c_char) -> *mut NeuralNet {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        return ptr::null_mut();
+    };
+
+    // Panics can't unwind into C
+    match panic::catch_unwind(|| NeuralNet::load(path)) {
+        Ok(Ok(net)) => Box::into_raw(Box::new(net)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Predict the probabilities of a single instance with n_features features, and write them to output
+/// If the sizes don't match the model or the prediction panics, the output is filled with NaNs
+///
+/// # Safety
+/// net must be a model returned by neural_net_load, input must hold n_features doubles
+/// and output must hold n_classes doubles
+#[no_mangle]
+pub unsafe extern "C" fn neural_net_predict(
+    net: *const NeuralNet,
+    input: *const f64,
+    n_features: usize,
+    output: *mut f64,
+    n_classes: usize,
+) {
+    if output.is_null() {
+        return;
+    }
+
+    let output = std::slice::from_raw_parts_mut(output, n_classes);
+    let Some(net) = net.as_ref() else {
+        output.fill(f64::NAN);
+        return;
+    };
+
+    // Input layers without a fixed input dimension (embeddings) take any number of features
+    if input.is_null()
+        || net.layers[0]
+            .input_dim()
+            .is_some_and(|input_dim| input_dim != n_features)
+        || n_classes != net.layers.last().unwrap().weights().ncols()
+    {
+        output.fill(f64::NAN);
+        return;
+    }
+
+    let input = ArrayView2::from_shape(
+        (1, n_features),
+        std::slice::from_raw_parts(input, n_features),
+    )
+    .unwrap();
+
+    if net.layers[0].validate_input(&input).is_err() {
+        output.fill(f64::NAN);
+        return;
+    }
+
+    let prediction = panic::catch_unwind(AssertUnwindSafe(|| net.predict(&input)));
+
+    match prediction {
+        Ok(prediction) => {
+            for (out, p) in output.iter_mut().zip(prediction.iter()) {
+                *out = *p;
+            }
+        }
+        Err(_) => output.fill(f64::NAN),
+    }
+}
+
+/// Free a model returned by neural_net_load. Does nothing if net is NULL
+///
+/// # Safety
+/// net must be a model returned by neural_net_load that wasn't freed already
+#[no_mangle]
+pub unsafe extern "C" fn neural_net_free(net: *mut NeuralNet) {
+    if !net.is_null() {
+        drop(Box::from_raw(net));
+    }
+}
+
+/// Calculate the accuracy of the model on an MNIST CSV dataset. Returns NaN if the dataset can't be parsed
+///
+/// # Safety
+/// net must be a model returned by neural_net_load and dataset_path must be a valid NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn neural_net_accuracy(
+    net: *const NeuralNet,
+    dataset_path: *const c_char,
+) -> f64 {
+    let (Some(net), false) = (net.as_ref(), dataset_path.is_null()) else {
+        return f64::NAN;
+    };
+    let Ok(path) = CStr::from_ptr(dataset_path).to_str() else {
+        return f64::NAN;
+    };
+
+    // The parser panics on malformed datasets, and panics can't unwind into C
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let dataset = mnist::parse_dataset(path);
+
+        metrics::accuracy(&net.predict(&dataset.data.view()), &dataset.target)
+    }))
+    .unwrap_or(f64::NAN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::layer::EmbeddingLayer;
+    use crate::model::neural_net::{InitMethod, NeuralNetBuilder};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::ffi::CString;
+
+    fn predict(net: &NeuralNet, input: &[f64], n_classes: usize) -> Vec<f64> {
+        let mut output = vec![0f64; n_classes];
+
+        unsafe {
+            neural_net_predict(
+                net,
+                input.as_ptr(),
+                input.len(),
+                output.as_mut_ptr(),
+                n_classes,
+            );
+        }
+
+        output
+    }
+
+    #[test]
+    fn load_and_predict_round_trip() {
+        let path = std::env::temp_dir().join(format!("ffi_model_{}.json", std::process::id()));
+        let net = NeuralNetBuilder::new(vec![3, 4, 2]).seed(0).build();
+        let input = [0.1f64, -0.2f64, 0.3f64];
+
+        net.save(path.to_str().unwrap()).unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let loaded = unsafe { neural_net_load(c_path.as_ptr()) };
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!loaded.is_null());
+        let expected = net.predict(&ArrayView2::from_shape((1, 3), &input).unwrap());
+        assert_eq!(
+            predict(unsafe { &*loaded }, &input, 2),
+            expected.iter().copied().collect::<Vec<_>>()
+        );
+
+        unsafe { neural_net_free(loaded) };
+    }
+
+    #[test]
+    fn load_returns_null_on_errors() {
+        let missing = CString::new("/nonexistent/model.json").unwrap();
+
+        assert!(unsafe { neural_net_load(missing.as_ptr()) }.is_null());
+        assert!(unsafe { neural_net_load(ptr::null()) }.is_null());
+    }
+
+    #[test]
+    fn predict_fills_nans_on_mismatched_sizes() {
+        let net = NeuralNetBuilder::new(vec![3, 4, 2]).seed(0).build();
+
+        assert!(predict(&net, &[0f64; 4], 2).iter().all(|x| x.is_nan()));
+        assert!(predict(&net, &[0f64; 3], 3).iter().all(|x| x.is_nan()));
+    }
+
+    #[test]
+    fn predict_takes_any_number_of_indices_with_an_input_embedding() {
+        let mut rng = StdRng::seed_from_u64(0);
+        // Two indices of 2-dimensional embeddings
+        let net = NeuralNetBuilder::new(vec![2, 4, 2])
+            .seed(0)
+            .build()
+            .with_input_embedding(EmbeddingLayer::new(5, 2, InitMethod::Default, &mut rng));
+
+        assert!(predict(&net, &[1f64, 3f64], 2)
+            .iter()
+            .all(|x| x.is_finite()));
+        // The layer after the embedding takes the embeddings of 2 indices, so predicting 3 panics (and is caught)
+        assert!(predict(&net, &[1f64, 3f64, 4f64], 2)
+            .iter()
+            .all(|x| x.is_nan()));
+        // The vocabulary holds the indices 0 to 4
+        assert!(predict(&net, &[1f64, 5f64], 2).iter().all(|x| x.is_nan()));
+        assert!(predict(&net, &[1.5, 3f64], 2).iter().all(|x| x.is_nan()));
+    }
+}
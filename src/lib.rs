@@ -0,0 +1,5 @@
+pub mod error;
+pub mod ffi;
+pub mod model;
+pub mod parsing;
+pub mod preprocessing;
@@ -1,21 +1,52 @@
-pub mod model;
-pub mod parsing;
-
-use clap::Parser;
-use json::object;
-use model::neural_net::{ActivationFunction, InitMethod};
-use model::{neural_net, Model};
-use ndarray::Axis;
-use parsing::mnist;
+use rust_neuralnet::{model, parsing, preprocessing};
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use model::callback::{GradientNormLogger, HistogramCallback};
+use model::clip::GradientClip;
+use model::curriculum::{CurriculumScheduler, PacingFunction};
+use model::ensemble::Ensemble;
+use model::history::TrainingHistory;
+use model::loss::{FocalLoss, LossFunction};
+use model::multitask::{MultiTaskConfig, MultiTaskNet};
+use model::neural_net::{
+    ActivationFunction, InitMethod, NeuralNet, NeuralNetBuilder, Task, Verbosity,
+};
+use model::noise::{GaussianNoiseLayer, NoiseLayer};
+use model::optimizer::Optimizer;
+use model::pruning::{self, PruningScheduler};
+use model::scheduler::{
+    CosineDecayRestarts, CosineWarmRestartExp, CosineWithWarmup, LRScheduler, PolynomialDecay,
+    SGDRScheduler,
+};
+use model::search::{GridSearchConfig, RandomSearchConfig};
+use model::vae::VAE;
+use model::{
+    ablation, adversarial, anomaly, gradient_flow, metrics, neural_net, onnx, quantized, search,
+    Model,
+};
+use ndarray::{Array1, Array2, ArrayView, Axis};
+use parsing::loader::DataLoader;
+use parsing::streaming::StreamingCsvDataset;
+use parsing::uci::{TargetPosition, UciConfig};
+use parsing::{mnist, npy, parquet, uci, CsvConfig, Dataset};
+use preprocessing::augmentation::GaussianNoise;
+use preprocessing::imputer::{MeanImputer, MedianImputer};
+use preprocessing::scaler::StandardScaler;
+use preprocessing::{feature_selection, Transform};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufReader, IsTerminal, Write};
+use std::sync::Arc;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
     /// The path of the training dataset
+    /// Not needed in online mode, where the training instances are read from stdin
     #[arg(short, long)]
-    train_path: String,
+    train_path: Option<String>,
 
     /// The path of the validation dataset
     #[arg(short, long)]
@@ -36,13 +67,17 @@ struct Args {
     /// Number of epochs to train the network for
     /// If this parameter is not provided, early stopping is used instead
     /// And you also need to specify a tolerance
-    #[arg(short, long, default_value = None)]
+    #[arg(long, default_value = None)]
     num_epochs: Option<usize>,
 
     /// Debug mode (save loss in a "time     loss" format)
     #[arg(short, long, default_value = None)]
     debug_path: Option<String>,
 
+    /// Save a plot of the losses over the epochs to this SVG file
+    #[arg(long, default_value = None)]
+    plot_losses: Option<String>,
+
     /// Activation function used by the network
     #[arg(short, long, default_value = None)]
     activation_function: ActivationFunction,
@@ -51,46 +86,1035 @@ struct Args {
     #[arg(short, long, default_value = None)]
     initialization: InitMethod,
 
-    /// Tolerance for early stopping
+    /// Tolerance for early stopping: training ends once the validation loss improves by less than this
     #[arg(short, long, default_value_t = 0.0001)]
     epsilon: f64,
-    
+
     /// Whether or not to export the model's weights
     /// Weights are exported in JSON format
     #[arg(short, long, default_value = None)]
     weight_path: Option<String>,
+
+    /// Probability of dropping each hidden unit during training
+    #[arg(long, default_value_t = 0.0)]
+    dropout: f64,
+
+    /// Decay the LR geometrically toward the earlier layers by this factor per layer, e.g. for fine-tuning
+    #[arg(long, default_value = None)]
+    layerwise_lr_decay: Option<f64>,
+
+    /// Also report the confidence and calibration of the predictions with the logits divided by this temperature
+    #[arg(long, default_value = None)]
+    inference_temperature: Option<f64>,
+
+    /// Evaluate the model with Monte Carlo dropout, using this many stochastic forward passes
+    #[arg(long, default_value = None)]
+    mc_dropout_samples: Option<usize>,
+
+    /// Add Gaussian noise with this standard deviation to the outputs of the hidden layers during training
+    #[arg(long, default_value = None)]
+    noise_std: Option<f64>,
+
+    /// Add the center loss with this weight to the loss, which pulls the features of each class together
+    #[arg(long, default_value = None)]
+    center_loss_lambda: Option<f64>,
+
+    /// The rate at which the class centers of the center loss are updated
+    #[arg(long, default_value_t = 0.5)]
+    center_loss_alpha: f64,
+
+    /// Train by reading batches of the training set (an MNIST CSV) from disk instead of loading it into memory
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["feature_threshold", "loader_workers", "auto_val_fraction"]
+    )]
+    streaming_train: bool,
+
+    /// Load the training batches in this many background threads
+    #[arg(long, default_value = None)]
+    loader_workers: Option<usize>,
+
+    /// The number of batches the background threads load ahead of training
+    #[arg(long, default_value_t = 4)]
+    prefetch_batches: usize,
+
+    /// Skip hidden layers during training with stochastic depth, down to this survival probability at the last layer
+    #[arg(long, default_value = None)]
+    stochastic_depth: Option<f64>,
+
+    /// Standardize the features of the training set, and attach the scaler to the model so that it's applied to the
+    /// unscaled instances it's tested on (and saved with it)
+    #[arg(long, default_value_t = false, conflicts_with = "streaming_train")]
+    standardize: bool,
+
+    /// Replace the missing features of the training and validation sets with the mean (or the median) of their
+    /// feature in the training set
+    #[arg(long, default_value = None, conflicts_with = "streaming_train")]
+    impute_missing: Option<ImputeKind>,
+
+    /// The value that marks a missing feature for --impute-missing
+    #[arg(long, default_value_t = f64::NAN)]
+    impute_missing_value: f64,
+
+    /// Print the class distribution of the training set before training
+    #[arg(long, default_value_t = false)]
+    dataset_stats: bool,
+
+    /// Weight the cross-entropy of each class inversely to its frequency in the training set
+    #[arg(long, default_value_t = false)]
+    auto_balance: bool,
+
+    /// Train with curriculum learning, on the easiest instances (by their loss) first
+    #[arg(long, default_value = None, conflicts_with_all = ["streaming_train", "loader_workers", "auto_val_fraction"])]
+    curriculum_pacing: Option<PacingKind>,
+
+    /// The fraction of the training set the first epoch of the curriculum trains on
+    #[arg(long, default_value_t = 0.2)]
+    curriculum_start: f64,
+
+    /// The fraction of the training set the linear pacing adds after each epoch
+    #[arg(long, default_value_t = 0.1)]
+    pacing_slope: f64,
+
+    /// The factor the exponential pacing multiplies the fraction by after each epoch
+    #[arg(long, default_value_t = 1.5)]
+    pacing_base: f64,
+
+    /// The epochs at which the step pacing grows the fraction
+    #[arg(long, num_args = 1.., value_delimiter = ' ', default_values_t = [2f64, 4f64, 6f64])]
+    pacing_steps: Vec<f64>,
+
+    /// Print a summary of the model after training
+    #[arg(long, default_value_t = false)]
+    summary: bool,
+
+    /// Export the graph of the network before training, as a Mermaid flowchart if the path ends with .mmd and in
+    /// the Graphviz DOT format otherwise
+    #[arg(long)]
+    export_graph: Option<String>,
+
+    /// Train with the weights stored at f32 precision, and the gradients and updates computed in f64
+    #[arg(long, default_value_t = false)]
+    mixed_precision: bool,
+
+    /// Clip the weight gradient of each layer relative to the norm of its weights (adaptive gradient clipping)
+    #[arg(long, default_value_t = false)]
+    gradient_clip_adaptive: bool,
+
+    /// The largest ratio of the norm of a layer's weight gradient to the norm of its weights with adaptive clipping
+    #[arg(long, default_value_t = 0.01)]
+    agc_lambda: f64,
+
+    /// Train each batch on only this fraction of its instances with the highest loss (online hard example mining)
+    #[arg(long, default_value = None)]
+    ohem_ratio: Option<f64>,
+
+    /// Calibrate the temperature of the model's softmax on the validation set after training
+    #[arg(long, default_value_t = false)]
+    calibrate_temperature: bool,
+
+    /// Also train an ensemble of this many nets, and compare it with its members on the validation set
+    #[arg(long, default_value = None)]
+    ensemble_size: Option<usize>,
+
+    /// Also train a snapshot ensemble with this many snapshots, and compare it with its snapshots on the validation set
+    #[arg(long, default_value = None)]
+    snapshot_ensemble: Option<usize>,
+
+    /// The number of epochs of each cosine cycle of the snapshot ensemble
+    #[arg(long, default_value_t = 5)]
+    snapshot_cycle_length: usize,
+
+    /// Loss function to train the network with (ignored when distilling from a teacher)
+    #[arg(long, default_value = "cross-entropy")]
+    loss_function: LossKind,
+
+    /// Activation applied to the outputs when predicting. Linear uses the softmax of the task (or the raw outputs with MSE)
+    #[arg(long, default_value = "linear")]
+    output_activation: ActivationFunction,
+
+    /// Focusing parameter of the focal loss
+    #[arg(long, default_value_t = 2.0)]
+    gamma: f64,
+
+    /// Weight the classes in the focal loss inversely to their frequency in the training set
+    #[arg(long, default_value_t = false)]
+    auto_focal_alpha: bool,
+
+    /// The weight of each class in the focal loss (one per output). All the classes are weighted equally by default
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    focal_alpha: Vec<f64>,
+
+    /// Path of a teacher model (in the JSON weights format) to distill into the trained network
+    #[arg(long, default_value = None)]
+    distillation_teacher: Option<String>,
+
+    /// Weight of the distillation loss relative to the loss on the hard labels
+    #[arg(long, default_value_t = 0.5)]
+    distillation_alpha: f64,
+
+    /// Temperature used to soften the outputs of the teacher and the student
+    #[arg(long, default_value_t = 4.0)]
+    distillation_temperature: f64,
+
+    /// Add the gradient penalty of WGAN-GP with this weight to the loss. For training a discriminator, with the real
+    /// instances labeled with the first class and the fake ones with another
+    #[arg(long, default_value = None)]
+    gradient_penalty_lambda: Option<f64>,
+
+    /// Path of a pretrained model (in the JSON weights format) to transfer layers from
+    /// The transferred layers are frozen during training
+    #[arg(long, default_value = None)]
+    transfer_from: Option<String>,
+
+    /// Number of layers to transfer from the pretrained model
+    #[arg(long, default_value_t = 1)]
+    transfer_layers: usize,
+
+    /// Extract the outputs of this layer on the validation set (layer 0 is the input layer)
+    #[arg(long, default_value = None)]
+    extract_features: Option<usize>,
+
+    /// Path of the .npy file the extracted features are saved to
+    #[arg(long, default_value = "features.npy")]
+    output_features: String,
+
+    /// Extract the outputs of the layer before the activation function is applied
+    #[arg(long, default_value_t = false)]
+    linear_features: bool,
+
+    /// Compute the saliency map of this class for every instance in the validation set
+    #[arg(long, default_value = None)]
+    saliency_class: Option<usize>,
+
+    /// Path of the CSV file the saliency maps are saved to (one instance per row)
+    #[arg(long, default_value = "saliency.csv")]
+    saliency_output: String,
+
+    /// Visualize the preferred input of a neuron given as layer,neuron (layer 0 is the input layer)
+    /// using activation maximization
+    #[arg(long, value_parser = parse_neuron)]
+    visualize_neuron: Option<(usize, usize)>,
+
+    /// Number of gradient ascent steps used to visualize the neuron
+    #[arg(long = "steps", default_value_t = 500)]
+    visualization_steps: usize,
+
+    /// Path of the CSV file the visualization of the neuron is saved to
+    #[arg(long = "output", default_value = "vis.csv")]
+    visualization_output: String,
+
+    /// Record the gradient norm of each layer after each batch, warn when they explode or vanish,
+    /// and print a summary after training
+    #[arg(long, default_value_t = false)]
+    log_gradient_norms: bool,
+
+    /// Print the validation instances with the highest loss after training
+    #[arg(long, default_value = None)]
+    hardest_samples: Option<usize>,
+
+    /// Print the pairs of neurons of each hidden layer with nearly parallel weights after training
+    #[arg(long, default_value_t = false)]
+    redundancy_analysis: bool,
+
+    /// The cosine similarity above which the redundancy analysis reports a pair of neurons
+    #[arg(long, default_value_t = 0.9)]
+    redundancy_threshold: f64,
+
+    /// Save histograms of the weights of each layer to <PREFIX>_epoch_<epoch>.csv during training
+    #[arg(long, default_value = None)]
+    weight_histograms: Option<String>,
+
+    /// Number of epochs between the saved weight histograms
+    #[arg(long, default_value_t = 1)]
+    histogram_every: usize,
+
+    /// Print the statistics of the gradients of each layer on the training set after training
+    #[arg(long, default_value_t = false)]
+    gradient_flow_report: bool,
+
+    /// Write the misclassified validation instances with the most confident predictions to this CSV file
+    #[arg(long, default_value = None)]
+    hardest_mistakes_csv: Option<String>,
+
+    /// The number of misclassified instances written to --hardest-mistakes-csv
+    #[arg(long, default_value_t = 20)]
+    num_hardest_mistakes: usize,
+
+    /// Also test the model with test-time augmentation, averaging the predictions of this many augmented copies
+    #[arg(long, default_value = None)]
+    tta_samples: Option<usize>,
+
+    /// The standard deviation of the Gaussian noise that augments the instances in test-time augmentation
+    #[arg(long, default_value_t = 0.05)]
+    tta_noise_std: f64,
+
+    /// Write the reliability diagram of the model on the validation set to this CSV file after training
+    /// Each line is a bin center, the mean confidence and the fraction correct of the predictions in the bin
+    #[arg(long, default_value = None)]
+    calibration_plot: Option<String>,
+
+    /// Evaluate the model on FGSM adversarial examples generated with these epsilons, e.g. 0.05 0.1 0.2
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    adversarial_eval_eps: Vec<f64>,
+
+    /// Prune this fraction of the weights (those with the smallest magnitudes) after training
+    #[arg(long, default_value = None)]
+    prune: Option<f64>,
+
+    /// Gradually prune the weights during training, up to this final sparsity (gradual magnitude pruning)
+    #[arg(long, default_value = None)]
+    gradual_prune: Option<f64>,
+
+    /// The sparsity the gradual pruning starts from
+    #[arg(long, default_value_t = 0.0)]
+    prune_initial_sparsity: f64,
+
+    /// The batch at which the gradual pruning begins
+    #[arg(long, default_value_t = 0)]
+    prune_begin_step: usize,
+
+    /// The batch at which the gradual pruning reaches the final sparsity
+    #[arg(long, default_value_t = 1000)]
+    prune_end_step: usize,
+
+    /// Number of batches between the gradual pruning steps
+    #[arg(long, default_value_t = 100)]
+    prune_frequency: usize,
+
+    /// Quantize the model to int8 after training, and save the quantized model to this path
+    #[arg(long, default_value = None)]
+    quantized_path: Option<String>,
+
+    /// Quantize the inputs of each layer to int8 as well, calibrating their ranges on the training set
+    #[arg(long, default_value_t = false)]
+    quantize_activations: bool,
+
+    /// Run the LR range test before training, and train with the LR it finds instead of the learning rate
+    #[arg(long, default_value_t = false)]
+    find_lr: bool,
+
+    /// Number of batches the LR range test trains for
+    #[arg(long, default_value_t = 100)]
+    lr_find_steps: usize,
+
+    /// Path of a CSV file the LRs and losses of the LR range test are saved to
+    #[arg(long, default_value = None)]
+    lr_find_output: Option<String>,
+
+    /// Learning rate schedule. The schedule starts from the learning rate and is stepped once per batch
+    #[arg(long, default_value = None)]
+    lr_scheduler: Option<SchedulerKind>,
+
+    /// Power of the polynomial decay
+    #[arg(long, default_value_t = 1.0)]
+    power: f64,
+
+    /// Number of batches the polynomial decay lasts for
+    #[arg(long, default_value_t = 1000)]
+    decay_steps: usize,
+
+    /// The LR at the end of the polynomial decay
+    #[arg(long, default_value_t = 0.0)]
+    end_lr: f64,
+
+    /// Restart the polynomial decay after each decay, doubling the number of decay steps
+    #[arg(long, default_value_t = false)]
+    lr_cycle: bool,
+
+    /// Number of batches in the first cycle of SGDR, sgdr-exp and cosine-restarts
+    #[arg(long = "T0", default_value_t = 10)]
+    t_0: usize,
+
+    /// Factor by which the length of each SGDR and sgdr-exp cycle grows
+    #[arg(long = "Tmult", default_value_t = 2)]
+    t_mult: usize,
+
+    /// Factor by which the length of each cosine-restarts cycle grows
+    #[arg(long, default_value_t = 2.0)]
+    t_mul: f64,
+
+    /// Factor by which the LR each cosine-restarts cycle starts from decays
+    #[arg(long, default_value_t = 1.0)]
+    m_mul: f64,
+
+    /// Factor by which the maximal LR of each sgdr-exp cycle decays
+    #[arg(long, default_value_t = 0.9)]
+    eta_max_decay: f64,
+
+    /// The minimal LR of cosine schedules
+    #[arg(long, default_value_t = 0.0)]
+    eta_min: f64,
+
+    /// Number of batches the LR of the cosine-warmup schedule increases linearly for
+    #[arg(long, default_value_t = 100)]
+    warmup_steps: usize,
+
+    /// Number of batches after which the cosine-warmup schedule reaches eta_min
+    #[arg(long, default_value_t = 1000)]
+    total_steps: usize,
+
+    /// Compare these saved models (e.g. trained with different activations or initializations) on the validation set
+    /// instead of training a network
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    ablation_configs: Vec<String>,
+
+    /// Test a dense network imported from this ONNX model on the validation set instead of training a network
+    #[arg(long, default_value = None)]
+    onnx_model: Option<String>,
+
+    /// Run a grid search over the hyperparams in this TOML config file instead of training a single network
+    #[arg(long, default_value = None)]
+    grid_search: Option<String>,
+
+    /// Train a multi-task net with the backbone and heads in this TOML config file (e.g. examples/multitask.toml)
+    /// instead of a single network
+    #[arg(long, default_value = None)]
+    task_config: Option<String>,
+
+    /// Train a variational autoencoder instead of a classifier. The hidden layers of the network structure are
+    /// used for the encoder, and in reverse for the decoder
+    #[arg(long, default_value_t = false)]
+    vae: bool,
+
+    /// With the autoencoder task, the fraction of the (normal) training instances that the anomaly threshold flags
+    #[arg(long, default_value_t = 0.05)]
+    anomaly_threshold_fpr: f64,
+
+    /// The dimension of the latent space of the VAE
+    #[arg(long, default_value_t = 20)]
+    latent_dim: usize,
+
+    /// The weight of the KL term in the loss of the VAE
+    #[arg(long, default_value_t = 1f64)]
+    vae_beta: f64,
+
+    /// Run a random search over the learning rate, batch size and dropout instead of training a single network
+    /// The configurations are trained for the number of epochs and evaluated on a held-out part of the training set
+    #[arg(long, default_value_t = false)]
+    random_search: bool,
+
+    /// Train with each of the sensitivity values of this hyperparam (learning_rate, batch_size, dropout or num_epochs),
+    /// holding all of the others fixed, and compare their accuracies on a held-out part of the training set
+    #[arg(long, default_value = None)]
+    sensitivity_param: Option<String>,
+
+    /// The values of the hyperparam the sensitivity analysis trains with
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    sensitivity_values: Vec<f64>,
+
+    /// Path of an SVG file the plot of the sensitivity analysis is saved to
+    #[arg(long, default_value = None)]
+    sensitivity_plot: Option<String>,
+
+    /// Number of configurations sampled by the random search
+    #[arg(long, default_value_t = 20)]
+    n_iter: usize,
+
+    /// Hold out this fraction of the training set (stratified by class) to compute the loss after each epoch
+    /// The validation dataset is still used to test the model after training
+    #[arg(long, default_value = None)]
+    auto_val_fraction: Option<f64>,
+
+    /// Optimizer used to update the weights
+    #[arg(long, default_value = "sgd")]
+    optimizer: OptimizerKind,
+
+    /// Training mode. In online mode the network is trained incrementally
+    /// on batches of batch_size CSV lines read from stdin
+    #[arg(long, default_value = "batch")]
+    mode: Mode,
+
+    /// How much progress is logged to stderr during training
+    #[arg(long, default_value = "epoch")]
+    verbosity: Verbosity,
+
+    /// Whether each instance has a single class (MNIST) or multiple labels
+    #[arg(long, default_value = "multiclass")]
+    task: Task,
+
+    /// Number of labels of a multi-label dataset. The datasets hold the labels followed by the features
+    #[arg(long, required_if_eq("task", "multilabel"))]
+    n_labels: Option<usize>,
+
+    /// Test the model by streaming the validation set (an MNIST CSV) in batches instead of loading it into memory
+    /// The validation set isn't used during training
+    #[arg(long, default_value_t = false)]
+    streaming_eval: bool,
+
+    /// Drop the features whose variance on the training set is at most this before training
+    /// The input layer of the network is resized to the number of retained features
+    #[arg(long, default_value = None, conflicts_with = "streaming_eval")]
+    feature_threshold: Option<f64>,
+
+    /// Format of the training and validation datasets
+    #[arg(long, default_value = "csv")]
+    dataset_format: DatasetFormat,
+
+    /// Column of a Parquet dataset that holds the classes
+    #[arg(long, default_value = "label")]
+    target_column: String,
+
+    /// Columns of a Parquet dataset used as features. All of the columns except the target are used by default
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    feature_columns: Vec<String>,
+
+    /// Separator of the values of a UCI dataset
+    #[arg(long, default_value_t = ',')]
+    uci_separator: char,
+
+    /// Whether the class is the first or the last value of each line of a UCI dataset
+    #[arg(long, default_value = "last")]
+    uci_target_position: TargetPosition,
+
+    /// Classes of a UCI dataset in the order of the one-hot encoding. The distinct classes are sorted by default
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    class_labels: Vec<String>,
+
+    /// Marks a missing value in a UCI dataset. Missing values are replaced with the mean of their column
+    #[arg(long, default_value = "?")]
+    missing_value: String,
+
+    /// Min-max scale the features of a UCI dataset to [0, 1]
+    #[arg(long, default_value_t = false)]
+    normalize: bool,
+
+    /// Seed of all the random sampling done by the network (weight initialization, dropout), for reproducible runs
+    #[arg(long, default_value = None)]
+    seed: Option<u64>,
 }
 
-/// Test the model on the validation set
-pub fn test_model(path: &str, model: &neural_net::NeuralNet) {
-    let dataset = mnist::parse_dataset(path);
-    let predictions = model.predict(&dataset.data.view());
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq)]
+enum Mode {
+    Batch,
+    Online,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq)]
+enum DatasetFormat {
+    Csv,
+    Parquet,
+    Uci,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum LossKind {
+    CrossEntropy,
+    Cosine,
+    Focal,
+    Mse,
+    Hinge,
+    CategoricalHinge,
+    KlDivergence,
+    Poisson,
+    LogCosh,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum PacingKind {
+    Linear,
+    Exponential,
+    Step,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum ImputeKind {
+    Mean,
+    Median,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum OptimizerKind {
+    Sgd,
+    Rprop,
+    Adam,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum SchedulerKind {
+    Polynomial,
+    Sgdr,
+    CosineWarmup,
+    CosineRestarts,
+    SgdrExp,
+}
+
+/// Train the network on batches read from stdin until EOF, printing the loss of each batch
+fn train_online(neural_net: &mut NeuralNet) {
+    neural_net
+        .check_config()
+        .expect("Failed to train the network");
+
+    let mut lines = Vec::with_capacity(neural_net.batch_size);
+    let mut batch_idx = 0;
+
+    for line in std::io::stdin().lines() {
+        lines.push(line.expect("Failed to read from stdin"));
+
+        if lines.len() == neural_net.batch_size {
+            train_online_batch(neural_net, &lines, &mut batch_idx);
+            lines.clear();
+        }
+    }
 
+    // The last batch may be smaller
+    train_online_batch(neural_net, &lines, &mut batch_idx);
+}
+
+/// Perform a single partial_fit step on some lines read from stdin
+fn train_online_batch(neural_net: &mut NeuralNet, lines: &[String], batch_idx: &mut usize) {
+    let batch = mnist::parse_lines(lines);
+
+    // Skip batches that don't contain any valid lines (e.g. only the CSV header)
+    if batch.data.nrows() != 0 {
+        let loss = neural_net.partial_fit(&batch.data.view(), &batch.target.view());
+        println!("Batch {}: loss {}", batch_idx, loss);
+        *batch_idx += 1;
+    }
+}
+
+/// Parse a neuron given as layer,neuron
+fn parse_neuron(s: &str) -> Result<(usize, usize), String> {
+    let (layer, neuron) = s
+        .split_once(',')
+        .ok_or("Expected a neuron in the format layer,neuron")?;
+
+    Ok((
+        layer.trim().parse().map_err(|_| "Invalid layer index")?,
+        neuron.trim().parse().map_err(|_| "Invalid neuron index")?,
+    ))
+}
+
+/// Construct the LR scheduler chosen in the command line arguments, if there is one
+fn build_scheduler(args: &Args) -> Option<Box<dyn LRScheduler>> {
+    match args.lr_scheduler.as_ref()? {
+        SchedulerKind::Polynomial => Some(Box::new(PolynomialDecay::new(
+            args.learning_rate,
+            args.end_lr,
+            args.decay_steps,
+            args.power,
+            args.lr_cycle,
+        ))),
+        SchedulerKind::Sgdr => Some(Box::new(SGDRScheduler::new(
+            args.t_0,
+            args.t_mult,
+            args.eta_min,
+            args.learning_rate,
+        ))),
+        SchedulerKind::CosineWarmup => Some(Box::new(CosineWithWarmup::new(
+            args.warmup_steps,
+            args.total_steps,
+            args.eta_min,
+            args.learning_rate,
+        ))),
+        SchedulerKind::CosineRestarts => Some(Box::new(CosineDecayRestarts::new(
+            args.learning_rate,
+            args.eta_min,
+            args.t_0,
+            args.t_mul,
+            args.m_mul,
+        ))),
+        SchedulerKind::SgdrExp => Some(Box::new(CosineWarmRestartExp::new(
+            args.t_0,
+            args.t_mult,
+            args.learning_rate,
+            args.eta_max_decay,
+            args.eta_min,
+        ))),
+    }
+}
+
+/// Count the instances whose predicted class differs from their target class
+fn count_mistakes(predictions: &Array2<f64>, target: &Array2<f64>) -> usize {
     let mut num_mistakes = 0;
 
     for (prediction, target_row) in predictions
         .axis_iter(Axis(0))
-        .zip(dataset.target.axis_iter(Axis(0)))
+        .zip(target.axis_iter(Axis(0)))
     {
-        let predict_digit = prediction
-            .iter()
-            .enumerate()
-            .max_by(|(_, a), (_, b)| a.total_cmp(b))
-            .unwrap()
-            .0;
-        let actual_digit = target_row
-            .iter()
-            .enumerate()
-            .max_by(|(_, a), (_, b)| a.total_cmp(b))
-            .unwrap()
-            .0;
+        let predict_digit = metrics::argmax(prediction);
+        let actual_digit = metrics::argmax(target_row);
 
         if predict_digit != actual_digit {
             num_mistakes += 1;
         }
     }
 
+    num_mistakes
+}
+
+/// Test the model on the validation set
+pub fn test_model(dataset: &Dataset, model: &neural_net::NeuralNet) {
+    let predictions = model.predict(&dataset.data.view());
+    let cm = metrics::confusion_matrix(&predictions, &dataset.target).mapv(|x| x as usize);
+    let evaluation = metrics::evaluate(model, dataset);
+
+    print!(
+        "{}",
+        metrics::format_confusion_matrix(&cm, None, std::io::stdout().is_terminal())
+    );
+    println!(
+        "Cohen's kappa is {:.4} and the MCC is {:.4}",
+        evaluation.cohens_kappa, evaluation.matthews_correlation_coefficient
+    );
+}
+
+/// Test a model that predicts counts (trained with the Poisson loss)
+fn test_count_model(dataset: &Dataset, model: &neural_net::NeuralNet) {
+    let predictions = model.predict(&dataset.data.view());
+    let deviance = metrics::poisson_deviance(
+        &predictions.iter().copied().collect(),
+        &dataset.target.iter().copied().collect(),
+    );
+
+    println!("Poisson deviance: {:.4}", deviance);
+}
+
+/// Test the model on a validation set streamed from disk in batches
+fn test_model_streaming(path: &str, model: &neural_net::NeuralNet) {
+    let reader = BufReader::new(File::open(path).expect("Failed to open the validation set"));
+    let evaluation = model
+        .evaluate_streaming(reader, model.batch_size, &CsvConfig::default())
+        .expect("Failed to evaluate the model");
+
+    println!(
+        "The accuracy is {:.4} and the loss is {:.4}",
+        evaluation.accuracy, evaluation.loss
+    );
+    println!(
+        "Cohen's kappa is {:.4} and the MCC is {:.4}",
+        evaluation.cohens_kappa, evaluation.matthews_correlation_coefficient
+    );
+}
+
+/// Report the mean confidence and the ECE of the predictions on the validation set at an inference temperature
+/// The temperature doesn't change the predicted classes, only how confident the model is in them
+fn test_model_temperature(dataset: &Dataset, model: &neural_net::NeuralNet, temperature: f64) {
+    const NUM_BINS: usize = 15;
+
+    let predictions = model.predict_with_temperature(&dataset.data.view(), temperature);
+    let mean_confidence = predictions
+        .axis_iter(Axis(0))
+        .map(|row| row.iter().copied().fold(0f64, f64::max))
+        .sum::<f64>()
+        / predictions.nrows() as f64;
+    let ece = metrics::expected_calibration_error(&predictions, &dataset.target, NUM_BINS);
+
+    println!(
+        "At a temperature of {}, the mean confidence is {:.4} and the ECE is {:.4}",
+        temperature, mean_confidence, ece
+    );
+}
+
+/// Test a multi-label model on the validation set
+fn test_multilabel_model(dataset: &Dataset, model: &neural_net::NeuralNet) {
+    const THRESHOLD: f64 = 0.5;
+
+    let predictions = model.predict(&dataset.data.view());
+
+    println!(
+        "The exact match ratio is {:.4}, the Hamming loss is {:.4} and the macro F1 score is {:.4}",
+        metrics::multilabel_accuracy(&predictions, &dataset.target, THRESHOLD),
+        metrics::multilabel_hamming_loss(&predictions, &dataset.target, THRESHOLD),
+        metrics::multilabel_f1_macro(&predictions, &dataset.target, THRESHOLD)
+    );
+}
+
+/// Parse a dataset in the format of the task
+fn parse_dataset(args: &Args, path: &str) -> Dataset {
+    match (&args.dataset_format, &args.task) {
+        (DatasetFormat::Parquet, _) => {
+            let feature_columns: Vec<&str> =
+                args.feature_columns.iter().map(String::as_str).collect();
+
+            parquet::parse_parquet(path, &args.target_column, &feature_columns)
+                .expect("Failed to parse the Parquet dataset")
+        }
+        (DatasetFormat::Uci, _) => {
+            let config = UciConfig {
+                separator: args.uci_separator,
+                target_col_position: args.uci_target_position,
+                class_labels: args.class_labels.clone(),
+                missing_value_str: Some(args.missing_value.clone()),
+                normalize: args.normalize,
+            };
+
+            uci::parse_uci(path, config).expect("Failed to parse the UCI dataset")
+        }
+        // The labels of an autoencoder's dataset aren't used
+        (DatasetFormat::Csv, Task::Multiclass | Task::Autoencoder) => mnist::parse_dataset(path),
+        (DatasetFormat::Csv, Task::Multilabel) => {
+            Dataset::from_multilabel_csv(path, args.n_labels.unwrap())
+                .expect("Failed to parse the multi-label dataset")
+        }
+    }
+}
+
+/// Test the model on the validation set using Monte Carlo dropout
+/// Also reports the mean variance of the predicted probabilities, which estimates the model's uncertainty
+pub fn test_model_mc_dropout(dataset: &Dataset, model: &neural_net::NeuralNet, n_samples: usize) {
+    let (mean, variance) = model.predict_mc_dropout(&dataset.data.view(), n_samples);
+    let num_mistakes = count_mistakes(&mean, &dataset.target);
+
     println!("The number of mistakes is {}", num_mistakes);
+    println!(
+        "The mean predictive variance is {}",
+        variance.mean().unwrap_or(0f64)
+    );
+}
+
+/// Test the model with test-time augmentation by Gaussian noise
+fn test_model_tta(dataset: &Dataset, model: &neural_net::NeuralNet, n_aug: usize, std: f64) {
+    const NOISE_SEED: u64 = 0;
+
+    let transforms: Vec<Box<dyn Transform>> = vec![Box::new(GaussianNoise::new(std, NOISE_SEED))];
+    let predictions = model.predict_tta(&dataset.data.view(), &transforms, n_aug);
+
+    println!(
+        "With test-time augmentation, the number of mistakes is {}",
+        count_mistakes(&predictions, &dataset.target)
+    );
+}
+
+/// Calibrate the temperature of the model, and report the calibration error before and after
+fn calibrate_model(dataset: &Dataset, model: &mut neural_net::NeuralNet) {
+    const NUM_BINS: usize = 15;
+
+    let before = metrics::expected_calibration_error(
+        &model.predict(&dataset.data.view()),
+        &dataset.target,
+        NUM_BINS,
+    );
+    let temperature = model.calibrate_temperature(dataset);
+    let after = metrics::expected_calibration_error(
+        &model.predict(&dataset.data.view()),
+        &dataset.target,
+        NUM_BINS,
+    );
+
+    println!("The calibrated temperature is {}", temperature);
+    println!("The ECE went from {} to {}", before, after);
+}
+
+/// Train an autoencoder on the training set, which is assumed to be normal, and report the anomalies in the validation set
+fn detect_anomalies(
+    neural_net: &mut NeuralNet,
+    dataset: &Dataset,
+    validation: &Dataset,
+    n_epochs: usize,
+    fpr_target: f64,
+) {
+    neural_net.fit_autoencoder(dataset, n_epochs);
+
+    let threshold = anomaly::fit_anomaly_threshold(neural_net, dataset, fpr_target);
+    let errors = anomaly::reconstruction_error(neural_net, &validation.data.view());
+    let num_anomalies = errors.iter().filter(|&&error| error > threshold).count();
+
+    println!(
+        "The anomaly threshold is a reconstruction error of {:.6}",
+        threshold
+    );
+    println!(
+        "{} of the {} validation instances are anomalies, and the mean reconstruction error is {:.6}",
+        num_anomalies,
+        validation.data.nrows(),
+        errors.mean().unwrap_or(0f64)
+    );
+}
+
+/// Train a VAE on the training set, and report its reconstruction loss on the validation set
+fn train_vae_model(args: &Args, dataset: &Dataset, validation: &Dataset) {
+    let structure = &args.network_structure;
+    let hidden = &structure[1..structure.len() - 1];
+    let encoder_structure = [&structure[..1], hidden, &[2 * args.latent_dim]].concat();
+    let decoder_structure: Vec<usize> = std::iter::once(args.latent_dim)
+        .chain(hidden.iter().rev().copied())
+        .chain(std::iter::once(structure[0]))
+        .collect();
+    let builder = |layer_structure: Vec<usize>| {
+        let builder = NeuralNetBuilder::new(layer_structure)
+            .batch_size(args.batch_size)
+            .learning_rate(args.learning_rate)
+            .activation_function(args.activation_function.clone())
+            .init_method(args.initialization.clone())
+            .verbosity(args.verbosity);
+
+        match args.seed {
+            Some(seed) => builder.seed(seed),
+            None => builder,
+        }
+    };
+    let mut vae = VAE::new(
+        builder(encoder_structure).build(),
+        builder(decoder_structure).build(),
+        args.latent_dim,
+    );
+
+    if let Some(seed) = args.seed {
+        vae = vae.with_seed(seed);
+    }
+
+    vae.train_vae(dataset, args.num_epochs.unwrap_or(10), args.vae_beta);
+
+    println!(
+        "The reconstruction loss on the validation set is {:.4} bits",
+        vae.reconstruction_loss(&validation.data.view())
+    );
+}
+
+/// Train a multi-task net on the targets its config derives from the classes, and report the validation accuracy
+/// of each head
+fn train_multitask_model(
+    args: &Args,
+    config: &MultiTaskConfig,
+    dataset: &Dataset,
+    validation: &Dataset,
+) {
+    let builder = |layer_structure: Vec<usize>| {
+        let builder = NeuralNetBuilder::new(layer_structure)
+            .batch_size(args.batch_size)
+            .learning_rate(args.learning_rate)
+            .activation_function(args.activation_function.clone())
+            .init_method(args.initialization.clone())
+            .verbosity(args.verbosity);
+
+        match args.seed {
+            Some(seed) => builder.seed(seed),
+            None => builder,
+        }
+    };
+    let num_features = *config.backbone.last().unwrap();
+    let heads = config
+        .head_names
+        .iter()
+        .zip(config.head_layers.iter())
+        .zip(config.loss_weights.iter())
+        .map(|((name, layers), weight)| {
+            let structure = [&[num_features], layers.as_slice()].concat();
+
+            (name.clone(), builder(structure).build(), *weight)
+        })
+        .collect();
+    let mut net = MultiTaskNet::new(builder(config.backbone.clone()).build(), heads);
+
+    net.fit(
+        &dataset.data,
+        &config.head_targets(&dataset.labels()),
+        args.num_epochs.unwrap_or(config.num_epochs),
+    );
+
+    let targets = config.head_targets(&validation.labels());
+
+    for ((name, predictions), target) in net.predict(&validation.data.view()).iter().zip(targets) {
+        println!(
+            "The accuracy of the {} head on the validation set is {:.4}",
+            name,
+            metrics::accuracy(predictions, &target)
+        );
+    }
+}
+
+/// Train an ensemble and compare its mistakes on the validation set with those of its members
+fn benchmark_ensemble(
+    builder: &NeuralNetBuilder,
+    dataset: &Dataset,
+    validation: &Dataset,
+    n_members: usize,
+) {
+    let seeds: Vec<u64> = (0..n_members as u64).collect();
+    let ensemble = Ensemble::train_members(builder, dataset, n_members, &seeds);
+
+    report_ensemble(&ensemble, validation);
+}
+
+/// Train a snapshot ensemble and compare its mistakes on the validation set with those of its snapshots
+fn benchmark_snapshot_ensemble(
+    builder: &NeuralNetBuilder,
+    dataset: &Dataset,
+    validation: &Dataset,
+    n_snapshots: usize,
+    cycle_length: usize,
+) {
+    let (_, ensemble) = builder
+        .build()
+        .fit_snapshot_ensemble(dataset, n_snapshots, cycle_length);
+
+    report_ensemble(&ensemble, validation);
+}
+
+/// Print the mistakes of an ensemble and of each of its members on the validation set
+fn report_ensemble(ensemble: &Ensemble, validation: &Dataset) {
+    let inputs = validation.data.view();
+
+    for (i, member) in ensemble.members.iter().enumerate() {
+        let num_mistakes = count_mistakes(&member.predict(&inputs), &validation.target);
+        println!("Ensemble member {} made {} mistakes", i, num_mistakes);
+    }
+
+    let average_mistakes = count_mistakes(&ensemble.predict(&inputs), &validation.target);
+    let vote_mistakes = count_mistakes(&ensemble.predict_vote(&inputs), &validation.target);
+
+    println!(
+        "The ensemble made {} mistakes when averaging",
+        average_mistakes
+    );
+    println!("The ensemble made {} mistakes when voting", vote_mistakes);
+}
+
+/// Write a matrix to a CSV file, one row per line
+fn write_matrix(path: &str, matrix: &Array2<f64>) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+
+    for row in matrix.axis_iter(Axis(0)) {
+        let line: Vec<String> = row.iter().map(|x| x.to_string()).collect();
+        file.write_all(format!("{}\n", line.join(",")).as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Print the number and fraction of the instances of each class, with a bar chart of the fractions
+fn print_class_distribution(dist: &[(usize, usize, f64)]) {
+    const BAR_WIDTH: usize = 40;
+    let largest = dist.first().map_or(0f64, |(_, _, fraction)| *fraction);
+
+    println!("class   count     fraction");
+
+    for (class, count, fraction) in dist {
+        let bar_len = if largest > 0f64 {
+            (fraction / largest * BAR_WIDTH as f64).round() as usize
+        } else {
+            0
+        };
+
+        println!(
+            "{:<7} {:<9} {:<9.4} {}",
+            class,
+            count,
+            fraction,
+            "#".repeat(bar_len)
+        );
+    }
+}
+
+/// Write misclassified instances to a CSV file with a header line
+fn write_mistakes(path: &str, mistakes: &[metrics::MisclassifiedSample]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+
+    file.write_all(b"index,true_class,predicted_class,confidence\n")?;
+
+    for mistake in mistakes {
+        file.write_all(
+            format!(
+                "{},{},{},{}\n",
+                mistake.index, mistake.true_class, mistake.predicted_class, mistake.confidence
+            )
+            .as_bytes(),
+        )?;
+    }
+
+    Ok(())
 }
 
 /// Write the losses to a debug file
@@ -104,50 +1128,696 @@ fn write_losses(debug_path: &str, losses: Vec<(usize, f64)>) -> std::io::Result<
     Ok(())
 }
 
-/// Write the weights of the model in JSON formats
-/// The keys are e.g. W0, b0, W1, b1. The values are provided in an array of the weights
-fn write_weights(weight_path: &str, model: &neural_net::NeuralNet) -> std::io::Result<()> {
-    let mut data = object! {};
-    let mut file = File::create(weight_path)?;
+fn main() {
+    let mut args = Args::parse();
+
+    if args.mode == Mode::Batch
+        && args.train_path.is_none()
+        && args.ablation_configs.is_empty()
+        && args.onnx_model.is_none()
+    {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--train-path is required unless --mode online, --onnx-model or --ablation-configs is used",
+            )
+            .exit();
+    }
+
+    let mut dataset = match args.train_path.as_deref() {
+        Some(path) if !args.streaming_train => parse_dataset(&args, path),
+        _ => Dataset::default(),
+    };
+    let mut validation = if args.streaming_eval {
+        Dataset::default()
+    } else {
+        parse_dataset(&args, &args.validation_path)
+    };
 
-    for (i, weight) in model.layers.iter().enumerate() {
-        let w: Vec<f64> = weight.0.iter().map(|x| *x).collect();
-        let b: Vec<f64> = weight.1.iter().map(|x| *x).collect();
-        let w_key = format!("W{}", i);
-        let b_key = format!("b{}", i);
+    if let Some(threshold) = args.feature_threshold {
+        let (data, selected) =
+            feature_selection::variance_threshold_filter(&dataset.data, threshold);
 
-        data[w_key] = w.into();
-        data[b_key] = b.into();
+        println!(
+            "Kept {} of {} features with a variance above {}",
+            selected.len(),
+            dataset.data.ncols(),
+            threshold
+        );
+
+        dataset.data = data;
+        validation.data = feature_selection::apply_feature_selection(&validation.data, &selected);
+        args.network_structure[0] = selected.len();
     }
 
-    file.write_all(data.dump().as_bytes())?;
+    if let Some(path) = &args.onnx_model {
+        let model = onnx::load_from_onnx(path).expect("Failed to import the ONNX model");
 
-    Ok(())
-}
+        test_model(&validation, &model);
 
-fn main() {
-    let args = Args::parse();
+        return;
+    }
 
-    let dataset = mnist::parse_dataset(&args.train_path);
-    let mut neural_net = neural_net::NeuralNet::new(
-        args.network_structure,
-        args.num_epochs,
-        args.batch_size,
-        args.learning_rate,
-        args.activation_function,
-        args.initialization,
-        args.epsilon,
-    );
+    if !args.ablation_configs.is_empty() {
+        let configs: Vec<(String, neural_net::NeuralNet)> = args
+            .ablation_configs
+            .iter()
+            .map(|path| {
+                let model = neural_net::NeuralNet::load(path).expect("Failed to load the model");
+
+                (path.clone(), model)
+            })
+            .collect();
+
+        print!("{}", ablation::ablation_study(&configs, &validation));
+
+        return;
+    }
+
+    if args.random_search {
+        let config = RandomSearchConfig {
+            lr_log_range: (1e-4, 1e-1),
+            batch_size_range: (16, 128),
+            dropout_range: (0f64, 0.5),
+            layer_structure: args.network_structure.clone(),
+            activation: args.activation_function.clone(),
+            num_epochs: args.num_epochs.unwrap_or(10),
+            val_fraction: 0.1,
+        };
+
+        let mut rng = match args.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        println!("accuracy   learning rate   batch size   dropout");
+
+        for result in search::random_search(config, args.n_iter, &dataset, &mut rng) {
+            println!(
+                "{:<10.4} {:<15.6} {:<12} {:.4}",
+                result.val_accuracy, result.learning_rate, result.batch_size, result.dropout_rate
+            );
+        }
+
+        return;
+    }
+
+    if let Some(path) = &args.grid_search {
+        let config =
+            GridSearchConfig::from_file(path).expect("Failed to parse the grid search config");
+
+        println!("accuracy   learning rate   batch size   activation   structure");
+
+        for result in search::grid_search(config, &dataset, &validation) {
+            println!(
+                "{:<10.4} {:<15} {:<12} {:<12} {:?}",
+                result.val_accuracy,
+                result.learning_rate,
+                result.batch_size,
+                format!("{:?}", result.activation),
+                result.layer_structure
+            );
+        }
+
+        return;
+    }
+
+    if let Some(path) = &args.task_config {
+        let config = MultiTaskConfig::from_file(path).expect("Failed to parse the task config");
+
+        train_multitask_model(&args, &config, &dataset, &validation);
+
+        return;
+    }
+
+    if args.vae {
+        train_vae_model(&args, &dataset, &validation);
+
+        return;
+    }
+
+    let loss_function = match &args.distillation_teacher {
+        Some(path) => LossFunction::Distillation {
+            teacher: Arc::new(NeuralNet::load(path).expect("Failed to load the teacher model")),
+            alpha: args.distillation_alpha,
+            temperature: args.distillation_temperature,
+        },
+        None if args.task == Task::Multilabel => LossFunction::BinaryCrossEntropy,
+        None if args.task == Task::Autoencoder => LossFunction::MSE,
+        None => match args.loss_function {
+            LossKind::CrossEntropy => LossFunction::CrossEntropy,
+            LossKind::Cosine => LossFunction::CosineSimilarity,
+            LossKind::Mse => LossFunction::MSE,
+            LossKind::Hinge => LossFunction::Hinge,
+            LossKind::CategoricalHinge => LossFunction::CategoricalHinge,
+            LossKind::KlDivergence => LossFunction::KLDivergence,
+            LossKind::Poisson => LossFunction::Poisson,
+            LossKind::LogCosh => LossFunction::LogCosh,
+            LossKind::Focal if args.auto_focal_alpha => {
+                LossFunction::Focal(FocalLoss::balanced(args.gamma, &dataset))
+            }
+            LossKind::Focal if !args.focal_alpha.is_empty() => LossFunction::Focal(FocalLoss::new(
+                args.gamma,
+                Array1::from_vec(args.focal_alpha.clone()),
+            )),
+            LossKind::Focal => LossFunction::Focal(FocalLoss::new(
+                args.gamma,
+                Array1::ones(*args.network_structure.last().unwrap()),
+            )),
+        },
+    };
+
+    if let LossFunction::Focal(focal) = &loss_function {
+        if focal.alpha.len() != *args.network_structure.last().unwrap() {
+            eprintln!(
+                "The focal loss needs an alpha for each of the {} outputs",
+                args.network_structure.last().unwrap()
+            );
+
+            return;
+        }
+
+        println!("Training with the {}", focal);
+    }
+
+    let loss_function = match args.gradient_penalty_lambda {
+        Some(lambda) => LossFunction::GradientPenalty {
+            base: Box::new(loss_function),
+            lambda,
+        },
+        None => loss_function,
+    };
+    let lr_scheduler = build_scheduler(&args);
+    let builder = NeuralNetBuilder::new(args.network_structure)
+        .num_epochs(args.num_epochs)
+        .batch_size(args.batch_size)
+        .learning_rate(args.learning_rate)
+        .activation_function(args.activation_function)
+        .init_method(args.initialization)
+        .early_stopping_epsilon(args.epsilon)
+        .dropout_rate(args.dropout)
+        .loss_function(loss_function)
+        .verbosity(args.verbosity)
+        .task(args.task)
+        .optimizer(match args.optimizer {
+            OptimizerKind::Sgd => Optimizer::SGD,
+            OptimizerKind::Rprop => Optimizer::rprop(),
+            OptimizerKind::Adam => Optimizer::adam(),
+        });
+    let builder = match args.seed {
+        Some(seed) => builder.seed(seed),
+        None => builder,
+    };
+
+    if let Some(param) = &args.sensitivity_param {
+        let results =
+            search::hyperparameter_sensitivity(&builder, &dataset, param, &args.sensitivity_values)
+                .expect("Failed to run the sensitivity analysis");
+
+        println!("{:<15} accuracy", param);
+
+        for (value, accuracy) in results.iter() {
+            println!("{:<15} {:.4}", value, accuracy);
+        }
+
+        if let Some(plot_path) = &args.sensitivity_plot {
+            if let Err(err) = TrainingHistory::save_sensitivity_plot_svg(&results, param, plot_path)
+            {
+                eprintln!("Failed to save the sensitivity plot: {}", err);
+            }
+        }
+
+        return;
+    }
+
+    let mut neural_net = builder.build();
+
+    if let Some(lr_scheduler) = lr_scheduler {
+        neural_net = neural_net.with_lr_scheduler(lr_scheduler);
+    }
+
+    neural_net = neural_net.with_output_activation(args.output_activation.clone());
+
+    if let Some(std) = args.noise_std {
+        neural_net = neural_net.with_noise(NoiseLayer::Gaussian(GaussianNoiseLayer { std }));
+    }
+
+    if args.auto_balance {
+        neural_net = neural_net.with_auto_class_weights(&dataset);
+    }
+
+    if let Some(lambda) = args.center_loss_lambda {
+        neural_net = neural_net.with_center_loss(lambda, args.center_loss_alpha);
+    }
+
+    if let Some(path) = &args.transfer_from {
+        neural_net = neural_net
+            .load_and_transfer(path, args.transfer_layers)
+            .expect("Failed to transfer from the pretrained model");
+    }
+
+    if args.dataset_stats {
+        // The most frequent class may have up to this many times the instances of the least frequent one
+        const BALANCE_THRESHOLD: f64 = 1.5;
+
+        print_class_distribution(&dataset.class_distribution());
+        println!(
+            "The training set is {}",
+            if dataset.is_balanced(BALANCE_THRESHOLD) {
+                "balanced"
+            } else {
+                "imbalanced"
+            }
+        );
+    }
+
+    if let Some(kind) = &args.impute_missing {
+        let imputer: Box<dyn Transform> = match kind {
+            ImputeKind::Mean => {
+                Box::new(MeanImputer::fit(&dataset.data, args.impute_missing_value))
+            }
+            ImputeKind::Median => {
+                Box::new(MedianImputer::fit(&dataset.data, args.impute_missing_value))
+            }
+        };
+
+        dataset.data = imputer.transform(&dataset.data);
+        validation.data = imputer.transform(&validation.data);
+    }
+
+    if args.standardize {
+        let scaler = StandardScaler::fit(&dataset.data);
+
+        dataset.data = scaler.transform(&dataset.data);
+        neural_net = neural_net.with_input_scaler(Box::new(scaler));
+    }
+
+    if let Some(min_survival_rate) = args.stochastic_depth {
+        neural_net = neural_net.with_stochastic_depth(min_survival_rate);
+    }
+
+    if args.mixed_precision {
+        neural_net = neural_net.with_mixed_precision(true);
+    }
+
+    if args.gradient_clip_adaptive {
+        neural_net = neural_net.with_gradient_clip(GradientClip::Adaptive {
+            lambda: args.agc_lambda,
+        });
+    }
+
+    if let Some(final_sparsity) = args.gradual_prune {
+        neural_net = neural_net.with_pruning_scheduler(PruningScheduler::new(
+            args.prune_initial_sparsity,
+            final_sparsity,
+            args.prune_begin_step,
+            args.prune_end_step,
+            args.prune_frequency,
+        ));
+    }
+
+    if let Some(hard_ratio) = args.ohem_ratio {
+        neural_net = neural_net.with_ohem(hard_ratio);
+    }
+
+    if let Some(decay) = args.layerwise_lr_decay {
+        neural_net.set_layerwise_lr_decay(args.learning_rate, decay);
+    }
+
+    if args.log_gradient_norms {
+        neural_net = neural_net.with_callback(Box::new(GradientNormLogger::default()));
+    }
+
+    if let Some(prefix) = &args.weight_histograms {
+        neural_net = neural_net.with_callback(Box::new(HistogramCallback::new(
+            args.histogram_every,
+            Some(prefix.clone()),
+        )));
+    }
+
+    if let Some(path) = &args.export_graph {
+        let saved = if path.ends_with(".mmd") {
+            std::fs::write(path, neural_net.to_mermaid()).map_err(|err| err.to_string())
+        } else {
+            neural_net.save_dot(path).map_err(|err| err.to_string())
+        };
+
+        if let Err(err) = saved {
+            eprintln!("Failed to export the graph: {}", err);
+        }
+    }
+
+    if args.task == Task::Autoencoder {
+        detect_anomalies(
+            &mut neural_net,
+            &dataset,
+            &validation,
+            args.num_epochs.unwrap_or(10),
+            args.anomaly_threshold_fpr,
+        );
+
+        return;
+    }
+
+    if args.mode == Mode::Online {
+        train_online(&mut neural_net);
+        test_model(&validation, &neural_net);
+
+        return;
+    }
+
+    if args.find_lr {
+        const MIN_LR: f64 = 1e-6;
+        const MAX_LR: f64 = 1f64;
+
+        let result = neural_net.find_lr(&dataset, MIN_LR, MAX_LR, args.lr_find_steps);
+        println!("The LR range test found an LR of {}", result.optimal_lr);
 
-    let losses = neural_net.fit(&dataset, &args.validation_path);
+        if let Some(path) = &args.lr_find_output {
+            let mut curve = Array2::zeros((0, 2));
+
+            for (lr, loss) in result.lrs.iter().zip(result.losses.iter()) {
+                curve.push_row(ArrayView::from(&[*lr, *loss])).unwrap();
+            }
+
+            let _ = write_matrix(path, &curve);
+        }
+
+        neural_net.learning_rate = result.optimal_lr;
+    }
+
+    let history = match (args.auto_val_fraction, args.loader_workers) {
+        _ if args.curriculum_pacing.is_some() => {
+            let pacing = match args.curriculum_pacing.as_ref().unwrap() {
+                PacingKind::Linear => PacingFunction::Linear {
+                    slope: args.pacing_slope,
+                },
+                PacingKind::Exponential => PacingFunction::Exponential {
+                    base: args.pacing_base,
+                },
+                PacingKind::Step => PacingFunction::Step {
+                    step_at: args.pacing_steps.clone(),
+                },
+            };
+
+            neural_net.fit_curriculum(
+                &dataset,
+                (!args.streaming_eval).then_some(&validation),
+                CurriculumScheduler::new(pacing, args.curriculum_start),
+            )
+        }
+        (Some(val_fraction), _) => {
+            neural_net.fit_with_auto_split(dataset.clone(), val_fraction, None)
+        }
+        (None, Some(n_workers)) => {
+            let mut loader = DataLoader::new(
+                Arc::new(dataset.clone()),
+                args.batch_size,
+                n_workers,
+                args.prefetch_batches,
+            );
+
+            neural_net.fit_source(&mut loader, (!args.streaming_eval).then_some(&validation))
+        }
+        (None, None) if args.streaming_train => {
+            let mut train_set = StreamingCsvDataset::new(
+                args.train_path.as_deref().unwrap(),
+                CsvConfig::default(),
+                args.batch_size,
+            )
+            .expect("Failed to index the training set");
+
+            neural_net.fit_source(
+                &mut train_set,
+                (!args.streaming_eval).then_some(&validation),
+            )
+        }
+        (None, None) if args.streaming_eval => neural_net
+            .try_fit(&dataset, None)
+            .expect("Failed to train the network"),
+        (None, None) => neural_net
+            .try_fit(&dataset, Some(&validation))
+            .expect("Failed to train the network"),
+    };
+
+    if let Some(logger) = neural_net.callback::<GradientNormLogger>() {
+        for idx in 0..neural_net.layers.len() {
+            let Some(norms) = logger.norms.get(&GradientNormLogger::key(idx)) else {
+                continue;
+            };
+            let mean = norms.iter().sum::<f64>() / norms.len() as f64;
+            let max = norms.iter().copied().fold(0f64, f64::max);
+
+            println!(
+                "Layer {} has a mean gradient norm of {:.4e} and a max of {:.4e}",
+                idx, mean, max
+            );
+
+            if let Some(ratios) = logger
+                .update_ratios
+                .get(&GradientNormLogger::ratio_key(idx))
+            {
+                println!(
+                    "Layer {} has a mean update-to-weight ratio of {:.4e}",
+                    idx,
+                    ratios.iter().sum::<f64>() / ratios.len() as f64
+                );
+            }
+
+            if let Some(agreements) = logger
+                .sign_agreements
+                .get(&GradientNormLogger::sign_agreement_key(idx))
+                .filter(|agreements| !agreements.is_empty())
+            {
+                println!(
+                    "Layer {} has a mean gradient sign agreement of {:.4}",
+                    idx,
+                    agreements.iter().sum::<f64>() / agreements.len() as f64
+                );
+            }
+
+            if let Some(snrs) = logger
+                .snrs
+                .get(&GradientNormLogger::snr_key(idx))
+                .filter(|snrs| !snrs.is_empty())
+            {
+                println!(
+                    "Layer {} has a mean weight update SNR of {:.4e}",
+                    idx,
+                    snrs.iter().sum::<f64>() / snrs.len() as f64
+                );
+            }
+        }
+    }
+
+    if args.gradual_prune.is_some() {
+        for (layer_idx, count) in pruning::count_nonzero_weights(&neural_net)
+            .into_iter()
+            .enumerate()
+        {
+            println!("Layer {} has {} nonzero weights", layer_idx, count);
+        }
+    }
+
+    if let Some(sparsity) = args.prune {
+        neural_net.prune(sparsity);
+
+        for (layer_idx, sparsity) in neural_net.pruning_stats() {
+            println!("Layer {} has a sparsity of {:.4}", layer_idx, sparsity);
+        }
+    }
+
+    if args.calibrate_temperature {
+        calibrate_model(&validation, &mut neural_net);
+    }
 
     if let Some(debug_path) = args.debug_path {
-        let _ = write_losses(&debug_path, losses);
+        let _ = write_losses(&debug_path, history.losses());
+    }
+
+    if let Some(plot_path) = &args.plot_losses {
+        if let Err(err) = history.save_loss_plot_svg(plot_path) {
+            eprintln!("Failed to save the loss plot: {}", err);
+        }
+    }
+
+    if args.summary {
+        print!("{}", neural_net.summary());
     }
 
     if let Some(weight_path) = args.weight_path {
-        let _ = write_weights(&weight_path, &neural_net);
+        let _ = neural_net.save(&weight_path);
+    }
+
+    if let Some(layer_idx) = args.extract_features {
+        let inputs = validation.data.view();
+        let features = if args.linear_features {
+            neural_net.extract_linear_features(&inputs, layer_idx)
+        } else {
+            neural_net.extract_features(&inputs, layer_idx)
+        };
+
+        match features.and_then(|features| npy::save_npy_f64_2d(&args.output_features, &features)) {
+            Ok(()) => println!("Saved the features to {}", args.output_features),
+            Err(err) => eprintln!("Failed to extract features: {}", err),
+        }
+    }
+
+    if let Some(target_class) = args.saliency_class {
+        let saliency = neural_net
+            .input_gradients(&validation.data, target_class)
+            .mapv(f64::abs);
+        let _ = write_matrix(&args.saliency_output, &saliency);
+    }
+
+    if let Some((layer_idx, neuron_idx)) = args.visualize_neuron {
+        const VISUALIZATION_LR: f64 = 0.1;
+        const VISUALIZATION_L2_REG: f64 = 1e-4;
+
+        let input = neural_net.maximize_activation(
+            layer_idx,
+            neuron_idx,
+            args.visualization_steps,
+            VISUALIZATION_LR,
+            VISUALIZATION_L2_REG,
+        );
+        let _ = write_matrix(&args.visualization_output, &input.insert_axis(Axis(0)));
+    }
+
+    if let Some(quantized_path) = args.quantized_path {
+        let quantized = if args.quantize_activations {
+            neural_net.quantize_activations(&dataset.data)
+        } else {
+            neural_net.quantize()
+        };
+        let (orig_accuracy, quant_accuracy) =
+            quantized::compare_accuracy(&neural_net, &quantized, &validation);
+
+        println!(
+            "The accuracy went from {:.4} to {:.4} after quantization",
+            orig_accuracy, quant_accuracy
+        );
+
+        let _ = quantized.save(&quantized_path);
+    }
+
+    if args.streaming_eval {
+        test_model_streaming(&args.validation_path, &neural_net);
+    } else if args.task == Task::Multilabel {
+        test_multilabel_model(&validation, &neural_net);
+    } else if let Some(n_samples) = args.mc_dropout_samples {
+        test_model_mc_dropout(&validation, &neural_net, n_samples);
+    } else if matches!(args.loss_function, LossKind::Poisson) {
+        test_count_model(&validation, &neural_net);
+    } else {
+        test_model(&validation, &neural_net);
+    }
+
+    if let Some(temperature) = args.inference_temperature {
+        test_model_temperature(&validation, &neural_net, temperature);
+    }
+
+    if let Some(n_aug) = args.tta_samples {
+        test_model_tta(&validation, &neural_net, n_aug, args.tta_noise_std);
+    }
+
+    if let Some(path) = &args.calibration_plot {
+        const CALIBRATION_BINS: usize = 15;
+
+        let (centers, confidences, fractions_correct) =
+            metrics::prediction_confidence_histogram(&neural_net, &validation, CALIBRATION_BINS);
+        let mut diagram = Array2::zeros((0, 3));
+
+        for ((center, confidence), fraction_correct) in centers
+            .iter()
+            .zip(confidences.iter())
+            .zip(fractions_correct.iter())
+        {
+            diagram
+                .push_row(ArrayView::from(&[*center, *confidence, *fraction_correct]))
+                .unwrap();
+        }
+
+        let _ = write_matrix(path, &diagram);
+    }
+
+    if let Some(k) = args.hardest_samples {
+        println!("index    loss       true class   predicted class");
+
+        for (idx, loss, true_class, predicted_class) in
+            metrics::hardest_k_samples(&neural_net, &validation, k)
+        {
+            println!(
+                "{:<8} {:<10.4} {:<12} {}",
+                idx, loss, true_class, predicted_class
+            );
+        }
+    }
+
+    if args.gradient_flow_report {
+        print!(
+            "{}",
+            gradient_flow::format_gradient_flow(&gradient_flow::gradient_flow_report(
+                &neural_net,
+                &dataset
+            ))
+        );
+    }
+
+    if args.redundancy_analysis {
+        const NUM_PAIRS_SHOWN: usize = 5;
+
+        // The output layer's neurons are the classes, so only the hidden layers are analyzed
+        for layer_idx in 0..neural_net.layers.len() - 1 {
+            let pairs =
+                pruning::redundant_neuron_pairs(&neural_net, layer_idx, args.redundancy_threshold)
+                    .expect("Invalid layer");
+
+            println!(
+                "Layer {} has {} pairs of neurons with a cosine similarity above {}",
+                layer_idx,
+                pairs.len(),
+                args.redundancy_threshold
+            );
+
+            for (i, j, similarity) in pairs.iter().take(NUM_PAIRS_SHOWN) {
+                println!("  neurons {} and {}: {:.4}", i, j, similarity);
+            }
+        }
+    }
+
+    if let Some(path) = &args.hardest_mistakes_csv {
+        let mistakes = metrics::find_hardest_misclassifications(
+            &neural_net,
+            &validation,
+            args.num_hardest_mistakes,
+        );
+
+        if let Err(err) = write_mistakes(path, &mistakes) {
+            eprintln!("Failed to write the hardest mistakes: {}", err);
+        }
     }
 
-    test_model(&args.validation_path, &neural_net);
+    if !args.adversarial_eval_eps.is_empty() {
+        println!("epsilon    adversarial accuracy");
+
+        for epsilon in args.adversarial_eval_eps {
+            let accuracy = adversarial::fgsm_accuracy(&neural_net, &validation, epsilon);
+            println!("{:<10} {:.4}", epsilon, accuracy);
+        }
+    }
+
+    if let Some(n_members) = args.ensemble_size {
+        benchmark_ensemble(&builder, &dataset, &validation, n_members);
+    }
+
+    if let Some(n_snapshots) = args.snapshot_ensemble {
+        benchmark_snapshot_ensemble(
+            &builder,
+            &dataset,
+            &validation,
+            n_snapshots,
+            args.snapshot_cycle_length,
+        );
+    }
 }
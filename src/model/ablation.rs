@@ -0,0 +1,154 @@
+use std::fmt;
+
+use super::metrics::{evaluate, EvaluationResult};
+use super::neural_net::NeuralNet;
+use crate::parsing::Dataset;
+
+/// The metrics of several trained models on the same test set, sorted by accuracy (best first)
+#[derive(Clone, Debug)]
+pub struct AblationReport {
+    pub results: Vec<(String, EvaluationResult)>,
+}
+
+impl fmt::Display for AblationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name_width = self
+            .results
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(std::iter::once("config".len()))
+            .max()
+            .unwrap();
+
+        writeln!(f, "{:<name_width$}   accuracy   kappa    MCC", "config")?;
+
+        for (name, result) in &self.results {
+            writeln!(
+                f,
+                "{:<name_width$}   {:<8.4}   {:<6.4}   {:.4}",
+                name, result.accuracy, result.cohens_kappa, result.matthews_correlation_coefficient
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Evaluate each of the (named) trained models on the same test set
+pub fn ablation_study(configs: &[(String, NeuralNet)], test_dataset: &Dataset) -> AblationReport {
+    let mut results: Vec<(String, EvaluationResult)> = configs
+        .iter()
+        .map(|(name, model)| (name.clone(), evaluate(model, test_dataset)))
+        .collect();
+
+    results.sort_by(|(_, a), (_, b)| b.accuracy.total_cmp(&a.accuracy));
+
+    AblationReport { results }
+}
+
+/// Paired t-test of the accuracies of two models on the same cross-validation folds
+/// Returns the two-sided p-value of the null hypothesis that both models have the same mean accuracy
+pub fn paired_t_test(acc_a: &[f64], acc_b: &[f64]) -> f64 {
+    assert_eq!(
+        acc_a.len(),
+        acc_b.len(),
+        "Both models must be evaluated on the same folds"
+    );
+    assert!(acc_a.len() > 1, "The t-test needs at least two folds");
+
+    let n = acc_a.len() as f64;
+    let diffs: Vec<f64> = acc_a.iter().zip(acc_b).map(|(a, b)| a - b).collect();
+    let mean = diffs.iter().sum::<f64>() / n;
+    let variance = diffs.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / (n - 1f64);
+
+    // Identical differences in all folds: either there's no difference at all, or a certain one
+    if variance == 0f64 {
+        return if mean == 0f64 { 1f64 } else { 0f64 };
+    }
+
+    let t = mean / (variance / n).sqrt();
+    let dof = n - 1f64;
+
+    // P(|T| > |t|) for a t distribution with dof degrees of freedom
+    regularized_incomplete_beta(dof / (dof + t * t), dof / 2f64, 0.5)
+}
+
+/// The log of the gamma function, using the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |sum, (i, c)| {
+            sum + c / (x + 1f64 + i as f64)
+        });
+
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// The regularized incomplete beta function I_x(a, b)
+fn regularized_incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0f64 {
+        return 0f64;
+    }
+    if x >= 1f64 {
+        return 1f64;
+    }
+
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1f64 - x).ln()).exp();
+
+    // The continued fraction converges quickly for x < (a + 1) / (a + b + 2), and the symmetry
+    // I_x(a, b) = 1 - I_(1 - x)(b, a) is used otherwise
+    if x < (a + 1f64) / (a + b + 2f64) {
+        front * beta_continued_fraction(x, a, b) / a
+    } else {
+        1f64 - front * beta_continued_fraction(1f64 - x, b, a) / b
+    }
+}
+
+/// Evaluate the continued fraction of the incomplete beta function with the modified Lentz method
+fn beta_continued_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 200;
+    const EPSILON: f64 = 1e-12;
+    const TINY: f64 = 1e-300;
+
+    let clamp_tiny = |v: f64| if v.abs() < TINY { TINY } else { v };
+    let mut c = 1f64;
+    let mut d = 1f64 / clamp_tiny(1f64 - (a + b) * x / (a + 1f64));
+    let mut h = d;
+
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+        let m2 = 2f64 * m;
+
+        // The even step of the recurrence
+        let numerator = m * (b - m) * x / ((a + m2 - 1f64) * (a + m2));
+        d = 1f64 / clamp_tiny(1f64 + numerator * d);
+        c = clamp_tiny(1f64 + numerator / c);
+        h *= d * c;
+
+        // The odd step
+        let numerator = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1f64));
+        d = 1f64 / clamp_tiny(1f64 + numerator * d);
+        c = clamp_tiny(1f64 + numerator / c);
+        let delta = d * c;
+        h *= delta;
+
+        if (delta - 1f64).abs() < EPSILON {
+            break;
+        }
+    }
+
+    h
+}
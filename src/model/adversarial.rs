@@ -0,0 +1,76 @@
+use ndarray::Array2;
+
+use super::metrics::accuracy;
+use super::neural_net::NeuralNet;
+use super::Model;
+use crate::parsing::Dataset;
+
+/// Generate adversarial examples using the Fast Gradient Sign Method
+/// Each feature is moved by epsilon in the direction that increases the loss, and clipped back to [0, 1]
+pub fn fgsm(
+    model: &NeuralNet,
+    inputs: &Array2<f64>,
+    targets: &Array2<f64>,
+    epsilon: f64,
+) -> Array2<f64> {
+    let grad = model.loss_input_gradients(&inputs.view(), &targets.view());
+    // f64::signum returns 1 for +0, but a zero gradient shouldn't move the input
+    let sign = grad.mapv(|x| if x == 0f64 { 0f64 } else { x.signum() });
+
+    (inputs + epsilon * sign).mapv(|x| x.clamp(0f64, 1f64))
+}
+
+/// Calculate the accuracy of the model on FGSM adversarial examples generated from the dataset
+pub fn fgsm_accuracy(model: &NeuralNet, dataset: &Dataset, epsilon: f64) -> f64 {
+    let adversarial = fgsm(model, &dataset.data, &dataset.target, epsilon);
+    let predictions = model.predict(&adversarial.view());
+
+    accuracy(&predictions, &dataset.target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::neural_net::{NeuralNetBuilder, Verbosity};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn fgsm_accuracy_drops_as_epsilon_grows() {
+        // The class of each instance is the feature with the largest value. FGSM clips to [0, 1], so the
+        // features are in that range
+        let mut rng = StdRng::seed_from_u64(0);
+        let data = Array2::from_shape_fn((2000, 4), |_| rng.gen_range(0f64..1f64));
+        let mut target = Array2::zeros((2000, 4));
+
+        for (row, instance) in data.rows().into_iter().enumerate() {
+            let class = (0..4)
+                .max_by(|&a, &b| instance[a].total_cmp(&instance[b]))
+                .unwrap();
+            target[[row, class]] = 1f64;
+        }
+
+        let (train, test) = Dataset { data, target }.split(0.25, Some(0));
+        let mut net = NeuralNetBuilder::new(vec![4, 32, 4])
+            .num_epochs(Some(20))
+            .batch_size(32)
+            .learning_rate(0.05)
+            .seed(1)
+            .verbosity(Verbosity::Silent)
+            .build();
+
+        net.fit(&train, None);
+
+        let accuracies: Vec<f64> = [0f64, 0.02, 0.05, 0.1, 0.2]
+            .iter()
+            .map(|&epsilon| fgsm_accuracy(&net, &test, epsilon))
+            .collect();
+
+        assert!(accuracies[0] > 0.85, "accuracy {}", accuracies[0]);
+        assert!(
+            accuracies.windows(2).all(|pair| pair[1] < pair[0]),
+            "accuracies {:?}",
+            accuracies
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use ndarray::{Array1, ArrayView2, Axis};
+
+use super::neural_net::NeuralNet;
+use super::Model;
+use crate::parsing::Dataset;
+
+/// The MSE between each instance and its reconstruction by an autoencoder
+pub fn reconstruction_error(model: &NeuralNet, inputs: &ArrayView2<f64>) -> Array1<f64> {
+    let reconstructions = model.predict(inputs);
+
+    (&reconstructions - inputs)
+        .mapv(|x| x * x)
+        .mean_axis(Axis(1))
+        .unwrap()
+}
+
+/// Find the reconstruction error above which instances are anomalies, such that a fraction fpr_target of the
+/// (normal) instances of normal_data are false positives
+pub fn fit_anomaly_threshold(model: &NeuralNet, normal_data: &Dataset, fpr_target: f64) -> f64 {
+    let mut errors = reconstruction_error(model, &normal_data.data.view()).to_vec();
+    errors.sort_by(f64::total_cmp);
+
+    // The instances with an error above the threshold are flagged, so the threshold is the (1 - fpr) quantile
+    let num_negatives = ((1f64 - fpr_target) * errors.len() as f64).ceil() as usize;
+
+    errors[num_negatives.clamp(1, errors.len()) - 1]
+}
@@ -0,0 +1,318 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+
+use ndarray::Array2;
+
+use super::history::TrainingHistory;
+use super::layer::LayerGradients;
+use super::neural_net::{Gradients, NeuralNet};
+use crate::error::Result;
+
+/// The number of bins of the weight histograms
+const NUM_HISTOGRAM_BINS: usize = 20;
+
+/// Hooks that are called during training, e.g. for logging or monitoring
+pub trait Callback: Any + Send + Sync {
+    /// Called after each training step with the loss of the batch and the gradients of each layer
+    fn on_batch_end(&mut self, _model: &NeuralNet, _loss: f64, _grads: &Gradients) {}
+
+    /// Called after each epoch, once its losses are recorded in the history
+    fn on_epoch_end(&mut self, _model: &NeuralNet, _history: &TrainingHistory) {}
+}
+
+/// The ratio (lr * ||weight grad||) / ||weights|| of each layer, i.e. the relative size of its update
+/// Ratios around 1e-3 indicate a good LR, while ratios above 1e-1 suggest the LR is too large and below 1e-4 too small
+pub fn compute_update_to_weight_ratio(model: &NeuralNet, grads: &[LayerGradients]) -> Vec<f64> {
+    model
+        .layers
+        .iter()
+        .zip(grads.iter())
+        .zip(model.lr_multipliers.iter())
+        .map(|((layer, (weight_grad, _)), multiplier)| {
+            let grad_norm = weight_grad.iter().map(|x| x * x).sum::<f64>().sqrt();
+            let weight_norm = layer.weights().iter().map(|x| x * x).sum::<f64>().sqrt();
+
+            model.learning_rate * multiplier * grad_norm / weight_norm.max(f64::MIN_POSITIVE)
+        })
+        .collect()
+}
+
+/// The ratio ||W_after - W_before|| / ||W_before|| (Frobenius norms) of each layer, i.e. the relative size of the
+/// update the optimizer actually made. Around 1e-3 is healthy, below 1e-5 the LR is likely too small and above 1e-1
+/// too large
+pub fn snr_weight_updates(pre_update: &[Array2<f64>], post_update: &[Array2<f64>]) -> Vec<f64> {
+    pre_update
+        .iter()
+        .zip(post_update.iter())
+        .map(|(pre, post)| {
+            let delta_norm = (post - pre).iter().map(|x| x * x).sum::<f64>().sqrt();
+            let weight_norm = pre.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+            delta_norm / weight_norm.max(f64::MIN_POSITIVE)
+        })
+        .collect()
+}
+
+/// The fraction of the weight gradients of each layer that have the same sign as in the previous step (0 counts as
+/// its own sign). Agreement well above 0.5 means the steps keep going in the same direction and the LR can be raised,
+/// while agreement around 0.5 means the gradients are mostly noise
+pub fn gradient_sign_agreement(
+    grad_current: &[Array2<f64>],
+    grad_previous: &[Array2<f64>],
+) -> Vec<f64> {
+    grad_current
+        .iter()
+        .zip(grad_previous.iter())
+        .map(|(current, previous)| {
+            let num_agreeing = current
+                .iter()
+                .zip(previous.iter())
+                .filter(|(a, b)| sign(**a) == sign(**b))
+                .count();
+
+            num_agreeing as f64 / current.len().max(1) as f64
+        })
+        .collect()
+}
+
+fn sign(x: f64) -> i8 {
+    (x > 0f64) as i8 - (x < 0f64) as i8
+}
+
+/// Records the L2 (Frobenius) norm of the weight gradients of each layer after each batch, and the ratio of the
+/// size of its update to the size of its weights (both predicted from the gradient and measured as the SNR of the
+/// actual update), and the sign agreement of its gradients with the previous batch. Warns when a layer's gradients explode or vanish
+#[derive(Clone, Debug)]
+pub struct GradientNormLogger {
+    pub norms: HashMap<String, Vec<f64>>, // e.g. "layer_0_W_grad_norm" -> the norm after each batch
+    pub update_ratios: HashMap<String, Vec<f64>>, // e.g. "layer_0_update_ratio" -> the ratio after each batch
+    pub sign_agreements: HashMap<String, Vec<f64>>, // e.g. "layer_0_sign_agreement" -> from the second batch on
+    pub snrs: HashMap<String, Vec<f64>>, // e.g. "layer_0_snr" -> from the second batch on
+    pub explode_threshold: f64,
+    pub vanish_threshold: f64,
+    prev_grads: Vec<Array2<f64>>,
+    prev_weights: Vec<Array2<f64>>, // The weights after the previous batch, i.e. before the update of this one
+}
+
+impl GradientNormLogger {
+    pub fn new(explode_threshold: f64, vanish_threshold: f64) -> GradientNormLogger {
+        GradientNormLogger {
+            norms: HashMap::new(),
+            update_ratios: HashMap::new(),
+            sign_agreements: HashMap::new(),
+            snrs: HashMap::new(),
+            explode_threshold,
+            vanish_threshold,
+            prev_grads: vec![],
+            prev_weights: vec![],
+        }
+    }
+
+    /// The key of the norms of a layer in norms
+    pub fn key(layer_idx: usize) -> String {
+        format!("layer_{}_W_grad_norm", layer_idx)
+    }
+
+    /// The key of the update-to-weight ratios of a layer in update_ratios
+    pub fn ratio_key(layer_idx: usize) -> String {
+        format!("layer_{}_update_ratio", layer_idx)
+    }
+
+    /// The key of the sign agreements of a layer in sign_agreements
+    pub fn sign_agreement_key(layer_idx: usize) -> String {
+        format!("layer_{}_sign_agreement", layer_idx)
+    }
+
+    /// The key of the SNRs of the weight updates of a layer in snrs
+    pub fn snr_key(layer_idx: usize) -> String {
+        format!("layer_{}_snr", layer_idx)
+    }
+}
+
+impl Default for GradientNormLogger {
+    fn default() -> Self {
+        GradientNormLogger::new(1e3, 1e-7)
+    }
+}
+
+impl Callback for GradientNormLogger {
+    fn on_batch_end(&mut self, model: &NeuralNet, _loss: f64, grads: &Gradients) {
+        for (idx, ratio) in compute_update_to_weight_ratio(model, grads)
+            .into_iter()
+            .enumerate()
+        {
+            self.update_ratios
+                .entry(GradientNormLogger::ratio_key(idx))
+                .or_default()
+                .push(ratio);
+        }
+
+        let weight_grads: Vec<Array2<f64>> = grads.iter().map(|(w, _)| w.clone()).collect();
+
+        for (idx, agreement) in gradient_sign_agreement(&weight_grads, &self.prev_grads)
+            .into_iter()
+            .enumerate()
+        {
+            self.sign_agreements
+                .entry(GradientNormLogger::sign_agreement_key(idx))
+                .or_default()
+                .push(agreement);
+        }
+
+        self.prev_grads = weight_grads;
+
+        let weights: Vec<Array2<f64>> = model
+            .layers
+            .iter()
+            .map(|layer| layer.weights().into_owned())
+            .collect();
+
+        for (idx, snr) in snr_weight_updates(&self.prev_weights, &weights)
+            .into_iter()
+            .enumerate()
+        {
+            self.snrs
+                .entry(GradientNormLogger::snr_key(idx))
+                .or_default()
+                .push(snr);
+        }
+
+        self.prev_weights = weights;
+
+        for (idx, (weight_grad, _)) in grads.iter().enumerate() {
+            let norm = weight_grad.iter().map(|x| x * x).sum::<f64>().sqrt();
+            let norms = self.norms.entry(GradientNormLogger::key(idx)).or_default();
+
+            norms.push(norm);
+
+            if norm > self.explode_threshold {
+                eprintln!(
+                    "Warning: the gradient norm of layer {} exploded to {:.4e} (batch {})",
+                    idx,
+                    norm,
+                    norms.len()
+                );
+            } else if norm < self.vanish_threshold {
+                eprintln!(
+                    "Warning: the gradient norm of layer {} vanished to {:.4e} (batch {})",
+                    idx,
+                    norm,
+                    norms.len()
+                );
+            }
+        }
+    }
+}
+
+/// A histogram of the weights of a layer. The edges are the NUM_HISTOGRAM_BINS + 1 boundaries of the bins
+#[derive(Clone, Debug)]
+pub struct WeightHistogram {
+    pub layer_idx: usize,
+    pub edges: Vec<f64>,
+    pub counts: Vec<usize>,
+}
+
+/// Compute a histogram of the weights of each layer over the range of its weights
+/// Weights piling up at the edges of the range (or a range that keeps growing) indicate saturation
+pub fn compute_weight_histograms(model: &NeuralNet) -> Vec<WeightHistogram> {
+    model
+        .layers
+        .iter()
+        .enumerate()
+        .map(|(layer_idx, layer)| {
+            let weights = layer.weights();
+            let min = weights.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = weights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let (min, max) = if weights.is_empty() {
+                (0f64, 0f64)
+            } else {
+                (min, max)
+            };
+            let bin_width = (max - min).max(f64::EPSILON) / NUM_HISTOGRAM_BINS as f64;
+            let mut counts = vec![0; NUM_HISTOGRAM_BINS];
+
+            for &weight in weights.iter() {
+                let bin = ((weight - min) / bin_width) as usize;
+                counts[bin.min(NUM_HISTOGRAM_BINS - 1)] += 1;
+            }
+
+            WeightHistogram {
+                layer_idx,
+                edges: (0..=NUM_HISTOGRAM_BINS)
+                    .map(|bin| min + bin as f64 * bin_width)
+                    .collect(),
+                counts,
+            }
+        })
+        .collect()
+}
+
+/// Save the histograms to a CSV file with a line per bin
+pub fn save_weight_histograms(histograms: &[WeightHistogram], path: &str) -> Result<()> {
+    let mut file = File::create(path)?;
+
+    file.write_all(b"layer,bin_start,bin_end,count\n")?;
+
+    for histogram in histograms {
+        for (bin, count) in histogram.counts.iter().enumerate() {
+            file.write_all(
+                format!(
+                    "{},{},{},{}\n",
+                    histogram.layer_idx,
+                    histogram.edges[bin],
+                    histogram.edges[bin + 1],
+                    count
+                )
+                .as_bytes(),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Records the weight histograms of the layers every_n_epochs epochs. If a path prefix is given, each record is
+/// also saved to <path_prefix>_epoch_<epoch>.csv
+#[derive(Clone, Debug)]
+pub struct HistogramCallback {
+    pub every_n_epochs: usize,
+    pub path_prefix: Option<String>,
+    pub histograms: Vec<(usize, Vec<WeightHistogram>)>, // The epoch (starting from 1) and the histograms of each record
+}
+
+impl HistogramCallback {
+    pub fn new(every_n_epochs: usize, path_prefix: Option<String>) -> HistogramCallback {
+        HistogramCallback {
+            every_n_epochs: every_n_epochs.max(1),
+            path_prefix,
+            histograms: vec![],
+        }
+    }
+}
+
+impl Callback for HistogramCallback {
+    fn on_epoch_end(&mut self, model: &NeuralNet, history: &TrainingHistory) {
+        let epoch = history.train_losses.len();
+
+        if !epoch.is_multiple_of(self.every_n_epochs) {
+            return;
+        }
+
+        let histograms = compute_weight_histograms(model);
+
+        if let Some(prefix) = &self.path_prefix {
+            let path = format!("{}_epoch_{}.csv", prefix, epoch);
+
+            if let Err(err) = save_weight_histograms(&histograms, &path) {
+                eprintln!(
+                    "Warning: failed to save the weight histograms to {}: {}",
+                    path, err
+                );
+            }
+        }
+
+        self.histograms.push((epoch, histograms));
+    }
+}
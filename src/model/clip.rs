@@ -0,0 +1,95 @@
+use super::layer::Layer;
+use super::neural_net::Gradients;
+use super::privacy::clip_gradients;
+
+/// The smallest weight norm adaptive clipping scales by, so that the gradients of zero-initialized weights aren't zeroed
+const AGC_MIN_WEIGHT_NORM: f64 = 1e-3;
+
+/// How the gradients are clipped before each update
+#[derive(Clone, Debug)]
+pub enum GradientClip {
+    /// Scale all the gradients down so that their total L2 norm is at most max_norm
+    Norm { max_norm: f64 },
+    /// Adaptive gradient clipping (Brock et al. 2021): scale the weight gradient of each layer down so that
+    /// ||grad||_F / ||W||_F is at most lambda, so the threshold follows the scale of the weights
+    Adaptive { lambda: f64 },
+}
+
+impl GradientClip {
+    /// Clip the gradients of the layers in place. Adaptive clipping only clips the weight gradients
+    pub fn clip(&self, grads: &mut Gradients, layers: &[Box<dyn Layer>]) {
+        match self {
+            GradientClip::Norm { max_norm } => clip_gradients(grads, *max_norm),
+            GradientClip::Adaptive { lambda } => {
+                for ((weight_grad, _), layer) in grads.iter_mut().zip(layers.iter()) {
+                    let grad_norm = weight_grad.iter().map(|x| x * x).sum::<f64>().sqrt();
+                    let weight_norm = layer
+                        .weights()
+                        .iter()
+                        .map(|x| x * x)
+                        .sum::<f64>()
+                        .sqrt()
+                        .max(AGC_MIN_WEIGHT_NORM);
+                    let max_norm = lambda * weight_norm;
+
+                    if grad_norm > max_norm {
+                        *weight_grad *= max_norm / grad_norm;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::layer::DenseLayer;
+    use ndarray::{Array1, Array2, ArrayView2};
+
+    fn frobenius_norm(x: &ArrayView2<f64>) -> f64 {
+        x.iter().map(|x| x * x).sum::<f64>().sqrt()
+    }
+
+    #[test]
+    fn adaptive_clipping_bounds_the_ratio_of_each_layer() {
+        let lambda = 0.01;
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(DenseLayer::new(
+                Array2::from_shape_fn((3, 4), |(i, j)| i as f64 - j as f64),
+                Array1::zeros(4),
+            )),
+            Box::new(DenseLayer::new(
+                Array2::from_elem((4, 2), 0.5),
+                Array1::zeros(2),
+            )),
+            // Zero weights are clipped relative to the minimum weight norm
+            Box::new(DenseLayer::new(Array2::zeros((2, 2)), Array1::zeros(2))),
+        ];
+        let small_grad = Array2::from_elem((4, 2), 1e-4);
+        let mut grads: Gradients = vec![
+            (Array2::from_elem((3, 4), 5f64), Array1::from_elem(4, 7f64)),
+            (small_grad.clone(), Array1::from_elem(2, 7f64)),
+            (Array2::from_elem((2, 2), 1f64), Array1::from_elem(2, 7f64)),
+        ];
+
+        GradientClip::Adaptive { lambda }.clip(&mut grads, &layers);
+
+        for ((weight_grad, bias_grad), layer) in grads.iter().zip(layers.iter()) {
+            let weight_norm = frobenius_norm(&layer.weights().view()).max(AGC_MIN_WEIGHT_NORM);
+
+            assert!(frobenius_norm(&weight_grad.view()) / weight_norm <= lambda + 1e-12);
+            assert_eq!(bias_grad, Array1::from_elem(bias_grad.len(), 7f64));
+        }
+
+        // The clipped gradients keep their direction, and the ones within the threshold aren't changed
+        assert!(
+            (frobenius_norm(&grads[0].0.view())
+                - lambda * frobenius_norm(&layers[0].weights().view()))
+            .abs()
+                < 1e-12
+        );
+        assert!(grads[0].0.iter().all(|&x| x == grads[0].0[[0, 0]]));
+        assert_eq!(grads[1].0, small_grad);
+    }
+}
@@ -0,0 +1,173 @@
+use super::metrics::per_sample_loss;
+use super::neural_net::NeuralNet;
+use crate::parsing::Dataset;
+use ndarray::{Array2, Axis};
+
+/// How the fraction of the training set a curriculum trains on grows with the epochs
+#[derive(Clone, Debug)]
+pub enum PacingFunction {
+    /// Add slope to the fraction after each epoch
+    Linear { slope: f64 },
+    /// Multiply the fraction by base after each epoch
+    Exponential { base: f64 },
+    /// Grow the fraction in equal steps at these epochs, reaching the full training set at the last one
+    Step { step_at: Vec<f64> },
+}
+
+/// Curriculum learning (Bengio et al. 2009): each epoch trains on the current_fraction of the training set with the
+/// lowest loss, so the network learns the easy instances before the hard (or mislabeled) ones
+#[derive(Clone, Debug)]
+pub struct CurriculumScheduler {
+    pub pacing: PacingFunction,
+    pub current_fraction: f64,
+    initial_fraction: f64,
+    epoch: usize,
+}
+
+impl CurriculumScheduler {
+    pub fn new(pacing: PacingFunction, initial_fraction: f64) -> CurriculumScheduler {
+        CurriculumScheduler {
+            pacing,
+            current_fraction: initial_fraction.clamp(0f64, 1f64),
+            initial_fraction: initial_fraction.clamp(0f64, 1f64),
+            epoch: 0,
+        }
+    }
+
+    /// Advance to the next epoch, and return the fraction of the training set it trains on
+    pub fn step(&mut self) -> f64 {
+        self.epoch += 1;
+
+        let epoch = self.epoch as f64;
+        let fraction = match &self.pacing {
+            PacingFunction::Linear { slope } => self.initial_fraction + slope * epoch,
+            PacingFunction::Exponential { base } => self.initial_fraction * base.powf(epoch),
+            PacingFunction::Step { step_at } => {
+                let num_steps = step_at.iter().filter(|&&at| at <= epoch).count();
+                let step_size = (1f64 - self.initial_fraction) / step_at.len().max(1) as f64;
+
+                self.initial_fraction + step_size * num_steps as f64
+            }
+        };
+        self.current_fraction = fraction.clamp(0f64, 1f64);
+
+        self.current_fraction
+    }
+
+    /// The indices of the current_fraction of the instances of each class with the lowest loss under the model
+    /// Selecting per class keeps the classes balanced as in the dataset, even if the model starts out predicting one
+    pub fn easiest_indices(&self, model: &NeuralNet, dataset: &Dataset) -> Vec<usize> {
+        let losses = per_sample_loss(model, dataset);
+        let labels = dataset.labels();
+        let mut indices = vec![];
+
+        for class in 0..dataset.target.ncols() {
+            let mut class_indices: Vec<usize> =
+                (0..labels.len()).filter(|&i| labels[i] == class).collect();
+            let num_selected = (self.current_fraction * class_indices.len() as f64).ceil() as usize;
+
+            class_indices.sort_by(|&a, &b| losses[a].total_cmp(&losses[b]));
+            indices.extend(class_indices.into_iter().take(num_selected.max(1)));
+        }
+
+        indices.sort_unstable();
+
+        indices
+    }
+}
+
+/// Online hard example mining (Shrivastava et al. 2016): select the hard_ratio of the instances of a batch with the
+/// highest loss under the model (at least one), so that training isn't spent on the instances it already gets right
+pub fn online_hard_example_mining(
+    model: &NeuralNet,
+    batch: &Array2<f64>,
+    targets: &Array2<f64>,
+    hard_ratio: f64,
+) -> (Array2<f64>, Array2<f64>) {
+    let batch_dataset = Dataset {
+        data: batch.clone(),
+        target: targets.clone(),
+    };
+    let losses = per_sample_loss(model, &batch_dataset);
+    let num_hard =
+        ((hard_ratio * losses.len() as f64).ceil() as usize).clamp(1, losses.len().max(1));
+    let mut indices: Vec<usize> = (0..losses.len()).collect();
+
+    indices.sort_by(|&a, &b| losses[b].total_cmp(&losses[a]));
+    indices.truncate(num_hard);
+
+    (
+        batch.select(Axis(0), &indices),
+        targets.select(Axis(0), &indices),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::neural_net::{NeuralNetBuilder, Verbosity};
+    use crate::model::Model;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Instances whose class is the feature with the largest value, with noise_rate of the labels replaced by a
+    /// random class
+    fn noisy_dataset(rows: usize, noise_rate: f64, seed: u64) -> Dataset {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let data = Array2::from_shape_fn((rows, 3), |_| rng.gen_range(-1f64..1f64));
+        let mut target = Array2::zeros((rows, 3));
+
+        for (row, instance) in data.rows().into_iter().enumerate() {
+            let class = if rng.gen::<f64>() < noise_rate {
+                rng.gen_range(0..3)
+            } else {
+                (0..3)
+                    .max_by(|&a, &b| instance[a].total_cmp(&instance[b]))
+                    .unwrap()
+            };
+            target[[row, class]] = 1f64;
+        }
+
+        Dataset { data, target }
+    }
+
+    #[test]
+    fn curriculum_reaches_the_target_loss_in_no_more_epochs() {
+        let train = noisy_dataset(1000, 0.3, 0);
+        let validation = noisy_dataset(500, 0f64, 1);
+        let build = || {
+            NeuralNetBuilder::new(vec![3, 16, 3])
+                .num_epochs(Some(10))
+                .batch_size(32)
+                .learning_rate(0.05)
+                .seed(2)
+                .verbosity(Verbosity::Silent)
+                .build()
+        };
+
+        let history = build().fit(&train, Some(&validation));
+        let curriculum_history = build().fit_curriculum(
+            &train,
+            Some(&validation),
+            CurriculumScheduler::new(PacingFunction::Linear { slope: 0.1 }, 0.5),
+        );
+        // The target is the best validation loss of the plain fit, so that both of them reach it
+        let target = history
+            .val_losses
+            .iter()
+            .copied()
+            .fold(f64::INFINITY, f64::min);
+        let epochs_to_target = |losses: &[f64]| losses.iter().position(|&loss| loss <= target);
+
+        let epochs = epochs_to_target(&history.val_losses).unwrap();
+        let curriculum_epochs = epochs_to_target(&curriculum_history.val_losses);
+
+        assert!(
+            curriculum_epochs.is_some_and(|curriculum_epochs| curriculum_epochs <= epochs),
+            "the curriculum took {:?} epochs to reach {}, the plain fit {}",
+            curriculum_epochs.map(|epochs| epochs + 1),
+            target,
+            epochs + 1
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use ndarray::{Array2, ArrayView2, Axis};
+
+use super::metrics::argmax;
+use super::neural_net::{NeuralNet, NeuralNetBuilder};
+use super::Model;
+use crate::parsing::Dataset;
+
+/// An ensemble of neural nets whose predictions are combined
+#[derive(Default)]
+pub struct Ensemble {
+    pub members: Vec<NeuralNet>,
+}
+
+impl Ensemble {
+    pub fn new() -> Ensemble {
+        Ensemble::default()
+    }
+
+    pub fn add_member(&mut self, net: NeuralNet) {
+        self.members.push(net);
+    }
+
+    /// Train n_members nets built by the builder, each initialized with a different seed for diversity
+    pub fn train_members(
+        builder: &NeuralNetBuilder,
+        dataset: &Dataset,
+        n_members: usize,
+        seeds: &[u64],
+    ) -> Ensemble {
+        assert!(
+            seeds.len() >= n_members,
+            "Expected a seed for each of the {} members, got {}",
+            n_members,
+            seeds.len()
+        );
+
+        let mut ensemble = Ensemble::new();
+
+        for seed in seeds.iter().take(n_members) {
+            let mut net = builder.clone().seed(*seed).build();
+            net.fit(dataset, None);
+
+            ensemble.add_member(net);
+        }
+
+        ensemble
+    }
+
+    /// Predict the probabilities by averaging the softmax outputs of the members
+    pub fn predict(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        let mut predictions = self.members[0].predict(inputs);
+
+        for member in &self.members[1..] {
+            predictions = predictions + member.predict(inputs);
+        }
+
+        predictions / self.members.len() as f64
+    }
+
+    /// Predict using majority voting. Each row holds the fraction of the members that voted for each class,
+    /// so the majority class is the argmax of the row
+    pub fn predict_vote(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        let mut votes = Array2::zeros((0, 0));
+
+        for member in &self.members {
+            let predictions = member.predict(inputs);
+
+            if votes.is_empty() {
+                votes = Array2::zeros(predictions.dim());
+            }
+
+            for (i, row) in predictions.axis_iter(Axis(0)).enumerate() {
+                votes[[i, argmax(row)]] += 1f64;
+            }
+        }
+
+        votes / self.members.len() as f64
+    }
+}
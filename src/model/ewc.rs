@@ -0,0 +1,75 @@
+use ndarray::{s, Array1, Array2};
+
+use super::layer::Layer;
+use super::neural_net::{Gradients, NeuralNet};
+use crate::parsing::Dataset;
+
+/// The Fisher information is estimated from at most this many samples of the dataset
+const MAX_FISHER_SAMPLES: usize = 1000;
+
+/// Elastic weight consolidation (Kirkpatrick et al. 2017) keeps the weights that were important for a previous task
+/// close to their values after learning it, with a quadratic penalty weighted by their Fisher information
+#[derive(Clone, Debug)]
+pub struct EWC {
+    pub fisher: Vec<Array2<f64>>, // The diagonal Fisher information of the weights of each layer
+    pub anchors: Vec<(Array2<f64>, Array1<f64>)>, // The weights and biases after learning the previous task
+    pub lambda: f64,                              // The strength of the penalty
+}
+
+impl EWC {
+    /// The penalty lambda / 2 * sum(F * (W - W_anchor)^2)
+    pub fn penalty(&self, layers: &[Box<dyn Layer>]) -> f64 {
+        let sum: f64 = layers
+            .iter()
+            .zip(self.anchors.iter())
+            .zip(self.fisher.iter())
+            .map(|((layer, (anchor, _)), fisher)| {
+                (fisher * &(&layer.weights() - anchor).mapv(|d| d * d)).sum()
+            })
+            .sum();
+
+        self.lambda / 2f64 * sum
+    }
+
+    /// Add the gradient of the penalty, lambda * F * (W - W_anchor), to the weight gradients
+    pub fn add_penalty_gradients(&self, grads: &mut Gradients, layers: &[Box<dyn Layer>]) {
+        for ((((weight_grad, _), layer), (anchor, _)), fisher) in grads
+            .iter_mut()
+            .zip(layers.iter())
+            .zip(self.anchors.iter())
+            .zip(self.fisher.iter())
+        {
+            weight_grad.scaled_add(self.lambda, &(fisher * &(&layer.weights() - anchor)));
+        }
+    }
+}
+
+/// Estimate the diagonal Fisher information of the weights of each layer as the mean squared gradient
+/// of the per-sample loss, over (at most MAX_FISHER_SAMPLES) samples of the dataset
+pub fn compute_fisher(model: &NeuralNet, dataset: &Dataset) -> Vec<Array2<f64>> {
+    let mut fisher: Vec<Array2<f64>> = model
+        .layers
+        .iter()
+        .map(|layer| Array2::zeros(layer.weights().dim()))
+        .collect();
+    let n_samples = dataset.data.nrows().min(MAX_FISHER_SAMPLES);
+
+    for i in 0..n_samples {
+        let grads = model.gradients(
+            &dataset.data.slice(s![i..i + 1, ..]),
+            &dataset.target.slice(s![i..i + 1, ..]),
+        );
+
+        for (f, (weight_grad, _)) in fisher.iter_mut().zip(grads) {
+            *f += &weight_grad.mapv(|g| g * g);
+        }
+    }
+
+    if n_samples > 0 {
+        for f in fisher.iter_mut() {
+            *f /= n_samples as f64;
+        }
+    }
+
+    fisher
+}
@@ -0,0 +1,250 @@
+use ndarray::{concatenate, Array2, Axis};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::Mutex;
+
+use super::loss::LossFunction;
+use super::neural_net::{activation, delta_activation, Gradients, NeuralNet, Task, Verbosity};
+use super::noise::standard_normal;
+use crate::parsing::Dataset;
+
+/// The mean losses of the discriminator and the generator in each epoch of training a GAN (in bits)
+#[derive(Clone, Debug, Default)]
+pub struct GANHistory {
+    pub discriminator_losses: Vec<f64>,
+    pub generator_losses: Vec<f64>,
+}
+
+/// A generative adversarial network (Goodfellow et al. 2014)
+/// The generator maps noise z ~ N(0, I) of size noise_dim to samples, and the discriminator, which has a single
+/// output, predicts the probability that a sample is real. The outputs of the generator are passed through its
+/// output activation (e.g. Sigmoid for images scaled to [0, 1])
+pub struct GAN {
+    pub generator: NeuralNet,
+    pub discriminator: NeuralNet,
+    pub noise_dim: usize,
+    rng: Mutex<StdRng>, // Samples the noise. Behind a mutex because generate takes &self
+}
+
+impl GAN {
+    /// The discriminator is trained with binary cross-entropy, so its loss function and task are overridden
+    pub fn new(generator: NeuralNet, mut discriminator: NeuralNet, noise_dim: usize) -> GAN {
+        discriminator.loss_function = LossFunction::BinaryCrossEntropy;
+        discriminator.task = Task::Multilabel;
+
+        GAN {
+            generator,
+            discriminator,
+            noise_dim,
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// Add the gradient penalty of WGAN-GP with this weight to the discriminator's loss
+    pub fn with_gradient_penalty(mut self, lambda: f64) -> GAN {
+        self.discriminator.loss_function = LossFunction::GradientPenalty {
+            base: Box::new(self.discriminator.loss_function.clone()),
+            lambda,
+        };
+
+        self
+    }
+
+    pub fn with_seed(self, seed: u64) -> GAN {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+
+        self
+    }
+
+    /// Sample a batch of noise vectors from N(0, I)
+    fn sample_noise(&self, n_samples: usize) -> Array2<f64> {
+        let mut rng = self.rng.lock().unwrap();
+
+        Array2::from_shape_simple_fn((n_samples, self.noise_dim), || standard_normal(&mut *rng))
+    }
+
+    /// Generate n_samples samples from random noise
+    pub fn generate(&self, n_samples: usize) -> Array2<f64> {
+        let act = &self.generator.output_activation;
+
+        self.generator
+            .logits(&self.sample_noise(n_samples).view())
+            .mapv(|x| activation(act, x))
+    }
+
+    /// Train the GAN on the instances of real_data (the targets aren't used), in batches of the discriminator's batch size
+    /// Each batch trains the discriminator on the real instances and as many generated samples, and the generator is
+    /// trained after every d_steps_per_g_step discriminator steps to maximize log(D(G(z))) (the non-saturating loss)
+    pub fn train(
+        &mut self,
+        real_data: &Dataset,
+        n_epochs: usize,
+        d_steps_per_g_step: usize,
+        lr: f64,
+    ) -> GANHistory {
+        let mut history = GANHistory::default();
+        let batch_size = self.discriminator.batch_size;
+
+        self.generator.learning_rate = lr;
+        self.discriminator.learning_rate = lr;
+
+        for epoch in 0..n_epochs {
+            let mut d_losses = vec![];
+            let mut g_losses = vec![];
+
+            for (step, real_batch) in real_data
+                .data
+                .axis_chunks_iter(Axis(0), batch_size)
+                .enumerate()
+            {
+                let fake_batch = self.generate(real_batch.nrows());
+                let inputs = concatenate![Axis(0), real_batch, fake_batch];
+                let targets = concatenate![
+                    Axis(0),
+                    Array2::ones((real_batch.nrows(), 1)),
+                    Array2::zeros((fake_batch.nrows(), 1))
+                ];
+
+                d_losses.push(
+                    self.discriminator
+                        .partial_fit(&inputs.view(), &targets.view()),
+                );
+
+                if (step + 1) % d_steps_per_g_step.max(1) == 0 {
+                    g_losses.push(self.generator_step(batch_size));
+                }
+            }
+
+            history
+                .discriminator_losses
+                .push(d_losses.iter().sum::<f64>() / d_losses.len() as f64);
+            history
+                .generator_losses
+                .push(g_losses.iter().sum::<f64>() / g_losses.len() as f64);
+
+            if self.discriminator.verbosity >= Verbosity::Epoch {
+                eprintln!(
+                    "[Epoch {}/{}] d_loss={:.4} g_loss={:.4}",
+                    epoch + 1,
+                    n_epochs,
+                    history.discriminator_losses.last().unwrap(),
+                    history.generator_losses.last().unwrap()
+                );
+            }
+        }
+
+        history
+    }
+
+    /// Perform a GD step of the generator on a batch of noise, with the discriminator fixed
+    /// Returns the generator's loss -log(D(G(z))) before the step
+    fn generator_step(&mut self, batch_size: usize) -> f64 {
+        let noise = self.sample_noise(batch_size);
+        let act = self.generator.output_activation.clone();
+        let (g_hidden, g_hidden_linear, g_dropout_masks) =
+            self.generator.forward(&noise.view(), true);
+        let g_logits = g_hidden.last().unwrap();
+        let samples = g_logits.mapv(|x| activation(&act, x));
+
+        // The generator is trained to make the discriminator label its samples as real
+        let targets = Array2::ones((batch_size, 1));
+        let (d_hidden, d_hidden_linear, d_dropout_masks) =
+            self.discriminator.forward(&samples.view(), false);
+        let d_logits = d_hidden.last().unwrap();
+        let loss =
+            self.discriminator
+                .loss_function
+                .loss(d_logits, &targets.view(), &samples.view());
+        let d_grad =
+            self.discriminator
+                .loss_function
+                .gradient(d_logits, &targets.view(), &samples.view());
+        let (_, sample_grad) =
+            self.discriminator
+                .backward(&d_hidden, &d_hidden_linear, &d_dropout_masks, d_grad);
+
+        let g_grad = sample_grad * g_logits.mapv(|x| delta_activation(&act, x));
+        let (grads, _) =
+            self.generator
+                .backward(&g_hidden, &g_hidden_linear, &g_dropout_masks, g_grad);
+        self.generator.apply_gradients(&grads);
+
+        loss
+    }
+}
+
+/// The step of the finite differences the gradient of the gradient penalty is estimated with
+const PENALTY_STEP: f64 = 1e-4;
+
+/// The gradient penalty of WGAN-GP (Gulrajani et al. 2017): lambda * mean((||grad_x D(x_hat)|| - 1)^2), where the
+/// x_hat are sampled uniformly on the segments between pairs of real and fake instances, and D(x) is the first
+/// output (before the output activation) of the model
+pub fn gradient_penalty(
+    model: &NeuralNet,
+    real: &Array2<f64>,
+    fake: &Array2<f64>,
+    lambda: f64,
+) -> f64 {
+    let input_grads = model.score_input_gradients(&model.interpolate(real, fake).view(), 0);
+
+    lambda
+        * input_grads
+            .axis_iter(Axis(0))
+            .map(|grad| (grad.dot(&grad).sqrt() - 1f64).powi(2))
+            .sum::<f64>()
+        / input_grads.nrows().max(1) as f64
+}
+
+/// The gradient penalty and its gradients WRT the parameters of the model
+/// The gradient of ||grad_x D|| is the gradient of the derivative of D in the direction u = grad_x D / ||grad_x D||,
+/// which is estimated with the central difference (D(x_hat + h * u) - D(x_hat - h * u)) / 2h, so it takes two
+/// backward passes instead of differentiating through the backward pass
+pub(super) fn gradient_penalty_gradients(
+    model: &NeuralNet,
+    real: &Array2<f64>,
+    fake: &Array2<f64>,
+    lambda: f64,
+) -> (f64, Gradients) {
+    let x_hat = model.interpolate(real, fake);
+    let input_grads = model.score_input_gradients(&x_hat.view(), 0);
+    let norms: Vec<f64> = input_grads
+        .axis_iter(Axis(0))
+        .map(|grad| grad.dot(&grad).sqrt())
+        .collect();
+    let n = norms.len().max(1) as f64;
+    let penalty = lambda * norms.iter().map(|norm| (norm - 1f64).powi(2)).sum::<f64>() / n;
+
+    let mut directions = input_grads;
+    let mut coefs = Array2::zeros((norms.len(), model.layers.last().unwrap().biases().len()));
+
+    for (i, norm) in norms.iter().enumerate() {
+        directions
+            .row_mut(i)
+            .mapv_inplace(|x| x / norm.max(f64::MIN_POSITIVE));
+        // Like the other losses, the gradient is per instance (not divided by the batch size)
+        coefs[[i, 0]] = lambda * 2f64 * (norm - 1f64) / (2f64 * PENALTY_STEP);
+    }
+
+    let mut grads = None;
+
+    for (sign, coefs) in [(1f64, coefs.clone()), (-1f64, -coefs)] {
+        let shifted = &x_hat + &(&directions * sign * PENALTY_STEP);
+        let (hidden, hidden_linear, dropout_masks) = model.forward(&shifted.view(), false);
+        let (shifted_grads, _) = model.backward(&hidden, &hidden_linear, &dropout_masks, coefs);
+
+        grads = Some(match grads {
+            None => shifted_grads,
+            Some(grads) => add_gradients(grads, shifted_grads),
+        });
+    }
+
+    (penalty, grads.unwrap())
+}
+
+/// Sum two sets of gradients of the same network
+pub(super) fn add_gradients(a: Gradients, b: Gradients) -> Gradients {
+    a.into_iter()
+        .zip(b)
+        .map(|((w_a, b_a), (w_b, b_b))| (w_a + w_b, b_a + b_b))
+        .collect()
+}
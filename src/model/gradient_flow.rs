@@ -0,0 +1,74 @@
+use super::neural_net::NeuralNet;
+use crate::parsing::Dataset;
+
+/// Gradients with a smaller magnitude than this are counted as vanishing
+const VANISHING_THRESHOLD: f64 = 1e-7;
+
+/// The width of the longest bar of the chart
+const BAR_WIDTH: usize = 40;
+
+/// The number of orders of magnitude below the largest mean gradient that the chart shows
+const CHART_DECADES: f64 = 8f64;
+
+/// Statistics of the weight gradients of a layer
+#[derive(Clone, Debug)]
+pub struct LayerGradFlow {
+    pub layer_idx: usize,
+    pub mean_grad: f64, // The mean magnitude of the gradients
+    pub std_grad: f64,
+    pub max_grad: f64,
+    pub min_grad: f64,
+    pub pct_vanishing: f64, // The fraction of the gradients with a magnitude below 1e-7
+}
+
+/// Compute the weight gradients of the loss of the dataset in a single forward-backward pass, and summarize the
+/// gradients of each layer. Mean gradients that shrink toward the input layer indicate vanishing gradients
+pub fn gradient_flow_report(model: &NeuralNet, dataset: &Dataset) -> Vec<LayerGradFlow> {
+    model
+        .gradients(&dataset.data.view(), &dataset.target.view())
+        .iter()
+        .enumerate()
+        .map(|(layer_idx, (weight_grad, _))| LayerGradFlow {
+            layer_idx,
+            mean_grad: weight_grad.mapv(f64::abs).mean().unwrap_or(0f64),
+            std_grad: weight_grad.std(0f64),
+            max_grad: weight_grad.fold(f64::NEG_INFINITY, |a, &b| a.max(b)),
+            min_grad: weight_grad.fold(f64::INFINITY, |a, &b| a.min(b)),
+            pct_vanishing: weight_grad
+                .iter()
+                .filter(|x| x.abs() < VANISHING_THRESHOLD)
+                .count() as f64
+                / weight_grad.len().max(1) as f64,
+        })
+        .collect()
+}
+
+/// Format the report as a table with an ASCII bar chart of the mean gradient of each layer (on a log scale),
+/// from the output layer to the input layer
+pub fn format_gradient_flow(report: &[LayerGradFlow]) -> String {
+    let largest = report
+        .iter()
+        .map(|flow| flow.mean_grad)
+        .fold(f64::MIN_POSITIVE, f64::max);
+    let mut out =
+        String::from("layer   mean |grad|   std          min          max          vanishing\n");
+
+    for flow in report.iter().rev() {
+        let decades_below = (largest / flow.mean_grad.max(f64::MIN_POSITIVE)).log10();
+        let bar_len = (BAR_WIDTH as f64 * (1f64 - decades_below / CHART_DECADES))
+            .clamp(0f64, BAR_WIDTH as f64);
+
+        out.push_str(&format!(
+            "{:<7} {:<13.4e} {:<12.4e} {:<12.4e} {:<12.4e} {:<9.4} {}\n",
+            flow.layer_idx,
+            flow.mean_grad,
+            flow.std_grad,
+            flow.min_grad,
+            flow.max_grad,
+            flow.pct_vanishing,
+            "#".repeat(bar_len.round() as usize)
+        ));
+    }
+
+    out
+}
@@ -0,0 +1,222 @@
+use crate::error::Result;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+
+// Dimensions of the loss plot in pixels
+const PLOT_WIDTH: f64 = 640f64;
+const PLOT_HEIGHT: f64 = 400f64;
+const PLOT_MARGIN: f64 = 50f64;
+const NUM_TICKS: usize = 5;
+
+/// A curve of a plot: its label, its color and its (x, y) points
+type Curve<'a> = (&'a str, &'a str, Vec<(f64, f64)>);
+
+/// The losses recorded while training a model, one entry per epoch
+#[derive(Clone, Debug, Default)]
+pub struct TrainingHistory {
+    pub train_losses: Vec<f64>, // The mean loss of the batches trained on during each epoch
+    pub val_losses: Vec<f64>, // The loss on the validation set after each epoch. Empty if there's no validation set
+}
+
+impl TrainingHistory {
+    pub fn new() -> TrainingHistory {
+        TrainingHistory::default()
+    }
+
+    pub fn num_epochs(&self) -> usize {
+        self.train_losses.len()
+    }
+
+    /// Return the loss as a function of the epoch (used for plotting)
+    /// The validation losses are used if there are any, and the training losses otherwise
+    pub fn losses(&self) -> Vec<(usize, f64)> {
+        let losses = if self.val_losses.is_empty() {
+            &self.train_losses
+        } else {
+            &self.val_losses
+        };
+
+        losses.iter().copied().enumerate().collect()
+    }
+
+    /// The loss early stopping is based on - the last validation loss if there is one, and the last training loss otherwise
+    pub fn last_loss(&self) -> Option<f64> {
+        self.val_losses.last().or(self.train_losses.last()).copied()
+    }
+
+    /// Save a plot of the training and validation losses over the epochs as an SVG file
+    pub fn save_loss_plot_svg(&self, path: &str) -> Result<()> {
+        let mut file = File::create(path)?;
+
+        file.write_all(self.loss_plot_svg().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Generate the SVG of the loss plot
+    fn loss_plot_svg(&self) -> String {
+        let points = |losses: &Vec<f64>| {
+            losses
+                .iter()
+                .enumerate()
+                .map(|(epoch, loss)| (epoch as f64, *loss))
+                .collect()
+        };
+        let curves = [
+            ("train loss", "steelblue", points(&self.train_losses)),
+            ("val loss", "darkorange", points(&self.val_losses)),
+        ];
+
+        // The epoch axis starts from 0 and spans at least one epoch
+        line_plot_svg(&curves, "epoch", "loss", 1, Some((0f64, 1f64)))
+    }
+
+    /// Save a plot of the validation accuracy as a function of the value of a hyperparam as an SVG file
+    /// The results are (value of the hyperparam, validation accuracy) pairs, as returned by hyperparameter_sensitivity
+    pub fn save_sensitivity_plot_svg(
+        results: &[(f64, f64)],
+        param: &str,
+        path: &str,
+    ) -> Result<()> {
+        let mut points = results.to_vec();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut file = File::create(path)?;
+        let svg = line_plot_svg(
+            &[("val accuracy", "steelblue", points)],
+            param,
+            "accuracy",
+            3,
+            None,
+        );
+
+        file.write_all(svg.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Generate the SVG of a plot of some labeled curves of (x, y) points. The x axis tick labels have x_precision
+/// decimals. If min_x_range is set to (start, min_length), the x axis starts at start and spans at least min_length
+fn line_plot_svg(
+    curves: &[Curve],
+    x_label: &str,
+    y_label: &str,
+    x_precision: usize,
+    min_x_range: Option<(f64, f64)>,
+) -> String {
+    let all_points = || curves.iter().flat_map(|(_, _, points)| points.iter());
+    let bounds = |coord: fn(&(f64, f64)) -> f64| {
+        (
+            all_points().map(coord).fold(f64::INFINITY, f64::min),
+            all_points().map(coord).fold(f64::NEG_INFINITY, f64::max),
+        )
+    };
+    let (min_y, max_y) = match all_points().count() {
+        // An empty plot is plotted as empty axes
+        0 => (0f64, 1f64),
+        _ => bounds(|p| p.1),
+    };
+    let (min_x, max_x) = match (min_x_range, all_points().count()) {
+        (Some((start, min_length)), _) => (start, bounds(|p| p.0).1.max(start + min_length)),
+        (None, 0) => (0f64, 1f64),
+        (None, _) => bounds(|p| p.0),
+    };
+    // Avoid dividing by zero if all the values are equal
+    let y_range = (max_y - min_y).max(f64::EPSILON);
+    let x_range = (max_x - min_x).max(f64::EPSILON);
+    let (left, right) = (PLOT_MARGIN, PLOT_WIDTH - PLOT_MARGIN);
+    let (top, bottom) = (PLOT_MARGIN, PLOT_HEIGHT - PLOT_MARGIN);
+    let x = |value: f64| left + (value - min_x) / x_range * (right - left);
+    let y = |value: f64| bottom - (value - min_y) / y_range * (bottom - top);
+    let mut svg = String::new();
+
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#,
+        PLOT_WIDTH, PLOT_HEIGHT
+    );
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+
+    // Axes
+    let _ = writeln!(
+        svg,
+        r#"<line x1="{left}" y1="{bottom}" x2="{right}" y2="{bottom}" stroke="black"/>"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<line x1="{left}" y1="{top}" x2="{left}" y2="{bottom}" stroke="black"/>"#
+    );
+
+    // Tick marks and their labels
+    for i in 0..NUM_TICKS {
+        let frac = i as f64 / (NUM_TICKS - 1) as f64;
+        let x_value = min_x + frac * x_range;
+        let y_value = min_y + frac * y_range;
+
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{0}" y1="{bottom}" x2="{0}" y2="{1}" stroke="black"/><text x="{0}" y="{2}" text-anchor="middle">{3:.4$}</text>"#,
+            x(x_value),
+            bottom + 5f64,
+            bottom + 20f64,
+            x_value,
+            x_precision
+        );
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{0}" y1="{1}" x2="{left}" y2="{1}" stroke="black"/><text x="{2}" y="{3}" text-anchor="end">{4:.3}</text>"#,
+            left - 5f64,
+            y(y_value),
+            left - 8f64,
+            y(y_value) + 4f64,
+            y_value
+        );
+    }
+
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+        (left + right) / 2f64,
+        PLOT_HEIGHT - 10f64,
+        x_label
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="15" y="{0}" text-anchor="middle" transform="rotate(-90 15 {0})">{1}</text>"#,
+        (top + bottom) / 2f64,
+        y_label
+    );
+
+    // The curves and the legend
+    for (idx, (label, color, points)) in curves.iter().filter(|c| !c.2.is_empty()).enumerate() {
+        let points: Vec<String> = points
+            .iter()
+            .map(|(px, py)| format!("{:.2},{:.2}", x(*px), y(*py)))
+            .collect();
+        let legend_y = top + 20f64 * idx as f64;
+
+        let _ = writeln!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+            points.join(" "),
+            color
+        );
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{0}" y1="{1}" x2="{2}" y2="{1}" stroke="{3}" stroke-width="2"/><text x="{4}" y="{5}">{6}</text>"#,
+            right - 110f64,
+            legend_y,
+            right - 90f64,
+            color,
+            right - 85f64,
+            legend_y + 4f64,
+            label
+        );
+    }
+
+    svg.push_str("</svg>\n");
+
+    svg
+}
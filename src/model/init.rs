@@ -0,0 +1,45 @@
+use ndarray::ArrayView2;
+
+/// Which of the fans of a layer the variance of its initial weights is scaled by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FanMode {
+    FanIn,
+    FanOut,
+    /// The mean of the fan-in and the fan-out (Glorot & Bengio 2010)
+    FanAvg,
+}
+
+/// The distribution the initial weights are sampled from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Distribution {
+    Uniform,
+    Normal,
+}
+
+/// The fan-in and the fan-out of a weight matrix, which has a row for each input and a column for each output
+pub fn compute_fan(weights: &ArrayView2<f64>) -> (usize, usize) {
+    (weights.nrows(), weights.ncols())
+}
+
+/// Variance scaling initialization: the weights have variance scale / fan, where fan is chosen by mode
+/// Returns the bound b of U(-b, b) = sqrt(3 * scale / fan) for a uniform distribution, and the standard deviation
+/// sqrt(scale / fan) for a normal distribution. E.g. Xavier is (FanAvg, scale = 1) and He is (FanIn, scale = 2)
+pub fn variance_scaling(
+    fan_in: usize,
+    fan_out: usize,
+    mode: FanMode,
+    distribution: Distribution,
+    scale: f64,
+) -> f64 {
+    let fan = match mode {
+        FanMode::FanIn => fan_in as f64,
+        FanMode::FanOut => fan_out as f64,
+        FanMode::FanAvg => (fan_in + fan_out) as f64 / 2f64,
+    };
+    let variance = scale / fan.max(1f64);
+
+    match distribution {
+        Distribution::Uniform => (3f64 * variance).sqrt(),
+        Distribution::Normal => variance.sqrt(),
+    }
+}
@@ -0,0 +1,602 @@
+use ndarray::linalg::general_mat_mul;
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2, ArrayViewMut2, Axis, CowArray, Ix1, Ix2};
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+
+use super::init::{self, compute_fan, variance_scaling, FanMode};
+use super::neural_net::InitMethod;
+use super::optimizer::{LayerState, Optimizer};
+use crate::error::{NeuralNetError, Result};
+
+/// What a layer keeps from its forward pass for its backward pass
+#[derive(Clone, Debug, Default)]
+pub struct LayerCache {
+    pub input: Array2<f64>,
+}
+
+/// The gradients WRT the weight matrix and the bias vector of a layer
+pub type LayerGradients = (Array2<f64>, Array1<f64>);
+
+/// Buffers for the gradients of a training step, allocated once for an architecture and a batch size so that
+/// backprop doesn't allocate them on every step
+#[derive(Clone, Debug, Default)]
+pub struct GradientBuffers {
+    pub weight_grads: Vec<Array2<f64>>,
+    pub bias_grads: Vec<Array1<f64>>,
+    pub activations: Vec<Array2<f64>>, // The gradient WRT the output of each hidden layer, a row per instance
+}
+
+impl GradientBuffers {
+    /// Whether the buffers have the shapes of the gradients of these layers, for batches of num_rows instances
+    pub fn fits(&self, layers: &[Box<dyn Layer>], num_rows: usize) -> bool {
+        self.weight_grads.len() == layers.len()
+            && self.activations.len() + 1 == layers.len()
+            && layers
+                .iter()
+                .zip(self.weight_grads.iter().zip(self.bias_grads.iter()))
+                .all(|(layer, (weight_grad, bias_grad))| {
+                    weight_grad.dim() == layer.weights().dim()
+                        && bias_grad.len() == layer.biases().len()
+                })
+            && layers
+                .iter()
+                .skip(1)
+                .zip(self.activations.iter())
+                .all(|(next, output_grad)| {
+                    output_grad.nrows() >= num_rows && output_grad.ncols() == next.weights().nrows()
+                })
+    }
+
+    /// Move the weight and bias gradients out of the buffers, without copying them
+    pub fn take_gradients(&mut self) -> Vec<LayerGradients> {
+        std::mem::take(&mut self.weight_grads)
+            .into_iter()
+            .zip(std::mem::take(&mut self.bias_grads))
+            .collect()
+    }
+
+    /// Move the gradients of a step back into the buffers, so that the next step reuses them
+    pub fn restore_gradients(&mut self, grads: Vec<LayerGradients>) {
+        (self.weight_grads, self.bias_grads) = grads.into_iter().unzip();
+    }
+}
+
+/// A layer of a network. The network applies the activation function, noise and dropout to the outputs of its
+/// hidden layers, so a layer only computes the transformation of its parameters
+/// The weights and biases are exposed for the features that work on the parameters directly (e.g. pruning, saving)
+pub trait Layer: Send + Sync {
+    /// Compute the output of the layer for a batch of inputs
+    fn forward(&self, input: &Array2<f64>, training: bool) -> (Array2<f64>, LayerCache);
+
+    /// Given the gradient WRT the output of the layer, compute the gradient WRT its input and its parameters
+    /// Like the rest of the network, the weight gradients are summed over the batch and the bias gradients averaged
+    fn backward(&self, grad: &Array2<f64>, cache: &LayerCache) -> (Array2<f64>, LayerGradients);
+
+    /// Backprop like backward, writing the gradients into preallocated buffers. The gradient WRT the input is only
+    /// computed if there's a buffer for it
+    fn backward_into(
+        &self,
+        grad: &ArrayView2<f64>,
+        input: &Array2<f64>,
+        weight_grad: &mut Array2<f64>,
+        bias_grad: &mut Array1<f64>,
+        input_grad: Option<ArrayViewMut2<f64>>,
+    ) {
+        let cache = LayerCache {
+            input: input.clone(),
+        };
+        let (layer_input_grad, (layer_weight_grad, layer_bias_grad)) =
+            self.backward(&grad.to_owned(), &cache);
+
+        weight_grad.assign(&layer_weight_grad);
+        bias_grad.assign(&layer_bias_grad);
+
+        if let Some(mut input_grad) = input_grad {
+            input_grad.assign(&layer_input_grad);
+        }
+    }
+
+    /// Update the parameters of the layer with the optimizer. state is the optimizer's state for this layer, if any
+    fn update(
+        &mut self,
+        grads: &LayerGradients,
+        optimizer: &Optimizer,
+        learning_rate: f64,
+        state: Option<&mut LayerState>,
+    );
+
+    /// The number of trainable parameters of the layer
+    fn parameter_count(&self) -> usize;
+
+    /// The weights and the biases at f64 precision. Layers that store them at a lower precision return a copy
+    fn weights(&self) -> CowArray<'_, f64, Ix2>;
+    fn biases(&self) -> CowArray<'_, f64, Ix1>;
+    fn set_weights(&mut self, weights: Array2<f64>);
+    fn set_biases(&mut self, biases: Array1<f64>);
+
+    fn clone_box(&self) -> Box<dyn Layer>;
+
+    /// The number of features of the inputs of the layer, or None if it takes any number of them
+    fn input_dim(&self) -> Option<usize> {
+        Some(self.weights().nrows())
+    }
+
+    /// The kind of the layer, which save stores for the layers that aren't dense so that load can rebuild them
+    fn kind(&self) -> &'static str {
+        "dense"
+    }
+
+    /// Check that the values of the inputs can be passed to the layer. The number of features is checked with input_dim
+    fn validate_input(&self, _input: &ArrayView2<f64>) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Clone for Box<dyn Layer> {
+    fn clone(&self) -> Box<dyn Layer> {
+        self.clone_box()
+    }
+}
+
+/// A fully connected layer: output = input * weights + biases
+#[derive(Clone, Debug)]
+pub struct DenseLayer {
+    pub weights: Array2<f64>,
+    pub biases: Array1<f64>,
+}
+
+impl DenseLayer {
+    pub fn new(weights: Array2<f64>, biases: Array1<f64>) -> DenseLayer {
+        DenseLayer { weights, biases }
+    }
+}
+
+impl Layer for DenseLayer {
+    fn forward(&self, input: &Array2<f64>, _training: bool) -> (Array2<f64>, LayerCache) {
+        let output = input.dot(&self.weights) + &self.biases;
+
+        (
+            output,
+            LayerCache {
+                input: input.clone(),
+            },
+        )
+    }
+
+    fn backward(&self, grad: &Array2<f64>, cache: &LayerCache) -> (Array2<f64>, LayerGradients) {
+        let weight_grad = cache.input.t().dot(grad);
+        let bias_grad = grad.mean_axis(Axis(0)).unwrap();
+
+        (grad.dot(&self.weights.t()), (weight_grad, bias_grad))
+    }
+
+    fn backward_into(
+        &self,
+        grad: &ArrayView2<f64>,
+        input: &Array2<f64>,
+        weight_grad: &mut Array2<f64>,
+        bias_grad: &mut Array1<f64>,
+        input_grad: Option<ArrayViewMut2<f64>>,
+    ) {
+        general_mat_mul(1f64, &input.t(), grad, 0f64, weight_grad);
+
+        bias_grad.fill(0f64);
+        for row in grad.rows() {
+            *bias_grad += &row;
+        }
+        *bias_grad /= grad.nrows() as f64;
+
+        if let Some(mut input_grad) = input_grad {
+            general_mat_mul(1f64, grad, &self.weights.t(), 0f64, &mut input_grad);
+        }
+    }
+
+    fn update(
+        &mut self,
+        grads: &LayerGradients,
+        optimizer: &Optimizer,
+        learning_rate: f64,
+        state: Option<&mut LayerState>,
+    ) {
+        optimizer.update_parameters(
+            &mut self.weights,
+            &mut self.biases,
+            grads,
+            learning_rate,
+            state,
+        );
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.weights.len() + self.biases.len()
+    }
+
+    fn weights(&self) -> CowArray<'_, f64, Ix2> {
+        CowArray::from(self.weights.view())
+    }
+
+    fn biases(&self) -> CowArray<'_, f64, Ix1> {
+        CowArray::from(self.biases.view())
+    }
+
+    fn set_weights(&mut self, weights: Array2<f64>) {
+        self.weights = weights;
+    }
+
+    fn set_biases(&mut self, biases: Array1<f64>) {
+        self.biases = biases;
+    }
+
+    fn clone_box(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// A dense layer that stores its weights and biases as f32, which halves their memory. The forward and backward
+/// passes cast them to f64 at the matrix products, and updates are computed in f64 (so the optimizer state stays
+/// f64) before the parameters are rounded back to f32
+#[derive(Clone, Debug)]
+pub struct MixedPrecisionLayer {
+    pub weights: Array2<f32>,
+    pub biases: Array1<f32>,
+}
+
+impl MixedPrecisionLayer {
+    /// A layer with the weights and biases rounded to the nearest f32
+    pub fn new(weights: &ArrayView2<f64>, biases: &ArrayView1<f64>) -> MixedPrecisionLayer {
+        MixedPrecisionLayer {
+            weights: weights.mapv(|x| x as f32),
+            biases: biases.mapv(|x| x as f32),
+        }
+    }
+
+    fn weights_f64(&self) -> Array2<f64> {
+        self.weights.mapv(f64::from)
+    }
+
+    fn biases_f64(&self) -> Array1<f64> {
+        self.biases.mapv(f64::from)
+    }
+}
+
+impl Layer for MixedPrecisionLayer {
+    fn forward(&self, input: &Array2<f64>, _training: bool) -> (Array2<f64>, LayerCache) {
+        let output = input.dot(&self.weights_f64()) + &self.biases_f64();
+
+        (
+            output,
+            LayerCache {
+                input: input.clone(),
+            },
+        )
+    }
+
+    fn backward(&self, grad: &Array2<f64>, cache: &LayerCache) -> (Array2<f64>, LayerGradients) {
+        let weight_grad = cache.input.t().dot(grad);
+        let bias_grad = grad.mean_axis(Axis(0)).unwrap();
+
+        (grad.dot(&self.weights_f64().t()), (weight_grad, bias_grad))
+    }
+
+    fn update(
+        &mut self,
+        grads: &LayerGradients,
+        optimizer: &Optimizer,
+        learning_rate: f64,
+        state: Option<&mut LayerState>,
+    ) {
+        let (mut weights, mut biases) = (self.weights_f64(), self.biases_f64());
+
+        optimizer.update_parameters(&mut weights, &mut biases, grads, learning_rate, state);
+        self.set_weights(weights);
+        self.set_biases(biases);
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.weights.len() + self.biases.len()
+    }
+
+    fn weights(&self) -> CowArray<'_, f64, Ix2> {
+        CowArray::from(self.weights_f64())
+    }
+
+    fn biases(&self) -> CowArray<'_, f64, Ix1> {
+        CowArray::from(self.biases_f64())
+    }
+
+    fn set_weights(&mut self, weights: Array2<f64>) {
+        self.weights = weights.mapv(|x| x as f32);
+    }
+
+    fn set_biases(&mut self, biases: Array1<f64>) {
+        self.biases = biases.mapv(|x| x as f32);
+    }
+
+    fn clone_box(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    // Without converting the weights to f64
+    fn input_dim(&self) -> Option<usize> {
+        Some(self.weights.nrows())
+    }
+}
+
+/// Maps integer indices (e.g. word ids or categories) to learned vectors: row i of the weights is the embedding of
+/// index i. Each input row holds seq_len indices, and its output is their embed_dim long embeddings one after the
+/// other. The indices must be smaller than vocab_size. Embedding layers have no biases
+#[derive(Clone, Debug)]
+pub struct EmbeddingLayer {
+    pub weight: Array2<f64>,
+    pub vocab_size: usize,
+    pub embed_dim: usize,
+    biases: Array1<f64>, // Always empty, the layer has no biases
+}
+
+impl EmbeddingLayer {
+    /// Initialize the embeddings like the weights of a dense layer with vocab_size inputs and embed_dim outputs
+    pub fn new(
+        vocab_size: usize,
+        embed_dim: usize,
+        init: InitMethod,
+        rng: &mut StdRng,
+    ) -> EmbeddingLayer {
+        let boundary = match init {
+            InitMethod::Default => 0.3,
+            InitMethod::Xavier => {
+                let (fan_in, fan_out) = compute_fan(&Array2::zeros((vocab_size, embed_dim)).view());
+
+                variance_scaling(
+                    fan_in,
+                    fan_out,
+                    FanMode::FanAvg,
+                    init::Distribution::Uniform,
+                    1f64,
+                )
+            }
+        };
+        let dist = Uniform::new(-boundary, boundary);
+
+        EmbeddingLayer::from_weights(Array2::from_shape_simple_fn(
+            (vocab_size, embed_dim),
+            || dist.sample(rng),
+        ))
+    }
+
+    /// An embedding layer with a row of weights for every index
+    pub fn from_weights(weight: Array2<f64>) -> EmbeddingLayer {
+        EmbeddingLayer {
+            vocab_size: weight.nrows(),
+            embed_dim: weight.ncols(),
+            weight,
+            biases: Array1::zeros(0),
+        }
+    }
+
+    /// The indices of the inputs, one after the other
+    /// Panics on an input that isn't an index of the vocabulary, which validate_input reports as an error instead
+    fn indices<'a>(&'a self, input: &'a Array2<f64>) -> impl Iterator<Item = usize> + 'a {
+        input.iter().map(|&idx| match self.to_index(idx) {
+            Ok(idx) => idx,
+            Err(err) => panic!("{}", err),
+        })
+    }
+
+    /// The index of the vocabulary an input holds, which must be an integer in [0, vocab_size)
+    fn to_index(&self, idx: f64) -> Result<usize> {
+        if idx.fract() != 0f64 || idx < 0f64 || idx >= self.vocab_size as f64 {
+            return Err(NeuralNetError::InvalidInput(format!(
+                "{} isn't an index of an embedding with a vocabulary of {}",
+                idx, self.vocab_size
+            )));
+        }
+
+        Ok(idx as usize)
+    }
+}
+
+impl Layer for EmbeddingLayer {
+    fn forward(&self, input: &Array2<f64>, _training: bool) -> (Array2<f64>, LayerCache) {
+        let rows: Vec<usize> = self.indices(input).collect();
+        let output = self
+            .weight
+            .select(Axis(0), &rows)
+            .into_shape((input.nrows(), input.ncols() * self.embed_dim))
+            .unwrap();
+
+        (
+            output,
+            LayerCache {
+                input: input.clone(),
+            },
+        )
+    }
+
+    /// Only the rows of the indices in the batch get a gradient. The indices aren't differentiable, so the gradient
+    /// WRT the input is zero
+    fn backward(&self, grad: &Array2<f64>, cache: &LayerCache) -> (Array2<f64>, LayerGradients) {
+        let mut weight_grad = Array2::zeros(self.weight.dim());
+        let grads = grad
+            .to_shape((grad.len() / self.embed_dim, self.embed_dim))
+            .unwrap();
+
+        for (idx, row_grad) in self.indices(&cache.input).zip(grads.rows()) {
+            let mut row = weight_grad.row_mut(idx);
+            row += &row_grad;
+        }
+
+        (
+            Array2::zeros(cache.input.dim()),
+            (weight_grad, Array1::zeros(0)),
+        )
+    }
+
+    /// With SGD only the rows that have a gradient are updated (a sparse update)
+    fn update(
+        &mut self,
+        grads: &LayerGradients,
+        optimizer: &Optimizer,
+        learning_rate: f64,
+        state: Option<&mut LayerState>,
+    ) {
+        match optimizer {
+            Optimizer::SGD => {
+                for (mut row, row_grad) in self.weight.rows_mut().into_iter().zip(grads.0.rows()) {
+                    if row_grad.iter().any(|&g| g != 0f64) {
+                        row.scaled_add(-learning_rate, &row_grad);
+                    }
+                }
+            }
+            _ => optimizer.update_parameters(
+                &mut self.weight,
+                &mut self.biases,
+                grads,
+                learning_rate,
+                state,
+            ),
+        }
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.weight.len()
+    }
+
+    fn weights(&self) -> CowArray<'_, f64, Ix2> {
+        CowArray::from(self.weight.view())
+    }
+
+    fn biases(&self) -> CowArray<'_, f64, Ix1> {
+        CowArray::from(self.biases.view())
+    }
+
+    fn set_weights(&mut self, weights: Array2<f64>) {
+        self.weight = weights;
+    }
+
+    fn set_biases(&mut self, biases: Array1<f64>) {
+        self.biases = biases;
+    }
+
+    fn clone_box(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    /// Each input can hold any number of indices
+    fn input_dim(&self) -> Option<usize> {
+        None
+    }
+
+    fn kind(&self) -> &'static str {
+        "embedding"
+    }
+
+    fn validate_input(&self, input: &ArrayView2<f64>) -> Result<()> {
+        input
+            .iter()
+            .try_for_each(|&idx| self.to_index(idx).map(|_| ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+    use rand::SeedableRng;
+
+    fn embedding() -> EmbeddingLayer {
+        EmbeddingLayer::from_weights(array![
+            [0f64, 1f64],
+            [2f64, 3f64],
+            [4f64, 5f64],
+            [6f64, 7f64]
+        ])
+    }
+
+    #[test]
+    fn mixed_precision_layer_rounds_its_parameters_to_f32() {
+        let weights = array![[0.1, -0.2], [0.3, 1f64 / 3f64]];
+        let biases = array![0.5, 1e-9];
+        let mut layer = MixedPrecisionLayer::new(&weights.view(), &biases.view());
+        let dense = DenseLayer::new(
+            weights.mapv(|x| x as f32 as f64),
+            biases.mapv(|x| x as f32 as f64),
+        );
+        let input = array![[1f64, 2f64], [-1f64, 0.5]];
+
+        assert_eq!(layer.weights(), dense.weights());
+        assert_eq!(
+            layer.forward(&input, false).0,
+            dense.forward(&input, false).0
+        );
+
+        // The update is computed in f64 and then rounded
+        let grads = (array![[1f64, 0f64], [0f64, 0f64]], array![0f64, 0f64]);
+        layer.update(&grads, &Optimizer::SGD, 0.01, None);
+
+        assert_eq!(layer.weights[[0, 0]], (0.1f32 as f64 - 0.01) as f32);
+        assert_eq!(layer.weights[[1, 1]], 1f32 / 3f32);
+    }
+
+    #[test]
+    fn embedding_forward_concatenates_the_rows_of_the_indices() {
+        let (output, _) = embedding().forward(&array![[2f64, 0f64], [3f64, 3f64]], false);
+
+        assert_eq!(
+            output,
+            array![[4f64, 5f64, 0f64, 1f64], [6f64, 7f64, 6f64, 7f64]]
+        );
+    }
+
+    #[test]
+    fn embedding_backward_and_sgd_update_only_touch_the_rows_of_the_indices() {
+        let mut layer = embedding();
+        let input = array![[2f64, 0f64], [2f64, 2f64]];
+        let (_, cache) = layer.forward(&input, true);
+        let grad = array![[1f64, 1f64, 0.5, 0.5], [2f64, 2f64, 3f64, 3f64]];
+        let (input_grad, grads) = layer.backward(&grad, &cache);
+
+        // The gradients of an index that appears more than once are summed
+        assert_eq!(
+            grads.0,
+            array![[0.5, 0.5], [0f64, 0f64], [6f64, 6f64], [0f64, 0f64]]
+        );
+        assert_eq!(grads.1.len(), 0);
+        assert_eq!(input_grad, Array2::<f64>::zeros((2, 2)));
+
+        layer.update(&grads, &Optimizer::SGD, 0.1, None);
+
+        assert_eq!(
+            layer.weight,
+            array![[-0.05, 0.95], [2f64, 3f64], [3.4, 4.4], [6f64, 7f64]]
+        );
+    }
+
+    #[test]
+    fn embedding_indices_must_be_in_the_vocabulary() {
+        let layer = embedding();
+
+        assert!(layer.validate_input(&array![[0f64, 3f64]].view()).is_ok());
+
+        for idx in [4f64, -1f64, 1.5, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                layer.validate_input(&array![[0f64, idx]].view()),
+                Err(NeuralNetError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't an index of an embedding")]
+    fn embedding_forward_panics_on_an_invalid_index() {
+        embedding().forward(&array![[1.5]], false);
+    }
+
+    #[test]
+    fn embeddings_with_the_same_seed_are_equal() {
+        let layer =
+            |seed| EmbeddingLayer::new(10, 3, InitMethod::Xavier, &mut StdRng::seed_from_u64(seed));
+
+        assert_eq!(layer(0).weight, layer(0).weight);
+        assert_ne!(layer(0).weight, layer(1).weight);
+    }
+}
@@ -0,0 +1,820 @@
+use ndarray::{concatenate, Array, Array1, Array2, ArrayView1, ArrayView2, Axis};
+use std::fmt;
+use std::sync::Arc;
+
+use super::metrics::argmax;
+use super::neural_net::{sigmoid, softplus, NeuralNet};
+use crate::parsing::{Dataset, TripletDataset};
+
+/// The loss function a neural net is trained to minimize
+#[derive(Clone, Default)]
+pub enum LossFunction {
+    /// Cross-entropy between the softmax of the outputs and the targets
+    #[default]
+    CrossEntropy,
+    /// Cross-entropy with the loss of each instance multiplied by the weight of its class (e.g. to balance the classes)
+    WeightedCrossEntropy { class_weights: Array1<f64> },
+    /// Knowledge distillation from a pretrained teacher: a weighted sum of the KL-divergence between
+    /// the temperature-softened outputs of the teacher and the student, and the cross-entropy with the hard labels
+    Distillation {
+        teacher: Arc<NeuralNet>,
+        alpha: f64,
+        temperature: f64,
+    },
+    /// One minus the cosine similarity between the outputs and the targets, averaged over the batch
+    CosineSimilarity,
+    /// Focal loss (Lin et al. 2017): cross-entropy down-weighted for the instances the model already classifies well
+    Focal(FocalLoss),
+    /// Binary cross-entropy between the sigmoid of each output and the matching target (used for multi-label
+    /// classification), summed over the outputs
+    BinaryCrossEntropy,
+    /// Contrastive loss on pairs of embeddings (Hadsell et al. 2006): similar pairs are pulled together
+    /// and dissimilar pairs are pushed at least margin apart. The batch holds the first instances
+    /// of the pairs followed by the second ones, and the targets are the labels of the pairs
+    Contrastive { margin: f64 },
+    /// Triplet loss (Schroff et al. 2015): the anchor should be closer to the positive than to the negative by at least margin
+    /// The batch holds the anchors, then the positives, then the negatives
+    Triplet { margin: f64 },
+    /// Squared error between the outputs and the targets, summed over the outputs and averaged over the batch
+    /// Used for regression, with a linear output activation
+    MSE,
+    /// Hinge loss max(0, 1 - y * z) of each output, summed over the outputs and averaged over the batch
+    /// The targets are -1 or +1 (0 is treated as -1, so one-hot targets are one-vs-rest)
+    Hinge,
+    /// Multi-class hinge loss (Crammer & Singer 2001): max(0, 1 - z_true + max of the other z), averaged over the batch
+    CategoricalHinge,
+    /// KL-divergence KL(targets || softmax of the outputs), for targets that are probability distributions
+    /// (e.g. soft labels) rather than one-hot
+    KLDivergence,
+    /// The base loss plus the gradient penalty of WGAN-GP with weight lambda, for training a GAN discriminator on
+    /// batches of real instances (with a first target of 1) and fake ones. The penalty depends on the network,
+    /// so it's added during training, and the loss and the gradient here are those of the base loss
+    GradientPenalty {
+        base: Box<LossFunction>,
+        lambda: f64,
+    },
+    /// Poisson negative log-likelihood softplus(z) - y * log(softplus(z)) of each output, summed over the outputs
+    /// and averaged over the batch. Used for regression of counts, which are predicted as the softplus of the outputs
+    Poisson,
+    /// log(cosh(z - y)) of each output, summed over the outputs and averaged over the batch. Used for regression:
+    /// it's about half the squared error for small errors and the absolute error for large ones, and it's smooth
+    LogCosh,
+    /// A user-defined loss: the closure takes the raw outputs of the network and the targets of a batch, and returns
+    /// the loss of the batch and its gradient WRT the outputs. Like the built-in losses, the gradient of each instance
+    /// shouldn't be divided by the batch size. The raw outputs are predicted, unless an output activation is set
+    Custom(CustomLossFn),
+}
+
+/// The closure of a custom loss, which returns the loss of the batch and its gradient
+pub type CustomLossFn = Arc<dyn Fn(&Array2<f64>, &Array2<f64>) -> (f64, Array2<f64>) + Send + Sync>;
+
+/// The focal loss -alpha_t * (1 - p_t)^gamma * log(p_t), where p_t is the predicted probability of the true class
+/// The (1 - p_t)^gamma factor focuses training on hard instances, and alpha weights the classes
+#[derive(Clone, Debug)]
+pub struct FocalLoss {
+    pub gamma: f64,
+    pub alpha: Array1<f64>, // The weight of each class
+}
+
+impl FocalLoss {
+    /// Focal loss where alpha[c] is the weight of class c
+    pub fn new(gamma: f64, alpha: Array1<f64>) -> FocalLoss {
+        FocalLoss { gamma, alpha }
+    }
+
+    /// Focal loss with the weight of each class inversely proportional to its frequency in the dataset
+    /// The weights are normalized to sum to the number of classes. Classes that don't appear get a weight of 0
+    pub fn balanced(gamma: f64, dataset: &Dataset) -> FocalLoss {
+        let counts = dataset.target.sum_axis(Axis(0));
+        let inverse = counts.mapv(|count| if count > 0f64 { count.recip() } else { 0f64 });
+        let alpha = &inverse * (counts.len() as f64 / inverse.sum());
+
+        FocalLoss { gamma, alpha }
+    }
+
+    /// The mean loss of a batch, in bits like cross_entropy
+    fn loss(&self, logits: &Array2<f64>, targets: &ArrayView2<f64>) -> f64 {
+        let total: f64 = logits
+            .axis_iter(Axis(0))
+            .zip(targets.axis_iter(Axis(0)))
+            .map(|(logits_row, target_row)| {
+                let class = argmax(target_row);
+                let log_p = log_softmax(logits_row)[class];
+
+                -self.alpha[class] * (1f64 - log_p.exp()).powf(self.gamma) * log_p
+            })
+            .sum();
+
+        total / (logits.nrows() as f64 * std::f64::consts::LN_2)
+    }
+
+    /// The gradient WRT the logits is the gradient of the cross-entropy (softmax - y) scaled by
+    /// alpha_t * ((1 - p_t)^gamma - gamma * p_t * (1 - p_t)^(gamma - 1) * log(p_t))
+    fn gradient(&self, logits: &Array2<f64>, targets: &ArrayView2<f64>) -> Array2<f64> {
+        let mut grad = softmax_rows(logits) - targets;
+
+        for ((mut grad_row, logits_row), target_row) in grad
+            .axis_iter_mut(Axis(0))
+            .zip(logits.axis_iter(Axis(0)))
+            .zip(targets.axis_iter(Axis(0)))
+        {
+            let class = argmax(target_row);
+            let log_p = log_softmax(logits_row)[class];
+            let p = log_p.exp();
+            // The second term goes to 0 as p_t goes to 1, even when (1 - p_t)^(gamma - 1) blows up
+            let correction = if p < 1f64 {
+                self.gamma * p * (1f64 - p).powf(self.gamma - 1f64) * log_p
+            } else {
+                0f64
+            };
+
+            grad_row *= self.alpha[class] * ((1f64 - p).powf(self.gamma) - correction);
+        }
+
+        grad
+    }
+}
+
+impl fmt::Display for FocalLoss {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let alpha: Vec<String> = self.alpha.iter().map(|a| format!("{:.4}", a)).collect();
+
+        write!(
+            f,
+            "focal loss (gamma={}, alpha=[{}])",
+            self.gamma,
+            alpha.join(", ")
+        )
+    }
+}
+
+/// The center loss (Wen et al. 2016), an auxiliary objective that pulls the features of each instance (the input
+/// of the output layer) towards a running prototype of its class: lambda * sum(||h_i - c_{y_i}||^2) / (2N)
+#[derive(Clone, Debug)]
+pub struct CenterLoss {
+    pub centers: Array2<f64>, // The center of each class
+    pub lambda: f64,
+    pub alpha: f64, // The rate at which the centers move towards the mean features of their class
+}
+
+impl CenterLoss {
+    /// Start with all the centers at the origin
+    pub fn new(num_classes: usize, feature_dim: usize, lambda: f64, alpha: f64) -> CenterLoss {
+        CenterLoss {
+            centers: Array2::zeros((num_classes, feature_dim)),
+            lambda,
+            alpha,
+        }
+    }
+
+    /// The mean loss of a batch
+    pub fn loss(&self, features: &Array2<f64>, labels: &[usize]) -> f64 {
+        let total: f64 = features
+            .axis_iter(Axis(0))
+            .zip(labels.iter())
+            .map(|(h, &label)| (&h - &self.centers.row(label)).mapv(|x| x * x).sum())
+            .sum();
+
+        self.lambda * total / (2f64 * features.nrows() as f64)
+    }
+
+    /// Move the center of each class in the batch towards the mean features of its instances:
+    /// c_y -= alpha * (c_y - mean_y)
+    pub fn update_centers(&mut self, features: &Array2<f64>, labels: &[usize]) {
+        for class in 0..self.centers.nrows() {
+            let rows: Vec<usize> = (0..labels.len()).filter(|&i| labels[i] == class).collect();
+
+            if rows.is_empty() {
+                continue;
+            }
+
+            let mean = features.select(Axis(0), &rows).mean_axis(Axis(0)).unwrap();
+            let mut center = self.centers.row_mut(class);
+            let step = (&center - &mean) * self.alpha;
+
+            center -= &step;
+        }
+    }
+}
+
+/// The gradient of the center loss WRT the features of each instance (without the lambda factor): h_i - c_{y_i}
+/// Like the gradients of the other losses, the gradient of each instance isn't divided by the batch size
+pub fn center_loss_gradient(
+    features: &Array2<f64>,
+    labels: &[usize],
+    centers: &Array2<f64>,
+) -> Array2<f64> {
+    features - &centers.select(Axis(0), labels)
+}
+
+/// Added to L2 norms to avoid division by zero
+const NORM_EPSILON: f64 = 1e-12;
+
+/// Added to the predicted rates of the Poisson loss, which can underflow to 0, before taking their log
+const POISSON_EPSILON: f64 = 1e-10;
+
+impl LossFunction {
+    /// Calculate the loss of a batch given the outputs of the network
+    /// The inputs of the batch are needed by losses that run another network on them (e.g. distillation)
+    pub fn loss(
+        &self,
+        logits: &Array2<f64>,
+        targets: &ArrayView2<f64>,
+        inputs: &ArrayView2<f64>,
+    ) -> f64 {
+        match self {
+            LossFunction::CrossEntropy => cross_entropy_from_logits(logits, targets.view()),
+            LossFunction::WeightedCrossEntropy { class_weights } => {
+                let total: f64 = logits
+                    .axis_iter(Axis(0))
+                    .zip(targets.axis_iter(Axis(0)))
+                    .map(|(z, t)| -t.dot(class_weights) * t.dot(&log_softmax(z)))
+                    .sum();
+
+                // Like cross_entropy, the loss is measured in bits
+                total / (logits.nrows() as f64 * std::f64::consts::LN_2)
+            }
+            LossFunction::Distillation {
+                teacher,
+                alpha,
+                temperature,
+            } => {
+                let soft_targets = softmax_rows(&(teacher.logits(inputs) / *temperature));
+                let soft_predictions = softmax_rows(&(logits / *temperature));
+                let hard_loss = cross_entropy_from_logits(logits, targets.view());
+
+                alpha * kl_divergence(&soft_predictions, &soft_targets) + (1f64 - alpha) * hard_loss
+            }
+            LossFunction::CosineSimilarity => {
+                let total: f64 = logits
+                    .axis_iter(Axis(0))
+                    .zip(targets.axis_iter(Axis(0)))
+                    .map(|(y_pred, y_true)| 1f64 - cosine_similarity(y_pred, y_true))
+                    .sum();
+
+                total / logits.nrows() as f64
+            }
+            LossFunction::Focal(focal) => focal.loss(logits, targets),
+            LossFunction::BinaryCrossEntropy => {
+                // -y log(sigmoid(z)) - (1 - y) log(1 - sigmoid(z)) = max(z, 0) - z * y + log(1 + exp(-|z|))
+                // which never takes the log of 0
+                let total: f64 = logits
+                    .iter()
+                    .zip(targets.iter())
+                    .map(|(z, y)| z.max(0f64) - z * y + (-z.abs()).exp().ln_1p())
+                    .sum();
+
+                // Like cross_entropy, the loss is measured in bits
+                total / (logits.nrows() as f64 * std::f64::consts::LN_2)
+            }
+            LossFunction::Contrastive { margin } => {
+                let (first, second) = split_pairs(logits);
+                let total: f64 = pair_distances(&first, &second)
+                    .iter()
+                    .zip(targets.column(0).iter())
+                    .map(|(d, y)| y * d.powi(2) + (1f64 - y) * (margin - d).max(0f64).powi(2))
+                    .sum();
+
+                total / first.nrows() as f64
+            }
+            LossFunction::Triplet { margin } => {
+                let (anchors, positives, negatives) = split_triplets(logits);
+                let total: f64 = pair_distances(&anchors, &positives)
+                    .iter()
+                    .zip(pair_distances(&anchors, &negatives).iter())
+                    .map(|(d_pos, d_neg)| (d_pos - d_neg + margin).max(0f64))
+                    .sum();
+
+                total / anchors.nrows() as f64
+            }
+            LossFunction::MSE => (logits - targets).mapv(|d| d * d).sum() / logits.nrows() as f64,
+            LossFunction::Hinge => {
+                let total: f64 = logits
+                    .iter()
+                    .zip(targets.iter())
+                    .map(|(z, t)| (1f64 - hinge_sign(*t) * z).max(0f64))
+                    .sum();
+
+                total / logits.nrows() as f64
+            }
+            LossFunction::CategoricalHinge => {
+                let total: f64 = logits
+                    .axis_iter(Axis(0))
+                    .zip(targets.axis_iter(Axis(0)))
+                    .map(|(z, t)| {
+                        let (true_class, runner_up) = hinge_classes(z, t);
+
+                        (1f64 - z[true_class] + z[runner_up]).max(0f64)
+                    })
+                    .sum();
+
+                total / logits.nrows() as f64
+            }
+            LossFunction::KLDivergence => kl_divergence(&softmax_rows(logits), &targets.to_owned()),
+            LossFunction::Poisson => {
+                let total: f64 = logits
+                    .iter()
+                    .zip(targets.iter())
+                    .map(|(z, y)| {
+                        let rate = softplus(*z);
+
+                        rate - y * (rate + POISSON_EPSILON).ln()
+                    })
+                    .sum();
+
+                total / logits.nrows() as f64
+            }
+            LossFunction::LogCosh => {
+                (logits - targets).mapv(log_cosh).sum() / logits.nrows() as f64
+            }
+            LossFunction::GradientPenalty { base, .. } => base.loss(logits, targets, inputs),
+            LossFunction::Custom(custom) => custom(logits, &targets.to_owned()).0,
+        }
+    }
+
+    /// Calculate the gradient of the loss WRT the outputs of the network
+    pub fn gradient(
+        &self,
+        logits: &Array2<f64>,
+        targets: &ArrayView2<f64>,
+        inputs: &ArrayView2<f64>,
+    ) -> Array2<f64> {
+        match self {
+            LossFunction::CrossEntropy => softmax_rows(logits) - targets,
+            LossFunction::WeightedCrossEntropy { class_weights } => {
+                let instance_weights = targets.dot(class_weights).insert_axis(Axis(1));
+
+                (softmax_rows(logits) - targets) * &instance_weights
+            }
+            LossFunction::Distillation {
+                teacher,
+                alpha,
+                temperature,
+            } => {
+                // The teacher is only used for inference, so we don't need its gradients
+                let soft_targets = softmax_rows(&(teacher.logits(inputs) / *temperature));
+                let soft_predictions = softmax_rows(&(logits / *temperature));
+                let soft_grad = (soft_predictions - soft_targets) / *temperature;
+                let hard_grad = softmax_rows(logits) - targets;
+
+                *alpha * soft_grad + (1f64 - alpha) * hard_grad
+            }
+            LossFunction::CosineSimilarity => {
+                let mut grad = Array2::zeros(logits.raw_dim());
+
+                // d(1 - cos)/dp = -(t / ||t|| - cos * p / ||p||) / ||p||
+                for ((mut grad_row, y_pred), y_true) in grad
+                    .axis_iter_mut(Axis(0))
+                    .zip(logits.axis_iter(Axis(0)))
+                    .zip(targets.axis_iter(Axis(0)))
+                {
+                    let pred_norm = l2_norm(y_pred) + NORM_EPSILON;
+                    let true_norm = l2_norm(y_true) + NORM_EPSILON;
+                    let cos = y_pred.dot(&y_true) / (pred_norm * true_norm);
+
+                    grad_row.assign(
+                        &(-(&y_true / true_norm - &y_pred * (cos / pred_norm)) / pred_norm),
+                    );
+                }
+
+                grad
+            }
+            LossFunction::Focal(focal) => focal.gradient(logits, targets),
+            LossFunction::BinaryCrossEntropy => logits.mapv(sigmoid) - targets,
+            LossFunction::Contrastive { margin } => {
+                let (first, second) = split_pairs(logits);
+                let mut diff = &first - &second;
+
+                for ((mut diff_row, d), y) in diff
+                    .axis_iter_mut(Axis(0))
+                    .zip(pair_distances(&first, &second).iter())
+                    .zip(targets.column(0).iter())
+                {
+                    let coef =
+                        2f64 * y - 2f64 * (1f64 - y) * (margin - d).max(0f64) / (d + NORM_EPSILON);
+
+                    diff_row *= coef;
+                }
+
+                concatenate![Axis(0), diff, -&diff]
+            }
+            LossFunction::Triplet { margin } => {
+                let (anchors, positives, negatives) = split_triplets(logits);
+                let mut pos_dir = &anchors - &positives;
+                let mut neg_dir = &anchors - &negatives;
+
+                // Normalize the differences to unit vectors, and zero out the triplets that satisfy the margin
+                for ((mut pos_row, mut neg_row), (d_pos, d_neg)) in pos_dir
+                    .axis_iter_mut(Axis(0))
+                    .zip(neg_dir.axis_iter_mut(Axis(0)))
+                    .zip(
+                        pair_distances(&anchors, &positives)
+                            .into_iter()
+                            .zip(pair_distances(&anchors, &negatives)),
+                    )
+                {
+                    let active = if d_pos - d_neg + margin > 0f64 {
+                        1f64
+                    } else {
+                        0f64
+                    };
+
+                    pos_row *= active / (d_pos + NORM_EPSILON);
+                    neg_row *= active / (d_neg + NORM_EPSILON);
+                }
+
+                concatenate![Axis(0), &pos_dir - &neg_dir, -&pos_dir, neg_dir]
+            }
+            LossFunction::MSE => 2f64 * (logits - targets),
+            LossFunction::Hinge => {
+                let mut grad = Array2::zeros(logits.raw_dim());
+
+                for ((g, z), t) in grad.iter_mut().zip(logits.iter()).zip(targets.iter()) {
+                    let y = hinge_sign(*t);
+
+                    if 1f64 - y * z > 0f64 {
+                        *g = -y;
+                    }
+                }
+
+                grad
+            }
+            LossFunction::CategoricalHinge => {
+                let mut grad = Array2::zeros(logits.raw_dim());
+
+                // Only the true class and the highest-scoring other class get a gradient, while the margin is violated
+                for ((mut grad_row, z), t) in grad
+                    .axis_iter_mut(Axis(0))
+                    .zip(logits.axis_iter(Axis(0)))
+                    .zip(targets.axis_iter(Axis(0)))
+                {
+                    let (true_class, runner_up) = hinge_classes(z, t);
+
+                    if 1f64 - z[true_class] + z[runner_up] > 0f64 {
+                        grad_row[true_class] = -1f64;
+                        grad_row[runner_up] = 1f64;
+                    }
+                }
+
+                grad
+            }
+            LossFunction::KLDivergence => {
+                // Through the softmax Jacobian, the gradient of -sum(q * log(p)) WRT the logits is p * sum(q) - q
+                let predictions = softmax_rows(logits);
+                let target_sums = targets.sum_axis(Axis(1)).insert_axis(Axis(1));
+
+                predictions * &target_sums - targets
+            }
+            LossFunction::Poisson => {
+                // d/dz of rate - y * log(rate) is (1 - y / rate) * sigmoid(z), since softplus' = sigmoid
+                let mut grad = logits.mapv(sigmoid);
+
+                for ((g, z), y) in grad.iter_mut().zip(logits.iter()).zip(targets.iter()) {
+                    *g *= 1f64 - y / (softplus(*z) + POISSON_EPSILON);
+                }
+
+                grad
+            }
+            LossFunction::LogCosh => (logits - targets).mapv(f64::tanh),
+            LossFunction::GradientPenalty { base, .. } => base.gradient(logits, targets, inputs),
+            LossFunction::Custom(custom) => custom(logits, &targets.to_owned()).1,
+        }
+    }
+}
+
+/// log(cosh(x)) = |x| + log(1 + exp(-2|x|)) - log(2), which doesn't overflow for large |x|, unlike cosh
+fn log_cosh(x: f64) -> f64 {
+    x.abs() + (-2f64 * x.abs()).exp().ln_1p() - std::f64::consts::LN_2
+}
+
+/// The label of an output for the hinge loss: +1 for a positive target, and -1 otherwise
+fn hinge_sign(target: f64) -> f64 {
+    if target > 0f64 {
+        1f64
+    } else {
+        -1f64
+    }
+}
+
+/// The true class of an instance, and the class other than it with the highest score
+fn hinge_classes(logits: ArrayView1<f64>, targets: ArrayView1<f64>) -> (usize, usize) {
+    let true_class = argmax(targets);
+    let runner_up = (0..logits.len())
+        .filter(|&c| c != true_class)
+        .max_by(|&a, &b| logits[a].total_cmp(&logits[b]))
+        .unwrap_or(true_class);
+
+    (true_class, runner_up)
+}
+
+/// Compute log(sum(exp(x))) as max(x) + log(sum(exp(x - max(x))))
+/// We shift the elements by the max, because otherwise we would have to compute the exp of very large values,
+/// which overflows to Inf
+pub fn log_sum_exp(x: ArrayView1<f64>) -> f64 {
+    let max = x.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+    max + x.iter().map(|x| (x - max).exp()).sum::<f64>().ln()
+}
+
+/// Softmax function - Convert scores into a probability distribution
+pub fn softmax(scores: ArrayView1<f64>) -> Array1<f64> {
+    let log_sum = log_sum_exp(scores);
+
+    scores.mapv(|x| (x - log_sum).exp())
+}
+
+/// Apply the softmax to every row of a scores matrix
+pub fn softmax_rows(scores: &Array2<f64>) -> Array2<f64> {
+    let mut predictions = Array::zeros((0, scores.ncols()));
+
+    for row in scores.axis_iter(Axis(0)) {
+        predictions.push_row(softmax(row).view()).unwrap();
+    }
+
+    predictions
+}
+
+/// Calculate the cross-entropy loss on a given batch
+pub fn cross_entropy(predictions: &Array2<f64>, target: ArrayView2<f64>) -> f64 {
+    let total: f64 = predictions
+        .axis_iter(Axis(0))
+        .zip(target.axis_iter(Axis(0)))
+        .map(|(actual_row, target_row)| target_row.dot(&actual_row.map(|x| x.log2())))
+        .sum();
+
+    -(1f64 / predictions.nrows() as f64) * total
+}
+
+/// Log of the softmax, computed as x_i - log_sum_exp(x) so that it never takes the log of 0
+pub fn log_softmax(scores: ArrayView1<f64>) -> Array1<f64> {
+    let log_sum = log_sum_exp(scores);
+
+    scores.mapv(|x| x - log_sum)
+}
+
+/// Calculate the cross-entropy loss on a given batch directly from the logits using the log-softmax
+/// Unlike applying cross_entropy to the softmax, this stays finite when some probabilities underflow to 0
+/// Like cross_entropy, the loss is measured in bits
+pub fn cross_entropy_from_logits(logits: &Array2<f64>, target: ArrayView2<f64>) -> f64 {
+    let total: f64 = logits
+        .axis_iter(Axis(0))
+        .zip(target.axis_iter(Axis(0)))
+        .map(|(logits_row, target_row)| target_row.dot(&log_softmax(logits_row)))
+        .sum();
+
+    -total / (logits.nrows() as f64 * std::f64::consts::LN_2)
+}
+
+/// The smallest predicted probability the KL-divergence takes the log of
+const MIN_PROBABILITY: f64 = 1e-10;
+
+/// Calculate the mean KL-divergence KL(target || predictions) over the rows of a batch
+fn kl_divergence(predictions: &Array2<f64>, target: &Array2<f64>) -> f64 {
+    let total: f64 = predictions
+        .iter()
+        .zip(target.iter())
+        .filter(|(_, q)| **q > 0f64)
+        .map(|(p, q)| q * (q / p.max(MIN_PROBABILITY)).log2())
+        .sum();
+
+    total / predictions.nrows() as f64
+}
+
+/// L2 norm of a vector
+fn l2_norm(v: ArrayView1<f64>) -> f64 {
+    v.dot(&v).sqrt()
+}
+
+/// Cosine similarity between two vectors
+fn cosine_similarity(a: ArrayView1<f64>, b: ArrayView1<f64>) -> f64 {
+    a.dot(&b) / ((l2_norm(a) + NORM_EPSILON) * (l2_norm(b) + NORM_EPSILON))
+}
+
+/// Compute the matrix of cosine similarities between the rows of a and the rows of b
+pub fn pairwise_cosine_similarity(a: &Array2<f64>, b: &Array2<f64>) -> Array2<f64> {
+    let normalize = |m: &Array2<f64>| {
+        let norms = m.map_axis(Axis(1), |row| l2_norm(row) + NORM_EPSILON);
+
+        m / &norms.insert_axis(Axis(1))
+    };
+
+    normalize(a).dot(&normalize(b).t())
+}
+
+/// Split a stacked batch of pairs into the first instances and the second instances
+fn split_pairs(logits: &Array2<f64>) -> (ArrayView2<'_, f64>, ArrayView2<'_, f64>) {
+    logits.view().split_at(Axis(0), logits.nrows() / 2)
+}
+
+/// Euclidean distance between each row of a and the matching row of b
+fn pair_distances(a: &ArrayView2<f64>, b: &ArrayView2<f64>) -> Array1<f64> {
+    (a - b).map_axis(Axis(1), l2_norm)
+}
+
+/// Split a stacked batch of triplets into the anchors, the positives and the negatives
+fn split_triplets(
+    logits: &Array2<f64>,
+) -> (
+    ArrayView2<'_, f64>,
+    ArrayView2<'_, f64>,
+    ArrayView2<'_, f64>,
+) {
+    let n = logits.nrows() / 3;
+    let (anchors, rest) = logits.view().split_at(Axis(0), n);
+    let (positives, negatives) = rest.split_at(Axis(0), n);
+
+    (anchors, positives, negatives)
+}
+
+/// Select the hardest triplet for each anchor: the farthest instance of the same class and the closest instance of
+/// another class. Only triplets that violate the margin are kept. Returns (anchor, positive, negative) row indices
+pub fn hard_triplet_indices(
+    embeddings: &Array2<f64>,
+    labels: &[usize],
+    margin: f64,
+) -> Vec<(usize, usize, usize)> {
+    let mut triplets = vec![];
+
+    for (anchor, embedding) in embeddings.axis_iter(Axis(0)).enumerate() {
+        let distances = (embeddings - &embedding).map_axis(Axis(1), l2_norm);
+        let hardest = |same_class: bool| {
+            (0..labels.len())
+                .filter(|&idx| idx != anchor && (labels[idx] == labels[anchor]) == same_class)
+                .max_by(|&a, &b| {
+                    let ordering = distances[a].total_cmp(&distances[b]);
+
+                    if same_class {
+                        ordering
+                    } else {
+                        ordering.reverse()
+                    }
+                })
+        };
+
+        if let (Some(positive), Some(negative)) = (hardest(true), hardest(false)) {
+            if distances[positive] - distances[negative] + margin > 0f64 {
+                triplets.push((anchor, positive, negative));
+            }
+        }
+    }
+
+    triplets
+}
+
+/// Mine the hardest triplets of a batch of embeddings (online hard mining)
+/// To train on the mined triplets, use TripletDataset::from_indices with hard_triplet_indices on the inputs
+pub fn mine_hard_triplets(
+    embeddings: &Array2<f64>,
+    labels: &[usize],
+    margin: f64,
+) -> TripletDataset {
+    TripletDataset::from_indices(
+        embeddings,
+        &hard_triplet_indices(embeddings, labels, margin),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::neural_net::NeuralNetBuilder;
+    use crate::model::Model;
+    use ndarray::array;
+
+    #[test]
+    fn cross_entropy_from_logits_stays_finite_with_a_dominant_logit() {
+        let logits = array![[100f64, 0f64, 0f64]];
+        let wrong_target = array![[0f64, 1f64, 0f64]];
+        let right_target = array![[1f64, 0f64, 0f64]];
+
+        let wrong_loss = cross_entropy_from_logits(&logits, wrong_target.view());
+        let right_loss = cross_entropy_from_logits(&logits, right_target.view());
+
+        // -log2(softmax(logits)[1]) = (100 + ln(e^100 + 2) - 100) / ln(2) ~= 100 / ln(2)
+        assert!(wrong_loss.is_finite());
+        assert!((wrong_loss - 100f64 / std::f64::consts::LN_2).abs() < 1e-9);
+        assert!(right_loss.abs() < 1e-12);
+
+        // The softmax of a larger gap underflows to 0, so the log of it is -Inf
+        let logits = array![[1000f64, 0f64, 0f64]];
+
+        assert!(!cross_entropy(&softmax_rows(&logits), wrong_target.view()).is_finite());
+        assert!(cross_entropy_from_logits(&logits, wrong_target.view()).is_finite());
+    }
+
+    #[test]
+    fn log_sum_exp_doesnt_overflow() {
+        let x = array![1000f64, 1001f64, 1002f64];
+        let naive = x.mapv(f64::exp).sum().ln();
+
+        assert!(naive.is_infinite());
+        assert!((log_sum_exp(x.view()) - 1002.40760596).abs() < 1e-8);
+        assert!((softmax(x.view()).sum() - 1f64).abs() < 1e-12);
+    }
+
+    #[test]
+    fn hinge_ignores_instances_beyond_the_margin() {
+        let inputs = Array2::zeros((3, 1));
+        // Correct with a large margin, correct within the margin, and wrong
+        let logits = array![[5f64], [-0.5], [2f64]];
+        let targets = array![[1f64], [-1f64], [-1f64]];
+        let loss = LossFunction::Hinge.loss(&logits, &targets.view(), &inputs.view());
+        let grad = LossFunction::Hinge.gradient(&logits, &targets.view(), &inputs.view());
+
+        assert!((loss - (0f64 + 0.5 + 3f64) / 3f64).abs() < 1e-12);
+        assert_eq!(grad, array![[0f64], [1f64], [1f64]]);
+
+        // A target of 0 is the negative class
+        let grad =
+            LossFunction::Hinge.gradient(&array![[-5f64]], &array![[0f64]].view(), &inputs.view());
+
+        assert_eq!(grad, array![[0f64]]);
+    }
+
+    #[test]
+    fn categorical_hinge_ignores_instances_beyond_the_margin() {
+        let inputs = Array2::zeros((2, 1));
+        // The true class is 0 in both: beyond the margin of class 2, and within the margin of class 1
+        let logits = array![[4f64, 1f64, 2.5], [1f64, 0.5, -3f64]];
+        let targets = array![[1f64, 0f64, 0f64], [1f64, 0f64, 0f64]];
+        let loss = LossFunction::CategoricalHinge.loss(&logits, &targets.view(), &inputs.view());
+        let grad =
+            LossFunction::CategoricalHinge.gradient(&logits, &targets.view(), &inputs.view());
+
+        assert!((loss - 0.5 / 2f64).abs() < 1e-12);
+        assert_eq!(grad.row(0), array![0f64, 0f64, 0f64]);
+        assert_eq!(grad.row(1), array![-1f64, 1f64, 0f64]);
+    }
+
+    #[test]
+    fn hinge_predictions_are_the_raw_outputs() {
+        let inputs = array![[0.3, -1f64], [2f64, 0.5]];
+
+        for loss_function in [LossFunction::Hinge, LossFunction::CategoricalHinge] {
+            let net = NeuralNetBuilder::new(vec![2, 4, 3])
+                .loss_function(loss_function)
+                .seed(0)
+                .build();
+
+            assert_eq!(net.predict(&inputs.view()), net.logits(&inputs.view()));
+        }
+    }
+
+    #[test]
+    fn focal_gradient_matches_finite_differences() {
+        const EPS: f64 = 1e-6;
+
+        let inputs = Array2::zeros((3, 1));
+        let logits = array![[2f64, -1f64, 0.5], [0.1, 0.3, -2f64], [-1f64, 4f64, 1f64]];
+        let targets = array![[1f64, 0f64, 0f64], [0f64, 0f64, 1f64], [0f64, 1f64, 0f64]];
+
+        for gamma in [0f64, 0.5, 2f64] {
+            let loss_function =
+                LossFunction::Focal(FocalLoss::new(gamma, array![0.25, 1f64, 2f64]));
+            let grad = loss_function.gradient(&logits, &targets.view(), &inputs.view());
+
+            for ((row, col), g) in grad.indexed_iter() {
+                let mut plus = logits.clone();
+                let mut minus = logits.clone();
+                plus[[row, col]] += EPS;
+                minus[[row, col]] -= EPS;
+
+                // The loss is the mean over the batch in bits, and the gradient is of the loss of each instance in nats
+                let numeric = (loss_function.loss(&plus, &targets.view(), &inputs.view())
+                    - loss_function.loss(&minus, &targets.view(), &inputs.view()))
+                    / (2f64 * EPS)
+                    * logits.nrows() as f64
+                    * std::f64::consts::LN_2;
+
+                assert!(
+                    (g - numeric).abs() < 1e-6,
+                    "gamma {}, logit ({}, {}): {} vs {}",
+                    gamma,
+                    row,
+                    col,
+                    g,
+                    numeric
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn balanced_focal_weights_are_inverse_frequencies() {
+        let dataset = Dataset {
+            data: Array2::zeros((4, 1)),
+            target: array![
+                [1f64, 0f64, 0f64],
+                [1f64, 0f64, 0f64],
+                [1f64, 0f64, 0f64],
+                [0f64, 1f64, 0f64]
+            ],
+        };
+        let focal = FocalLoss::balanced(2f64, &dataset);
+
+        // The weights 1/3 and 1 of the classes that appear are normalized to sum to 3
+        assert!((focal.alpha[0] - 0.75).abs() < 1e-12);
+        assert!((focal.alpha[1] - 2.25).abs() < 1e-12);
+        assert_eq!(focal.alpha[2], 0f64);
+    }
+}
@@ -0,0 +1,62 @@
+use super::optimizer::Optimizer;
+
+/// The number of bytes of an f64
+const BYTES_PER_VALUE: usize = std::mem::size_of::<f64>();
+
+/// An estimate of the memory training a dense network takes, in bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    pub weights_bytes: usize, // The weights and the biases
+    pub activations_bytes: usize,
+    pub gradients_bytes: usize,
+    pub optimizer_state_bytes: usize,
+    pub total_bytes: usize,
+}
+
+/// Estimate the memory of training a network with this layer structure on batches of batch_size instances
+/// The activations are the outputs of every layer (including the input layer) and the linear outputs of every layer
+/// but the input layer, which the forward pass stores for backprop. Dropout masks and optimizer scratch space
+/// aren't counted, so the estimate is a lower bound
+pub fn estimate_training_memory(
+    layer_structure: &[usize],
+    batch_size: usize,
+    optimizer: &Optimizer,
+) -> MemoryEstimate {
+    let num_params: usize = layer_structure
+        .windows(2)
+        .map(|fans| fans[0] * fans[1] + fans[1])
+        .sum();
+    let num_outputs: usize = layer_structure.iter().sum();
+    let num_linear_outputs: usize = layer_structure.iter().skip(1).sum();
+    let weights_bytes = num_params * BYTES_PER_VALUE;
+    let activations_bytes = batch_size * (num_outputs + num_linear_outputs) * BYTES_PER_VALUE;
+    let gradients_bytes = weights_bytes;
+    let optimizer_state_bytes = optimizer.state_arrays_per_param() * weights_bytes;
+
+    MemoryEstimate {
+        weights_bytes,
+        activations_bytes,
+        gradients_bytes,
+        optimizer_state_bytes,
+        total_bytes: weights_bytes + activations_bytes + gradients_bytes + optimizer_state_bytes,
+    }
+}
+
+/// Format a number of bytes with a binary unit, e.g. 1.50 MiB
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024f64 && unit < UNITS.len() - 1 {
+        value /= 1024f64;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,485 @@
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+
+use super::loss::log_softmax;
+use super::neural_net::NeuralNet;
+use super::Model;
+use crate::parsing::Dataset;
+use std::f64::consts::LN_2;
+
+/// Return the index of the largest element in a row (e.g. the predicted class of a probability vector)
+pub fn argmax(row: ArrayView1<f64>) -> usize {
+    row.iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap()
+        .0
+}
+
+/// Calculate the fraction of the predictions whose predicted class is the target class
+pub fn accuracy(predictions: &Array2<f64>, targets: &Array2<f64>) -> f64 {
+    let num_correct = predictions
+        .axis_iter(Axis(0))
+        .zip(targets.axis_iter(Axis(0)))
+        .filter(|(prediction, target)| argmax(prediction.view()) == argmax(target.view()))
+        .count();
+
+    num_correct as f64 / predictions.nrows() as f64
+}
+
+/// Count the instances of each (true class, predicted class) pair. Rows are the true classes
+pub fn confusion_matrix(predictions: &Array2<f64>, targets: &Array2<f64>) -> Array2<f64> {
+    let mut matrix = Array2::zeros((targets.ncols(), targets.ncols()));
+
+    for (prediction, target) in predictions
+        .axis_iter(Axis(0))
+        .zip(targets.axis_iter(Axis(0)))
+    {
+        matrix[[argmax(target), argmax(prediction)]] += 1f64;
+    }
+
+    matrix
+}
+
+/// Render a confusion matrix (rows are the true classes) as a table with the sum of each row and column,
+/// the recall of each class, and the overall accuracy below it
+/// If colorize is set, the diagonal is colored green, and cells holding at least 10% of their row are colored red
+pub fn format_confusion_matrix(
+    cm: &Array2<usize>,
+    class_names: Option<&[&str]>,
+    colorize: bool,
+) -> String {
+    const GREEN: &str = "\x1b[32m";
+    const RED: &str = "\x1b[31m";
+    const RESET: &str = "\x1b[0m";
+    const HIGH_FRACTION: f64 = 0.1;
+
+    let n_classes = cm.nrows();
+    let names: Vec<String> = (0..n_classes)
+        .map(|i| match class_names {
+            Some(names) => names[i].to_string(),
+            None => i.to_string(),
+        })
+        .collect();
+    let row_sums = cm.sum_axis(Axis(1));
+    let col_sums = cm.sum_axis(Axis(0));
+    let total = cm.sum();
+    // Every cell is at most the total, so it's as wide as any number in the table
+    let width = names
+        .iter()
+        .map(|name| name.len())
+        .chain([total.to_string().len(), "recall".len()])
+        .max()
+        .unwrap();
+    let label_width = names
+        .iter()
+        .map(|name| name.len())
+        .chain(["true \\ pred".len()])
+        .max()
+        .unwrap();
+    let mut out = format!("{:<label_width$}", "true \\ pred");
+
+    for name in &names {
+        out += &format!(" {:>width$}", name);
+    }
+
+    out += &format!(" {:>width$} {:>width$}\n", "sum", "recall");
+
+    for (i, name) in names.iter().enumerate() {
+        out += &format!("{:<label_width$}", name);
+
+        for j in 0..n_classes {
+            let cell = format!("{:>width$}", cm[[i, j]]);
+            let color = if i == j && cm[[i, j]] > 0 {
+                Some(GREEN)
+            } else if i != j
+                && cm[[i, j]] as f64 >= HIGH_FRACTION * row_sums[i] as f64
+                && cm[[i, j]] > 0
+            {
+                Some(RED)
+            } else {
+                None
+            };
+
+            match color.filter(|_| colorize) {
+                Some(color) => out += &format!(" {}{}{}", color, cell, RESET),
+                None => out += &format!(" {}", cell),
+            }
+        }
+
+        let recall = cm[[i, i]] as f64 / row_sums[i] as f64;
+        out += &format!(" {:>width$} {:>width$.4}\n", row_sums[i], recall);
+    }
+
+    out += &format!("{:<label_width$}", "sum");
+
+    for col_sum in col_sums.iter() {
+        out += &format!(" {:>width$}", col_sum);
+    }
+
+    out += &format!(" {:>width$}\n", total);
+    out += &format!("Accuracy: {:.4}\n", cm.diag().sum() as f64 / total as f64);
+
+    out
+}
+
+/// Calculate Cohen's kappa - the agreement between the predictions and the targets, corrected for chance agreement
+/// kappa = (p_o - p_e) / (1 - p_e), where p_o is the accuracy and p_e is the accuracy expected by chance
+/// given the class frequencies of the predictions and the targets
+/// If agreement by chance is perfect (p_e = 1) kappa is undefined, so NaN is returned
+pub fn cohens_kappa(predictions: &Array2<f64>, targets: &Array2<f64>) -> f64 {
+    kappa_from_confusion_matrix(&confusion_matrix(predictions, targets))
+}
+
+/// Calculate Cohen's kappa from a confusion matrix (see cohens_kappa)
+pub fn kappa_from_confusion_matrix(matrix: &Array2<f64>) -> f64 {
+    let total = matrix.sum();
+    let p_o = matrix.diag().sum() / total;
+    let p_e = matrix.sum_axis(Axis(1)).dot(&matrix.sum_axis(Axis(0))) / (total * total);
+
+    if p_e == 1f64 {
+        eprintln!("Warning: Cohen's kappa is undefined when the expected agreement by chance is 1");
+
+        return f64::NAN;
+    }
+
+    (p_o - p_e) / (1f64 - p_e)
+}
+
+/// Calculate the Matthews correlation coefficient - the correlation between the predicted and the true classes
+/// Uses the multi-class generalization of (TP*TN - FP*FN) / sqrt((TP+FP)(TP+FN)(TN+FP)(TN+FN)) (Gorodkin 2004)
+/// It ranges from -1 to 1, and is close to 0 for a random classifier even if the classes are imbalanced
+pub fn matthews_correlation_coefficient(model: &NeuralNet, dataset: &Dataset) -> f64 {
+    mcc_from_confusion_matrix(&confusion_matrix(
+        &model.predict(&dataset.data.view()),
+        &dataset.target,
+    ))
+}
+
+/// Calculate the Matthews correlation coefficient from a confusion matrix (see matthews_correlation_coefficient)
+pub fn mcc_from_confusion_matrix(matrix: &Array2<f64>) -> f64 {
+    let total = matrix.sum();
+    let correct = matrix.diag().sum();
+    let true_counts = matrix.sum_axis(Axis(1));
+    let pred_counts = matrix.sum_axis(Axis(0));
+    let numerator = correct * total - pred_counts.dot(&true_counts);
+    let denominator = ((total * total - pred_counts.dot(&pred_counts))
+        * (total * total - true_counts.dot(&true_counts)))
+    .sqrt();
+
+    // If all the predictions (or targets) are the same class there is no correlation
+    if denominator == 0f64 {
+        0f64
+    } else {
+        numerator / denominator
+    }
+}
+
+/// Metrics of a model on a dataset
+#[derive(Clone, Debug)]
+pub struct EvaluationResult {
+    pub accuracy: f64,
+    /// Mean cross-entropy loss (in bits)
+    pub loss: f64,
+    pub cohens_kappa: f64,
+    pub matthews_correlation_coefficient: f64,
+}
+
+impl EvaluationResult {
+    /// Compute the metrics from the confusion matrix and the mean loss of the predictions
+    pub fn from_confusion_matrix(matrix: &Array2<f64>, loss: f64) -> EvaluationResult {
+        EvaluationResult {
+            accuracy: matrix.diag().sum() / matrix.sum(),
+            loss,
+            cohens_kappa: kappa_from_confusion_matrix(matrix),
+            matthews_correlation_coefficient: mcc_from_confusion_matrix(matrix),
+        }
+    }
+}
+
+/// Evaluate a model on a dataset
+pub fn evaluate(model: &NeuralNet, dataset: &Dataset) -> EvaluationResult {
+    let predictions = model.predict(&dataset.data.view());
+
+    EvaluationResult::from_confusion_matrix(
+        &confusion_matrix(&predictions, &dataset.target),
+        per_sample_loss(model, dataset).mean().unwrap_or(0f64),
+    )
+}
+
+/// Split the predictions into n_bins equal-width bins by their confidence (the max probability)
+/// Returns (count, number of correct predictions, sum of confidences) of each bin
+fn confidence_bins(
+    predictions: &Array2<f64>,
+    targets: &Array2<f64>,
+    n_bins: usize,
+) -> Vec<(usize, usize, f64)> {
+    let mut bins = vec![(0usize, 0usize, 0f64); n_bins];
+
+    for (prediction, target) in predictions
+        .axis_iter(Axis(0))
+        .zip(targets.axis_iter(Axis(0)))
+    {
+        let predicted_class = argmax(prediction);
+        let confidence = prediction[predicted_class];
+        // A confidence of exactly 1 belongs to the last bin
+        let bin = ((confidence * n_bins as f64) as usize).min(n_bins - 1);
+
+        bins[bin].0 += 1;
+        bins[bin].2 += confidence;
+
+        if predicted_class == argmax(target) {
+            bins[bin].1 += 1;
+        }
+    }
+
+    bins
+}
+
+/// Calculate the expected calibration error of a set of predictions
+/// The predictions are split into n_bins equal-width bins by their confidence (the max probability),
+/// and the ECE is the weighted mean of |accuracy - confidence| over the bins
+pub fn expected_calibration_error(
+    predictions: &Array2<f64>,
+    targets: &Array2<f64>,
+    n_bins: usize,
+) -> f64 {
+    confidence_bins(predictions, targets, n_bins)
+        .iter()
+        .filter(|(count, _, _)| *count > 0)
+        .map(|(count, correct, confidence_sum)| {
+            let accuracy = *correct as f64 / *count as f64;
+            let confidence = confidence_sum / *count as f64;
+
+            (*count as f64 / predictions.nrows() as f64) * (accuracy - confidence).abs()
+        })
+        .sum()
+}
+
+/// The data of a reliability diagram: the center of each of the n_bins confidence bins, the mean confidence
+/// of the predictions in it and the fraction of them that are correct. The curves match for a calibrated model
+/// Empty bins have a NaN mean confidence and fraction correct
+pub fn prediction_confidence_histogram(
+    model: &NeuralNet,
+    dataset: &Dataset,
+    n_bins: usize,
+) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+    let predictions = model.predict(&dataset.data.view());
+    let bins = confidence_bins(&predictions, &dataset.target, n_bins);
+    let centers = (0..n_bins)
+        .map(|i| (i as f64 + 0.5) / n_bins as f64)
+        .collect();
+    let mean_confidences = bins
+        .iter()
+        .map(|(count, _, confidence_sum)| confidence_sum / *count as f64)
+        .collect();
+    let fractions_correct = bins
+        .iter()
+        .map(|(count, correct, _)| *correct as f64 / *count as f64)
+        .collect();
+
+    (centers, mean_confidences, fractions_correct)
+}
+
+/// Calculate the cross-entropy loss of each instance in the dataset
+pub fn per_sample_loss(model: &NeuralNet, dataset: &Dataset) -> Array1<f64> {
+    let scores = model.logits(&dataset.data.view()) / model.temperature;
+
+    // Use the log-softmax so that confidently wrong predictions get a finite loss
+    scores
+        .axis_iter(Axis(0))
+        .zip(dataset.target.axis_iter(Axis(0)))
+        .map(|(scores, target)| -target.dot(&log_softmax(scores)) / LN_2)
+        .collect()
+}
+
+/// Find the k instances with the highest loss, e.g. for error analysis
+/// Returns (instance index, loss, true class, predicted class) sorted by descending loss
+pub fn hardest_k_samples(
+    model: &NeuralNet,
+    dataset: &Dataset,
+    k: usize,
+) -> Vec<(usize, f64, usize, usize)> {
+    let losses = per_sample_loss(model, dataset);
+    let predictions = model.predict(&dataset.data.view());
+    let mut indices: Vec<usize> = (0..losses.len()).collect();
+
+    indices.sort_by(|&a, &b| losses[b].total_cmp(&losses[a]));
+
+    indices
+        .into_iter()
+        .take(k)
+        .map(|idx| {
+            (
+                idx,
+                losses[idx],
+                argmax(dataset.target.row(idx)),
+                argmax(predictions.row(idx)),
+            )
+        })
+        .collect()
+}
+
+/// An instance the model assigned to the wrong class
+#[derive(Clone, Debug)]
+pub struct MisclassifiedSample {
+    pub index: usize,
+    pub true_class: usize,
+    pub predicted_class: usize,
+    pub confidence: f64, // The predicted probability of the wrong class
+}
+
+/// Find the n misclassified instances with the most confident predictions, sorted by descending confidence
+/// These are the most deceptive instances (or mislabeled ones), unlike the hardest samples, which include
+/// instances that are correctly classified with a low confidence
+pub fn find_hardest_misclassifications(
+    model: &NeuralNet,
+    dataset: &Dataset,
+    n: usize,
+) -> Vec<MisclassifiedSample> {
+    let predictions = model.predict(&dataset.data.view());
+    let mut mistakes: Vec<MisclassifiedSample> = predictions
+        .axis_iter(Axis(0))
+        .zip(dataset.target.axis_iter(Axis(0)))
+        .enumerate()
+        .filter_map(|(index, (prediction, target))| {
+            let predicted_class = argmax(prediction);
+            let true_class = argmax(target);
+
+            (predicted_class != true_class).then_some(MisclassifiedSample {
+                index,
+                true_class,
+                predicted_class,
+                confidence: prediction[predicted_class],
+            })
+        })
+        .collect();
+
+    mistakes.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    mistakes.truncate(n);
+
+    mistakes
+}
+
+/// Threshold the probabilities of a multi-label model into binary predictions
+fn multilabel_predictions(predictions: &Array2<f64>, threshold: f64) -> Array2<bool> {
+    predictions.mapv(|p| p >= threshold)
+}
+
+/// Calculate the fraction of the instances whose labels are all predicted correctly (the exact match ratio)
+pub fn multilabel_accuracy(
+    predictions: &Array2<f64>,
+    targets: &Array2<f64>,
+    threshold: f64,
+) -> f64 {
+    let predictions = multilabel_predictions(predictions, threshold);
+    let num_correct = predictions
+        .axis_iter(Axis(0))
+        .zip(targets.axis_iter(Axis(0)))
+        .filter(|(prediction, target)| {
+            prediction
+                .iter()
+                .zip(target.iter())
+                .all(|(p, t)| *p == (*t == 1f64))
+        })
+        .count();
+
+    num_correct as f64 / predictions.nrows() as f64
+}
+
+/// Calculate the fraction of the labels that are predicted incorrectly
+pub fn multilabel_hamming_loss(
+    predictions: &Array2<f64>,
+    targets: &Array2<f64>,
+    threshold: f64,
+) -> f64 {
+    let predictions = multilabel_predictions(predictions, threshold);
+    let num_wrong = predictions
+        .iter()
+        .zip(targets.iter())
+        .filter(|(p, t)| **p != (**t == 1f64))
+        .count();
+
+    num_wrong as f64 / predictions.len() as f64
+}
+
+/// Calculate the mean of the F1 scores of the labels
+/// Labels that are never predicted and never present have an F1 score of 0
+pub fn multilabel_f1_macro(
+    predictions: &Array2<f64>,
+    targets: &Array2<f64>,
+    threshold: f64,
+) -> f64 {
+    let predictions = multilabel_predictions(predictions, threshold);
+    let f1_scores: Vec<f64> = predictions
+        .axis_iter(Axis(1))
+        .zip(targets.axis_iter(Axis(1)))
+        .map(|(prediction, target)| {
+            let (mut tp, mut fp, mut fn_) = (0f64, 0f64, 0f64);
+
+            for (p, t) in prediction.iter().zip(target.iter()) {
+                match (*p, *t == 1f64) {
+                    (true, true) => tp += 1f64,
+                    (true, false) => fp += 1f64,
+                    (false, true) => fn_ += 1f64,
+                    (false, false) => {}
+                }
+            }
+
+            if tp == 0f64 {
+                0f64
+            } else {
+                2f64 * tp / (2f64 * tp + fp + fn_)
+            }
+        })
+        .collect();
+
+    f1_scores.iter().sum::<f64>() / f1_scores.len() as f64
+}
+
+/// The mean Poisson deviance 2 * (y * log(y / mu) - (y - mu)) of predicted counts mu, where y * log(y / mu) is 0 for y = 0
+/// It's 0 for perfect predictions, and lower is better
+pub fn poisson_deviance(predictions: &Array1<f64>, targets: &Array1<f64>) -> f64 {
+    let total: f64 = predictions
+        .iter()
+        .zip(targets.iter())
+        .map(|(mu, y)| {
+            let log_ratio = if *y > 0f64 { y * (y / mu).ln() } else { 0f64 };
+
+            2f64 * (log_ratio - (y - mu))
+        })
+        .sum();
+
+    total / predictions.len() as f64
+}
+
+/// The index of the bin of x, where bins are the increasing edges of the bins. Values below the first edge are in
+/// the first bin, and values from the last edge on are in the last one
+fn digitize(x: f64, bins: &[f64]) -> usize {
+    let num_bins = bins.len().saturating_sub(1).max(1);
+
+    bins.get(1..)
+        .unwrap_or_default()
+        .iter()
+        .filter(|&&edge| x >= edge)
+        .count()
+        .min(num_bins - 1)
+}
+
+/// Class probabilities from the outputs of a regression model: the outputs of each instance are digitized into the
+/// bins (given by their increasing edges), and the fraction of its outputs in each bin is the probability of that
+/// class. With a single output, each instance gets a probability of 1 for the bin of its output
+pub fn regression_to_class_probs(regression_output: &Array2<f64>, bins: &[f64]) -> Array2<f64> {
+    let num_bins = bins.len().saturating_sub(1).max(1);
+    let mut probs = Array2::zeros((regression_output.nrows(), num_bins));
+
+    for (mut probs_row, output_row) in probs
+        .axis_iter_mut(Axis(0))
+        .zip(regression_output.axis_iter(Axis(0)))
+    {
+        for &x in output_row.iter() {
+            probs_row[digitize(x, bins)] += 1f64 / output_row.len() as f64;
+        }
+    }
+
+    probs
+}
@@ -1,10 +1,53 @@
 use ndarray::{Array2, ArrayView2};
 
-use crate::parsing::Dataset;
+use crate::parsing::{mnist, Dataset};
+use history::TrainingHistory;
 
+pub mod ablation;
+pub mod adversarial;
+pub mod anomaly;
+pub mod callback;
+pub mod clip;
+pub mod curriculum;
+pub mod ensemble;
+pub mod ewc;
+pub mod gan;
+pub mod gradient_flow;
+pub mod history;
+pub mod init;
+pub mod layer;
+pub mod loss;
+pub mod memory;
+pub mod metrics;
+pub mod multitask;
 pub mod neural_net;
+pub mod noise;
+pub mod onnx;
+pub mod optimizer;
+pub mod privacy;
+pub mod pruning;
+pub mod quantized;
+pub mod scheduler;
+pub mod search;
+pub mod tape;
+pub mod vae;
+pub mod variational_dropout;
+pub mod weight_norm;
 
 pub trait Model {
-    fn fit(&mut self, dataset: &Dataset, test_path: &str) -> Vec<(usize, f64)>;
+    fn fit(&mut self, dataset: &Dataset, validation: Option<&Dataset>) -> TrainingHistory;
     fn predict(&self, instance: &ArrayView2<f64>) -> Array2<f64>;
+
+    /// Parse the datasets from disk and fit the model to them
+    /// The validation set is only parsed once, before training starts
+    fn fit_from_paths(
+        &mut self,
+        train_path: &str,
+        validation_path: Option<&str>,
+    ) -> TrainingHistory {
+        let dataset = mnist::parse_dataset(train_path);
+        let validation = validation_path.map(mnist::parse_dataset);
+
+        self.fit(&dataset, validation.as_ref())
+    }
 }
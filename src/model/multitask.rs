@@ -0,0 +1,208 @@
+use ndarray::{s, Array2, ArrayView2, Axis};
+use std::fs::File;
+use std::io::Read;
+use std::time::Instant;
+
+use super::history::TrainingHistory;
+use super::neural_net::{activation, delta_activation, NeuralNet, Verbosity};
+use super::Model;
+use crate::error::{NeuralNetError, Result};
+use serde::Deserialize;
+
+/// A network with a backbone shared by several tasks, each of which has its own head
+/// The features the heads take are the outputs of the backbone after its activation function
+/// Each head has a name and a weight of its loss in the total loss of the net
+pub struct MultiTaskNet {
+    pub backbone: NeuralNet,
+    pub heads: Vec<(String, NeuralNet, f64)>,
+}
+
+/// The structure of a multi-task net. Each head maps the class of an instance to its own class through its label
+/// map, e.g. [0, 1, 0, 1, 0, 1, 0, 1, 0, 1] for the parity of a digit
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MultiTaskConfig {
+    pub backbone: Vec<usize>,
+    pub head_names: Vec<String>,
+    pub head_layers: Vec<Vec<usize>>, // The sizes of the layers of each head after the backbone
+    #[serde(default)]
+    pub loss_weights: Vec<f64>,
+    pub label_maps: Vec<Vec<usize>>,
+    #[serde(default = "default_num_epochs")]
+    pub num_epochs: usize,
+}
+
+fn default_num_epochs() -> usize {
+    10
+}
+
+impl MultiTaskConfig {
+    /// Parse a TOML config file, e.g.
+    /// backbone = [784, 500]
+    /// head_names = ["digit", "parity"]
+    /// head_layers = [[10], [2]]
+    pub fn from_file(path: &str) -> Result<MultiTaskConfig> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+
+        let mut config: MultiTaskConfig = toml::from_str(&contents)
+            .map_err(|err| NeuralNetError::Parse(format!("{}: {}", path, err)))?;
+        let num_heads = config.head_names.len();
+
+        if config.backbone.len() < 2
+            || config.head_layers.len() != num_heads
+            || config.label_maps.len() != num_heads
+            || config.head_layers.iter().any(Vec::is_empty)
+        {
+            return Err(NeuralNetError::Parse(format!(
+                "{}: The config needs a backbone, and the layers and label map of each head",
+                path
+            )));
+        }
+
+        // The losses of the heads are weighted equally by default
+        if config.loss_weights.is_empty() {
+            config.loss_weights = vec![1f64; num_heads];
+        }
+
+        Ok(config)
+    }
+
+    /// The targets of each head for instances of these classes
+    pub fn head_targets(&self, labels: &[usize]) -> Vec<Array2<f64>> {
+        self.label_maps
+            .iter()
+            .zip(self.head_layers.iter())
+            .map(|(label_map, layers)| {
+                let mut target = Array2::zeros((labels.len(), *layers.last().unwrap()));
+
+                for (idx, &label) in labels.iter().enumerate() {
+                    target[[idx, label_map[label]]] = 1f64;
+                }
+
+                target
+            })
+            .collect()
+    }
+}
+
+impl MultiTaskNet {
+    pub fn new(backbone: NeuralNet, heads: Vec<(String, NeuralNet, f64)>) -> MultiTaskNet {
+        MultiTaskNet { backbone, heads }
+    }
+
+    /// The shared features of the instances, i.e. the activated outputs of the backbone
+    pub fn features(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        self.backbone
+            .logits(inputs)
+            .mapv(|x| activation(&self.backbone.activation_function, x))
+    }
+
+    /// The predictions of each head, by its name
+    pub fn predict(&self, inputs: &ArrayView2<f64>) -> Vec<(String, Array2<f64>)> {
+        let features = self.features(inputs);
+
+        self.heads
+            .iter()
+            .map(|(name, head, _)| (name.clone(), head.predict(&features.view())))
+            .collect()
+    }
+
+    /// Train the net to minimize the weighted sum of the losses of the heads, given the targets of each head
+    /// The batch size is the batch size of the backbone, and each net is updated with its own LR and optimizer
+    /// The history holds the mean loss of the batches in each epoch (in bits)
+    pub fn fit(
+        &mut self,
+        data: &Array2<f64>,
+        targets: &[Array2<f64>],
+        n_epochs: usize,
+    ) -> TrainingHistory {
+        let mut history = TrainingHistory::new();
+        let start = Instant::now();
+        let batch_size = self.backbone.batch_size;
+
+        for epoch in 0..n_epochs {
+            let mut total_loss = 0f64;
+            let mut num_batches = 0;
+
+            for (batch_idx, batch) in data.axis_chunks_iter(Axis(0), batch_size).enumerate() {
+                let start = batch_idx * batch_size;
+                let batch_targets: Vec<ArrayView2<f64>> = targets
+                    .iter()
+                    .map(|target| target.slice(s![start..start + batch.nrows(), ..]))
+                    .collect();
+
+                total_loss += self.train_batch(&batch, &batch_targets);
+                num_batches += 1;
+            }
+
+            history.train_losses.push(total_loss / num_batches as f64);
+
+            if self.backbone.verbosity >= Verbosity::Epoch {
+                eprintln!(
+                    "[Epoch {}/{}] loss={:.4} elapsed={:.1}s",
+                    epoch + 1,
+                    n_epochs,
+                    history.train_losses.last().unwrap(),
+                    start.elapsed().as_secs_f64()
+                );
+            }
+        }
+
+        history
+    }
+
+    /// Perform a GD step of the backbone and the heads on a batch. Returns the loss of the batch before the step
+    /// The gradients that flow from the heads into the features are summed into the gradient of the backbone
+    fn train_batch(&mut self, batch: &ArrayView2<f64>, targets: &[ArrayView2<f64>]) -> f64 {
+        let (b_hidden, b_hidden_linear, b_dropout_masks) = self.backbone.forward(batch, true);
+        let b_linear = b_hidden.last().unwrap();
+        let features = b_linear.mapv(|x| activation(&self.backbone.activation_function, x));
+        let mut features_grad = Array2::zeros(features.dim());
+        let mut total_loss = 0f64;
+
+        for ((_, head, weight), target) in self.heads.iter_mut().zip(targets.iter()) {
+            let (h_hidden, h_hidden_linear, h_dropout_masks) = head.forward(&features.view(), true);
+            let logits = h_hidden.last().unwrap();
+            let loss = head.loss_function.loss(logits, target, &features.view());
+            let grad = *weight
+                * head
+                    .loss_function
+                    .gradient(logits, target, &features.view());
+            let (h_grads, input_grad) =
+                head.backward(&h_hidden, &h_hidden_linear, &h_dropout_masks, grad);
+
+            head.apply_gradients(&h_grads);
+            features_grad += &input_grad;
+            total_loss += *weight * loss;
+        }
+
+        let b_grad = features_grad
+            * b_linear.mapv(|x| delta_activation(&self.backbone.activation_function, x));
+        let (b_grads, _) =
+            self.backbone
+                .backward(&b_hidden, &b_hidden_linear, &b_dropout_masks, b_grad);
+
+        self.backbone.apply_gradients(&b_grads);
+
+        total_loss
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multitask_config_parses_the_example() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/multitask.toml");
+        let config = MultiTaskConfig::from_file(path).unwrap();
+
+        assert_eq!(config.backbone, vec![784, 500]);
+        assert_eq!(config.head_names, vec!["digit", "parity"]);
+        assert_eq!(config.head_layers, vec![vec![10], vec![2]]);
+        assert_eq!(config.loss_weights, vec![1.0, 0.5]);
+        assert_eq!(config.label_maps[1], vec![0, 1, 0, 1, 0, 1, 0, 1, 0, 1]);
+        assert_eq!(config.num_epochs, 10);
+    }
+}
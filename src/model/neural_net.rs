@@ -1,18 +1,101 @@
-use crate::parsing::{mnist::parse_dataset, Dataset};
-use ndarray::{Array, Array1, Array2, ArrayView1, ArrayView2, Axis};
-use rand::distributions::{Distribution, Uniform};
+use crate::error::{NeuralNetError, Result};
+use crate::parsing::loader::DataSource;
+use crate::parsing::{npy, CsvConfig, Dataset, PairedDataset, TripletDataset};
+use crate::preprocessing::{transform_from_json, Transform};
+use clap::ValueEnum;
+use json::object;
+use ndarray::{s, Array, Array1, Array2, ArrayView1, ArrayView2, Axis, CowArray, Ix2, Zip};
+use rand::distributions::{Bernoulli, Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
+use std::any::Any;
+use std::fs::File;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::callback::{
+    compute_update_to_weight_ratio, gradient_sign_agreement, snr_weight_updates, Callback,
+};
+use super::clip::GradientClip;
+use super::curriculum::{online_hard_example_mining, CurriculumScheduler};
+use super::ensemble::Ensemble;
+use super::ewc::{compute_fisher, EWC};
+use super::gan::{add_gradients, gradient_penalty_gradients};
+use super::history::TrainingHistory;
+use super::init::{self, compute_fan, variance_scaling, FanMode};
+use super::layer::{
+    DenseLayer, EmbeddingLayer, GradientBuffers, Layer, LayerCache, LayerGradients,
+    MixedPrecisionLayer,
+};
+use super::loss::{
+    center_loss_gradient, cross_entropy_from_logits, softmax_rows, CenterLoss, LossFunction,
+};
+use super::memory::{estimate_training_memory, format_bytes};
+use super::metrics::{argmax, confusion_matrix, per_sample_loss, EvaluationResult};
+use super::noise::NoiseLayer;
+use super::optimizer::{Optimizer, OptimizerState};
+use super::privacy::{add_gaussian_noise, clip_gradients, DPConfig};
+use super::pruning::PruningScheduler;
+use super::quantized::QuantizedNeuralNet;
+use super::scheduler::{LRScheduler, SGDRScheduler};
+use super::tape::{ForwardRecord, GradientTape};
+use super::variational_dropout::{VariationalDropout, VariationalDropoutLayer};
+use super::weight_norm::{init_weight_norm_from_dense, WeightNormLayer};
 use super::Model;
 
+/// The per-layer matrices produced by a forward pass (outputs, linear outputs, dropout masks)
+type Activations = Vec<Array2<f64>>;
+
+/// The gradients WRT the weight matrix and the bias vector of each layer
+pub type Gradients = Vec<LayerGradients>;
+
 /// Represents a neural net
 pub struct NeuralNet {
-    pub layers: Vec<(Array2<f64>, Array1<f64>)>, // Each layer holds a weight matrix and a bias vector
+    pub layers: Vec<Box<dyn Layer>>,
     pub num_epochs: Option<usize>, // If num_epochs is Some(number), we train the network for number epochs
     // Otherwise, if it is None, early stopping is used
     pub batch_size: usize, // Training hyperparams
     pub learning_rate: f64,
     pub activation_function: ActivationFunction,
-    pub epsilon: f64, // Tolerance for early stopping.
+    pub output_activation: ActivationFunction, // Applied to the outputs in predict. Linear uses the softmax (or sigmoid) of the task
+    pub early_stopping_epsilon: f64, // Early stopping ends training once the loss improves by less than this
+    pub dropout_rate: f64, // Probability of dropping each hidden unit during training. 0 disables dropout
+    pub temperature: f64, // The logits are divided by the temperature before the softmax in predict
+    pub loss_function: LossFunction,
+    pub frozen_layers: Vec<bool>, // The weights of frozen layers aren't updated during training
+    pub masks: Vec<Array2<bool>>, // Pruned weights are false in the mask, and stay zero during training
+    pub lr_scheduler: Option<Box<dyn LRScheduler>>, // If set, the LR of each batch is taken from the scheduler
+    pub optimizer: Optimizer,
+    pub optimizer_state: OptimizerState, // Kept between batches (and calls to fit)
+    pub verbosity: Verbosity,
+    pub task: Task,
+    pub spectral_norm: Option<SpectralNormConfig>, // If set, the weights are spectrally normalized after every step
+    spectral_u: Vec<Array1<f64>>, // The estimated top left singular vector of each layer, reused between steps
+    prev_weight_grads: Vec<Array2<f64>>, // The weight gradients of the previous step, kept at the Batch verbosity
+    sign_agreement: Vec<f64>, // The gradient sign agreement of each layer in the last step, logged at the Batch verbosity
+    gradient_buffers: Option<GradientBuffers>, // Reused by the training steps, and reallocated when the shapes change
+    pub reuse_gradient_buffers: bool, // If false, every training step allocates new gradients instead of using the buffers
+    pub weight_norm: Option<Vec<WeightNormLayer>>, // If set, training updates these parameters instead of the weights
+    pub variational_dropout: Option<VariationalDropout>, // If set, the weights are multiplied by learned noise during training
+    pub differential_privacy: Option<DPConfig>, // If set, the network is trained with DP-SGD
+    pub ewc: Option<EWC>, // If set, the weights are kept close to the weights learned for the previous task
+    pub input_scaler: Option<Box<dyn Transform>>, // If set, it's applied to the inputs of inference (but not of training)
+    pub stochastic_depth_rates: Vec<f64>, // The probability that each layer is skipped in a training pass. Empty disables it
+    pub mixed_precision: bool, // If set, the dense layers store their parameters as f32 (see with_mixed_precision)
+    pub gradient_clip: Option<GradientClip>, // If set, the gradients are clipped before every update
+    pub ohem_ratio: Option<f64>, // If set, each batch is trained on only this fraction of its instances with the highest loss
+    pub pruning_scheduler: Option<PruningScheduler>, // If set, the net is gradually pruned during training
+    pub center_loss: Option<CenterLoss>, // If set, it's added to the loss (except with DP-SGD)
+    pub gradient_checkpointing: Option<usize>, // If set, training only stores the activations of every this many layers. It can't be used with DP-SGD or the center loss
+    pub lr_multipliers: Vec<f64>, // The LR of each layer is the LR of the net times its multiplier
+    pub callbacks: Vec<Box<dyn Callback>>,
+    pub noise: Option<NoiseLayer>, // If set, noise is added to the outputs of the hidden layers during training
+    pub training_mode: bool, // If false, dropout and noise are disabled even in training passes (evaluation mode)
+    tape: Mutex<Option<GradientTape>>, // Records the forward passes while with_tape is running
+    rng: Mutex<StdRng>, // Used for all random sampling (e.g. dropout masks). Behind a mutex because inference takes &self
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -30,6 +113,262 @@ pub enum InitMethod {
     Xavier,
 }
 
+/// The kind of problem the network solves, which determines how its outputs are turned into probabilities
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Task {
+    /// Each instance has exactly one class: the probabilities are the softmax of the outputs
+    #[default]
+    Multiclass,
+    /// Each instance can have any number of labels: every output is a separate sigmoid probability
+    Multilabel,
+    /// The network reconstructs its inputs: the outputs are used as they are
+    Autoencoder,
+}
+
+/// How much progress is logged (to stderr) during training. Each level also logs everything the previous levels log
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Silent,
+    /// The losses, LR and elapsed time after each epoch
+    #[default]
+    Epoch,
+    /// The loss of each batch
+    Batch,
+    /// The norms of the gradients and statistics of the weights of each layer after each batch
+    Debug,
+}
+
+/// Builds neural nets. Hyperparams that aren't set explicitly use the same defaults as the CLI
+#[derive(Clone)]
+pub struct NeuralNetBuilder {
+    layer_structure: Vec<usize>,
+    num_epochs: Option<usize>,
+    batch_size: usize,
+    learning_rate: f64,
+    activation_function: ActivationFunction,
+    init_method: InitMethod,
+    early_stopping_epsilon: f64,
+    dropout_rate: f64,
+    loss_function: LossFunction,
+    optimizer: Optimizer,
+    seed: Option<u64>, // Seed of the RNG of the net (including the weight initialization). If it is None, it's seeded randomly
+    verbosity: Verbosity,
+    task: Task,
+}
+
+impl Default for NeuralNetBuilder {
+    fn default() -> Self {
+        NeuralNetBuilder::for_mnist()
+    }
+}
+
+/// Spectral normalization (Miyato et al. 2018) divides each weight matrix by its largest singular value
+/// The singular value is estimated with power iteration
+#[derive(Clone, Debug)]
+pub struct SpectralNormConfig {
+    pub n_power_iterations: usize, // The vectors are reused between steps, so a few iterations are enough
+}
+
+impl Default for SpectralNormConfig {
+    fn default() -> Self {
+        SpectralNormConfig {
+            n_power_iterations: 3,
+        }
+    }
+}
+
+/// The results of the LR range test
+pub struct LRFinderResult {
+    pub lrs: Vec<f64>,
+    pub losses: Vec<f64>, // The loss of the batch that was trained with each LR
+    pub optimal_lr: f64,  // The LR at which the loss decreases the fastest
+}
+
+/// Everything a training step changes, so that a trial run (e.g. the LR range test) can be undone
+struct TrainingState {
+    layers: Vec<Box<dyn Layer>>,
+    learning_rate: f64,
+    masks: Vec<Array2<bool>>,
+    optimizer_state: OptimizerState,
+    spectral_u: Vec<Array1<f64>>,
+    prev_weight_grads: Vec<Array2<f64>>,
+    sign_agreement: Vec<f64>,
+    weight_norm: Option<Vec<WeightNormLayer>>,
+    variational_dropout: Option<VariationalDropout>,
+    pruning_scheduler: Option<PruningScheduler>,
+    center_loss: Option<CenterLoss>,
+    callbacks: Vec<Box<dyn Callback>>,
+    rng: StdRng,
+}
+
+impl NeuralNetBuilder {
+    pub fn new(layer_structure: Vec<usize>) -> NeuralNetBuilder {
+        NeuralNetBuilder {
+            layer_structure,
+            num_epochs: None,
+            batch_size: 50,
+            learning_rate: 0.01,
+            activation_function: ActivationFunction::ReLU,
+            init_method: InitMethod::Xavier,
+            early_stopping_epsilon: 0.0001,
+            dropout_rate: 0f64,
+            loss_function: LossFunction::CrossEntropy,
+            optimizer: Optimizer::SGD,
+            seed: None,
+            verbosity: Verbosity::default(),
+            task: Task::default(),
+        }
+    }
+
+    /// A builder pre-configured for MNIST classification
+    pub fn for_mnist() -> NeuralNetBuilder {
+        // One hidden layer of 128 units is enough for MNIST, and it trains quickly
+        NeuralNetBuilder::new(vec![784, 128, 10])
+            // A small LR keeps training stable without tuning
+            .learning_rate(0.001)
+            // Small batches give more updates per epoch, which makes up for the small LR
+            .batch_size(32)
+            // A fixed number of epochs makes runs predictable, unlike early stopping
+            .num_epochs(Some(50))
+            // ReLU doesn't saturate, so the gradients don't vanish
+            .activation_function(ActivationFunction::ReLU)
+            // Xavier init keeps the scale of the activations similar between layers
+            .init_method(InitMethod::Xavier)
+    }
+
+    pub fn num_epochs(mut self, num_epochs: Option<usize>) -> NeuralNetBuilder {
+        self.num_epochs = num_epochs;
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> NeuralNetBuilder {
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn learning_rate(mut self, learning_rate: f64) -> NeuralNetBuilder {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    pub fn activation_function(
+        mut self,
+        activation_function: ActivationFunction,
+    ) -> NeuralNetBuilder {
+        self.activation_function = activation_function;
+        self
+    }
+
+    pub fn init_method(mut self, init_method: InitMethod) -> NeuralNetBuilder {
+        self.init_method = init_method;
+        self
+    }
+
+    pub fn early_stopping_epsilon(mut self, early_stopping_epsilon: f64) -> NeuralNetBuilder {
+        self.early_stopping_epsilon = early_stopping_epsilon;
+        self
+    }
+
+    pub fn dropout_rate(mut self, dropout_rate: f64) -> NeuralNetBuilder {
+        self.dropout_rate = dropout_rate;
+        self
+    }
+
+    pub fn loss_function(mut self, loss_function: LossFunction) -> NeuralNetBuilder {
+        self.loss_function = loss_function;
+        self
+    }
+
+    pub fn optimizer(mut self, optimizer: Optimizer) -> NeuralNetBuilder {
+        self.optimizer = optimizer;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> NeuralNetBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn verbosity(mut self, verbosity: Verbosity) -> NeuralNetBuilder {
+        self.verbosity = verbosity;
+        self
+    }
+
+    pub fn task(mut self, task: Task) -> NeuralNetBuilder {
+        self.task = task;
+        self
+    }
+
+    /// Set the input dimension (the first element of the layer structure) to the number of features of the dataset
+    pub fn infer_input_from(mut self, dataset: &Dataset) -> NeuralNetBuilder {
+        if let Some(input_dim) = self.layer_structure.first_mut() {
+            *input_dim = NeuralNet::infer_input_shape(dataset);
+        }
+
+        self
+    }
+
+    /// Construct the neural net, initializing its weights according to the init method
+    pub fn build(&self) -> NeuralNet {
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let layers = match self.init_method {
+            InitMethod::Default => init_layers_default(&self.layer_structure, &mut rng),
+            InitMethod::Xavier => init_layers_xavier(&self.layer_structure, &mut rng),
+        };
+        let masks = layers
+            .iter()
+            .map(|layer| Array2::from_elem(layer.weights().dim(), true))
+            .collect();
+
+        NeuralNet {
+            layers,
+            num_epochs: self.num_epochs,
+            batch_size: self.batch_size,
+            learning_rate: self.learning_rate,
+            activation_function: self.activation_function.clone(),
+            output_activation: ActivationFunction::Linear,
+            early_stopping_epsilon: self.early_stopping_epsilon,
+            dropout_rate: self.dropout_rate,
+            temperature: 1f64,
+            loss_function: self.loss_function.clone(),
+            frozen_layers: vec![false; layer_structure_len(&self.layer_structure)],
+            masks,
+            lr_scheduler: None,
+            optimizer: self.optimizer.clone(),
+            optimizer_state: OptimizerState::new(),
+            verbosity: self.verbosity,
+            task: self.task,
+            spectral_norm: None,
+            spectral_u: vec![],
+            prev_weight_grads: vec![],
+            sign_agreement: vec![],
+            gradient_buffers: None,
+            reuse_gradient_buffers: true,
+            weight_norm: None,
+            variational_dropout: None,
+            differential_privacy: None,
+            ewc: None,
+            input_scaler: None,
+            stochastic_depth_rates: vec![],
+            mixed_precision: false,
+            gradient_clip: None,
+            ohem_ratio: None,
+            pruning_scheduler: None,
+            center_loss: None,
+            gradient_checkpointing: None,
+            lr_multipliers: vec![1f64; layer_structure_len(&self.layer_structure)],
+            callbacks: vec![],
+            noise: None,
+            training_mode: true,
+            tape: Mutex::new(None),
+            rng: Mutex::new(rng),
+        }
+    }
+}
+
 impl NeuralNet {
     /// Construct a new neural net according to the specified hyperparams
     pub fn new(
@@ -39,301 +378,3698 @@ impl NeuralNet {
         learning_rate: f64,
         activation_function: ActivationFunction,
         init_method: InitMethod,
-        epsilon: f64,
+        early_stopping_epsilon: f64,
     ) -> NeuralNet {
-        let layers = match init_method {
-            InitMethod::Default => init_layers_default(&layer_structure),
-            InitMethod::Xavier => init_layers_xavier(&layer_structure),
+        NeuralNetBuilder::new(layer_structure)
+            .num_epochs(num_epochs)
+            .batch_size(batch_size)
+            .learning_rate(learning_rate)
+            .activation_function(activation_function)
+            .init_method(init_method)
+            .early_stopping_epsilon(early_stopping_epsilon)
+            .build()
+    }
+
+    /// Construct a new neural net for multi-label classification with n_labels labels (the size of the output layer)
+    /// Each output is a separate sigmoid probability, and the network is trained with the binary cross-entropy
+    pub fn new_multilabel(layer_structure: Vec<usize>, n_labels: usize) -> NeuralNet {
+        assert_eq!(
+            layer_structure.last(),
+            Some(&n_labels),
+            "The output layer must have a unit for each label"
+        );
+
+        NeuralNetBuilder::new(layer_structure)
+            .task(Task::Multilabel)
+            .loss_function(LossFunction::BinaryCrossEntropy)
+            .build()
+    }
+
+    /// Re-seed the RNG used for random sampling during training and inference (e.g. dropout masks)
+    /// The weights are already initialized by now, so use NeuralNetBuilder::seed to also make the initialization reproducible
+    pub fn with_seed(self, seed: u64) -> NeuralNet {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Train the network with dropout applied to the outputs of the hidden layers
+    pub fn with_dropout(mut self, dropout_rate: f64) -> NeuralNet {
+        self.dropout_rate = dropout_rate;
+
+        self
+    }
+
+    /// Apply act to the outputs in predict instead of the softmax (or sigmoid) of the task, e.g. Sigmoid for
+    /// binary classification. A Linear output keeps the softmax of the task, unless the loss is MSE or log-cosh
+    /// (regression), a hinge loss or a custom loss, in which case the raw outputs are predicted, or Poisson, which
+    /// predicts their softplus. The losses are still computed from the raw outputs
+    pub fn with_output_activation(mut self, act: ActivationFunction) -> NeuralNet {
+        self.output_activation = act;
+
+        self
+    }
+
+    /// Turn the (temperature-scaled) raw outputs into predictions according to the output activation
+    fn apply_output_activation(&self, scores: Array2<f64>) -> Array2<f64> {
+        match (&self.output_activation, &self.loss_function, self.task) {
+            (ActivationFunction::Linear, LossFunction::MSE | LossFunction::LogCosh, _) => scores,
+            // The scores of a model trained with a hinge loss aren't logits
+            (
+                ActivationFunction::Linear,
+                LossFunction::Hinge | LossFunction::CategoricalHinge,
+                _,
+            ) => scores,
+            // A custom loss is computed on the raw outputs, so they're what it trains the model to predict
+            (ActivationFunction::Linear, LossFunction::Custom(_), _) => scores,
+            // Counts are positive
+            (ActivationFunction::Linear, LossFunction::Poisson, _) => scores.mapv(softplus),
+            (ActivationFunction::Linear, _, Task::Multiclass) => softmax_rows(&scores),
+            (ActivationFunction::Linear, _, Task::Multilabel) => scores.mapv(sigmoid),
+            (ActivationFunction::Linear, _, Task::Autoencoder) => scores,
+            (act, _, _) => scores.mapv(|x| activation(act, x)),
+        }
+    }
+
+    /// Scale the inputs of inference with a scaler (e.g. the one the training set was standardized with), so that the
+    /// model can be used on unscaled instances. The scaler is saved with the model
+    pub fn with_input_scaler(mut self, scaler: Box<dyn Transform>) -> NeuralNet {
+        self.input_scaler = Some(scaler);
+
+        self
+    }
+
+    /// Detach the input scaler and return it
+    pub fn remove_input_scaler(&mut self) -> Option<Box<dyn Transform>> {
+        self.input_scaler.take()
+    }
+
+    /// Predict the probabilities of a single instance
+    pub fn predict_single(&self, input: &ArrayView1<f64>) -> Array1<f64> {
+        self.predict(&input.view().insert_axis(Axis(0)))
+            .row(0)
+            .to_owned()
+    }
+
+    /// A description of the layers of the network and how it transforms its inputs and outputs
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+
+        for (idx, layer) in self.layers.iter().enumerate() {
+            let (fan_in, fan_out) = compute_fan(&layer.weights().view());
+
+            summary += &format!(
+                "Layer {}: {} -> {} ({} parameters)\n",
+                idx,
+                fan_in,
+                fan_out,
+                layer.parameter_count()
+            );
+        }
+
+        summary += &format!(
+            "Total parameters: {}\nActivation: {:?}\nOutput activation: {:?}\nTask: {:?}\nInput scaler: {}\n",
+            self.layers.iter().map(|layer| layer.parameter_count()).sum::<usize>(),
+            self.activation_function,
+            self.output_activation,
+            self.task,
+            if self.input_scaler.is_some() { "attached" } else { "none" }
+        );
+
+        let layer_structure: Vec<usize> = self
+            .layers
+            .first()
+            .map(|layer| layer.weights().nrows())
+            .into_iter()
+            .chain(self.layers.iter().map(|layer| layer.weights().ncols()))
+            .collect();
+        let memory = estimate_training_memory(&layer_structure, self.batch_size, &self.optimizer);
+
+        summary += &format!(
+            "Training memory (batch size {}): weights {}, activations {}, gradients {}, optimizer state {}, total {}\n",
+            self.batch_size,
+            format_bytes(memory.weights_bytes),
+            format_bytes(memory.activations_bytes),
+            format_bytes(memory.gradients_bytes),
+            format_bytes(memory.optimizer_state_bytes),
+            format_bytes(memory.total_bytes)
+        );
+
+        summary
+    }
+
+    /// A Graphviz DOT description of the network, with a node for each layer (and for the dropout after each hidden
+    /// layer, if it's enabled) and edges that follow the data from the input to the output
+    pub fn to_dot(&self) -> String {
+        let nodes = self.graph_nodes();
+        let mut dot = String::from("digraph network {\n    rankdir=TB;\n    node [shape=box];\n");
+
+        for (id, label) in nodes.iter() {
+            dot += &format!("    {} [label=\"{}\"];\n", id, label.join("\\n"));
+        }
+
+        for (prev, next) in nodes.iter().zip(nodes.iter().skip(1)) {
+            dot += &format!("    {} -> {};\n", prev.0, next.0);
+        }
+
+        dot + "}\n"
+    }
+
+    /// Save the DOT description of the network, which can be rendered with e.g. dot -Tpng model.dot -o model.png
+    pub fn save_dot(&self, path: &str) -> Result<()> {
+        File::create(path)?.write_all(self.to_dot().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// The same graph as to_dot as a Mermaid flowchart, which GitHub renders in markdown
+    pub fn to_mermaid(&self) -> String {
+        let nodes = self.graph_nodes();
+        let mut mermaid = String::from("flowchart TD\n");
+
+        for (id, label) in nodes.iter() {
+            mermaid += &format!("    {}[\"{}\"]\n", id, label.join("<br/>"));
+        }
+
+        for (prev, next) in nodes.iter().zip(nodes.iter().skip(1)) {
+            mermaid += &format!("    {} --> {}\n", prev.0, next.0);
+        }
+
+        mermaid
+    }
+
+    /// The ID and the lines of the label of each node of the graph of the network, in the order of the data flow
+    fn graph_nodes(&self) -> Vec<(String, Vec<String>)> {
+        // The inputs of an embedding are indices, so their dimension isn't known
+        let input_label = match self.layers.first().and_then(|layer| layer.input_dim()) {
+            Some(input_dim) => format!("Input ({})", input_dim),
+            None => "Input".to_string(),
         };
+        let mut nodes = vec![("input".to_string(), vec![input_label])];
 
-        NeuralNet {
-            layers,
-            num_epochs,
-            batch_size,
-            learning_rate,
-            activation_function,
-            epsilon,
+        for (idx, layer) in self.layers.iter().enumerate() {
+            let kind = layer.kind();
+            let (fan_in, fan_out) = compute_fan(&layer.weights().view());
+            let activation = if idx + 1 == self.layers.len() {
+                &self.output_activation
+            } else {
+                &self.activation_function
+            };
+
+            nodes.push((
+                format!("layer{}", idx),
+                vec![
+                    format!("{}{} {}", kind[..1].to_uppercase(), &kind[1..], idx),
+                    format!("{} -> {}", fan_in, fan_out),
+                    format!("activation: {:?}", activation),
+                    format!("{} parameters", layer.parameter_count()),
+                ],
+            ));
+
+            if self.dropout_rate > 0f64 && idx + 1 < self.layers.len() {
+                nodes.push((
+                    format!("dropout{}", idx),
+                    vec!["Dropout".to_string(), format!("p = {}", self.dropout_rate)],
+                ));
+            }
         }
+
+        nodes.push(("output".to_string(), vec!["Output".to_string()]));
+
+        nodes
+    }
+
+    /// Add noise to the outputs of the hidden layers during training
+    pub fn with_noise(mut self, noise: NoiseLayer) -> NeuralNet {
+        self.noise = Some(noise);
+
+        self
     }
 
     // Perform a forward pass of the network on some input.
     // Returns the outputs of the hidden layers, and the non-activated outputs of the hidden layers (used for backprop)
-    fn forward(&self, inputs: &ArrayView2<f64>) -> (Vec<Array2<f64>>, Vec<Array2<f64>>) {
+    // If training is set (and the net is in training mode), noise is added to the outputs of the hidden layers,
+    // then they are randomly dropped, and the scaled dropout masks are returned as well
+    pub(super) fn forward(
+        &self,
+        inputs: &ArrayView2<f64>,
+        training: bool,
+    ) -> (Activations, Activations, Activations) {
         let mut hidden = vec![];
         let mut hidden_linear = vec![];
+        let mut dropout_masks = vec![];
+        let mut rng = self.rng.lock().unwrap();
         // The first layer is a passthrough layer, so it outputs whatever its input is
         hidden.push(inputs.to_owned());
 
-        // We iterate for every layer
-        let mut it = self.layers.iter().peekable();
-
-        // Iterate over the layers
-        while let Some(layer) = it.next() {
-            // The output of the layer without applying the activation function
-            let lin_output = hidden.last().unwrap().dot(&layer.0) + &layer.1;
-            // The real output of the layer - If the layer is a hidden layer, we apply the activation function
-            // and otherwise (this is the output layer) the output is the same as the linear output
-            let real_output = lin_output.map(|x| match it.peek() {
-                Some(_) => activation(&self.activation_function, *x),
-                None => *x,
-            });
+        for idx in 0..self.layers.len() {
+            let (real_output, lin_output, mask) =
+                self.forward_layer(idx, hidden.last().unwrap(), training, &mut rng);
 
             hidden.push(real_output);
             hidden_linear.push(lin_output);
+            dropout_masks.extend(mask);
+        }
+
+        if let Some(tape) = self.tape.lock().unwrap().as_mut() {
+            tape.record(ForwardRecord {
+                hidden: hidden.clone(),
+                hidden_linear: hidden_linear.clone(),
+                dropout_masks: dropout_masks.clone(),
+            });
         }
 
-        (hidden, hidden_linear)
+        (hidden, hidden_linear, dropout_masks)
     }
 
-    /// Calculate the gradients using backprop and perform a GD step
-    fn backward_and_update(
-        &mut self,
-        hidden: Vec<Array2<f64>>,
-        hidden_linear: Vec<Array2<f64>>,
+    // Perform the forward pass of a single layer on its input
+    // Returns the real output of the layer, its non-activated output and its dropout mask (if dropout was applied)
+    fn forward_layer(
+        &self,
+        idx: usize,
+        input: &Array2<f64>,
+        training: bool,
+        rng: &mut StdRng,
+    ) -> (Array2<f64>, Array2<f64>, Option<Array2<f64>>) {
+        let is_hidden = idx + 1 < self.layers.len();
+        let training = training && self.training_mode;
+        let skip_rate = self
+            .stochastic_depth_rates
+            .get(idx)
+            .copied()
+            .unwrap_or(0f64);
+
+        // A layer skipped by stochastic depth outputs its input, and has an empty linear output
+        if training && skip_rate > 0f64 && rng.gen::<f64>() < skip_rate {
+            let mask = (self.dropout_rate > 0f64).then(|| Array2::ones(input.dim()));
+
+            return (input.clone(), Array2::zeros((input.nrows(), 0)), mask);
+        }
+
+        // The output of the layer without applying the activation function
+        let (lin_output, _) = self.layers[idx].forward(input, training);
+
+        // The output layer's real output is the same as its linear output
+        if !is_hidden {
+            return (lin_output.clone(), lin_output, None);
+        }
+
+        let mut real_output = lin_output.map(|x| activation(&self.activation_function, *x));
+
+        if let Some(noise) = self.noise.as_ref().filter(|_| training) {
+            // The noise is additive, so the gradient flows through it unchanged
+            real_output += &noise.sample(real_output.dim(), rng);
+        }
+
+        if !training || self.dropout_rate <= 0f64 {
+            return (real_output, lin_output, None);
+        }
+
+        let mask = dropout_mask(real_output.dim(), self.dropout_rate, rng);
+        real_output *= &mask;
+
+        (real_output, lin_output, Some(mask))
+    }
+
+    /// Calculate the gradients using backprop
+    /// Returns the gradients WRT the weights and biases of each layer, and the gradient WRT the inputs
+    pub(super) fn backward(
+        &self,
+        hidden: &Activations,
+        hidden_linear: &Activations,
+        dropout_masks: &Activations,
         grad: Array2<f64>,
-    ) {
+    ) -> (Gradients, Array2<f64>) {
+        self.backward_with_feature_grad(hidden, hidden_linear, dropout_masks, grad, None)
+    }
+
+    /// Backprop like backward, where feature_grad is the gradient of an auxiliary loss WRT the input of the
+    /// output layer (e.g. the center loss), and is added to the gradient that flows into it
+    fn backward_with_feature_grad(
+        &self,
+        hidden: &Activations,
+        hidden_linear: &Activations,
+        dropout_masks: &Activations,
+        grad: Array2<f64>,
+        feature_grad: Option<Array2<f64>>,
+    ) -> (Gradients, Array2<f64>) {
         // The gradient WRT the current layer
         let mut grad_help = grad;
+        let mut grads = vec![];
 
         for idx in (0..self.layers.len()).rev() {
-            // If we aren't at the last layer, we need to change the gradient
-            if idx != self.layers.len() - 1 {
-                let step_mat =
-                    hidden_linear[idx].map(|x| delta_activation(&self.activation_function, *x));
-                grad_help = grad_help * step_mat;
+            let (layer_grads, input_grad) = self.backward_layer(
+                idx,
+                &hidden[idx],
+                &hidden_linear[idx],
+                dropout_masks.get(idx),
+                grad_help,
+            );
+
+            grads.push(layer_grads);
+            grad_help = input_grad;
+
+            if idx == self.layers.len() - 1 {
+                if let Some(feature_grad) = &feature_grad {
+                    grad_help += feature_grad;
+                }
             }
+        }
 
-            // Gradient WRT the weights in the current layer
-            let weight_grad = hidden[idx].t().dot(&grad_help);
-            // Gradient WRT the biases in the current layer
-            let bias_grad = &grad_help.mean_axis(Axis(0)).unwrap();
+        grads.reverse();
 
-            // Perform GD step
-            let new_weights = &self.layers[idx].0 - self.learning_rate * weight_grad;
-            let new_biases = &self.layers[idx].1 - self.learning_rate * bias_grad;
+        // After the first layer, the helper variable holds the gradient WRT the inputs
+        (grads, grad_help)
+    }
 
-            // Update the helper variable
-            grad_help = grad_help.dot(&self.layers[idx].0.t());
+    /// Reuse the gradient buffers between training steps (the default), or allocate the gradients on every step
+    pub fn with_gradient_buffers(mut self, enabled: bool) -> NeuralNet {
+        self.reuse_gradient_buffers = enabled;
 
-            self.layers[idx] = (new_weights, new_biases);
+        if !enabled {
+            self.gradient_buffers = None;
         }
+
+        self
     }
 
-    fn fit_net_static(
-        &mut self,
-        dataset: &Dataset,
-        test_path: &str,
-        num_epochs: usize,
-    ) -> Vec<(usize, f64)> {
-        let mut losses = vec![];
+    /// Allocate the buffers of the gradients of a training step on batches of up to batch_size instances
+    pub fn preallocate_buffers(&self, batch_size: usize) -> GradientBuffers {
+        GradientBuffers {
+            weight_grads: self
+                .layers
+                .iter()
+                .map(|layer| Array2::zeros(layer.weights().dim()))
+                .collect(),
+            bias_grads: self
+                .layers
+                .iter()
+                .map(|layer| Array1::zeros(layer.biases().len()))
+                .collect(),
+            // The output of each hidden layer is the input of the next one
+            activations: self
+                .layers
+                .iter()
+                .skip(1)
+                .map(|next| Array2::zeros((batch_size, next.weights().nrows())))
+                .collect(),
+        }
+    }
 
-        for num_epoch in 0..num_epochs {
-            // Get a batch of instances and their targets
-            for (input_batch, target_batch) in dataset
-                .data
-                .axis_chunks_iter(Axis(0), self.batch_size)
-                .zip(dataset.target.axis_chunks_iter(Axis(0), self.batch_size))
-            {
-                let (hidden, hidden_linear) = self.forward(&input_batch);
+    /// Backprop like backward, writing the gradients into the buffers instead of allocating them on every step
+    /// The gradient WRT the inputs of the network isn't computed, since training doesn't need it
+    fn backward_buffered(
+        &self,
+        hidden: &Activations,
+        hidden_linear: &Activations,
+        dropout_masks: &Activations,
+        grad: Array2<f64>,
+        buffers: &mut GradientBuffers,
+    ) -> Gradients {
+        let num_rows = grad.nrows();
+        let mut loss_grad = grad;
+
+        for idx in (0..self.layers.len()).rev() {
+            // The buffer of the gradient WRT the output of a layer is the input gradient buffer of the next one
+            let (lower, upper) = buffers.activations.split_at_mut(idx);
+            let mut output_grad = match upper.first_mut() {
+                Some(buffer) => buffer.slice_mut(s![..num_rows, ..]),
+                None => loss_grad.view_mut(),
+            };
+            let input_grad = lower
+                .last_mut()
+                .map(|buffer| buffer.slice_mut(s![..num_rows, ..]));
+            let lin_output = &hidden_linear[idx];
 
-                let scores = hidden.last().unwrap();
-                let mut predictions = Array::zeros((0, scores.ncols()));
+            // A skipped layer passes the gradient to its input unchanged, and its parameters get no gradient
+            if lin_output.ncols() == 0 {
+                buffers.weight_grads[idx].fill(0f64);
+                buffers.bias_grads[idx].fill(0f64);
 
-                // Construct softmax matrix
-                for row in scores.axis_iter(Axis(0)) {
-                    predictions.push_row(softmax(row).view()).unwrap();
+                if let Some(mut input_grad) = input_grad {
+                    input_grad.assign(&output_grad);
                 }
 
-                // Gradient is initialized to the gradient of the loss WRT the output layer
-                let grad = predictions - target_batch;
+                continue;
+            }
+
+            if idx != self.layers.len() - 1 {
+                Zip::from(&mut output_grad)
+                    .and(lin_output)
+                    .for_each(|grad, &x| *grad *= delta_activation(&self.activation_function, x));
 
-                self.backward_and_update(hidden, hidden_linear, grad);
+                if let Some(mask) = dropout_masks.get(idx) {
+                    output_grad *= mask;
+                }
             }
 
-                let loss = test_loss(self, test_path);
-                losses.push((num_epoch, loss));
+            self.layers[idx].backward_into(
+                &output_grad.view(),
+                &hidden[idx],
+                &mut buffers.weight_grads[idx],
+                &mut buffers.bias_grads[idx],
+                input_grad,
+            );
         }
 
-        losses
+        buffers.take_gradients()
     }
 
-    fn fit_net_dynamic(
-        &mut self,
-        dataset: &Dataset,
-        test_path: &str,
-        tolerance: f64,
-    ) -> Vec<(usize, f64)> {
-        let mut prev_loss;
-        let mut curr_loss = f64::INFINITY;
-        let mut losses = vec![];
-        let mut num_epoch = 0;
-
-        loop {
-            for (input_batch, target_batch) in dataset
-                .data
-                .axis_chunks_iter(Axis(0), self.batch_size)
-                .zip(dataset.target.axis_chunks_iter(Axis(0), self.batch_size))
-            {
-                let (hidden, hidden_linear) = self.forward(&input_batch);
+    /// Backprop through a single layer, given its input, its non-activated output, and the gradient WRT its output
+    /// Returns the gradients WRT the weights and biases of the layer, and the gradient WRT its input
+    fn backward_layer(
+        &self,
+        idx: usize,
+        input: &Array2<f64>,
+        lin_output: &Array2<f64>,
+        dropout_mask: Option<&Array2<f64>>,
+        output_grad: Array2<f64>,
+    ) -> (LayerGradients, Array2<f64>) {
+        let mut grad = output_grad;
 
-                let scores = hidden.last().unwrap();
-                let mut predictions = Array::zeros((0, scores.ncols()));
+        // A skipped layer passes the gradient to its input unchanged, and its parameters get no gradient
+        if lin_output.ncols() == 0 {
+            let layer = &self.layers[idx];
 
-                // Construct softmax matrix
-                for row in scores.axis_iter(Axis(0)) {
-                    predictions.push_row(softmax(row).view()).unwrap();
-                }
+            return (
+                (
+                    Array2::zeros(layer.weights().dim()),
+                    Array1::zeros(layer.biases().len()),
+                ),
+                grad,
+            );
+        }
 
-                // Gradient is initialized to the gradient of the loss WRT the output layer
-                let grad = predictions - target_batch;
+        // If we aren't at the last layer, we need to change the gradient
+        if idx != self.layers.len() - 1 {
+            let step_mat = lin_output.map(|x| delta_activation(&self.activation_function, *x));
+            grad = grad * step_mat;
 
-                self.backward_and_update(hidden, hidden_linear, grad);
+            // Dropped units don't contribute to the output, so they don't get any gradient
+            if let Some(mask) = dropout_mask {
+                grad *= mask;
             }
+        }
 
-            let loss = test_loss(self, test_path);
-            losses.push((num_epoch, loss));
+        let cache = LayerCache {
+            input: input.clone(),
+        };
+        let (input_grad, layer_grads) = self.layers[idx].backward(&grad, &cache);
 
-            prev_loss = curr_loss;
-            curr_loss = loss;
-            num_epoch += 1;
+        (layer_grads, input_grad)
+    }
 
-            if curr_loss - prev_loss >= tolerance {
-                break;
-            }
-        }
+    /// Train with gradient checkpointing: the forward pass only keeps the inputs of every every-th layer,
+    /// and backprop recomputes the other activations from them. This trades compute for activation memory
+    pub fn with_gradient_checkpointing(mut self, every: usize) -> NeuralNet {
+        assert!(every > 0, "The checkpoints must be at least a layer apart");
+        self.gradient_checkpointing = Some(every);
 
-        losses
+        self
     }
-}
 
-impl Model for NeuralNet {
-    /// Fit the model to the dataset
-    /// Return the model loss as a function of time (used for plotting)
-    fn fit(&mut self, dataset: &Dataset, test_path: &str) -> Vec<(usize, f64)> {
-        if let Some(num_epochs) = self.num_epochs {
-            self.fit_net_static(dataset, test_path, num_epochs)
-        } else {
-            self.fit_net_dynamic(
-                dataset,
-                test_path,
-                self.epsilon
-            )
-        }
-    }
+    // Perform a training forward pass that keeps only the inputs of every every-th layer (the checkpoints),
+    // with the state of the RNG before each of them, so that their noise and dropout masks can be recomputed
+    // Returns the checkpoints and the output of the network
+    fn forward_checkpointed(
+        &self,
+        inputs: &ArrayView2<f64>,
+        every: usize,
+    ) -> (Vec<(Array2<f64>, StdRng)>, Array2<f64>) {
+        let mut rng = self.rng.lock().unwrap();
+        let mut checkpoints = vec![];
+        let mut output = inputs.to_owned();
 
-    /// Predict the probabities for a set of instances - each instance is a row in "inputs"
-    fn predict(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
-        let (hidden, _) = self.forward(inputs);
-        let scores = hidden.last().unwrap();
-        // Construct the softmax
-        let mut predictions = Array::zeros((0, scores.ncols()));
+        for idx in 0..self.layers.len() {
+            if idx % every == 0 {
+                checkpoints.push((output.clone(), rng.clone()));
+            }
 
-        for row in scores.axis_iter(Axis(0)) {
-            predictions.push_row(softmax(row).view()).unwrap();
+            output = self.forward_layer(idx, &output, true, &mut rng).0;
         }
 
-        predictions
+        (checkpoints, output)
     }
-}
 
-fn activation(name: &ActivationFunction, z: f64) -> f64 {
-    match name {
-        ActivationFunction::ReLU => z.max(0f64),
-        ActivationFunction::Sigmoid => (1f64 + (-z).exp()).recip(),
-        ActivationFunction::Tanh => (z.exp() - (-z).exp()) / (z.exp() + (-z).exp()),
-        ActivationFunction::Linear => 3f64 * z + 1f64,
-        ActivationFunction::LeakyReLU => z.max(0.01 * z),
-    }
-}
+    /// Backprop from the checkpoints of forward_checkpointed. The activations of each segment of layers
+    /// between two checkpoints are recomputed from its checkpoint when the backward pass reaches it
+    fn backward_checkpointed(
+        &self,
+        checkpoints: Vec<(Array2<f64>, StdRng)>,
+        every: usize,
+        grad: Array2<f64>,
+    ) -> Gradients {
+        let mut grad_help = grad;
+        let mut grads = vec![];
 
-fn delta_activation(name: &ActivationFunction, z: f64) -> f64 {
-    match name {
-        ActivationFunction::ReLU => {
-            if z > 0f64 {
-                1f64
-            } else {
-                0f64
+        for (segment, (input, mut rng)) in checkpoints.into_iter().enumerate().rev() {
+            let start = segment * every;
+            let end = (start + every).min(self.layers.len());
+            let mut hidden = vec![input];
+            let mut hidden_linear = vec![];
+            let mut dropout_masks = vec![];
+
+            for idx in start..end {
+                let (real_output, lin_output, mask) =
+                    self.forward_layer(idx, hidden.last().unwrap(), true, &mut rng);
+
+                hidden.push(real_output);
+                hidden_linear.push(lin_output);
+                dropout_masks.push(mask);
             }
-        }
-        ActivationFunction::Sigmoid => activation(name, z) * (1f64 - activation(name, z)),
-        ActivationFunction::Tanh => 1f64 - activation(name, z) * activation(name, z),
-        ActivationFunction::Linear => 3f64,
-        ActivationFunction::LeakyReLU => {
-            if z > 0f64 {
-                1f64
-            } else {
-                0.01f64
+
+            for idx in (start..end).rev() {
+                let (layer_grads, input_grad) = self.backward_layer(
+                    idx,
+                    &hidden[idx - start],
+                    &hidden_linear[idx - start],
+                    dropout_masks[idx - start].as_ref(),
+                    grad_help,
+                );
+
+                grads.push(layer_grads);
+                grad_help = input_grad;
             }
         }
-    }
-}
-
-fn init_layers_default(layer_structure: &[usize]) -> Vec<(Array2<f64>, Array1<f64>)> {
-    let mut layers = vec![];
-    let mut rng = rand::thread_rng();
-    // Weights are initialized from a uniform distribiution
-    let distribution = Uniform::new(-0.3, 0.3);
 
-    for i in 0..layer_structure.len() - 1 {
-        // Random matrix of the weights between this layer and the next layer
-        let weights = Array::zeros((layer_structure[i], layer_structure[i + 1]))
-            .map(|_: &f64| distribution.sample(&mut rng));
-        // Bias vector between this layer and the next layer. Init'd to ondes
-        let bias = Array::ones(layer_structure[i + 1]);
+        grads.reverse();
 
-        layers.push((weights, bias));
+        grads
     }
 
-    layers
-}
+    /// Update the weights using the gradients of each layer and the optimizer. Frozen layers aren't updated
+    pub fn apply_gradients(&mut self, grads: &[LayerGradients]) {
+        // Pruned weights don't get any gradient, so that they stay zero
+        let grads: Gradients = grads
+            .iter()
+            .zip(self.masks.iter())
+            .map(|((weight_grad, bias_grad), mask)| {
+                (
+                    weight_grad * &mask.mapv(|keep| keep as u8 as f64),
+                    bias_grad.clone(),
+                )
+            })
+            .collect();
 
-fn init_layers_xavier(layer_structure: &[usize]) -> Vec<(Array2<f64>, Array1<f64>)> {
-    let mut layers = vec![];
-    let mut rng = rand::thread_rng();
+        let learning_rates = self.effective_lr_per_layer();
 
-    for i in 0..layer_structure.len() - 1 {
-        let boundary = 6f64.sqrt() / (layer_structure[i] + layer_structure[i + 1]) as f64;
-        let dist = Uniform::new(-boundary, boundary);
+        // The weight norm parameters are trained with plain GD, and the optimizer only updates the biases
+        if let Some(weight_norm) = &mut self.weight_norm {
+            for (((layer, (weight_grad, _)), frozen), learning_rate) in weight_norm
+                .iter_mut()
+                .zip(grads.iter())
+                .zip(self.frozen_layers.iter())
+                .zip(learning_rates.iter())
+            {
+                if !frozen {
+                    let (g_grad, v_grad) = layer.gradients(weight_grad);
 
-        let weights = Array::zeros((layer_structure[i], layer_structure[i + 1]))
-            .map(|_: &f64| dist.sample(&mut rng));
-        let bias = Array::zeros(layer_structure[i + 1]);
+                    layer.g.scaled_add(-learning_rate, &g_grad);
+                    layer.v.scaled_add(-learning_rate, &v_grad);
+                }
+            }
+        }
+
+        self.optimizer.update(
+            &mut self.layers,
+            &grads,
+            &self.frozen_layers,
+            &learning_rates,
+            &mut self.optimizer_state,
+        );
 
-        layers.push((weights, bias));
+        if let Some(weight_norm) = &self.weight_norm {
+            for (layer, weight_norm_layer) in self.layers.iter_mut().zip(weight_norm.iter()) {
+                layer.set_weights(weight_norm_layer.weights());
+            }
+        }
     }
 
-    layers
-}
+    /// Train with stochastic depth (Huang et al. 2016): each hidden layer is skipped in a training pass with a
+    /// probability that grows linearly with its depth, from survival probability 1 at the first layer to
+    /// min_survival_rate at the last. Only the layers whose input and output sizes match can be skipped
+    pub fn with_stochastic_depth(mut self, min_survival_rate: f64) -> NeuralNet {
+        let num_layers = self.layers.len();
 
-/// Softmax function - Convert scores into a probability distribution
-fn softmax(scores: ArrayView1<f64>) -> Array1<f64> {
-    let max = scores.iter().max_by(|x, y| x.total_cmp(y)).unwrap();
-    // We use a numerical trick where we shift the elements by the max, because otherwise
-    // We would have to compute the exp of very large values which wraps to NaN
-    let shift_scores = scores.map(|x| x - max);
-    let sum: f64 = shift_scores.iter().map(|x| x.exp()).sum();
+        self.stochastic_depth_rates = self
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(idx, layer)| {
+                let (fan_in, fan_out) = compute_fan(&layer.weights().view());
 
-    (0..scores.len())
-        .map(|x| shift_scores[x].exp() / sum)
-        .collect()
-}
+                if idx + 1 == num_layers || fan_in != fan_out {
+                    return 0f64;
+                }
 
-/// Calculate the cross-entropy loss on a given batch
-fn cross_entropy(predictions: &Array2<f64>, target: ArrayView2<f64>) -> f64 {
-    let total: f64 = predictions
-        .axis_iter(Axis(0))
-        .zip(target.axis_iter(Axis(0)))
-        .map(|(actual_row, target_row)| target_row.dot(&actual_row.map(|x| x.log2())))
-        .sum();
+                (idx as f64 / (num_layers - 1) as f64) * (1f64 - min_survival_rate)
+            })
+            .collect();
 
-    -1f64 * (1f64 / predictions.nrows() as f64) * total
-}
+        self
+    }
 
-fn test_loss(model: &NeuralNet, test_path: &str) -> f64 {
-    let test_dataset = parse_dataset(test_path);
-    let predictions = model.predict(&test_dataset.data.view());
+    /// Replace the first layer with an embedding layer, so that the inputs are indices. The second layer must take
+    /// seq_len * embed_dim inputs, where seq_len is the number of indices of each instance
+    pub fn with_input_embedding(mut self, embedding: EmbeddingLayer) -> NeuralNet {
+        self.masks[0] = Array2::from_elem(embedding.weight.dim(), true);
+        self.layers[0] = Box::new(embedding);
 
-    let target = test_dataset.target;
+        self
+    }
 
-    cross_entropy(&predictions, target.view())
+    /// Clip the gradients before every update
+    pub fn with_gradient_clip(mut self, gradient_clip: GradientClip) -> NeuralNet {
+        self.gradient_clip = Some(gradient_clip);
+
+        self
+    }
+
+    /// Train each batch on only the hard_ratio of its instances with the highest loss (online hard example mining)
+    pub fn with_ohem(mut self, hard_ratio: f64) -> NeuralNet {
+        self.ohem_ratio = Some(hard_ratio);
+
+        self
+    }
+
+    /// Gradually prune the net during training according to the schedule, which is stepped once per update
+    pub fn with_pruning_scheduler(mut self, pruning_scheduler: PruningScheduler) -> NeuralNet {
+        self.pruning_scheduler = Some(pruning_scheduler);
+
+        self
+    }
+
+    /// Store the weights and biases of the dense layers as f32, which halves their memory (see MixedPrecisionLayer)
+    /// The activations, the gradients and the optimizer state are still f64. Disabling it converts the layers back
+    pub fn with_mixed_precision(mut self, enabled: bool) -> NeuralNet {
+        self.mixed_precision = enabled;
+        self.layers = self
+            .layers
+            .iter()
+            .map(|layer| match layer.kind() {
+                "dense" => {
+                    self.dense_layer(layer.weights().into_owned(), layer.biases().into_owned())
+                }
+                _ => layer.clone(),
+            })
+            .collect();
+
+        self
+    }
+
+    /// A dense layer with these parameters, stored as f32 if the net uses mixed precision
+    fn dense_layer(&self, weights: Array2<f64>, biases: Array1<f64>) -> Box<dyn Layer> {
+        if self.mixed_precision {
+            Box::new(MixedPrecisionLayer::new(&weights.view(), &biases.view()))
+        } else {
+            Box::new(DenseLayer::new(weights, biases))
+        }
+    }
+
+    /// Train the network with weight normalization. The current weights are decomposed into their magnitudes and directions
+    pub fn with_weight_norm(mut self) -> NeuralNet {
+        self.weight_norm = Some(
+            self.layers
+                .iter()
+                .map(|layer| init_weight_norm_from_dense(&layer.weights().view()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Train the network with sparse variational dropout. kl_weight is the weight of the KL term of the objective
+    /// Use prune_variational_dropout after training to remove the weights whose noise dominates them
+    pub fn with_variational_dropout(mut self, kl_weight: f64) -> NeuralNet {
+        self.variational_dropout = Some(VariationalDropout {
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| VariationalDropoutLayer::new(layer.weights().dim()))
+                .collect(),
+            kl_weight,
+        });
+        self
+    }
+
+    /// Replace the weights with noisy weights for a variational dropout training step
+    /// Returns the noise-free weights and the sampled noise of each layer, which restore_variational_weights needs
+    fn apply_variational_noise(&mut self) -> Option<Vec<(Array2<f64>, Array2<f64>)>> {
+        let variational_dropout = self.variational_dropout.as_ref()?;
+
+        if !self.training_mode {
+            return None;
+        }
+
+        let mut rng = self.rng.lock().unwrap();
+
+        Some(
+            self.layers
+                .iter_mut()
+                .zip(variational_dropout.layers.iter())
+                .map(|(dense, layer)| {
+                    let weights = dense.weights().into_owned();
+                    let epsilon = layer.sample_epsilon(&mut *rng);
+
+                    dense.set_weights(&weights * &layer.noise(&epsilon));
+
+                    (weights, epsilon)
+                })
+                .collect(),
+        )
+    }
+
+    /// Restore the noise-free weights after a variational dropout training step, and perform a GD step on log(alpha)
+    /// Returns the gradients WRT the noise-free weights
+    fn restore_variational_weights(
+        &mut self,
+        saved: Vec<(Array2<f64>, Array2<f64>)>,
+        grads: Gradients,
+    ) -> Gradients {
+        let Some(variational_dropout) = &mut self.variational_dropout else {
+            return grads;
+        };
+        let kl_weight = variational_dropout.kl_weight;
+
+        self.layers
+            .iter_mut()
+            .zip(variational_dropout.layers.iter_mut())
+            .zip(saved)
+            .zip(grads)
+            .zip(self.frozen_layers.iter())
+            .map(
+                |((((dense, layer), (clean, epsilon)), (weight_grad, bias_grad)), frozen)| {
+                    let (weight_grad, log_alpha_grad) =
+                        layer.gradients(&clean.view(), &weight_grad, &epsilon);
+
+                    dense.set_weights(clean);
+
+                    if !frozen {
+                        let log_alpha_grad = log_alpha_grad + layer.kl_gradient() * kl_weight;
+                        layer.update(&log_alpha_grad, self.learning_rate);
+                    }
+
+                    (weight_grad, bias_grad)
+                },
+            )
+            .collect()
+    }
+
+    /// Zero (and prune) the weights whose alpha is above the threshold, i.e. the weights that are mostly noise
+    pub fn prune_variational_dropout(&mut self, threshold: f64) {
+        let Some(variational_dropout) = &self.variational_dropout else {
+            return;
+        };
+        let log_threshold = threshold.ln();
+
+        for ((dense, mask), layer) in self
+            .layers
+            .iter_mut()
+            .zip(self.masks.iter_mut())
+            .zip(variational_dropout.layers.iter())
+        {
+            let mut weights = dense.weights().into_owned();
+
+            Zip::from(&mut weights)
+                .and(mask)
+                .and(&layer.log_alpha)
+                .for_each(|w, keep, log_alpha| {
+                    if *log_alpha > log_threshold {
+                        *w = 0f64;
+                        *keep = false;
+                    }
+                });
+            dense.set_weights(weights);
+        }
+    }
+
+    /// Consolidate the weights learned on a task before training on the next one with elastic weight consolidation
+    /// The Fisher information is estimated on the dataset of the learned task. Replaces any previous consolidation
+    pub fn consolidate(&mut self, dataset: &Dataset, lambda: f64) {
+        self.ewc = Some(EWC {
+            fisher: compute_fisher(self, dataset),
+            anchors: self
+                .layers
+                .iter()
+                .map(|layer| (layer.weights().into_owned(), layer.biases().into_owned()))
+                .collect(),
+            lambda,
+        });
+    }
+
+    /// Train with cross-entropy weighted by the auto_class_weights of the dataset, which balances its classes
+    pub fn with_auto_class_weights(mut self, dataset: &Dataset) -> NeuralNet {
+        self.loss_function = LossFunction::WeightedCrossEntropy {
+            class_weights: dataset.auto_class_weights(),
+        };
+
+        self
+    }
+
+    /// Add the center loss to the loss during training, with centers the size of the input of the output layer
+    pub fn with_center_loss(mut self, lambda: f64, alpha: f64) -> NeuralNet {
+        let output_layer = self.layers.last().unwrap();
+
+        self.center_loss = Some(CenterLoss::new(
+            output_layer.biases().len(),
+            output_layer.weights().nrows(),
+            lambda,
+            alpha,
+        ));
+
+        self
+    }
+
+    /// Train the network with differentially private SGD
+    pub fn with_differential_privacy(mut self, config: DPConfig) -> NeuralNet {
+        self.differential_privacy = Some(config);
+        self
+    }
+
+    /// Spectrally normalize the weights after every training step
+    pub fn with_spectral_norm(mut self, config: SpectralNormConfig) -> NeuralNet {
+        self.spectral_norm = Some(config);
+        self
+    }
+
+    /// Divide each weight matrix by its largest singular value (its spectral norm), estimated with power iteration
+    /// The estimated singular vectors are cached, so repeated calls refine the estimates
+    pub fn apply_spectral_norm(&mut self) {
+        let n_power_iterations = self
+            .spectral_norm
+            .as_ref()
+            .map_or(SpectralNormConfig::default().n_power_iterations, |config| {
+                config.n_power_iterations
+            });
+
+        if self.spectral_u.len() != self.layers.len() {
+            let mut rng = self.rng.lock().unwrap();
+            let uniform = Uniform::new(-1f64, 1f64);
+
+            self.spectral_u = self
+                .layers
+                .iter()
+                .map(|layer| {
+                    normalize(Array1::from_shape_fn(layer.weights().nrows(), |_| {
+                        uniform.sample(&mut *rng)
+                    }))
+                })
+                .collect();
+        }
+
+        for (idx, (layer, u)) in self
+            .layers
+            .iter_mut()
+            .zip(self.spectral_u.iter_mut())
+            .enumerate()
+        {
+            // Frozen layers aren't changed during training
+            if self.frozen_layers[idx] {
+                continue;
+            }
+
+            let weights = layer.weights();
+
+            let mut v = normalize(weights.t().dot(u));
+
+            for _ in 0..n_power_iterations {
+                *u = normalize(weights.dot(&v));
+                v = normalize(weights.t().dot(u));
+            }
+
+            let sigma = u.dot(&weights.dot(&v));
+
+            if sigma > 0f64 {
+                let normalized = &weights / sigma;
+
+                layer.set_weights(normalized);
+            }
+        }
+    }
+
+    /// Called after each training step: reapplies the spectral normalization if it's enabled
+    pub fn update_spectral_norm(&mut self) {
+        if self.spectral_norm.is_some() {
+            self.apply_spectral_norm();
+        }
+    }
+
+    /// Run f while recording the forward passes of the network on the tape, and return its result
+    /// Use GradientTape::gradient to compute the gradients afterwards
+    pub fn with_tape(
+        &mut self,
+        tape: &mut GradientTape,
+        f: impl FnOnce(&mut NeuralNet) -> Array2<f64>,
+    ) -> Array2<f64> {
+        *self.tape.lock().unwrap() = Some(std::mem::take(tape));
+
+        let result = f(self);
+
+        *tape = self.tape.lock().unwrap().take().unwrap();
+
+        result
+    }
+
+    /// Calculate the gradients using backprop and perform a GD step. Returns the gradients of the step
+    /// compute_grads computes the gradients from what the forward pass stored (recomputing activations if needed)
+    fn backward_and_update(
+        &mut self,
+        compute_grads: impl FnOnce(&NeuralNet) -> Gradients,
+        variational_noise: Option<Vec<(Array2<f64>, Array2<f64>)>>,
+    ) -> Gradients {
+        let grads = compute_grads(self);
+        let mut grads = match variational_noise {
+            Some(saved) => self.restore_variational_weights(saved, grads),
+            None => grads,
+        };
+
+        if let Some(ewc) = &self.ewc {
+            ewc.add_penalty_gradients(&mut grads, &self.layers);
+        }
+
+        if let Some(gradient_clip) = &self.gradient_clip {
+            gradient_clip.clip(&mut grads, &self.layers);
+        }
+
+        let pre_update: Vec<Array2<f64>> = if self.verbosity >= Verbosity::Debug {
+            self.layers
+                .iter()
+                .map(|layer| layer.weights().into_owned())
+                .collect()
+        } else {
+            vec![]
+        };
+
+        self.apply_gradients(&grads);
+        self.update_spectral_norm();
+
+        if let Some(sparsity) = self.pruning_scheduler.as_mut().and_then(|p| p.step()) {
+            self.prune(sparsity);
+        }
+
+        if self.verbosity >= Verbosity::Batch {
+            let weight_grads: Vec<Array2<f64>> = grads.iter().map(|(w, _)| w.clone()).collect();
+
+            self.sign_agreement = gradient_sign_agreement(&weight_grads, &self.prev_weight_grads);
+            self.prev_weight_grads = weight_grads;
+        }
+
+        if self.verbosity >= Verbosity::Debug {
+            self.log_layer_stats(&grads, &pre_update);
+        }
+
+        grads
+    }
+
+    /// Compute the gradients of a batch with DP-SGD: the gradients of each sample are clipped, summed and noised
+    /// The rows of a forward pass are independent, so the gradients of each sample are backpropagated from its rows
+    fn private_gradients(
+        &self,
+        hidden: &Activations,
+        hidden_linear: &Activations,
+        dropout_masks: &Activations,
+        grad: &Array2<f64>,
+        config: &DPConfig,
+    ) -> Gradients {
+        let batch_size = grad.nrows();
+        let mut sum: Gradients = self
+            .layers
+            .iter()
+            .map(|layer| {
+                (
+                    Array2::zeros(layer.weights().dim()),
+                    Array1::zeros(layer.biases().len()),
+                )
+            })
+            .collect();
+
+        for i in 0..batch_size {
+            let (mut grads, _) = self.backward(
+                &sample_rows(hidden, i),
+                &sample_rows(hidden_linear, i),
+                &sample_rows(dropout_masks, i),
+                grad.slice(s![i..i + 1, ..]).to_owned(),
+            );
+            clip_gradients(&mut grads, config.max_grad_norm);
+
+            for ((weight_sum, bias_sum), (weight_grad, bias_grad)) in sum.iter_mut().zip(grads) {
+                *weight_sum += &weight_grad;
+                *bias_sum += &bias_grad;
+            }
+        }
+
+        add_gaussian_noise(
+            &mut sum,
+            config.noise_multiplier * config.max_grad_norm,
+            &mut *self.rng.lock().unwrap(),
+        );
+
+        // Like in backward, the weight gradients are summed over the batch and the bias gradients are averaged
+        for (_, bias_grad) in sum.iter_mut() {
+            *bias_grad /= batch_size as f64;
+        }
+
+        sum
+    }
+
+    /// Log the norm of the gradient and statistics of the weights of each layer, with the SNR of the update from
+    /// the weights before it
+    fn log_layer_stats(&self, grads: &Gradients, pre_update: &[Array2<f64>]) {
+        let update_ratios = compute_update_to_weight_ratio(self, grads);
+        let post_update: Vec<Array2<f64>> = self
+            .layers
+            .iter()
+            .map(|layer| layer.weights().into_owned())
+            .collect();
+        let snrs = snr_weight_updates(pre_update, &post_update);
+
+        for (idx, (((layer, (weight_grad, bias_grad)), update_ratio), snr)) in self
+            .layers
+            .iter()
+            .zip(grads.iter())
+            .zip(update_ratios)
+            .zip(snrs)
+            .enumerate()
+        {
+            let weights = layer.weights();
+            let grad_norm =
+                (weight_grad.mapv(|x| x * x).sum() + bias_grad.mapv(|x| x * x).sum()).sqrt();
+            let mean = weights.mean().unwrap();
+
+            eprintln!(
+                "  [Layer {}] grad_norm={:.6} update_ratio={:.2e} snr={:.2e} weight_mean={:.6} weight_std={:.6} weight_min={:.6} weight_max={:.6}",
+                idx,
+                grad_norm,
+                update_ratio,
+                snr,
+                mean,
+                weights.std(0f64),
+                weights.fold(f64::INFINITY, |a, &b| a.min(b)),
+                weights.fold(f64::NEG_INFINITY, |a, &b| a.max(b)),
+            );
+        }
+    }
+
+    /// Compute the gradient of the score (the output before the softmax) of target_class WRT the inputs
+    /// The magnitude of the gradient of each feature is its saliency - how much it influences the score
+    /// The inputs are scaled by the input scaler first, so the gradient is WRT the scaled inputs
+    pub fn input_gradients(&self, input: &Array2<f64>, target_class: usize) -> Array2<f64> {
+        self.score_input_gradients(&self.scaled_inputs(&input.view()).view(), target_class)
+    }
+
+    /// input_gradients without the input scaler, for inputs that are already scaled (e.g. training batches)
+    pub(super) fn score_input_gradients(
+        &self,
+        inputs: &ArrayView2<f64>,
+        target_class: usize,
+    ) -> Array2<f64> {
+        let (hidden, hidden_linear, dropout_masks) = self.forward(inputs, false);
+        let mut grad = Array2::zeros(hidden.last().unwrap().dim());
+        grad.column_mut(target_class).fill(1f64);
+
+        let (_, input_grad) = self.backward(&hidden, &hidden_linear, &dropout_masks, grad);
+
+        input_grad
+    }
+
+    /// Attribute the predicted probability of target_class to the input features using layer-wise relevance propagation
+    /// with the LRP-epsilon rule (Bach et al. 2015). The relevance of each neuron is split between the neurons of the
+    /// previous layer in proportion to their contributions a_i * w_ij, so the relevances roughly sum to the prediction
+    /// The stabilizer epsilon takes the sign of the denominator to avoid dividing by values close to zero
+    /// The input is scaled by the input scaler first, so the relevances are those of the scaled features
+    pub fn lrp(&self, input: &ArrayView1<f64>, target_class: usize, epsilon: f64) -> Array1<f64> {
+        let input = self.scaled_inputs(&input.view().insert_axis(Axis(0)));
+        let (hidden, _, _) = self.forward(&input.view(), false);
+        let prediction = self.apply_output_activation(hidden.last().unwrap() / self.temperature);
+        let mut relevance = Array1::zeros(prediction.ncols());
+        relevance[target_class] = prediction[[0, target_class]];
+
+        for idx in (0..self.layers.len()).rev() {
+            let a = hidden[idx].row(0);
+            let weights = self.layers[idx].weights();
+            let z = a.dot(&weights);
+            let s = relevance / z.mapv(|z| z + epsilon * if z >= 0f64 { 1f64 } else { -1f64 });
+
+            relevance = &a * &weights.dot(&s);
+        }
+
+        relevance
+    }
+
+    /// Compute the gradient of the output of neuron_idx in layer layer_idx (0 is the input layer) WRT the inputs
+    fn activation_input_gradients(
+        &self,
+        inputs: &ArrayView2<f64>,
+        layer_idx: usize,
+        neuron_idx: usize,
+    ) -> Array2<f64> {
+        let (hidden, hidden_linear, _) = self.forward(inputs, false);
+        let mut grad = Array2::zeros(hidden[layer_idx].dim());
+        grad.column_mut(neuron_idx).fill(1f64);
+
+        for idx in (0..layer_idx).rev() {
+            // The output layer doesn't have an activation function
+            if idx != self.layers.len() - 1 {
+                grad *=
+                    &hidden_linear[idx].map(|x| delta_activation(&self.activation_function, *x));
+            }
+
+            grad = grad.dot(&self.layers[idx].weights().t());
+        }
+
+        grad
+    }
+
+    /// Find an input that maximizes the output of neuron_idx in layer layer_idx (0 is the input layer) with gradient ascent
+    /// Starting from a random input, each step adds lr times the gradient of the neuron and decays the input by l2_reg
+    /// The input is clipped to [0, 1] after each step
+    pub fn maximize_activation(
+        &self,
+        layer_idx: usize,
+        neuron_idx: usize,
+        n_steps: usize,
+        lr: f64,
+        l2_reg: f64,
+    ) -> Array1<f64> {
+        assert!(
+            (1..=self.layers.len()).contains(&layer_idx),
+            "Layer {} isn't a hidden or output layer",
+            layer_idx
+        );
+        assert!(
+            neuron_idx < self.layers[layer_idx - 1].weights().ncols(),
+            "Layer {} doesn't have a neuron {}",
+            layer_idx,
+            neuron_idx
+        );
+
+        let uniform = Uniform::new(0f64, 1f64);
+        let mut input = {
+            let mut rng = self.rng.lock().unwrap();
+
+            Array2::from_shape_fn((1, self.layers[0].weights().nrows()), |_| {
+                uniform.sample(&mut *rng)
+            })
+        };
+
+        for _ in 0..n_steps {
+            let grad = self.activation_input_gradients(&input.view(), layer_idx, neuron_idx);
+
+            input = (&input + &(grad * lr) - &input * l2_reg).mapv(|x| x.clamp(0f64, 1f64));
+        }
+
+        input.row(0).to_owned()
+    }
+
+    /// Compute the gradients of the loss of a batch WRT the weights and biases of each layer, without updating them
+    pub fn gradients(&self, inputs: &ArrayView2<f64>, targets: &ArrayView2<f64>) -> Gradients {
+        let (hidden, hidden_linear, dropout_masks) = self.forward(inputs, false);
+        let grad = self
+            .loss_function
+            .gradient(hidden.last().unwrap(), targets, inputs);
+
+        let (grads, _) = self.backward(&hidden, &hidden_linear, &dropout_masks, grad);
+
+        grads
+    }
+
+    /// Compute the gradient of the loss WRT the inputs
+    /// The inputs are scaled by the input scaler first, so the gradient is WRT the scaled inputs
+    pub fn loss_input_gradients(
+        &self,
+        inputs: &ArrayView2<f64>,
+        targets: &ArrayView2<f64>,
+    ) -> Array2<f64> {
+        let inputs = self.scaled_inputs(inputs);
+        let (hidden, hidden_linear, dropout_masks) = self.forward(&inputs.view(), false);
+        let grad = self
+            .loss_function
+            .gradient(hidden.last().unwrap(), targets, &inputs.view());
+
+        let (_, input_grad) = self.backward(&hidden, &hidden_linear, &dropout_masks, grad);
+
+        input_grad
+    }
+
+    /// Attribute the score of target_class to the input features using integrated gradients
+    /// The gradients are averaged along the straight path from the baseline to the input (a Riemann sum with n_steps steps)
+    /// and multiplied by (input - baseline), so the attributions sum to roughly F(input) - F(baseline)
+    /// The input and the baseline are scaled by the input scaler first, so the path is between the scaled instances
+    pub fn integrated_gradients(
+        &self,
+        input: &ArrayView1<f64>,
+        baseline: &ArrayView1<f64>,
+        target_class: usize,
+        n_steps: usize,
+    ) -> Array1<f64> {
+        let input = self.scaled_inputs(&input.view().insert_axis(Axis(0)));
+        let input = input.row(0);
+        let baseline = self.scaled_inputs(&baseline.view().insert_axis(Axis(0)));
+        let baseline = baseline.row(0);
+        let diff = &input - &baseline;
+        let mut path = Array2::zeros((n_steps, input.len()));
+
+        // Row k - 1 holds the point baseline + k / n_steps * (input - baseline)
+        for (k, mut row) in path.axis_iter_mut(Axis(0)).enumerate() {
+            row.assign(&(&baseline + &diff * ((k + 1) as f64 / n_steps as f64)));
+        }
+
+        // All the points on the path share a single forward-backward pass
+        let grads = self.score_input_gradients(&path.view(), target_class);
+
+        grads.mean_axis(Axis(0)).unwrap() * diff
+    }
+
+    /// Run a single epoch of mini-batch GD over the dataset
+    /// Returns the mean loss of the batches
+    fn train_epoch(&mut self, dataset: &Dataset) -> f64 {
+        let mut total_loss = 0f64;
+        let mut num_batches = 0;
+
+        // Get a batch of instances and their targets
+        for (input_batch, target_batch) in dataset
+            .data
+            .axis_chunks_iter(Axis(0), self.batch_size)
+            .zip(dataset.target.axis_chunks_iter(Axis(0), self.batch_size))
+        {
+            let loss = self.partial_fit(&input_batch, &target_batch);
+
+            if self.verbosity >= Verbosity::Batch {
+                let sign_agreement: Vec<String> = self
+                    .sign_agreement
+                    .iter()
+                    .map(|agreement| format!("{:.3}", agreement))
+                    .collect();
+
+                eprintln!(
+                    "  [Batch {}] loss={:.4} sign_agreement=[{}]",
+                    num_batches + 1,
+                    loss,
+                    sign_agreement.join(", ")
+                );
+            }
+
+            total_loss += loss;
+            num_batches += 1;
+        }
+
+        total_loss / num_batches as f64
+    }
+
+    /// Train for a single epoch and record its losses in the history
+    fn fit_epoch(
+        &mut self,
+        dataset: &Dataset,
+        validation: Option<&Dataset>,
+        history: &mut TrainingHistory,
+    ) {
+        let train_loss = self.train_epoch(dataset);
+        history.train_losses.push(train_loss);
+
+        if let Some(validation) = validation {
+            history.val_losses.push(dataset_loss(self, validation));
+        }
+    }
+
+    /// Fit the model to the batches of a data source (e.g. a DataLoader that loads them in the background)
+    /// The source is reset at the start of every epoch. Like fit, it uses early stopping if num_epochs isn't set
+    pub fn fit_source(
+        &mut self,
+        source: &mut impl DataSource,
+        validation: Option<&Dataset>,
+    ) -> TrainingHistory {
+        self.fit_loop(|net, history| {
+            let mut total_loss = 0f64;
+            let mut num_batches = 0;
+
+            source.reset();
+
+            while let Some((input_batch, target_batch)) = source.next_batch() {
+                total_loss += net.partial_fit(&input_batch.view(), &target_batch.view());
+                num_batches += 1;
+            }
+
+            history.train_losses.push(total_loss / num_batches as f64);
+
+            if let Some(validation) = validation {
+                history.val_losses.push(dataset_loss(net, validation));
+            }
+        })
+    }
+
+    /// Fit the model with curriculum learning. The losses of the instances are recomputed at the start of each epoch,
+    /// and the training loss of an epoch is over the instances it trained on
+    /// The losses of an untrained network don't tell the easy instances apart, so the first epoch trains on all of them
+    pub fn fit_curriculum(
+        &mut self,
+        dataset: &Dataset,
+        validation: Option<&Dataset>,
+        mut scheduler: CurriculumScheduler,
+    ) -> TrainingHistory {
+        let mut warmed_up = false;
+
+        self.fit_loop(|net, history| {
+            if !warmed_up {
+                warmed_up = true;
+
+                return net.fit_epoch(dataset, validation, history);
+            }
+
+            let subset = dataset.select(&scheduler.easiest_indices(net, dataset));
+
+            net.fit_epoch(&subset, validation, history);
+            scheduler.step();
+        })
+    }
+
+    /// Train the network to reconstruct the instances of normal_data with the MSE loss, e.g. to detect anomalies
+    /// by their reconstruction error. The output layer must be the size of the input layer
+    pub fn fit_autoencoder(&mut self, normal_data: &Dataset, n_epochs: usize) -> TrainingHistory {
+        let num_outputs = self.layers.last().unwrap().biases().len();
+
+        assert_eq!(
+            normal_data.data.ncols(),
+            num_outputs,
+            "An autoencoder must have as many outputs as inputs"
+        );
+
+        self.loss_function = LossFunction::MSE;
+        self.task = Task::Autoencoder;
+        self.num_epochs = Some(n_epochs);
+
+        let dataset = Dataset {
+            data: normal_data.data.clone(),
+            target: normal_data.data.clone(),
+        };
+
+        self.fit(&dataset, None)
+    }
+
+    /// Incrementally train on a single batch, e.g. when the data arrives as a stream
+    /// The optimizer state and LR schedule carry over between calls. Returns the batch loss
+    /// (of its hard examples only with OHEM)
+    pub fn partial_fit(&mut self, x: &ArrayView2<f64>, y: &ArrayView2<f64>) -> f64 {
+        if let Some(lr_scheduler) = &mut self.lr_scheduler {
+            self.learning_rate = lr_scheduler.step();
+        }
+
+        if let Some(hard_ratio) = self.ohem_ratio {
+            let (hard_x, hard_y) =
+                online_hard_example_mining(self, &x.to_owned(), &y.to_owned(), hard_ratio);
+
+            return self.train_batch(&hard_x.view(), &hard_y.view());
+        }
+
+        self.train_batch(x, y)
+    }
+
+    /// Forget the optimizer state, e.g. to restart learning after a distribution shift
+    pub fn reset_optimizer_state(&mut self) {
+        self.optimizer_state = OptimizerState::new();
+    }
+
+    /// Perform a single GD step on a batch. Returns the loss of the batch before the step
+    fn train_batch(
+        &mut self,
+        input_batch: &ArrayView2<f64>,
+        target_batch: &ArrayView2<f64>,
+    ) -> f64 {
+        let variational_noise = self.apply_variational_noise();
+        let mut buffers = match self.gradient_buffers.take() {
+            _ if !self.reuse_gradient_buffers => None,
+            Some(buffers) if buffers.fits(&self.layers, input_batch.nrows()) => Some(buffers),
+            _ => Some(self.preallocate_buffers(self.batch_size.max(input_batch.nrows()))),
+        };
+
+        // DP-SGD needs the activations of each sample, so check_config rejects it with checkpointing
+        let (loss, grads) = match (self.gradient_checkpointing, &self.differential_privacy) {
+            (Some(every), None) => {
+                let (checkpoints, logits) = self.forward_checkpointed(input_batch, every);
+                let mut loss = self.loss_function.loss(&logits, target_batch, input_batch);
+                let grad = self
+                    .loss_function
+                    .gradient(&logits, target_batch, input_batch);
+                let penalty = self.loss_penalty(input_batch, target_batch);
+
+                if let Some((penalty_loss, _)) = &penalty {
+                    loss += penalty_loss;
+                }
+
+                let grads = self.backward_and_update(
+                    |net| {
+                        let grads = net.backward_checkpointed(checkpoints, every, grad);
+
+                        match penalty {
+                            Some((_, penalty_grads)) => add_gradients(grads, penalty_grads),
+                            None => grads,
+                        }
+                    },
+                    variational_noise,
+                );
+
+                (loss, grads)
+            }
+            _ => {
+                let (hidden, hidden_linear, dropout_masks) = self.forward(input_batch, true);
+                let logits = hidden.last().unwrap();
+                let mut loss = self.loss_function.loss(logits, target_batch, input_batch);
+
+                // Gradient is initialized to the gradient of the loss WRT the output layer
+                let grad = self
+                    .loss_function
+                    .gradient(logits, target_batch, input_batch);
+
+                // The features the center loss is computed on are the inputs of the output layer
+                let features = &hidden[hidden.len() - 2];
+                let (feature_grad, labels) = match &self.center_loss {
+                    Some(center_loss) => {
+                        let labels: Vec<usize> =
+                            target_batch.axis_iter(Axis(0)).map(argmax).collect();
+                        loss += center_loss.loss(features, &labels);
+
+                        let feature_grad =
+                            center_loss_gradient(features, &labels, &center_loss.centers)
+                                * center_loss.lambda;
+
+                        (Some(feature_grad), labels)
+                    }
+                    None => (None, vec![]),
+                };
+
+                let penalty = self.loss_penalty(input_batch, target_batch);
+
+                if let Some((penalty_loss, _)) = &penalty {
+                    loss += penalty_loss;
+                }
+
+                let grads = self.backward_and_update(
+                    |net| {
+                        let grads = match &net.differential_privacy {
+                            Some(config) => net.private_gradients(
+                                &hidden,
+                                &hidden_linear,
+                                &dropout_masks,
+                                &grad,
+                                config,
+                            ),
+                            None if feature_grad.is_none() => match buffers.as_mut() {
+                                Some(buffers) => net.backward_buffered(
+                                    &hidden,
+                                    &hidden_linear,
+                                    &dropout_masks,
+                                    grad,
+                                    buffers,
+                                ),
+                                None => {
+                                    net.backward(&hidden, &hidden_linear, &dropout_masks, grad)
+                                        .0
+                                }
+                            },
+                            None => {
+                                net.backward_with_feature_grad(
+                                    &hidden,
+                                    &hidden_linear,
+                                    &dropout_masks,
+                                    grad,
+                                    feature_grad,
+                                )
+                                .0
+                            }
+                        };
+
+                        match penalty {
+                            Some((_, penalty_grads)) => add_gradients(grads, penalty_grads),
+                            None => grads,
+                        }
+                    },
+                    variational_noise,
+                );
+
+                if let Some(center_loss) = &mut self.center_loss {
+                    center_loss.update_centers(features, &labels);
+                }
+
+                (loss, grads)
+            }
+        };
+
+        self.run_callbacks(|callback, net| callback.on_batch_end(net, loss, &grads));
+
+        // The gradients of every path have the shapes of the buffers, so the next step can write into them
+        if let Some(mut buffers) = buffers {
+            buffers.restore_gradients(grads);
+            self.gradient_buffers = Some(buffers);
+        }
+
+        loss
+    }
+
+    /// The gradient penalty of the batch and its gradients, if the loss has one
+    fn loss_penalty(
+        &self,
+        input_batch: &ArrayView2<f64>,
+        target_batch: &ArrayView2<f64>,
+    ) -> Option<(f64, Gradients)> {
+        match &self.loss_function {
+            LossFunction::GradientPenalty { lambda, .. } => {
+                self.batch_gradient_penalty(input_batch, target_batch, *lambda)
+            }
+            _ => None,
+        }
+    }
+
+    /// The gradient penalty of a batch of a GAN discriminator and its gradients, where the real instances (with a first
+    /// target of 1) are paired with the fake ones. None if the batch doesn't have both
+    fn batch_gradient_penalty(
+        &self,
+        input_batch: &ArrayView2<f64>,
+        target_batch: &ArrayView2<f64>,
+        lambda: f64,
+    ) -> Option<(f64, Gradients)> {
+        let (real, fake): (Vec<usize>, Vec<usize>) =
+            (0..target_batch.nrows()).partition(|&i| target_batch[[i, 0]] >= 0.5);
+        let num_pairs = real.len().min(fake.len());
+
+        (num_pairs > 0).then(|| {
+            gradient_penalty_gradients(
+                self,
+                &input_batch.select(Axis(0), &real[..num_pairs]),
+                &input_batch.select(Axis(0), &fake[..num_pairs]),
+                lambda,
+            )
+        })
+    }
+
+    /// Sample an instance uniformly on the segment between each real instance and the matching fake one
+    pub(super) fn interpolate(&self, real: &Array2<f64>, fake: &Array2<f64>) -> Array2<f64> {
+        let mut rng = self.rng.lock().unwrap();
+        let eps = Array2::from_shape_simple_fn((real.nrows(), 1), || rng.gen::<f64>());
+
+        &eps * real + (1f64 - &eps) * fake
+    }
+
+    /// Call f on each of the callbacks. They are taken out of the net during the calls, so that they can access it
+    fn run_callbacks(&mut self, mut f: impl FnMut(&mut dyn Callback, &NeuralNet)) {
+        let mut callbacks = std::mem::take(&mut self.callbacks);
+
+        for callback in callbacks.iter_mut() {
+            f(callback.as_mut(), self);
+        }
+
+        self.callbacks = callbacks;
+    }
+
+    /// Add a callback that is called during training
+    pub fn with_callback(mut self, callback: Box<dyn Callback>) -> NeuralNet {
+        self.callbacks.push(callback);
+
+        self
+    }
+
+    /// Get the first callback of type T, e.g. to read what it recorded after training
+    pub fn callback<T: Callback>(&self) -> Option<&T> {
+        self.callbacks
+            .iter()
+            .find_map(|callback| (&**callback as &dyn Any).downcast_ref::<T>())
+    }
+
+    /// Save the training state, leaving the net with a fresh optimizer state and no callbacks
+    fn save_training_state(&mut self) -> TrainingState {
+        TrainingState {
+            layers: self.layers.clone(),
+            learning_rate: self.learning_rate,
+            masks: self.masks.clone(),
+            optimizer_state: std::mem::take(&mut self.optimizer_state),
+            spectral_u: self.spectral_u.clone(),
+            prev_weight_grads: self.prev_weight_grads.clone(),
+            sign_agreement: self.sign_agreement.clone(),
+            weight_norm: self.weight_norm.clone(),
+            variational_dropout: self.variational_dropout.clone(),
+            pruning_scheduler: self.pruning_scheduler.clone(),
+            center_loss: self.center_loss.clone(),
+            callbacks: std::mem::take(&mut self.callbacks),
+            rng: self.rng.lock().unwrap().clone(),
+        }
+    }
+
+    fn restore_training_state(&mut self, state: TrainingState) {
+        self.layers = state.layers;
+        self.learning_rate = state.learning_rate;
+        self.masks = state.masks;
+        self.optimizer_state = state.optimizer_state;
+        self.spectral_u = state.spectral_u;
+        self.prev_weight_grads = state.prev_weight_grads;
+        self.sign_agreement = state.sign_agreement;
+        self.weight_norm = state.weight_norm;
+        self.variational_dropout = state.variational_dropout;
+        self.pruning_scheduler = state.pruning_scheduler;
+        self.center_loss = state.center_loss;
+        self.callbacks = state.callbacks;
+        *self.rng.lock().unwrap() = state.rng;
+    }
+
+    /// A copy of the network for inference: the same parameters and outputs, without the training state
+    pub(super) fn snapshot(&self) -> NeuralNet {
+        let layer_structure: Vec<usize> = std::iter::once(self.layers[0].weights().nrows())
+            .chain(self.layers.iter().map(|layer| layer.biases().len()))
+            .collect();
+        let mut snapshot = NeuralNetBuilder::new(layer_structure)
+            .activation_function(self.activation_function.clone())
+            .loss_function(self.loss_function.clone())
+            .task(self.task)
+            .verbosity(self.verbosity)
+            .build();
+
+        snapshot.layers = self.layers.clone();
+        snapshot.output_activation = self.output_activation.clone();
+        snapshot.temperature = self.temperature;
+
+        snapshot
+    }
+
+    /// Train a snapshot ensemble (Huang et al. 2017): n_snapshots cycles of cosine annealing from the LR of the
+    /// net down to 0, each lasting cycle_length epochs. The weights at the end of each cycle, where the LR is at its
+    /// minimum, are a member of the ensemble, so the ensemble costs as much to train as the net
+    /// The LR, the LR scheduler and the number of epochs of the net are restored afterwards
+    pub fn fit_snapshot_ensemble(
+        &mut self,
+        dataset: &Dataset,
+        n_snapshots: usize,
+        cycle_length: usize,
+    ) -> (TrainingHistory, Ensemble) {
+        self.validate_config();
+
+        let steps_per_epoch = dataset.data.nrows().div_ceil(self.batch_size);
+        let saved_lr = self.learning_rate;
+        let saved_num_epochs = self.num_epochs.replace(n_snapshots * cycle_length);
+        let saved_scheduler = self.lr_scheduler.replace(Box::new(SGDRScheduler::new(
+            cycle_length * steps_per_epoch,
+            1,
+            0f64,
+            self.learning_rate,
+        )));
+        let mut history = TrainingHistory::new();
+        let mut ensemble = Ensemble::new();
+        let start = Instant::now();
+
+        for _ in 0..n_snapshots {
+            for _ in 0..cycle_length {
+                self.fit_epoch(dataset, None, &mut history);
+                self.log_epoch(&history, start);
+            }
+
+            ensemble.add_member(self.snapshot());
+        }
+
+        self.lr_scheduler = saved_scheduler;
+        self.learning_rate = saved_lr;
+        self.num_epochs = saved_num_epochs;
+
+        (history, ensemble)
+    }
+
+    /// Find a good learning rate using the LR range test (Smith 2015)
+    /// The model is trained for n_steps batches while the LR increases exponentially from min_lr to max_lr,
+    /// and the loss of each batch is recorded. The test starts with a fresh optimizer state and without the
+    /// callbacks, and all of the training state (the weights, the LR, the optimizer state, the RNG, ...) is
+    /// restored afterwards. The test stops early if the loss diverges
+    pub fn find_lr(
+        &mut self,
+        dataset: &Dataset,
+        min_lr: f64,
+        max_lr: f64,
+        n_steps: usize,
+    ) -> LRFinderResult {
+        // The loss has diverged once it's this many times larger than the best loss
+        const DIVERGENCE_FACTOR: f64 = 4f64;
+
+        self.validate_config();
+
+        let saved_state = self.save_training_state();
+        let mut lrs = vec![];
+        let mut losses = vec![];
+        let mut best_loss = f64::INFINITY;
+        // If there are less batches than steps, we go over the dataset multiple times
+        let batches = dataset
+            .data
+            .axis_chunks_iter(Axis(0), self.batch_size)
+            .zip(dataset.target.axis_chunks_iter(Axis(0), self.batch_size))
+            .cycle()
+            .take(n_steps);
+
+        for (step, (input_batch, target_batch)) in batches.enumerate() {
+            let progress = step as f64 / (n_steps.max(2) - 1) as f64;
+            self.learning_rate = min_lr * (max_lr / min_lr).powf(progress);
+
+            let loss = self.train_batch(&input_batch, &target_batch);
+
+            lrs.push(self.learning_rate);
+            losses.push(loss);
+
+            if !loss.is_finite() || loss > DIVERGENCE_FACTOR * best_loss {
+                break;
+            }
+
+            best_loss = best_loss.min(loss);
+        }
+
+        self.restore_training_state(saved_state);
+
+        let optimal_lr = steepest_descent_lr(&lrs, &losses);
+
+        LRFinderResult {
+            lrs,
+            losses,
+            optimal_lr,
+        }
+    }
+
+    /// Train the network with the LR given by the scheduler at each batch instead of a constant LR
+    pub fn with_lr_scheduler(mut self, lr_scheduler: Box<dyn LRScheduler>) -> NeuralNet {
+        self.lr_scheduler = Some(lr_scheduler);
+
+        self
+    }
+
+    /// Set the LR of the net to base_lr, and decay it geometrically toward the earlier layers:
+    /// the multiplier of layer i is decay^(n_layers - 1 - i), so the last layer is trained with base_lr
+    pub fn set_layerwise_lr_decay(&mut self, base_lr: f64, decay: f64) {
+        let n_layers = self.layers.len();
+
+        self.learning_rate = base_lr;
+        self.lr_multipliers = (0..n_layers)
+            .map(|i| decay.powi((n_layers - 1 - i) as i32))
+            .collect();
+    }
+
+    /// The LR that each layer is trained with
+    pub fn effective_lr_per_layer(&self) -> Vec<f64> {
+        self.lr_multipliers
+            .iter()
+            .map(|multiplier| self.learning_rate * multiplier)
+            .collect()
+    }
+
+    /// Freeze the first num_layers layers, so that their weights aren't updated during training
+    pub fn freeze_layers(&mut self, num_layers: usize) {
+        for frozen in self.frozen_layers.iter_mut().take(num_layers) {
+            *frozen = true;
+        }
+    }
+
+    /// Unfreeze all of the layers
+    pub fn unfreeze_layers(&mut self) {
+        self.frozen_layers.fill(false);
+    }
+
+    /// Prune the global_sparsity fraction of the weights with the smallest magnitudes across all layers
+    pub fn prune(&mut self, global_sparsity: f64) {
+        let mut pruned: Vec<Array2<f64>> = self
+            .layers
+            .iter()
+            .map(|layer| layer.weights().into_owned())
+            .collect();
+        // Each weight is represented by (magnitude, layer index, index in the layer)
+        let mut weights: Vec<(f64, usize, (usize, usize))> = pruned
+            .iter()
+            .enumerate()
+            .flat_map(|(layer_idx, layer_weights)| {
+                layer_weights
+                    .indexed_iter()
+                    .map(move |(idx, x)| (x.abs(), layer_idx, idx))
+            })
+            .collect();
+        let num_pruned = (global_sparsity * weights.len() as f64).round() as usize;
+
+        weights.sort_by(|(a, _, _), (b, _, _)| a.total_cmp(b));
+
+        for (_, layer_idx, idx) in weights.into_iter().take(num_pruned) {
+            self.masks[layer_idx][idx] = false;
+            pruned[layer_idx][idx] = 0f64;
+        }
+
+        for (layer, weights) in self.layers.iter_mut().zip(pruned) {
+            layer.set_weights(weights);
+        }
+    }
+
+    /// Prune the sparsity fraction of the weights with the smallest magnitudes in a single layer
+    pub fn prune_layer(&mut self, layer_idx: usize, sparsity: f64) {
+        let mut w = self.layers[layer_idx].weights().into_owned();
+        let mut weights: Vec<(f64, (usize, usize))> =
+            w.indexed_iter().map(|(idx, x)| (x.abs(), idx)).collect();
+        let num_pruned = (sparsity * weights.len() as f64).round() as usize;
+
+        weights.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        for (_, idx) in weights.into_iter().take(num_pruned) {
+            self.masks[layer_idx][idx] = false;
+            w[idx] = 0f64;
+        }
+
+        self.layers[layer_idx].set_weights(w);
+    }
+
+    /// Remove the (1 - keep_fraction) fraction of the neurons of a hidden layer whose incoming weights have the
+    /// smallest L2 norms (keeping at least one), shrinking the layer and the inputs of the next one
+    /// The optimizer state is reset, since it no longer matches the shapes of the parameters
+    pub fn prune_neurons(&mut self, layer_idx: usize, keep_fraction: f64) -> Result<()> {
+        let norms = self.neuron_norms(layer_idx)?;
+        let num_kept =
+            ((keep_fraction * norms.len() as f64).round() as usize).clamp(1, norms.len());
+        let mut order: Vec<usize> = (0..norms.len()).collect();
+
+        order.sort_by(|&a, &b| norms[b].total_cmp(&norms[a]));
+        order.truncate(num_kept);
+        order.sort_unstable();
+
+        self.remove_neurons(layer_idx, &order)
+    }
+
+    /// The L2 norm of the incoming weights of each neuron of a hidden layer
+    pub(super) fn neuron_norms(&self, layer_idx: usize) -> Result<Array1<f64>> {
+        if layer_idx + 1 >= self.layers.len() {
+            return Err(NeuralNetError::InvalidLayer {
+                index: layer_idx,
+                num_layers: self.layers.len() - 1,
+            });
+        }
+
+        Ok(self.layers[layer_idx]
+            .weights()
+            .map_axis(Axis(0), |column| column.dot(&column).sqrt()))
+    }
+
+    /// Keep only these neurons of a hidden layer: their columns of its weights and elements of its biases, and
+    /// their rows of the weights of the next layer. The per-weight training state is shrunk the same way
+    pub(super) fn remove_neurons(&mut self, layer_idx: usize, keep: &[usize]) -> Result<()> {
+        self.neuron_norms(layer_idx)?;
+
+        let next_idx = layer_idx + 1;
+        let select_layer = |weights: &ArrayView2<f64>, biases: &ArrayView1<f64>| {
+            (weights.select(Axis(1), keep), biases.select(Axis(0), keep))
+        };
+        let (weights, biases) = select_layer(
+            &self.layers[layer_idx].weights().view(),
+            &self.layers[layer_idx].biases().view(),
+        );
+        let next_weights = self.layers[next_idx].weights().select(Axis(0), keep);
+        let next_biases = self.layers[next_idx].biases().into_owned();
+
+        self.layers[layer_idx] = self.dense_layer(weights, biases);
+        self.layers[next_idx] = self.dense_layer(next_weights, next_biases);
+        self.masks[layer_idx] = self.masks[layer_idx].select(Axis(1), keep);
+        self.masks[next_idx] = self.masks[next_idx].select(Axis(0), keep);
+
+        if let Some(weight_norm) = &mut self.weight_norm {
+            for idx in [layer_idx, next_idx] {
+                weight_norm[idx] = init_weight_norm_from_dense(&self.layers[idx].weights().view());
+            }
+        }
+
+        if let Some(variational_dropout) = &mut self.variational_dropout {
+            let layers = &mut variational_dropout.layers;
+
+            layers[layer_idx].log_alpha = layers[layer_idx].log_alpha.select(Axis(1), keep);
+            layers[next_idx].log_alpha = layers[next_idx].log_alpha.select(Axis(0), keep);
+        }
+
+        if let Some(ewc) = &mut self.ewc {
+            ewc.fisher[layer_idx] = ewc.fisher[layer_idx].select(Axis(1), keep);
+            ewc.fisher[next_idx] = ewc.fisher[next_idx].select(Axis(0), keep);
+            ewc.anchors[layer_idx] = select_layer(
+                &ewc.anchors[layer_idx].0.view(),
+                &ewc.anchors[layer_idx].1.view(),
+            );
+            ewc.anchors[next_idx].0 = ewc.anchors[next_idx].0.select(Axis(0), keep);
+        }
+
+        // The centers are in the space of the inputs of the output layer
+        if let Some(center_loss) = self
+            .center_loss
+            .as_mut()
+            .filter(|_| next_idx + 1 == self.layers.len())
+        {
+            center_loss.centers = center_loss.centers.select(Axis(1), keep);
+        }
+
+        self.optimizer_state = OptimizerState::new();
+        self.spectral_u.clear();
+        self.prev_weight_grads.clear();
+
+        Ok(())
+    }
+
+    /// Return the fraction of pruned weights in each layer
+    pub fn pruning_stats(&self) -> Vec<(usize, f64)> {
+        self.masks
+            .iter()
+            .enumerate()
+            .map(|(i, mask)| {
+                let num_pruned = mask.iter().filter(|keep| !**keep).count();
+
+                (i, num_pruned as f64 / mask.len() as f64)
+            })
+            .collect()
+    }
+
+    /// Quantize the weights and biases of the model to int8
+    pub fn quantize(&self) -> QuantizedNeuralNet {
+        QuantizedNeuralNet::from_net(self)
+    }
+
+    /// Quantize the weights and biases of the model to int8, and the inputs of each layer as well (post-training
+    /// static quantization). The range of the inputs of each layer is calibrated on the calibration data
+    pub fn quantize_activations(&self, calibration_data: &Array2<f64>) -> QuantizedNeuralNet {
+        let (hidden, _, _) = self.forward(&calibration_data.view(), false);
+        let ranges: Vec<(f64, f64)> = hidden
+            .iter()
+            .take(self.layers.len())
+            .map(|inputs| {
+                (
+                    inputs.iter().copied().fold(f64::INFINITY, f64::min),
+                    inputs.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                )
+            })
+            .collect();
+
+        QuantizedNeuralNet::from_net(self).with_activation_ranges(&ranges)
+    }
+
+    /// Copy the weights and biases of the first num_layers layers from source, and freeze them
+    pub fn transfer_from(&mut self, source: &NeuralNet, num_layers: usize) -> Result<()> {
+        if num_layers > source.layers.len() || num_layers > self.layers.len() {
+            return Err(NeuralNetError::ShapeMismatch {
+                expected: vec![num_layers],
+                actual: vec![source.layers.len().min(self.layers.len())],
+            });
+        }
+
+        for i in 0..num_layers {
+            if source.layers[i].weights().shape() != self.layers[i].weights().shape() {
+                return Err(NeuralNetError::ShapeMismatch {
+                    expected: self.layers[i].weights().shape().to_vec(),
+                    actual: source.layers[i].weights().shape().to_vec(),
+                });
+            }
+        }
+
+        for i in 0..num_layers {
+            self.layers[i] = source.layers[i].clone();
+        }
+
+        self.freeze_layers(num_layers);
+
+        Ok(())
+    }
+
+    /// Load a model saved with save, and transfer its first num_layers layers to this model
+    pub fn load_and_transfer(mut self, path: &str, num_layers: usize) -> Result<NeuralNet> {
+        let source = NeuralNet::load(path)?;
+        self.transfer_from(&source, num_layers)?;
+
+        Ok(self)
+    }
+
+    /// Run the forward pass and return the outputs of layer layer_idx after the activation function
+    /// Layer 0 is the input layer, and the last layer is the output layer (whose output isn't activated)
+    /// The inputs are scaled by the input scaler first, so layer 0 holds the scaled inputs
+    pub fn extract_features(
+        &self,
+        inputs: &ArrayView2<f64>,
+        layer_idx: usize,
+    ) -> Result<Array2<f64>> {
+        self.check_layer_idx(layer_idx)?;
+
+        let (mut hidden, _, _) = self.forward(&self.scaled_inputs(inputs).view(), false);
+
+        Ok(hidden.swap_remove(layer_idx))
+    }
+
+    /// Run the forward pass and return the outputs of layer layer_idx before the activation function
+    /// The input layer has no linear outputs, so layer_idx must be at least 1
+    pub fn extract_linear_features(
+        &self,
+        inputs: &ArrayView2<f64>,
+        layer_idx: usize,
+    ) -> Result<Array2<f64>> {
+        self.check_layer_idx(layer_idx)?;
+
+        if layer_idx == 0 {
+            return Err(NeuralNetError::InvalidLayer {
+                index: layer_idx,
+                num_layers: self.layers.len() + 1,
+            });
+        }
+
+        let (_, mut hidden_linear, _) = self.forward(&self.scaled_inputs(inputs).view(), false);
+
+        Ok(hidden_linear.swap_remove(layer_idx - 1))
+    }
+
+    /// Embed the inputs using the outputs of the penultimate layer
+    pub fn embed(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        self.extract_features(inputs, self.layers.len() - 1)
+            .unwrap()
+    }
+
+    /// Check that layer_idx is the index of one of the layers, including the input layer
+    fn check_layer_idx(&self, layer_idx: usize) -> Result<()> {
+        if layer_idx > self.layers.len() {
+            return Err(NeuralNetError::InvalidLayer {
+                index: layer_idx,
+                num_layers: self.layers.len() + 1,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The inputs of inference scaled by the input scaler, or the inputs themselves if there isn't one
+    fn scaled_inputs<'a>(&self, inputs: &ArrayView2<'a, f64>) -> CowArray<'a, f64, Ix2> {
+        match &self.input_scaler {
+            Some(scaler) => CowArray::from(scaler.transform(&inputs.to_owned())),
+            None => CowArray::from(*inputs),
+        }
+    }
+
+    /// Compute the raw outputs of the output layer (before the softmax) for a set of instances
+    /// The inputs are scaled by the input scaler first, if there is one
+    pub fn logits(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        let (mut hidden, _, _) = self.forward(&self.scaled_inputs(inputs).view(), false);
+
+        hidden.pop().unwrap()
+    }
+
+    /// Compute the raw outputs of the output layer like during training, i.e. with dropout
+    pub fn forward_train(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        let (mut hidden, _, _) = self.forward(inputs, true);
+
+        hidden.pop().unwrap()
+    }
+
+    /// Predict the probabilities with the logits divided by temperature, instead of the (calibrated) temperature of the net
+    /// A temperature below 1 sharpens the distribution, and a temperature above 1 flattens it
+    pub fn predict_with_temperature(
+        &self,
+        inputs: &ArrayView2<f64>,
+        temperature: f64,
+    ) -> Array2<f64> {
+        self.apply_output_activation(self.logits(inputs) / temperature)
+    }
+
+    /// Classify the instances by the first output of a regression model: the class of an instance is the number of
+    /// the (increasing) thresholds its output reaches, e.g. a single threshold gives a binary classifier
+    pub fn predict_class_from_regression(
+        &self,
+        inputs: &ArrayView2<f64>,
+        thresholds: &[f64],
+    ) -> Array1<usize> {
+        self.predict(inputs).column(0).mapv(|x| {
+            thresholds
+                .iter()
+                .filter(|&&threshold| x >= threshold)
+                .count()
+        })
+    }
+
+    /// Evaluate the model on a CSV dataset read line by line from reader, e.g. a test set too large to fit in memory
+    /// Only batch_size lines are held at a time, and the metrics are computed from the accumulated confusion matrix
+    pub fn evaluate_streaming<R: BufRead>(
+        &self,
+        reader: R,
+        batch_size: usize,
+        config: &CsvConfig,
+    ) -> Result<EvaluationResult> {
+        let mut matrix = Array2::zeros((config.num_classes, config.num_classes));
+        let mut total_loss = 0f64;
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut evaluate_batch = |batch: Vec<(Vec<f64>, usize)>| -> Result<()> {
+            let dataset = Dataset::from_records(batch, config.num_classes)?;
+
+            matrix += &confusion_matrix(&self.predict(&dataset.data.view()), &dataset.target);
+            total_loss += per_sample_loss(self, &dataset).sum();
+
+            Ok(())
+        };
+
+        for (line_idx, line) in reader.lines().enumerate() {
+            let line = line?;
+
+            if (line_idx == 0 && config.has_header) || line.trim().is_empty() {
+                continue;
+            }
+
+            let record = config
+                .parse_record(&line)
+                .map_err(|msg| NeuralNetError::Parse(format!("Line {}: {}", line_idx + 1, msg)))?;
+            batch.push(record);
+
+            if batch.len() == batch_size {
+                evaluate_batch(std::mem::replace(
+                    &mut batch,
+                    Vec::with_capacity(batch_size),
+                ))?;
+            }
+        }
+
+        // The last batch may be smaller
+        if !batch.is_empty() {
+            evaluate_batch(batch)?;
+        }
+
+        let total = matrix.sum();
+
+        if total == 0f64 {
+            return Err(NeuralNetError::Parse("The dataset is empty".to_string()));
+        }
+
+        Ok(EvaluationResult::from_confusion_matrix(
+            &matrix,
+            total_loss / total,
+        ))
+    }
+
+    /// Predict using Monte Carlo dropout: run n_samples forward passes with dropout enabled (and nothing else of
+    /// training, e.g. noise or stochastic depth), and turn each into predictions like predict does
+    /// Returns the mean and the variance of the predictions. The variance estimates the model's uncertainty
+    /// The inputs are scaled by the input scaler first, if there is one
+    pub fn predict_mc_dropout(
+        &self,
+        inputs: &ArrayView2<f64>,
+        n_samples: usize,
+    ) -> (Array2<f64>, Array2<f64>) {
+        let inputs = self.scaled_inputs(inputs);
+        let num_outputs = self.layers.last().unwrap().biases().len();
+        let mut sum = Array2::<f64>::zeros((inputs.nrows(), num_outputs));
+        let mut sum_squares = Array2::<f64>::zeros((inputs.nrows(), num_outputs));
+
+        for _ in 0..n_samples {
+            let predictions = self
+                .apply_output_activation(self.dropout_logits(&inputs.view()) / self.temperature);
+
+            sum_squares = sum_squares + &predictions * &predictions;
+            sum = sum + predictions;
+        }
+
+        let mean = sum / n_samples as f64;
+        // Var(X) = E[X^2] - E[X]^2
+        let variance = (sum_squares / n_samples as f64 - &mean * &mean).mapv(|x| x.max(0f64));
+
+        (mean, variance)
+    }
+
+    /// The raw outputs of a forward pass where the outputs of the hidden layers are randomly dropped
+    fn dropout_logits(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        let mut rng = self.rng.lock().unwrap();
+        let mut output = inputs.to_owned();
+
+        for (idx, layer) in self.layers.iter().enumerate() {
+            let (lin_output, _) = layer.forward(&output, false);
+
+            output = if idx + 1 < self.layers.len() {
+                let real_output = lin_output.mapv(|x| activation(&self.activation_function, x));
+
+                if self.dropout_rate > 0f64 {
+                    real_output * dropout_mask(lin_output.dim(), self.dropout_rate, &mut *rng)
+                } else {
+                    real_output
+                }
+            } else {
+                lin_output
+            };
+        }
+
+        output
+    }
+
+    /// Predict the probabilities with test-time augmentation: in each of n_aug passes, every instance is
+    /// augmented by a transform sampled uniformly from transforms, and the predictions of the passes are averaged
+    /// The transforms must keep the number of features
+    pub fn predict_tta(
+        &self,
+        inputs: &ArrayView2<f64>,
+        transforms: &[Box<dyn Transform>],
+        n_aug: usize,
+    ) -> Array2<f64> {
+        let num_classes = self.layers.last().unwrap().biases().len();
+        let mut sum = Array2::<f64>::zeros((inputs.nrows(), num_classes));
+
+        for _ in 0..n_aug {
+            let choices: Vec<usize> = {
+                let mut rng = self.rng.lock().unwrap();
+
+                (0..inputs.nrows())
+                    .map(|_| rng.gen_range(0..transforms.len()))
+                    .collect()
+            };
+            let mut augmented = inputs.to_owned();
+
+            for (transform_idx, transform) in transforms.iter().enumerate() {
+                let rows: Vec<usize> = (0..inputs.nrows())
+                    .filter(|&row| choices[row] == transform_idx)
+                    .collect();
+
+                if rows.is_empty() {
+                    continue;
+                }
+
+                let transformed = transform.transform(&inputs.select(Axis(0), &rows));
+
+                for (&row, transformed_row) in rows.iter().zip(transformed.axis_iter(Axis(0))) {
+                    augmented.row_mut(row).assign(&transformed_row);
+                }
+            }
+
+            sum += &self.predict(&augmented.view());
+        }
+
+        sum / n_aug as f64
+    }
+
+    pub fn predict_mc_dropout_single(
+        &self,
+        input: &ArrayView1<f64>,
+        n_samples: usize,
+    ) -> (Array1<f64>, Array1<f64>) {
+        let inputs = input.view().insert_axis(Axis(0));
+        let (mean, variance) = self.predict_mc_dropout(&inputs, n_samples);
+
+        (mean.row(0).to_owned(), variance.row(0).to_owned())
+    }
+
+    /// Find the temperature that minimizes the NLL on the validation set, and store it in the model
+    /// The temperature is searched for on a logarithmic grid over [0.1, 10]
+    pub fn calibrate_temperature(&mut self, val_dataset: &Dataset) -> f64 {
+        const NUM_TEMPERATURES: usize = 200;
+        const MIN_TEMPERATURE: f64 = 0.1;
+        const MAX_TEMPERATURE: f64 = 10f64;
+
+        // The logits don't depend on the temperature so we only need to compute them once
+        let logits = self.logits(&val_dataset.data.view());
+        let log_ratio = (MAX_TEMPERATURE / MIN_TEMPERATURE).ln();
+
+        let (temperature, _) = (0..NUM_TEMPERATURES)
+            .map(|i| {
+                let t =
+                    MIN_TEMPERATURE * (log_ratio * i as f64 / (NUM_TEMPERATURES - 1) as f64).exp();
+                (
+                    t,
+                    cross_entropy_from_logits(&(&logits / t), val_dataset.target.view()),
+                )
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+
+        self.temperature = temperature;
+
+        temperature
+    }
+
+    /// Write the weights of the model in JSON format
+    /// The keys are e.g. W0, b0, W1, b1. The values are provided in an array of the weights
+    /// The pruned weights of each layer are stored in Mi as their (flat) indices in Wi
+    /// The activation function is stored as well, so that the model can be loaded back
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut data = object! {};
+        let mut file = File::create(path)?;
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let w: Vec<f64> = layer.weights().iter().copied().collect();
+            let b: Vec<f64> = layer.biases().iter().copied().collect();
+            let w_key = format!("W{}", i);
+            let b_key = format!("b{}", i);
+
+            data[w_key] = w.into();
+            data[b_key] = b.into();
+
+            let pruned: Vec<usize> = self.masks[i]
+                .iter()
+                .enumerate()
+                .filter(|(_, &keep)| !keep)
+                .map(|(idx, _)| idx)
+                .collect();
+
+            if !pruned.is_empty() {
+                data[format!("M{}", i)] = pruned.into();
+            }
+
+            // Embedding layers have no biases, so their width is stored to recover the shape of their weights
+            if layer.kind() == "embedding" {
+                data[format!("embedding_dim{}", i)] = layer.weights().ncols().into();
+            }
+        }
+
+        // The weight norm parameters are saved in addition to the effective weights, which load uses
+        if let Some(weight_norm) = &self.weight_norm {
+            for (i, layer) in weight_norm.iter().enumerate() {
+                let g: Vec<f64> = layer.g.iter().copied().collect();
+                let v: Vec<f64> = layer.v.iter().copied().collect();
+
+                data[format!("g{}", i)] = g.into();
+                data[format!("v{}", i)] = v.into();
+            }
+        }
+
+        if let Some(variational_dropout) = &self.variational_dropout {
+            for (i, layer) in variational_dropout.layers.iter().enumerate() {
+                let log_alpha: Vec<f64> = layer.log_alpha.iter().copied().collect();
+
+                data[format!("log_alpha{}", i)] = log_alpha.into();
+            }
+        }
+
+        if let Some(name) = self.activation_function.to_possible_value() {
+            data["activation"] = name.get_name().into();
+        }
+
+        if let Some(name) = self.output_activation.to_possible_value() {
+            data["output_activation"] = name.get_name().into();
+        }
+
+        if let Some(name) = self.task.to_possible_value() {
+            data["task"] = name.get_name().into();
+        }
+
+        if let Some(scaler) = &self.input_scaler {
+            data["input_scaler"] = scaler.to_json();
+        }
+
+        file.write_all(data.dump().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Load the weights of each layer i from the files Wi.npy and bi.npy in a directory (e.g. saved with np.save)
+    /// The weight matrices have a row for each input and a column for each output, so the weights
+    /// of a PyTorch nn.Linear have to be transposed before saving them
+    pub fn load_weights_from_npy_dir(&mut self, dir: &str) -> Result<()> {
+        let dir = Path::new(dir);
+        let mut layers: Vec<Box<dyn Layer>> = vec![];
+
+        for (idx, layer) in self.layers.iter().enumerate() {
+            let (weights, biases) = (layer.weights(), layer.biases());
+            let path = |name: String| dir.join(name).to_string_lossy().into_owned();
+            let new_weights = npy::load_npy_f64_2d(&path(format!("W{}.npy", idx)))?;
+            let new_biases = npy::load_npy_f64_1d(&path(format!("b{}.npy", idx)))?;
+
+            if new_weights.dim() != weights.dim() || new_biases.len() != biases.len() {
+                return Err(NeuralNetError::ShapeMismatch {
+                    expected: vec![weights.nrows(), weights.ncols(), biases.len()],
+                    actual: vec![new_weights.nrows(), new_weights.ncols(), new_biases.len()],
+                });
+            }
+
+            layers.push(self.dense_layer(new_weights, new_biases));
+        }
+
+        // NumPy files don't say which weights were pruned, so none of them are
+        self.masks = layers
+            .iter()
+            .map(|layer| Array2::from_elem(layer.weights().dim(), true))
+            .collect();
+        self.layers = layers;
+
+        Ok(())
+    }
+
+    /// Load a model saved with save. The shapes of the layers are inferred from the lengths of the weights
+    /// The training hyperparams of the loaded model are the defaults of NeuralNetBuilder
+    pub fn load(path: &str) -> Result<NeuralNet> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+
+        let data = json::parse(&contents).map_err(|e| NeuralNetError::Parse(e.to_string()))?;
+        let parse_vec = |key: &str| -> Result<Vec<f64>> {
+            data[key]
+                .members()
+                .map(|x| {
+                    x.as_f64().ok_or_else(|| {
+                        NeuralNetError::Parse(format!("Non-numeric value in {}", key))
+                    })
+                })
+                .collect()
+        };
+        let mut layers: Vec<Box<dyn Layer>> = vec![];
+
+        while data.has_key(&format!("W{}", layers.len())) {
+            let i = layers.len();
+            let w = parse_vec(&format!("W{}", i))?;
+            let b = parse_vec(&format!("b{}", i))?;
+
+            if let Some(embed_dim) = data[format!("embedding_dim{}", i)].as_usize() {
+                let weights = Array2::from_shape_vec((w.len() / embed_dim.max(1), embed_dim), w)
+                    .map_err(|e| NeuralNetError::Parse(e.to_string()))?;
+
+                layers.push(Box::new(EmbeddingLayer::from_weights(weights)));
+                continue;
+            }
+
+            if b.is_empty() || w.len() % b.len() != 0 {
+                return Err(NeuralNetError::Parse(format!(
+                    "The shapes of W{} and b{} are incompatible",
+                    i, i
+                )));
+            }
+
+            let weights = Array2::from_shape_vec((w.len() / b.len(), b.len()), w)
+                .map_err(|e| NeuralNetError::Parse(e.to_string()))?;
+
+            layers.push(Box::new(DenseLayer::new(weights, Array1::from_vec(b))));
+        }
+
+        if layers.is_empty() {
+            return Err(NeuralNetError::Parse(format!("No weights in {}", path)));
+        }
+
+        let activation_function = match data["activation"].as_str() {
+            Some(name) => {
+                ActivationFunction::from_str(name, true).map_err(NeuralNetError::Parse)?
+            }
+            None => ActivationFunction::ReLU,
+        };
+        // Models saved before the task was stored are multi-class
+        let task = match data["task"].as_str() {
+            Some(name) => Task::from_str(name, true).map_err(NeuralNetError::Parse)?,
+            None => Task::Multiclass,
+        };
+        let output_activation = match data["output_activation"].as_str() {
+            Some(name) => {
+                ActivationFunction::from_str(name, true).map_err(NeuralNetError::Parse)?
+            }
+            None => ActivationFunction::Linear,
+        };
+        let mut layer_structure: Vec<usize> =
+            layers.iter().map(|layer| layer.weights().nrows()).collect();
+        layer_structure.push(layers.last().unwrap().weights().ncols());
+
+        let mut net = NeuralNetBuilder::new(layer_structure)
+            .activation_function(activation_function)
+            .task(task)
+            .build()
+            .with_output_activation(output_activation);
+        net.layers = layers;
+
+        if data.has_key("input_scaler") {
+            net.input_scaler = Some(transform_from_json(&data["input_scaler"])?);
+        }
+
+        // Weights that were pruned before saving keep being pruned. Models saved before the masks were stored
+        // have no pruned weights
+        for (i, mask) in net.masks.iter_mut().enumerate() {
+            *mask = Array2::from_elem(net.layers[i].weights().dim(), true);
+
+            for idx in data[format!("M{}", i)].members() {
+                let idx = idx
+                    .as_usize()
+                    .filter(|&idx| idx < mask.len())
+                    .ok_or_else(|| {
+                        NeuralNetError::Parse(format!("Invalid pruned index in M{}", i))
+                    })?;
+
+                mask.as_slice_mut().unwrap()[idx] = false;
+            }
+        }
+
+        Ok(net)
+    }
+
+    /// Run the epochs of a fit: num_epochs of them if it's set, and until the loss stops improving otherwise
+    /// The loss is the validation loss if there is a validation set, and the training loss otherwise
+    fn fit_loop(
+        &mut self,
+        mut fit_epoch: impl FnMut(&mut Self, &mut TrainingHistory),
+    ) -> TrainingHistory {
+        self.validate_config();
+
+        let mut history = TrainingHistory::new();
+        let start = Instant::now();
+        let mut fit_epoch = |net: &mut Self, history: &mut TrainingHistory| {
+            fit_epoch(net, history);
+            net.log_epoch(history, start);
+            net.run_callbacks(|callback, net| callback.on_epoch_end(net, history));
+        };
+
+        if let Some(num_epochs) = self.num_epochs {
+            for _ in 0..num_epochs {
+                fit_epoch(self, &mut history);
+            }
+        } else {
+            let mut prev_loss;
+            let mut curr_loss = f64::INFINITY;
+
+            loop {
+                fit_epoch(self, &mut history);
+
+                prev_loss = curr_loss;
+                curr_loss = history.last_loss().unwrap();
+
+                if prev_loss - curr_loss < self.early_stopping_epsilon {
+                    break;
+                }
+            }
+        }
+
+        history
+    }
+
+    /// Log the losses of the last epoch in the history
+    fn log_epoch(&self, history: &TrainingHistory, start: Instant) {
+        if self.verbosity < Verbosity::Epoch {
+            return;
+        }
+
+        // With early stopping we don't know in advance how many epochs there are going to be
+        let num_epochs = self
+            .num_epochs
+            .map_or("?".to_string(), |num_epochs| num_epochs.to_string());
+        let val_loss = history.val_losses.last().map_or(String::new(), |val_loss| {
+            format!(" val_loss={:.4}", val_loss)
+        });
+
+        eprintln!(
+            "[Epoch {}/{}] loss={:.4}{} lr={:.6} elapsed={:.1}s",
+            history.num_epochs(),
+            num_epochs,
+            history.train_losses.last().unwrap(),
+            val_loss,
+            self.learning_rate,
+            start.elapsed().as_secs_f64()
+        );
+    }
+
+    /// Fit the model to batches whose instances are stacked groups (e.g. pairs) compared by the loss
+    fn fit_stacked(
+        &mut self,
+        batches: Vec<(Array2<f64>, Array2<f64>)>,
+        validation: Option<(Array2<f64>, Array2<f64>)>,
+    ) -> TrainingHistory {
+        self.fit_loop(|net, history| {
+            let total_loss: f64 = batches
+                .iter()
+                .map(|(inputs, targets)| net.partial_fit(&inputs.view(), &targets.view()))
+                .sum();
+            history.train_losses.push(total_loss / batches.len() as f64);
+
+            if let Some((inputs, targets)) = &validation {
+                let logits = net.logits(&inputs.view());
+                history.val_losses.push(net.loss_function.loss(
+                    &logits,
+                    &targets.view(),
+                    &inputs.view(),
+                ));
+            }
+        })
+    }
+
+    /// Fit the model to pairs of similar/dissimilar instances using the contrastive loss
+    /// The output of the network is used as an embedding rather than class scores
+    pub fn fit_pairs(
+        &mut self,
+        pairs: &PairedDataset,
+        validation: Option<&PairedDataset>,
+    ) -> TrainingHistory {
+        assert!(
+            matches!(self.loss_function, LossFunction::Contrastive { .. }),
+            "Fitting pairs requires the contrastive loss"
+        );
+
+        let batches = pairs.stacked_batches(self.batch_size);
+        let validation = validation.map(|validation| validation.stack(0, validation.labels.len()));
+
+        self.fit_stacked(batches, validation)
+    }
+
+    /// Fit the model to (anchor, positive, negative) triplets using the triplet loss
+    /// The output of the network is used as an embedding rather than class scores
+    pub fn fit_triplets(
+        &mut self,
+        triplets: &TripletDataset,
+        validation: Option<&TripletDataset>,
+    ) -> TrainingHistory {
+        assert!(
+            matches!(self.loss_function, LossFunction::Triplet { .. }),
+            "Fitting triplets requires the triplet loss"
+        );
+
+        let batches = triplets.stacked_batches(self.batch_size);
+        let validation =
+            validation.map(|validation| validation.stack(0, validation.anchors.nrows()));
+
+        self.fit_stacked(batches, validation)
+    }
+
+    /// Split the dataset into a training set and a stratified validation set holding val_fraction of the instances,
+    /// and fit the model to the training set
+    pub fn fit_with_auto_split(
+        &mut self,
+        dataset: Dataset,
+        val_fraction: f64,
+        seed: Option<u64>,
+    ) -> TrainingHistory {
+        let (train, validation) = dataset.split(val_fraction, seed);
+
+        self.fit(&train, Some(&validation))
+    }
+
+    /// The input dimension of a network for the dataset, which is its number of features
+    pub fn infer_input_shape(dataset: &Dataset) -> usize {
+        dataset.data.ncols()
+    }
+
+    /// Check that the instances of the dataset have as many features as the input layer, and that the input layer
+    /// can take their values (e.g. the indices of an embedding layer are in its vocabulary)
+    pub fn check_input_shape(&self, dataset: &Dataset) -> Result<()> {
+        self.layers[0].validate_input(&dataset.data.view())?;
+
+        let Some(input_dim) = self.layers[0].input_dim() else {
+            return Ok(());
+        };
+
+        if dataset.data.ncols() != input_dim {
+            return Err(NeuralNetError::ShapeMismatch {
+                expected: vec![dataset.data.nrows(), input_dim],
+                actual: dataset.data.shape().to_vec(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Check that the training options of the net can be used together
+    /// DP-SGD clips the gradient of each sample, and the center loss is computed on the inputs of the output layer,
+    /// so both need activations that gradient checkpointing doesn't keep
+    pub fn check_config(&self) -> Result<()> {
+        if self.gradient_checkpointing.is_none() {
+            return Ok(());
+        }
+
+        if self.differential_privacy.is_some() {
+            return Err(NeuralNetError::InvalidConfig(
+                "DP-SGD can't be used with gradient checkpointing".to_string(),
+            ));
+        }
+
+        if self.center_loss.is_some() {
+            return Err(NeuralNetError::InvalidConfig(
+                "The center loss can't be used with gradient checkpointing".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Panic before training if the training options of the net can't be used together, like fit does
+    /// The fits check once up front, so that a bad configuration doesn't abort them after some of the epochs
+    fn validate_config(&self) {
+        if let Err(err) = self.check_config() {
+            panic!("Failed to fit the model: {}", err);
+        }
+    }
+
+    /// Fit the model like fit, returning an error instead of panicking if the datasets don't match the input layer
+    /// or the training options can't be used together
+    pub fn try_fit(
+        &mut self,
+        dataset: &Dataset,
+        validation: Option<&Dataset>,
+    ) -> Result<TrainingHistory> {
+        self.check_config()?;
+        self.check_input_shape(dataset)?;
+
+        if let Some(validation) = validation {
+            self.check_input_shape(validation)?;
+        }
+
+        Ok(self.fit_loop(|net, history| net.fit_epoch(dataset, validation, history)))
+    }
+}
+
+impl Model for NeuralNet {
+    /// Fit the model to the dataset
+    /// Return the training and validation losses of each epoch (used for plotting)
+    /// Early stopping uses the validation loss if a validation set is provided, and the training loss otherwise
+    fn fit(&mut self, dataset: &Dataset, validation: Option<&Dataset>) -> TrainingHistory {
+        match self.try_fit(dataset, validation) {
+            Ok(history) => history,
+            Err(err) => panic!("Failed to fit the model: {}", err),
+        }
+    }
+
+    /// Predict the probabities for a set of instances - each instance is a row in "inputs"
+    fn predict(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        self.apply_output_activation(self.logits(inputs) / self.temperature)
+    }
+}
+
+/// Numerically stable sigmoid: exp is only ever applied to non-positive values, so it can't overflow
+pub fn sigmoid(z: f64) -> f64 {
+    if z >= 0f64 {
+        1f64 / (1f64 + (-z).exp())
+    } else {
+        let ez = z.exp();
+
+        ez / (1f64 + ez)
+    }
+}
+
+/// Numerically stable softplus log(1 + exp(z)) = max(z, 0) + log(1 + exp(-|z|))
+pub fn softplus(z: f64) -> f64 {
+    z.max(0f64) + (-z.abs()).exp().ln_1p()
+}
+
+pub fn activation(name: &ActivationFunction, z: f64) -> f64 {
+    match name {
+        ActivationFunction::ReLU => z.max(0f64),
+        ActivationFunction::Sigmoid => sigmoid(z),
+        ActivationFunction::Tanh => z.tanh(),
+        ActivationFunction::Linear => 3f64 * z + 1f64,
+        ActivationFunction::LeakyReLU => z.max(0.01 * z),
+    }
+}
+
+pub(super) fn delta_activation(name: &ActivationFunction, z: f64) -> f64 {
+    match name {
+        ActivationFunction::ReLU => {
+            if z > 0f64 {
+                1f64
+            } else {
+                0f64
+            }
+        }
+        ActivationFunction::Sigmoid => activation(name, z) * (1f64 - activation(name, z)),
+        ActivationFunction::Tanh => 1f64 - activation(name, z) * activation(name, z),
+        ActivationFunction::Linear => 3f64,
+        ActivationFunction::LeakyReLU => {
+            if z > 0f64 {
+                1f64
+            } else {
+                0.01f64
+            }
+        }
+    }
+}
+
+/// The second derivative of an activation function, e.g. for second-order methods that use the Hessian
+/// The piecewise linear activations have a second derivative of 0 (except at 0, where it's undefined)
+pub fn second_delta_activation(name: &ActivationFunction, z: f64) -> f64 {
+    match name {
+        ActivationFunction::Sigmoid => {
+            let sigma = activation(name, z);
+
+            sigma * (1f64 - sigma) * (1f64 - 2f64 * sigma)
+        }
+        ActivationFunction::Tanh => {
+            let tanh = activation(name, z);
+
+            -2f64 * tanh * (1f64 - tanh * tanh)
+        }
+        ActivationFunction::ReLU | ActivationFunction::Linear | ActivationFunction::LeakyReLU => {
+            0f64
+        }
+    }
+}
+
+/// Find the LR at which the loss decreases the fastest, i.e. the most negative slope of the loss WRT log(LR)
+/// The losses of single batches are noisy, so they are smoothed with an exponential moving average first
+fn steepest_descent_lr(lrs: &[f64], losses: &[f64]) -> f64 {
+    const SMOOTHING: f64 = 0.9;
+
+    let mut smoothed = Vec::with_capacity(losses.len());
+    let mut average = 0f64;
+
+    for (i, loss) in losses.iter().enumerate() {
+        average = SMOOTHING * average + (1f64 - SMOOTHING) * loss;
+        // Correct the bias towards the initial value of the average
+        smoothed.push(average / (1f64 - SMOOTHING.powi(i as i32 + 1)));
+    }
+
+    (1..lrs.len())
+        .filter(|&i| smoothed[i].is_finite())
+        .map(|i| {
+            let slope = (smoothed[i] - smoothed[i - 1]) / (lrs[i].ln() - lrs[i - 1].ln());
+
+            (lrs[i], slope)
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(lr, _)| lr)
+        .unwrap_or(lrs[0])
+}
+
+/// The number of weight layers in a network with the given structure
+fn layer_structure_len(layer_structure: &[usize]) -> usize {
+    layer_structure.len().saturating_sub(1)
+}
+
+fn init_layers_default(layer_structure: &[usize], rng: &mut impl Rng) -> Vec<Box<dyn Layer>> {
+    let mut layers: Vec<Box<dyn Layer>> = vec![];
+    // Weights are initialized from a uniform distribiution
+    let distribution = Uniform::new(-0.3, 0.3);
+
+    for i in 0..layer_structure.len() - 1 {
+        // Random matrix of the weights between this layer and the next layer
+        let weights = Array::zeros((layer_structure[i], layer_structure[i + 1]))
+            .map(|_: &f64| distribution.sample(rng));
+        // Bias vector between this layer and the next layer. Init'd to ondes
+        let bias = Array::ones(layer_structure[i + 1]);
+
+        layers.push(Box::new(DenseLayer::new(weights, bias)));
+    }
+
+    layers
+}
+
+fn init_layers_xavier(layer_structure: &[usize], rng: &mut impl Rng) -> Vec<Box<dyn Layer>> {
+    let mut layers: Vec<Box<dyn Layer>> = vec![];
+
+    for i in 0..layer_structure.len() - 1 {
+        let weights = Array2::zeros((layer_structure[i], layer_structure[i + 1]));
+        let (fan_in, fan_out) = compute_fan(&weights.view());
+        // Xavier init keeps the variance of the activations and the gradients similar between layers
+        let boundary = variance_scaling(
+            fan_in,
+            fan_out,
+            FanMode::FanAvg,
+            init::Distribution::Uniform,
+            1f64,
+        );
+        let dist = Uniform::new(-boundary, boundary);
+
+        let weights = weights.map(|_: &f64| dist.sample(rng));
+        let bias = Array::zeros(layer_structure[i + 1]);
+
+        layers.push(Box::new(DenseLayer::new(weights, bias)));
+    }
+
+    layers
+}
+
+/// Scale a vector to unit L2 norm. The zero vector is returned as is
+fn normalize(v: Array1<f64>) -> Array1<f64> {
+    let norm = v.dot(&v).sqrt();
+
+    if norm > 0f64 {
+        v / norm
+    } else {
+        v
+    }
+}
+
+/// Sample an inverted dropout mask - kept units are scaled by 1 / (1 - rate) so that
+/// the expected output of each layer doesn't change between training and inference
+fn dropout_mask(dim: (usize, usize), rate: f64, rng: &mut impl Rng) -> Array2<f64> {
+    let distribution = Bernoulli::new(1f64 - rate).unwrap();
+    let scale = (1f64 - rate).recip();
+
+    Array::zeros(dim).map(|_: &f64| {
+        if distribution.sample(rng) {
+            scale
+        } else {
+            0f64
+        }
+    })
+}
+
+/// Take row i of each of the per-layer matrices of a forward pass
+fn sample_rows(activations: &Activations, i: usize) -> Activations {
+    activations
+        .iter()
+        .map(|m| m.slice(s![i..i + 1, ..]).to_owned())
+        .collect()
+}
+
+/// Calculate the loss of the model on an in-memory dataset
+fn dataset_loss(model: &NeuralNet, dataset: &Dataset) -> f64 {
+    let inputs = dataset.data.view();
+    let logits = model.logits(&inputs);
+
+    model
+        .loss_function
+        .loss(&logits, &dataset.target.view(), &inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::callback::GradientNormLogger;
+    use crate::model::curriculum::PacingFunction;
+    use crate::model::loss::CustomLossFn;
+    use crate::model::metrics::{accuracy, poisson_deviance};
+    use crate::model::noise::GaussianNoiseLayer;
+    use crate::model::quantized::compare_accuracy;
+    use crate::parsing::mnist;
+    use crate::preprocessing::scaler::StandardScaler;
+    use std::panic::AssertUnwindSafe;
+    use std::sync::Arc;
+
+    fn random_inputs(rows: usize, cols: usize, seed: u64) -> Array2<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        Array2::from_shape_fn((rows, cols), |_| rng.gen_range(-1f64..1f64))
+    }
+
+    /// The MNIST training and test sets in the CSVs at the paths of MNIST_TRAIN_CSV and MNIST_TEST_CSV
+    /// (e.g. from https://www.kaggle.com/datasets/oddrationale/mnist-in-csv)
+    fn mnist_datasets() -> (Dataset, Dataset) {
+        let path = |var: &str| {
+            std::env::var(var)
+                .unwrap_or_else(|_| panic!("Set {} to the path of the MNIST CSV", var))
+        };
+
+        (
+            mnist::parse_dataset(&path("MNIST_TRAIN_CSV")),
+            mnist::parse_dataset(&path("MNIST_TEST_CSV")),
+        )
+    }
+
+    /// A dataset where the class of each instance is the feature with the largest value
+    fn random_dataset(rows: usize, features: usize, seed: u64) -> Dataset {
+        let data = random_inputs(rows, features, seed);
+        let mut target = Array2::zeros((rows, features));
+
+        for (idx, row) in data.rows().into_iter().enumerate() {
+            target[[idx, argmax(row)]] = 1f64;
+        }
+
+        Dataset { data, target }
+    }
+
+    #[test]
+    fn find_lr_restores_the_training_state() {
+        let dataset = random_dataset(64, 4, 0);
+        let build = || {
+            NeuralNetBuilder::new(vec![4, 8, 4])
+                .optimizer(Optimizer::rprop())
+                .dropout_rate(0.2)
+                .num_epochs(Some(2))
+                .batch_size(8)
+                .seed(1)
+                .build()
+                .with_center_loss(0.1, 0.5)
+                .with_pruning_scheduler(PruningScheduler::new(0f64, 0.5, 0, 10, 2))
+                .with_callback(Box::new(GradientNormLogger::default()))
+        };
+        let mut tested = build();
+        let mut untested = build();
+
+        tested.fit(&dataset, None);
+        untested.fit(&dataset, None);
+        tested.find_lr(&dataset, 1e-4, 1f64, 20);
+        tested.fit(&dataset, None);
+        untested.fit(&dataset, None);
+
+        for (a, b) in tested.layers.iter().zip(untested.layers.iter()) {
+            assert_eq!(a.weights(), b.weights());
+            assert_eq!(a.biases(), b.biases());
+        }
+        assert_eq!(tested.masks, untested.masks);
+        assert_eq!(
+            tested.callback::<GradientNormLogger>().unwrap().norms,
+            untested.callback::<GradientNormLogger>().unwrap().norms
+        );
+    }
+
+    #[test]
+    fn mc_dropout_without_dropout_matches_predict() {
+        let inputs = random_inputs(8, 4, 0);
+        let nets = [
+            NeuralNetBuilder::new(vec![4, 6, 3])
+                .loss_function(LossFunction::MSE)
+                .seed(1)
+                .build(),
+            NeuralNet::new_multilabel(vec![4, 6, 3], 3),
+        ];
+
+        for net in nets {
+            // Training noise isn't part of MC dropout
+            let net = net.with_noise(NoiseLayer::Gaussian(GaussianNoiseLayer { std: 1f64 }));
+            let (mean, variance) = net.predict_mc_dropout(&inputs.view(), 5);
+
+            assert!((&mean - &net.predict(&inputs.view()))
+                .iter()
+                .all(|d| d.abs() < 1e-12));
+            assert!(variance.iter().all(|&v| v < 1e-12));
+        }
+    }
+
+    #[test]
+    fn mc_dropout_samples_dropout_masks() {
+        let inputs = random_inputs(8, 4, 0);
+        let net = NeuralNetBuilder::new(vec![4, 16, 3])
+            .dropout_rate(0.5)
+            .seed(1)
+            .build();
+        let (mean, variance) = net.predict_mc_dropout(&inputs.view(), 20);
+
+        // The mean of the softmax outputs is still a distribution
+        for row in mean.rows() {
+            assert!((row.sum() - 1f64).abs() < 1e-9);
+        }
+        assert!(variance.iter().any(|&v| v > 0f64));
+    }
+
+    #[test]
+    fn save_and_load_keep_the_pruning_masks() {
+        let path = std::env::temp_dir().join(format!("pruning_masks_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut net = NeuralNetBuilder::new(vec![4, 8, 4]).seed(0).build();
+
+        net.prune_layer(0, 0.25);
+        // A weight that is zero without being pruned shouldn't become pruned
+        let mut weights = net.layers[1].weights().into_owned();
+        weights[[0, 0]] = 0f64;
+        net.layers[1].set_weights(weights);
+        net.save(path).unwrap();
+        let loaded = NeuralNet::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.masks, net.masks);
+        assert_eq!(loaded.masks[0].iter().filter(|&&keep| !keep).count(), 8);
+        assert!(loaded.masks[1].iter().all(|&keep| keep));
+    }
+
+    #[test]
+    fn lrp_conserves_the_relevance() {
+        let mut net = NeuralNetBuilder::new(vec![6, 16, 8, 3]).seed(0).build();
+        let inputs = random_inputs(4, 6, 1);
+
+        // The relevance is only conserved exactly when no part of it is absorbed by the biases
+        for layer in net.layers.iter_mut() {
+            let num_biases = layer.biases().len();
+
+            layer.set_biases(Array1::zeros(num_biases));
+        }
+
+        let predictions = net.predict(&inputs.view());
+
+        for (input, prediction) in inputs.rows().into_iter().zip(predictions.rows()) {
+            for target_class in 0..3 {
+                let relevance = net.lrp(&input, target_class, 1e-12);
+
+                assert!((relevance.sum() - prediction[target_class]).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn inference_and_attribution_scale_the_inputs() {
+        let raw = random_inputs(5, 4, 2) * 10f64 + 3f64;
+        let scaler = StandardScaler::fit(&raw);
+        let scaled = scaler.transform(&raw);
+        let targets = random_dataset(5, 4, 3).target;
+        let build = || NeuralNetBuilder::new(vec![4, 8, 4]).seed(1).build();
+        let net = build();
+        let scaled_net = build().with_input_scaler(Box::new(scaler));
+        let close = |a: &Array2<f64>, b: &Array2<f64>| {
+            assert!(Zip::from(a).and(b).all(|a, b| (a - b).abs() < 1e-9));
+        };
+
+        close(&scaled_net.logits(&raw.view()), &net.logits(&scaled.view()));
+        close(
+            &scaled_net.predict(&raw.view()),
+            &net.predict(&scaled.view()),
+        );
+        close(
+            &scaled_net.predict_mc_dropout(&raw.view(), 3).0,
+            &net.predict_mc_dropout(&scaled.view(), 3).0,
+        );
+        close(
+            &scaled_net.input_gradients(&raw, 1),
+            &net.input_gradients(&scaled, 1),
+        );
+        close(
+            &scaled_net.loss_input_gradients(&raw.view(), &targets.view()),
+            &net.loss_input_gradients(&scaled.view(), &targets.view()),
+        );
+        close(
+            &scaled_net.extract_features(&raw.view(), 1).unwrap(),
+            &net.extract_features(&scaled.view(), 1).unwrap(),
+        );
+        close(
+            &scaled_net.extract_linear_features(&raw.view(), 2).unwrap(),
+            &net.extract_linear_features(&scaled.view(), 2).unwrap(),
+        );
+
+        let (raw_row, scaled_row) = (raw.row(0), scaled.row(0));
+        let (raw_baseline, scaled_baseline) = (raw.row(1), scaled.row(1));
+
+        close(
+            &scaled_net.lrp(&raw_row, 2, 1e-9).insert_axis(Axis(0)),
+            &net.lrp(&scaled_row, 2, 1e-9).insert_axis(Axis(0)),
+        );
+        close(
+            &scaled_net
+                .integrated_gradients(&raw_row, &raw_baseline, 2, 10)
+                .insert_axis(Axis(0)),
+            &net.integrated_gradients(&scaled_row, &scaled_baseline, 2, 10)
+                .insert_axis(Axis(0)),
+        );
+    }
+
+    #[test]
+    fn integrated_gradients_satisfy_completeness() {
+        let net = NeuralNetBuilder::new(vec![4, 16, 3]).build();
+        let inputs = random_inputs(2, 4, 0);
+        let (input, baseline) = (inputs.row(0), inputs.row(1));
+
+        for target_class in 0..3 {
+            let attributions = net.integrated_gradients(&input, &baseline, target_class, 1000);
+            let logits = net.logits(&inputs.view());
+            let score_diff = logits[[0, target_class]] - logits[[1, target_class]];
+
+            assert!((attributions.sum() - score_diff).abs() < 1e-3 * score_diff.abs().max(1f64));
+        }
+    }
+
+    #[test]
+    fn auto_class_weights_improve_the_minority_recall() {
+        // About 10% of the instances are of class 1, and the noise makes the classes overlap, so an
+        // unweighted net is better off predicting class 0 near the boundary
+        let mut rng = StdRng::seed_from_u64(0);
+        let data = random_inputs(2000, 2, 1);
+        let mut target = Array2::zeros((2000, 2));
+
+        for (row, instance) in data.rows().into_iter().enumerate() {
+            let score = instance.sum() + rng.gen_range(-0.5f64..0.5f64);
+            target[[row, usize::from(score > 1f64)]] = 1f64;
+        }
+
+        let (train, test) = Dataset { data, target }.split(0.25, Some(0));
+        let build = || {
+            NeuralNetBuilder::new(vec![2, 16, 2])
+                .num_epochs(Some(20))
+                .batch_size(32)
+                .learning_rate(0.05)
+                .seed(1)
+                .verbosity(Verbosity::Silent)
+                .build()
+        };
+        let minority_recall = |net: &NeuralNet| {
+            let predictions = net.predict(&test.data.view());
+            let minority: Vec<usize> = (0..test.data.nrows())
+                .filter(|&row| test.target[[row, 1]] == 1f64)
+                .collect();
+            let recalled = minority
+                .iter()
+                .filter(|&&row| argmax(predictions.row(row)) == 1)
+                .count();
+
+            recalled as f64 / minority.len() as f64
+        };
+
+        let mut net = build();
+        net.fit(&train, None);
+        let mut weighted_net = build().with_auto_class_weights(&train);
+        weighted_net.fit(&train, None);
+
+        let (recall, weighted_recall) = (minority_recall(&net), minority_recall(&weighted_net));
+
+        assert!(
+            weighted_recall > recall + 0.1,
+            "minority recall {} unweighted, {} weighted",
+            recall,
+            weighted_recall
+        );
+    }
+
+    #[test]
+    fn sigmoid_and_tanh_saturate_without_nan() {
+        assert!(sigmoid(-1000f64).abs() < 1e-300);
+        assert_eq!(sigmoid(1000f64), 1f64);
+        assert_eq!(
+            activation(&ActivationFunction::Sigmoid, -1000f64),
+            sigmoid(-1000f64)
+        );
+        assert_eq!(activation(&ActivationFunction::Tanh, 1000f64), 1f64);
+        assert_eq!(activation(&ActivationFunction::Tanh, -1000f64), -1f64);
+        assert!(!delta_activation(&ActivationFunction::Sigmoid, -1000f64).is_nan());
+        assert!(!delta_activation(&ActivationFunction::Tanh, 1000f64).is_nan());
+    }
+
+    #[test]
+    fn second_delta_activation_matches_finite_differences() {
+        const EPS: f64 = 1e-5;
+
+        for act in [
+            ActivationFunction::ReLU,
+            ActivationFunction::Sigmoid,
+            ActivationFunction::Tanh,
+            ActivationFunction::Linear,
+            ActivationFunction::LeakyReLU,
+        ] {
+            // The kinks of ReLU and LeakyReLU at 0 have no second derivative
+            for z in [-4f64, -1.3, -0.2, 0.3, 0.9, 2.5, 6f64] {
+                let numeric = (delta_activation(&act, z + EPS) - delta_activation(&act, z - EPS))
+                    / (2f64 * EPS);
+
+                assert!(
+                    (second_delta_activation(&act, z) - numeric).abs() < 1e-6,
+                    "{:?} at {}",
+                    act,
+                    z
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn training_with_a_seed_is_reproducible() {
+        let dataset = random_dataset(64, 4, 0);
+        let (train, validation) = dataset.split(0.25, Some(0));
+        let train_with_seed = || {
+            let mut net = NeuralNetBuilder::new(vec![4, 16, 4])
+                .dropout_rate(0.3)
+                .num_epochs(Some(3))
+                .batch_size(8)
+                .seed(42)
+                .build()
+                .with_seed(42)
+                .with_noise(NoiseLayer::Gaussian(GaussianNoiseLayer { std: 0.1 }));
+            let history = net.fit(&train, Some(&validation));
+
+            (net, history)
+        };
+
+        let (a, a_history) = train_with_seed();
+        let (b, b_history) = train_with_seed();
+
+        for (a, b) in a.layers.iter().zip(b.layers.iter()) {
+            assert_eq!(a.weights(), b.weights());
+            assert_eq!(a.biases(), b.biases());
+        }
+
+        assert_eq!(a_history.train_losses, b_history.train_losses);
+        assert_eq!(a_history.val_losses, b_history.val_losses);
+    }
+
+    #[test]
+    #[ignore = "needs the MNIST CSVs, see mnist_datasets"]
+    fn default_config_trains_on_mnist() {
+        let (train, test) = mnist_datasets();
+        let mut net = NeuralNetBuilder::default()
+            .verbosity(Verbosity::Silent)
+            .seed(0)
+            .build();
+
+        net.fit(&train, None);
+
+        assert!(accuracy(&net.predict(&test.data.view()), &test.target) > 0.9);
+    }
+
+    #[test]
+    #[ignore = "needs the MNIST CSVs, see mnist_datasets"]
+    fn static_quantization_keeps_the_mnist_accuracy() {
+        let (train, test) = mnist_datasets();
+        let mut net = NeuralNetBuilder::default()
+            .verbosity(Verbosity::Silent)
+            .seed(0)
+            .build();
+
+        net.fit(&train, None);
+
+        let calibration = train
+            .data
+            .slice(s![..train.data.nrows().min(1000), ..])
+            .to_owned();
+        let quantized = net.quantize_activations(&calibration);
+        let (accuracy, quantized_accuracy) = compare_accuracy(&net, &quantized, &test);
+
+        assert!(
+            accuracy - quantized_accuracy < 0.01,
+            "accuracy {} before quantization, {} after",
+            accuracy,
+            quantized_accuracy
+        );
+    }
+
+    #[test]
+    #[ignore = "needs the MNIST CSVs, see mnist_datasets"]
+    fn mixed_precision_keeps_the_mnist_accuracy() {
+        let (train, test) = mnist_datasets();
+        let test_accuracy = |mixed_precision: bool| {
+            let mut net = NeuralNetBuilder::default()
+                .num_epochs(Some(10))
+                .verbosity(Verbosity::Silent)
+                .seed(0)
+                .build()
+                .with_mixed_precision(mixed_precision);
+
+            net.fit(&train, None);
+
+            accuracy(&net.predict(&test.data.view()), &test.target)
+        };
+        let (full_accuracy, mixed_accuracy) = (test_accuracy(false), test_accuracy(true));
+
+        assert!(
+            (full_accuracy - mixed_accuracy).abs() <= 0.001,
+            "accuracy {} with f64 weights, {} with f32 weights",
+            full_accuracy,
+            mixed_accuracy
+        );
+    }
+
+    /// A regression test of the whole training pipeline. MNIST isn't downloaded: MNIST_TRAIN_CSV and MNIST_TEST_CSV
+    /// must be the paths of the training and test CSVs (a label column followed by 784 pixel columns)
+    #[test]
+    #[ignore = "needs the MNIST CSVs, see mnist_datasets"]
+    fn test_mnist_accuracy_baseline() {
+        let (train, test) = mnist_datasets();
+        let mut net = NeuralNetBuilder::new(vec![784, 500, 10])
+            .init_method(InitMethod::Xavier)
+            .activation_function(ActivationFunction::ReLU)
+            .optimizer(Optimizer::adam())
+            .learning_rate(0.001)
+            .num_epochs(Some(20))
+            .verbosity(Verbosity::Silent)
+            .seed(0)
+            .build();
+
+        let history = net.fit(&train, None);
+
+        let first_losses = &history.train_losses[..5];
+        assert!(
+            first_losses.windows(2).all(|pair| pair[1] < pair[0]),
+            "training losses {:?}",
+            first_losses
+        );
+        let test_accuracy = accuracy(&net.predict(&test.data.view()), &test.target);
+        assert!(test_accuracy >= 0.97, "accuracy {}", test_accuracy);
+    }
+
+    #[test]
+    fn noise_is_only_added_in_training_mode() {
+        let inputs = random_inputs(32, 4, 1);
+        let noisy_net = |seed: u64| {
+            NeuralNetBuilder::new(vec![4, 64, 2])
+                .seed(0)
+                .build()
+                .with_seed(seed)
+                .with_noise(NoiseLayer::Gaussian(GaussianNoiseLayer { std: 0.5 }))
+        };
+        let clean = NeuralNetBuilder::new(vec![4, 64, 2]).seed(0).build();
+        let (clean_hidden, _, _) = clean.forward(&inputs.view(), false);
+
+        // Evaluation passes, and training passes in evaluation mode, are noiseless
+        let mut net = noisy_net(1);
+        let (eval_hidden, _, _) = net.forward(&inputs.view(), false);
+        net.training_mode = false;
+        let (eval_mode_hidden, _, _) = net.forward(&inputs.view(), true);
+
+        assert_eq!(eval_hidden, clean_hidden);
+        assert_eq!(eval_mode_hidden, clean_hidden);
+
+        // Training passes add noise with the standard deviation of the layer, and the same seed gives the same noise
+        let (train_hidden, _, _) = noisy_net(1).forward(&inputs.view(), true);
+        let (same_seed_hidden, _, _) = noisy_net(1).forward(&inputs.view(), true);
+        let noise = &train_hidden[1] - &clean_hidden[1];
+        let std = (noise.mapv(|x| x * x).sum() / noise.len() as f64).sqrt();
+
+        assert_eq!(train_hidden, same_seed_hidden);
+        assert!((std - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn checkpointing_keeps_the_gradient_penalty() {
+        let inputs = random_inputs(8, 3, 0);
+        // Alternating real and fake instances
+        let targets =
+            Array2::from_shape_fn((8, 2), |(row, col)| ((row + col) % 2 == 0) as u8 as f64);
+        let train = |checkpointing: bool| {
+            let mut net = NeuralNetBuilder::new(vec![3, 8, 8, 2])
+                .loss_function(LossFunction::GradientPenalty {
+                    base: Box::new(LossFunction::CrossEntropy),
+                    lambda: 10f64,
+                })
+                .seed(2)
+                .build();
+
+            if checkpointing {
+                net = net.with_gradient_checkpointing(1);
+            }
+
+            let loss = net.partial_fit(&inputs.view(), &targets.view());
+            let weights: Vec<Array2<f64>> = net
+                .layers
+                .iter()
+                .map(|layer| layer.weights().into_owned())
+                .collect();
+
+            (loss, weights)
+        };
+        let (loss, weights) = train(false);
+        let (checkpointed_loss, checkpointed_weights) = train(true);
+        let base_loss = LossFunction::CrossEntropy.loss(
+            &NeuralNetBuilder::new(vec![3, 8, 8, 2])
+                .seed(2)
+                .build()
+                .logits(&inputs.view()),
+            &targets.view(),
+            &inputs.view(),
+        );
+
+        assert!(loss > base_loss + 1e-6);
+        assert!((checkpointed_loss - loss).abs() < 1e-12);
+
+        for (a, b) in weights.iter().zip(checkpointed_weights.iter()) {
+            assert!(Zip::from(a).and(b).all(|a, b| (a - b).abs() < 1e-12));
+        }
+    }
+
+    #[test]
+    fn center_loss_cant_be_used_with_checkpointing() {
+        let dataset = random_dataset(16, 3, 0);
+        let mut net = NeuralNetBuilder::new(vec![3, 8, 3])
+            .num_epochs(Some(1))
+            .build()
+            .with_center_loss(0.1, 0.5)
+            .with_gradient_checkpointing(1);
+
+        assert!(matches!(
+            net.try_fit(&dataset, None),
+            Err(NeuralNetError::InvalidConfig(_))
+        ));
+
+        net.gradient_checkpointing = None;
+        assert!(net.try_fit(&dataset, None).is_ok());
+    }
+
+    #[test]
+    fn differential_privacy_cant_be_used_with_checkpointing() {
+        let dataset = random_dataset(16, 3, 0);
+        let mut net = NeuralNetBuilder::new(vec![3, 8, 3])
+            .num_epochs(Some(1))
+            .build()
+            .with_differential_privacy(DPConfig {
+                noise_multiplier: 1f64,
+                max_grad_norm: 1f64,
+                delta: 1e-5,
+            })
+            .with_gradient_checkpointing(1);
+
+        assert!(matches!(
+            net.try_fit(&dataset, None),
+            Err(NeuralNetError::InvalidConfig(_))
+        ));
+
+        net.gradient_checkpointing = None;
+        assert!(net.try_fit(&dataset, None).is_ok());
+    }
+
+    #[test]
+    fn fit_curriculum_checks_the_config_before_training() {
+        let dataset = random_dataset(16, 3, 0);
+        let mut net = NeuralNetBuilder::new(vec![3, 8, 3])
+            .num_epochs(Some(2))
+            .build()
+            .with_center_loss(0.1, 0.5)
+            .with_gradient_checkpointing(1);
+        let weights: Vec<Array2<f64>> = net
+            .layers
+            .iter()
+            .map(|layer| layer.weights().into_owned())
+            .collect();
+        let scheduler = CurriculumScheduler::new(PacingFunction::Linear { slope: 0.5 }, 0.5);
+
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            net.fit_curriculum(&dataset, None, scheduler)
+        }));
+
+        assert!(result.is_err());
+
+        for (layer, weights) in net.layers.iter().zip(weights.iter()) {
+            assert_eq!(layer.weights(), weights);
+        }
+    }
+
+    #[test]
+    fn poisson_loss_fits_synthetic_counts() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let data = random_inputs(2000, 2, 1);
+        let rates = data.map_axis(Axis(1), |x| (0.5 + x[0] - 0.5 * x[1]).exp());
+        // Sample the counts with Knuth's algorithm: the number of uniforms whose product stays above exp(-rate)
+        let counts = rates.mapv(|rate| {
+            let limit = (-rate).exp();
+            let mut product = rng.gen::<f64>();
+            let mut count = 0;
+
+            while product > limit {
+                product *= rng.gen::<f64>();
+                count += 1;
+            }
+
+            count as f64
+        });
+        let dataset = Dataset {
+            data,
+            target: counts.clone().insert_axis(Axis(1)),
+        };
+        let (train, test) = dataset.split(0.25, Some(0));
+        let mut net = NeuralNetBuilder::new(vec![2, 16, 1])
+            .activation_function(ActivationFunction::Tanh)
+            .loss_function(LossFunction::Poisson)
+            .learning_rate(0.01)
+            .num_epochs(Some(30))
+            .batch_size(32)
+            .seed(3)
+            .verbosity(Verbosity::Silent)
+            .build();
+
+        net.fit(&train, None);
+
+        let predictions = net.predict(&test.data.view()).column(0).to_owned();
+        let targets = test.target.column(0).to_owned();
+        let mean = Array1::from_elem(targets.len(), train.target.mean().unwrap());
+        let model_deviance = poisson_deviance(&predictions, &targets);
+        let mean_deviance = poisson_deviance(&mean, &targets);
+
+        assert!(predictions.iter().all(|&mu| mu > 0f64));
+        assert!(
+            model_deviance < 0.8 * mean_deviance,
+            "deviance {} of the model, {} of the mean",
+            model_deviance,
+            mean_deviance
+        );
+    }
+
+    #[test]
+    fn custom_msle_loss_trains_a_regression_model() {
+        // The mean squared logarithmic error, with the predictions clipped above -1 so that the log is defined
+        let msle: CustomLossFn = Arc::new(|predictions, targets| {
+            let clipped = predictions.mapv(|p| p.max(-0.99));
+            let diff = clipped.mapv(f64::ln_1p) - targets.mapv(f64::ln_1p);
+            let loss = diff.mapv(|d| d * d).sum() / predictions.nrows() as f64;
+            let mut grad = 2f64 * &diff / clipped.mapv(|p| p + 1f64);
+
+            Zip::from(&mut grad).and(predictions).for_each(|g, &p| {
+                if p < -0.99 {
+                    *g = 0f64;
+                }
+            });
+
+            (loss, grad)
+        });
+        let data = random_inputs(1000, 2, 4);
+        let target = data
+            .map_axis(Axis(1), |x| (1.5 * x[0] - x[1]).exp())
+            .insert_axis(Axis(1));
+        let (train, test) = Dataset { data, target }.split(0.2, Some(0));
+        let mut net = NeuralNetBuilder::new(vec![2, 16, 1])
+            .activation_function(ActivationFunction::Tanh)
+            .loss_function(LossFunction::Custom(Arc::clone(&msle)))
+            .learning_rate(0.01)
+            .num_epochs(Some(40))
+            .batch_size(16)
+            .seed(5)
+            .verbosity(Verbosity::Silent)
+            .build();
+
+        let history = net.fit(&train, None);
+        let predictions = net.predict(&test.data.view());
+        let mean = Array2::from_elem(test.target.raw_dim(), train.target.mean().unwrap());
+        let (model_loss, _) = msle(&predictions, &test.target);
+        let (mean_loss, _) = msle(&mean, &test.target);
+
+        // The custom loss is what fit reports, and predict returns the raw outputs it was trained on
+        assert!(history.train_losses.last().unwrap() < &(0.2 * history.train_losses[0]));
+        assert_eq!(predictions, net.logits(&test.data.view()));
+        assert!(
+            model_loss < 0.2 * mean_loss,
+            "MSLE {} of the model, {} of the mean",
+            model_loss,
+            mean_loss
+        );
+    }
+
+    #[test]
+    fn try_fit_rejects_indices_outside_the_vocabulary() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut net = NeuralNetBuilder::new(vec![4, 4, 2])
+            .num_epochs(Some(1))
+            .seed(0)
+            .build()
+            .with_input_embedding(EmbeddingLayer::new(5, 2, InitMethod::Default, &mut rng));
+        let dataset = Dataset {
+            data: ndarray::array![[0f64, 4f64], [2f64, 7f64]],
+            target: ndarray::array![[1f64, 0f64], [0f64, 1f64]],
+        };
+
+        assert!(matches!(
+            net.try_fit(&dataset, None),
+            Err(NeuralNetError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn gradient_buffers_dont_change_the_training() {
+        let dataset = random_dataset(50, 4, 0);
+        let train = |reuse: bool| {
+            let mut net = NeuralNetBuilder::new(vec![4, 8, 8, 4])
+                .dropout_rate(0.2)
+                .num_epochs(Some(3))
+                .batch_size(8)
+                .seed(1)
+                .verbosity(Verbosity::Silent)
+                .build()
+                .with_gradient_buffers(reuse);
+            let history = net.fit(&dataset, None);
+
+            (history.train_losses, net.layers[0].weights().into_owned())
+        };
+
+        assert_eq!(train(true), train(false));
+    }
+
+    #[test]
+    fn mixed_precision_trains_like_full_precision() {
+        let dataset = random_dataset(256, 4, 0);
+        let train = |mixed_precision: bool| {
+            let mut net = NeuralNetBuilder::new(vec![4, 16, 4])
+                .num_epochs(Some(5))
+                .batch_size(16)
+                .seed(0)
+                .verbosity(Verbosity::Silent)
+                .build()
+                .with_mixed_precision(mixed_precision);
+            let history = net.fit(&dataset, None);
+
+            (net, history.train_losses)
+        };
+        let (_, losses) = train(false);
+        let (net, mixed_losses) = train(true);
+
+        for (loss, mixed_loss) in losses.iter().zip(mixed_losses.iter()) {
+            assert!(
+                (loss - mixed_loss).abs() < 1e-4,
+                "{} vs {}",
+                loss,
+                mixed_loss
+            );
+        }
+
+        // Converting the layers back to f64 keeps the trained weights
+        let weights = net.layers[0].weights().into_owned();
+        let predictions = net.predict(&dataset.data.view());
+        let net = net.with_mixed_precision(false);
+
+        assert_eq!(net.layers[0].weights(), weights);
+        assert_eq!(net.predict(&dataset.data.view()), predictions);
+    }
 }
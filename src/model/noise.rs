@@ -0,0 +1,73 @@
+use ndarray::Array2;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Adds zero-mean Gaussian noise with standard deviation std to the activations during training
+#[derive(Clone, Debug)]
+pub struct GaussianNoiseLayer {
+    pub std: f64,
+}
+
+/// Adds noise sampled uniformly from [-range, range] to the activations during training
+#[derive(Clone, Debug)]
+pub struct UniformNoiseLayer {
+    pub range: f64,
+}
+
+/// Noise injected after each hidden layer as a regularizer. It's the identity during evaluation
+#[derive(Clone, Debug)]
+pub enum NoiseLayer {
+    Gaussian(GaussianNoiseLayer),
+    Uniform(UniformNoiseLayer),
+}
+
+impl NoiseLayer {
+    /// Sample a noise matrix to be added to activations of the given dimensions
+    pub fn sample(&self, dim: (usize, usize), rng: &mut impl Rng) -> Array2<f64> {
+        match self {
+            NoiseLayer::Gaussian(layer) => {
+                Array2::from_shape_simple_fn(dim, || layer.std * standard_normal(rng))
+            }
+            NoiseLayer::Uniform(layer) => {
+                Array2::from_shape_simple_fn(dim, || rng.gen_range(-1f64..=1f64) * layer.range)
+            }
+        }
+    }
+}
+
+/// Sample from N(0, 1) using the Box-Muller transform
+pub(super) fn standard_normal(rng: &mut impl Rng) -> f64 {
+    // gen() samples from [0, 1), so 1 - u1 is never 0 and the log is finite
+    let u1 = 1f64 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+
+    (-2f64 * u1.ln()).sqrt() * (2f64 * PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn gaussian_noise_has_the_std_of_the_layer() {
+        let noise = NoiseLayer::Gaussian(GaussianNoiseLayer { std: 2f64 });
+        let samples = noise.sample((100, 100), &mut StdRng::seed_from_u64(0));
+        let mean = samples.mean().unwrap();
+        let std = samples.std(0f64);
+
+        assert!(mean.abs() < 0.05);
+        assert!((std - 2f64).abs() < 0.05);
+    }
+
+    #[test]
+    fn uniform_noise_stays_within_the_range() {
+        let noise = NoiseLayer::Uniform(UniformNoiseLayer { range: 0.3 });
+        let samples = noise.sample((100, 100), &mut StdRng::seed_from_u64(0));
+
+        assert!(samples.iter().all(|x| x.abs() <= 0.3));
+        // The variance of U(-a, a) is a^2 / 3
+        assert!((samples.var(0f64) - 0.03).abs() < 0.002);
+    }
+}
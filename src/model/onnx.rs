@@ -0,0 +1,548 @@
+use ndarray::{Array1, Array2, ArrayD, Ix2};
+use std::collections::{HashMap, VecDeque};
+
+use super::layer::{DenseLayer, Layer};
+use super::loss::LossFunction;
+use super::neural_net::{ActivationFunction, NeuralNet, NeuralNetBuilder, Task};
+use crate::error::{NeuralNetError, Result};
+use crate::parsing::onnx::{
+    parse_onnx_graph, write_onnx_graph, OnnxGraph, OnnxNode, OnnxValueInfo,
+};
+
+/// A dense layer read from the graph, with the activation that follows it (if any)
+struct ImportedLayer {
+    weights: Array2<f64>,
+    biases: Array1<f64>,
+    activation: Option<String>,
+}
+
+/// Import a dense network from an ONNX model. The graph must be a chain of MatMul (or Gemm) nodes, each followed by
+/// an optional Add of its bias and an activation (Relu, Sigmoid, Tanh or LeakyRelu, which are the same for all of
+/// the hidden layers). The output layer can be followed by a Softmax, which is what predict applies anyway
+/// The weights are read from the initializers of the graph
+pub fn load_from_onnx(path: &str) -> Result<NeuralNet> {
+    let graph = parse_onnx_graph(path)?;
+    let malformed = |msg: &str| NeuralNetError::Parse(format!("{}: {}", path, msg));
+    let input = graph
+        .inputs
+        .iter()
+        .find(|input| !graph.initializers.contains_key(&input.name))
+        .ok_or_else(|| malformed("The graph has no input"))?;
+    let output = graph
+        .outputs
+        .first()
+        .ok_or_else(|| malformed("The graph has no output"))?;
+    let mut current = input.name.clone();
+    let mut layers: Vec<ImportedLayer> = vec![];
+
+    for node in sort_nodes(&graph).map_err(|msg| malformed(&msg))? {
+        let data_inputs: Vec<&String> = node
+            .inputs
+            .iter()
+            .filter(|name| !name.is_empty() && !graph.initializers.contains_key(*name))
+            .collect();
+
+        if data_inputs != [&current] {
+            return Err(malformed("The graph isn't a sequential network"));
+        }
+
+        match node.op_type.as_str() {
+            "MatMul" => {
+                let weights = initializer_matrix(&graph, node).map_err(|msg| malformed(&msg))?;
+
+                layers.push(ImportedLayer {
+                    biases: Array1::zeros(weights.ncols()),
+                    weights,
+                    activation: None,
+                });
+            }
+            "Gemm" => {
+                if node.int_attributes.get("transA").copied().unwrap_or(0) != 0 {
+                    return Err(malformed("Gemm with a transposed input isn't supported"));
+                }
+
+                let alpha = node.float_attributes.get("alpha").copied().unwrap_or(1f64);
+                let beta = node.float_attributes.get("beta").copied().unwrap_or(1f64);
+                let mut weights =
+                    initializer_matrix(&graph, node).map_err(|msg| malformed(&msg))? * alpha;
+
+                if node.int_attributes.get("transB").copied().unwrap_or(0) != 0 {
+                    weights = weights.reversed_axes();
+                }
+
+                let biases = match node
+                    .inputs
+                    .get(2)
+                    .and_then(|name| graph.initializers.get(name))
+                {
+                    Some(bias) => flatten_bias(bias, weights.ncols(), &node.op_type)? * beta,
+                    None => Array1::zeros(weights.ncols()),
+                };
+
+                layers.push(ImportedLayer {
+                    weights,
+                    biases,
+                    activation: None,
+                });
+            }
+            "Add" => {
+                let layer = layers
+                    .last_mut()
+                    .filter(|layer| layer.activation.is_none())
+                    .ok_or_else(|| malformed("An Add doesn't follow a MatMul"))?;
+                let bias = node
+                    .inputs
+                    .iter()
+                    .find_map(|name| graph.initializers.get(name))
+                    .ok_or_else(|| malformed("An Add has no bias initializer"))?;
+
+                layer.biases += &flatten_bias(bias, layer.biases.len(), &node.op_type)?;
+            }
+            "Relu" | "Sigmoid" | "Tanh" | "LeakyRelu" | "Softmax" => {
+                let layer = layers
+                    .last_mut()
+                    .filter(|layer| layer.activation.is_none())
+                    .ok_or_else(|| malformed("An activation doesn't follow a layer"))?;
+
+                layer.activation = Some(node.op_type.clone());
+            }
+            // The inputs are already flat
+            "Flatten" | "Identity" => {}
+            op => return Err(NeuralNetError::UnsupportedOp { op: op.to_string() }),
+        }
+
+        current = node.outputs.first().cloned().unwrap_or_default();
+    }
+
+    if current != output.name {
+        return Err(malformed(
+            "The output of the graph isn't the output of its last node",
+        ));
+    }
+
+    build_net(layers, &input.shape, &output.shape).map_err(|err| match err {
+        NeuralNetError::Parse(msg) => malformed(&msg),
+        err => err,
+    })
+}
+
+/// Export a dense network as an ONNX model that load_from_onnx can import: a MatMul and an Add of the bias for every
+/// layer, followed by the activation of the hidden layers, and a Softmax (or the output activation) after the output
+/// layer. The temperature is folded into the output layer. Embedding layers, input scalers, the Linear hidden
+/// activation and outputs that predict doesn't turn into probabilities (e.g. of regression) have no such graph
+pub fn save_to_onnx(net: &NeuralNet, path: &str) -> Result<()> {
+    let unsupported = |op: &str| NeuralNetError::UnsupportedOp { op: op.to_string() };
+
+    if net.input_scaler.is_some() {
+        return Err(unsupported("input scaler"));
+    }
+
+    let hidden_op = op_from_activation(&net.activation_function)
+        .ok_or_else(|| unsupported(&format!("{:?} hidden activation", net.activation_function)))?;
+    let output_op = match (&net.output_activation, &net.loss_function, net.task) {
+        (
+            ActivationFunction::Linear,
+            LossFunction::MSE
+            | LossFunction::LogCosh
+            | LossFunction::Hinge
+            | LossFunction::CategoricalHinge
+            | LossFunction::Custom(_)
+            | LossFunction::Poisson,
+            _,
+        ) => None,
+        (ActivationFunction::Linear, _, Task::Multiclass) => Some("Softmax"),
+        (ActivationFunction::Linear, _, _) => None,
+        (act, _, _) => op_from_activation(act),
+    }
+    .ok_or_else(|| unsupported("raw outputs"))?;
+
+    let mut graph = OnnxGraph::default();
+    let mut current = "input".to_string();
+
+    for (idx, layer) in net.layers.iter().enumerate() {
+        if layer.kind() != "dense" {
+            return Err(unsupported(&format!("{} layer", layer.kind())));
+        }
+
+        let is_output = idx + 1 == net.layers.len();
+        // predict divides the outputs of the output layer by the temperature
+        let scale = if is_output {
+            1f64 / net.temperature
+        } else {
+            1f64
+        };
+        let (weights_name, biases_name) = (format!("W{}", idx), format!("b{}", idx));
+
+        graph
+            .initializers
+            .insert(weights_name.clone(), (&layer.weights() * scale).into_dyn());
+        graph
+            .initializers
+            .insert(biases_name.clone(), (&layer.biases() * scale).into_dyn());
+
+        let mut add_node = |op_type: &str, inputs: Vec<String>, output: String| {
+            let mut node = OnnxNode {
+                op_type: op_type.to_string(),
+                inputs,
+                outputs: vec![output.clone()],
+                ..OnnxNode::default()
+            };
+
+            if op_type == "LeakyRelu" {
+                node.float_attributes.insert("alpha".to_string(), 0.01);
+            }
+
+            graph.nodes.push(node);
+            output
+        };
+
+        current = add_node(
+            "MatMul",
+            vec![current, weights_name],
+            format!("matmul{}", idx),
+        );
+        current = add_node("Add", vec![current, biases_name], format!("linear{}", idx));
+        current = add_node(
+            if is_output { output_op } else { hidden_op },
+            vec![current],
+            if is_output {
+                "output".to_string()
+            } else {
+                format!("hidden{}", idx)
+            },
+        );
+    }
+
+    let num_inputs = net.layers.first().map(|layer| layer.weights().nrows());
+    let num_outputs = net.layers.last().map(|layer| layer.biases().len());
+
+    graph.inputs.push(OnnxValueInfo {
+        name: "input".to_string(),
+        shape: vec![None, num_inputs],
+    });
+    graph.outputs.push(OnnxValueInfo {
+        name: current,
+        shape: vec![None, num_outputs],
+    });
+
+    write_onnx_graph(path, &graph)
+}
+
+fn op_from_activation(act: &ActivationFunction) -> Option<&'static str> {
+    match act {
+        ActivationFunction::ReLU => Some("Relu"),
+        ActivationFunction::Sigmoid => Some("Sigmoid"),
+        ActivationFunction::Tanh => Some("Tanh"),
+        ActivationFunction::LeakyReLU => Some("LeakyRelu"),
+        ActivationFunction::Linear => None,
+    }
+}
+
+/// Order the nodes so that each one comes after the nodes that compute its inputs (Kahn's algorithm)
+fn sort_nodes(graph: &OnnxGraph) -> std::result::Result<Vec<&OnnxNode>, String> {
+    let producers: HashMap<&String, usize> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, node)| node.outputs.iter().map(move |output| (output, idx)))
+        .collect();
+    let mut num_dependencies = vec![0; graph.nodes.len()];
+    let mut consumers = vec![vec![]; graph.nodes.len()];
+
+    for (idx, node) in graph.nodes.iter().enumerate() {
+        for input in node.inputs.iter() {
+            if let Some(&producer) = producers.get(input) {
+                num_dependencies[idx] += 1;
+                consumers[producer].push(idx);
+            }
+        }
+    }
+
+    let mut ready: VecDeque<usize> = (0..graph.nodes.len())
+        .filter(|&idx| num_dependencies[idx] == 0)
+        .collect();
+    let mut order = vec![];
+
+    while let Some(idx) = ready.pop_front() {
+        order.push(&graph.nodes[idx]);
+
+        for &consumer in consumers[idx].iter() {
+            num_dependencies[consumer] -= 1;
+
+            if num_dependencies[consumer] == 0 {
+                ready.push_back(consumer);
+            }
+        }
+    }
+
+    if order.len() != graph.nodes.len() {
+        return Err("The graph has a cycle".to_string());
+    }
+
+    Ok(order)
+}
+
+/// The weight matrix of a MatMul or a Gemm, which is its second input
+fn initializer_matrix(
+    graph: &OnnxGraph,
+    node: &OnnxNode,
+) -> std::result::Result<Array2<f64>, String> {
+    node.inputs
+        .get(1)
+        .and_then(|name| graph.initializers.get(name))
+        .ok_or_else(|| format!("The weights of a {} aren't an initializer", node.op_type))?
+        .clone()
+        .into_dimensionality::<Ix2>()
+        .map_err(|_| format!("The weights of a {} aren't a matrix", node.op_type))
+}
+
+/// A bias of shape (n) or (1, n) as a vector
+fn flatten_bias(bias: &ArrayD<f64>, len: usize, op_type: &str) -> Result<Array1<f64>> {
+    if bias.len() != len {
+        return Err(NeuralNetError::ShapeMismatch {
+            expected: vec![len],
+            actual: bias.shape().to_vec(),
+        });
+    }
+
+    bias.iter()
+        .copied()
+        .collect::<Array1<f64>>()
+        .into_shape(len)
+        .map_err(|_| NeuralNetError::Parse(format!("The bias of an {} isn't a vector", op_type)))
+}
+
+/// Construct the network from the imported layers, checking that its shapes match the graph
+fn build_net(
+    layers: Vec<ImportedLayer>,
+    input_shape: &[Option<usize>],
+    output_shape: &[Option<usize>],
+) -> Result<NeuralNet> {
+    let (output_layer, hidden_layers) = layers
+        .split_last()
+        .ok_or_else(|| NeuralNetError::Parse("The graph has no layers".to_string()))?;
+    let hidden_activation = hidden_layers
+        .first()
+        .map(|layer| layer.activation.clone())
+        .unwrap_or(Some("Relu".to_string()));
+
+    if hidden_layers
+        .iter()
+        .any(|layer| layer.activation != hidden_activation)
+    {
+        return Err(NeuralNetError::Parse(
+            "All of the hidden layers must have the same activation".to_string(),
+        ));
+    }
+
+    let activation_function = match hidden_activation.as_deref() {
+        Some(op) if op != "Softmax" => activation_from_op(op),
+        _ => {
+            return Err(NeuralNetError::Parse(
+                "The hidden layers must have a Relu, Sigmoid, Tanh or LeakyRelu activation"
+                    .to_string(),
+            ))
+        }
+    };
+    let output_activation = match output_layer.activation.as_deref() {
+        None | Some("Softmax") => ActivationFunction::Linear,
+        Some(op) => activation_from_op(op),
+    };
+
+    for (prev, next) in layers.iter().zip(layers.iter().skip(1)) {
+        if prev.weights.ncols() != next.weights.nrows() {
+            return Err(NeuralNetError::ShapeMismatch {
+                expected: vec![prev.weights.ncols()],
+                actual: vec![next.weights.nrows()],
+            });
+        }
+    }
+
+    let layer_structure: Vec<usize> = std::iter::once(layers[0].weights.nrows())
+        .chain(layers.iter().map(|layer| layer.weights.ncols()))
+        .collect();
+
+    // The last dimension of the graph's input and output are the features (the first is usually the batch size)
+    for (shape, expected) in [
+        (input_shape, layer_structure[0]),
+        (output_shape, *layer_structure.last().unwrap()),
+    ] {
+        if let Some(Some(actual)) = shape.last() {
+            if *actual != expected {
+                return Err(NeuralNetError::ShapeMismatch {
+                    expected: vec![expected],
+                    actual: vec![*actual],
+                });
+            }
+        }
+    }
+
+    let mut net = NeuralNetBuilder::new(layer_structure)
+        .activation_function(activation_function)
+        .build()
+        .with_output_activation(output_activation);
+    net.layers = layers
+        .into_iter()
+        .map(|layer| Box::new(DenseLayer::new(layer.weights, layer.biases)) as Box<dyn Layer>)
+        .collect();
+
+    Ok(net)
+}
+
+fn activation_from_op(op: &str) -> ActivationFunction {
+    match op {
+        "Sigmoid" => ActivationFunction::Sigmoid,
+        "Tanh" => ActivationFunction::Tanh,
+        "LeakyRelu" => ActivationFunction::LeakyReLU,
+        _ => ActivationFunction::ReLU,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::metrics::accuracy;
+    use crate::model::neural_net::Verbosity;
+    use crate::model::Model;
+    use crate::parsing::Dataset;
+    use ndarray::{Array, Zip};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("onnx_{}_{}.onnx", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Instances whose class is the feature with the largest value
+    fn argmax_dataset(rows: usize, seed: u64) -> Dataset {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let data = Array2::from_shape_fn((rows, 3), |_| rng.gen_range(-1f64..1f64));
+        let mut target = Array2::zeros((rows, 3));
+
+        for (row, instance) in data.rows().into_iter().enumerate() {
+            let class = (0..3)
+                .max_by(|&a, &b| instance[a].total_cmp(&instance[b]))
+                .unwrap();
+            target[[row, class]] = 1f64;
+        }
+
+        Dataset { data, target }
+    }
+
+    #[test]
+    fn exported_nets_are_imported_with_the_same_predictions() {
+        let dataset = argmax_dataset(200, 0);
+        let (train, test) = dataset.split(0.25, Some(0));
+
+        for act in [
+            ActivationFunction::ReLU,
+            ActivationFunction::Sigmoid,
+            ActivationFunction::Tanh,
+            ActivationFunction::LeakyReLU,
+        ] {
+            let path = temp_path(&format!("{:?}", act));
+            let mut net = NeuralNetBuilder::new(vec![3, 16, 8, 3])
+                .activation_function(act)
+                .num_epochs(Some(10))
+                .batch_size(16)
+                .seed(1)
+                .verbosity(Verbosity::Silent)
+                .build();
+
+            net.fit(&train, None);
+            net.temperature = 1.5;
+            save_to_onnx(&net, &path).unwrap();
+
+            let imported = load_from_onnx(&path).unwrap();
+            let expected = net.predict(&test.data.view());
+            let actual = imported.predict(&test.data.view());
+
+            assert_eq!(imported.layers.len(), 3);
+            assert!(Zip::from(&expected)
+                .and(&actual)
+                .all(|a, b| (a - b).abs() < 1e-12));
+            assert_eq!(
+                accuracy(&actual, &test.target),
+                accuracy(&expected, &test.target)
+            );
+
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn nets_without_an_onnx_graph_are_not_exported() {
+        let path = temp_path("unsupported");
+        let linear = NeuralNetBuilder::new(vec![3, 4, 2])
+            .activation_function(ActivationFunction::Linear)
+            .build();
+        let regression = NeuralNetBuilder::new(vec![3, 4, 1])
+            .loss_function(LossFunction::MSE)
+            .build();
+
+        for net in [linear, regression] {
+            assert!(matches!(
+                save_to_onnx(&net, &path),
+                Err(NeuralNetError::UnsupportedOp { .. })
+            ));
+        }
+
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    /// A graph of a single MatMul of a 2x3 matrix, followed by an op
+    fn single_layer_graph(op_type: &str, input_shape: Vec<Option<usize>>) -> OnnxGraph {
+        let node = |op_type: &str, inputs: &[&str], output: &str| OnnxNode {
+            op_type: op_type.to_string(),
+            inputs: inputs.iter().map(|input| input.to_string()).collect(),
+            outputs: vec![output.to_string()],
+            ..OnnxNode::default()
+        };
+        let mut graph = OnnxGraph {
+            nodes: vec![node("MatMul", &["x", "W"], "z"), node(op_type, &["z"], "y")],
+            ..OnnxGraph::default()
+        };
+
+        graph
+            .initializers
+            .insert("W".to_string(), Array::zeros((2, 3)).into_dyn());
+        graph.inputs.push(OnnxValueInfo {
+            name: "x".to_string(),
+            shape: input_shape,
+        });
+        graph.outputs.push(OnnxValueInfo {
+            name: "y".to_string(),
+            shape: vec![None, Some(3)],
+        });
+
+        graph
+    }
+
+    #[test]
+    fn unknown_ops_and_mismatched_shapes_are_errors() {
+        let path = temp_path("invalid");
+
+        write_onnx_graph(&path, &single_layer_graph("Conv", vec![None, Some(2)])).unwrap();
+        assert!(matches!(
+            load_from_onnx(&path),
+            Err(NeuralNetError::UnsupportedOp { op }) if op == "Conv"
+        ));
+
+        write_onnx_graph(&path, &single_layer_graph("Relu", vec![None, Some(5)])).unwrap();
+        assert!(matches!(
+            load_from_onnx(&path),
+            Err(NeuralNetError::ShapeMismatch { .. })
+        ));
+
+        write_onnx_graph(&path, &single_layer_graph("Relu", vec![None, Some(2)])).unwrap();
+        assert_eq!(
+            load_from_onnx(&path).unwrap().layers[0].weights().dim(),
+            (2, 3)
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+use ndarray::{Array, Array1, Array2, ArrayView, Dimension, Ix1, Ix2, Zip};
+
+use super::layer::{Layer, LayerGradients};
+
+/// The optimization algorithm used to update the weights from their gradients
+#[derive(Clone, Debug, Default)]
+pub enum Optimizer {
+    /// Plain gradient descent - the parameters move by -learning_rate * gradient
+    #[default]
+    SGD,
+    /// Resilient backpropagation. Each parameter has its own step size, which grows by eta_plus while the sign of
+    /// its gradient stays the same and shrinks by eta_minus when it flips. Only the sign of the gradient is used
+    RPROP {
+        delta_0: f64,
+        delta_min: f64,
+        delta_max: f64,
+        eta_plus: f64,
+        eta_minus: f64,
+    },
+    /// Adam (Kingma & Ba, 2015). Each parameter moves by the moving average of its gradient, divided by the root of
+    /// the moving average of its squared gradient, so the step size doesn't depend on the scale of the gradients
+    Adam {
+        beta1: f64,
+        beta2: f64,
+        epsilon: f64,
+    },
+}
+
+/// The state the optimizer keeps about a single parameter array between steps
+pub enum ParamState<D: Dimension> {
+    RPROP {
+        deltas: Array<f64, D>,     // The step size of each element
+        prev_grads: Array<f64, D>, // The gradient of the previous step
+        prev_steps: Array<f64, D>, // The update applied in the previous step
+    },
+    Adam {
+        m: Array<f64, D>, // The moving average of the gradients
+        v: Array<f64, D>, // The moving average of the squared gradients
+        updates: i32,     // The number of times the array was updated, for the bias correction
+    },
+}
+
+impl<D: Dimension> ParamState<D> {
+    /// The initial state of an optimizer that keeps one (SGD doesn't)
+    fn new(param: &ArrayView<f64, D>, optimizer: &Optimizer) -> ParamState<D> {
+        match optimizer {
+            Optimizer::SGD => panic!("SGD doesn't keep a state"),
+            Optimizer::RPROP { delta_0, .. } => ParamState::RPROP {
+                deltas: Array::from_elem(param.raw_dim(), *delta_0),
+                prev_grads: Array::zeros(param.raw_dim()),
+                prev_steps: Array::zeros(param.raw_dim()),
+            },
+            Optimizer::Adam { .. } => ParamState::Adam {
+                m: Array::zeros(param.raw_dim()),
+                v: Array::zeros(param.raw_dim()),
+                updates: 0,
+            },
+        }
+    }
+}
+
+/// The state the optimizer keeps about the weights and the biases of a layer
+pub type LayerState = (ParamState<Ix2>, ParamState<Ix1>);
+
+/// The state of the optimizer for all of the layers of a network
+#[derive(Default)]
+pub struct OptimizerState {
+    layers: Vec<LayerState>,
+    pub step: usize, // The number of updates performed so far
+}
+
+impl OptimizerState {
+    pub fn new() -> OptimizerState {
+        OptimizerState::default()
+    }
+}
+
+impl Optimizer {
+    /// RPROP with the step sizes recommended by Riedmiller & Braun (1993)
+    pub fn rprop() -> Optimizer {
+        Optimizer::RPROP {
+            delta_0: 0.1,
+            delta_min: 1e-6,
+            delta_max: 50f64,
+            eta_plus: 1.2,
+            eta_minus: 0.5,
+        }
+    }
+
+    /// Adam with the hyperparameters recommended by Kingma & Ba (2015)
+    pub fn adam() -> Optimizer {
+        Optimizer::Adam {
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+        }
+    }
+
+    /// The number of arrays of the size of the parameters the optimizer keeps as its state
+    pub fn state_arrays_per_param(&self) -> usize {
+        match self {
+            Optimizer::SGD => 0,
+            // The step sizes, the previous gradients and the previous updates
+            Optimizer::RPROP { .. } => 3,
+            // The moving averages of the gradients and of the squared gradients
+            Optimizer::Adam { .. } => 2,
+        }
+    }
+
+    /// Update the parameters of every layer in place using its gradients
+    /// Layers that are skipped (e.g. frozen layers) aren't updated, and their state doesn't change
+    /// Each layer has its own learning rate (RPROP doesn't use them, Adam scales its steps by them)
+    pub fn update(
+        &self,
+        layers: &mut [Box<dyn Layer>],
+        grads: &[LayerGradients],
+        skip: &[bool],
+        learning_rates: &[f64],
+        state: &mut OptimizerState,
+    ) {
+        if !matches!(self, Optimizer::SGD) && state.layers.is_empty() {
+            state.layers = layers
+                .iter()
+                .map(|layer| {
+                    (
+                        ParamState::new(&layer.weights().view(), self),
+                        ParamState::new(&layer.biases().view(), self),
+                    )
+                })
+                .collect();
+        }
+
+        for (idx, (layer, layer_grads)) in layers.iter_mut().zip(grads.iter()).enumerate() {
+            if skip[idx] {
+                continue;
+            }
+
+            layer.update(
+                layer_grads,
+                self,
+                learning_rates[idx],
+                state.layers.get_mut(idx),
+            );
+        }
+
+        state.step += 1;
+    }
+
+    /// Update the weights and the biases of a single layer in place. RPROP and Adam need the state of the layer
+    pub fn update_parameters(
+        &self,
+        weights: &mut Array2<f64>,
+        biases: &mut Array1<f64>,
+        (weight_grad, bias_grad): &LayerGradients,
+        learning_rate: f64,
+        state: Option<&mut LayerState>,
+    ) {
+        match (self, state) {
+            (Optimizer::SGD, _) => {
+                weights.scaled_add(-learning_rate, weight_grad);
+                biases.scaled_add(-learning_rate, bias_grad);
+            }
+            (Optimizer::RPROP { .. }, Some((weight_state, bias_state))) => {
+                self.rprop_update(weights, weight_grad, weight_state);
+                self.rprop_update(biases, bias_grad, bias_state);
+            }
+            (Optimizer::RPROP { .. }, None) => panic!("RPROP needs the state of the layer"),
+            (Optimizer::Adam { .. }, Some((weight_state, bias_state))) => {
+                self.adam_update(weights, weight_grad, learning_rate, weight_state);
+                self.adam_update(biases, bias_grad, learning_rate, bias_state);
+            }
+            (Optimizer::Adam { .. }, None) => panic!("Adam needs the state of the layer"),
+        }
+    }
+
+    /// Perform a bias-corrected Adam step on a single parameter array
+    fn adam_update<D: Dimension>(
+        &self,
+        param: &mut Array<f64, D>,
+        grad: &Array<f64, D>,
+        learning_rate: f64,
+        state: &mut ParamState<D>,
+    ) {
+        let Optimizer::Adam {
+            beta1,
+            beta2,
+            epsilon,
+        } = *self
+        else {
+            return;
+        };
+        let ParamState::Adam { m, v, updates } = state else {
+            panic!("The optimizer state isn't Adam's");
+        };
+
+        // The moving averages start at 0, so they are biased towards 0 in the first steps
+        *updates += 1;
+        let first_correction = 1f64 - beta1.powi(*updates);
+        let second_correction = 1f64 - beta2.powi(*updates);
+
+        Zip::from(param)
+            .and(grad)
+            .and(m)
+            .and(v)
+            .for_each(|param, &grad, m, v| {
+                *m = beta1 * *m + (1f64 - beta1) * grad;
+                *v = beta2 * *v + (1f64 - beta2) * grad * grad;
+                *param -= learning_rate * (*m / first_correction)
+                    / ((*v / second_correction).sqrt() + epsilon);
+            });
+    }
+
+    /// Perform an RPROP step with weight backtracking on a single parameter array
+    fn rprop_update<D: Dimension>(
+        &self,
+        param: &mut Array<f64, D>,
+        grad: &Array<f64, D>,
+        state: &mut ParamState<D>,
+    ) {
+        let Optimizer::RPROP {
+            delta_min,
+            delta_max,
+            eta_plus,
+            eta_minus,
+            ..
+        } = *self
+        else {
+            return;
+        };
+        let ParamState::RPROP {
+            deltas,
+            prev_grads,
+            prev_steps,
+        } = state
+        else {
+            panic!("The optimizer state isn't RPROP's");
+        };
+
+        Zip::from(param)
+            .and(grad)
+            .and(deltas)
+            .and(prev_grads)
+            .and(prev_steps)
+            .for_each(|param, &grad, delta, prev_grad, prev_step| {
+                let agreement = grad * *prev_grad;
+
+                if agreement > 0f64 {
+                    *delta = (*delta * eta_plus).min(delta_max);
+                    *prev_step = -sign(grad) * *delta;
+                    *param += *prev_step;
+                    *prev_grad = grad;
+                } else if agreement < 0f64 {
+                    // We jumped over a minimum - shrink the step and revert the previous step
+                    // The gradient is zeroed so that the step size isn't shrunk again in the next step
+                    *delta = (*delta * eta_minus).max(delta_min);
+                    *param -= *prev_step;
+                    *prev_step = 0f64;
+                    *prev_grad = 0f64;
+                } else {
+                    *prev_step = -sign(grad) * *delta;
+                    *param += *prev_step;
+                    *prev_grad = grad;
+                }
+            });
+    }
+}
+
+/// The sign of x, where the sign of 0 is 0 (unlike f64::signum)
+fn sign(x: f64) -> f64 {
+    if x > 0f64 {
+        1f64
+    } else if x < 0f64 {
+        -1f64
+    } else {
+        0f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn adam_first_step_moves_by_the_learning_rate() {
+        // After the bias correction, the first step is learning_rate * sign(gradient) whatever the scale
+        let mut weights = array![[1f64, -2f64], [0.5, 3f64]];
+        let mut biases = array![0f64, 1f64];
+        let grads = (array![[100f64, -0.01], [0f64, 2f64]], array![-5f64, 1e-3]);
+        let optimizer = Optimizer::adam();
+        let mut state = (
+            ParamState::new(&weights.view(), &optimizer),
+            ParamState::new(&biases.view(), &optimizer),
+        );
+
+        optimizer.update_parameters(&mut weights, &mut biases, &grads, 0.1, Some(&mut state));
+
+        let expected_weights = array![[0.9, -1.9], [0.5, 2.9]];
+        let expected_biases = array![0.1, 0.9];
+        assert!(Zip::from(&weights)
+            .and(&expected_weights)
+            .all(|a, b| (a - b).abs() < 1e-6));
+        assert!(Zip::from(&biases)
+            .and(&expected_biases)
+            .all(|a, b| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn adam_minimizes_a_quadratic() {
+        // f(w) = sum((w - target)^2), whose gradient is 2 * (w - target)
+        let target = array![[3f64, -1f64]];
+        let mut weights = Array2::zeros((1, 2));
+        let mut biases = Array1::zeros(1);
+        let optimizer = Optimizer::adam();
+        let mut state = (
+            ParamState::new(&weights.view(), &optimizer),
+            ParamState::new(&biases.view(), &optimizer),
+        );
+
+        for _ in 0..2000 {
+            let grads = (2f64 * (&weights - &target), Array1::zeros(1));
+            optimizer.update_parameters(&mut weights, &mut biases, &grads, 0.05, Some(&mut state));
+        }
+
+        assert!(
+            Zip::from(&weights)
+                .and(&target)
+                .all(|a, b| (a - b).abs() < 1e-3),
+            "{}",
+            weights
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use ndarray::Zip;
+use rand::Rng;
+
+use super::neural_net::Gradients;
+use super::noise::standard_normal;
+
+/// The Renyi divergence orders over which the privacy budget is optimized
+const RDP_ORDERS: std::ops::RangeInclusive<u32> = 2..=256;
+
+/// Differentially private SGD (Abadi et al. 2016): the gradient of each sample is clipped, and Gaussian noise
+/// is added to their sum, so that no single sample has a large effect on the weights
+#[derive(Clone, Debug)]
+pub struct DPConfig {
+    pub noise_multiplier: f64, // The std of the noise, relative to max_grad_norm
+    pub max_grad_norm: f64,    // The gradient of each sample is clipped to this L2 norm
+    pub delta: f64, // The probability that the epsilon guarantee fails. Should be below 1 / (number of samples)
+}
+
+/// The L2 norm of the gradients of all of the layers together
+pub fn gradients_norm(grads: &Gradients) -> f64 {
+    grads
+        .iter()
+        .map(|(weight_grad, bias_grad)| {
+            weight_grad.iter().map(|x| x * x).sum::<f64>()
+                + bias_grad.iter().map(|x| x * x).sum::<f64>()
+        })
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Scale the gradients of a sample down so that their L2 norm is at most max_norm
+pub fn clip_gradients(grads: &mut Gradients, max_norm: f64) {
+    let norm = gradients_norm(grads);
+
+    if norm > max_norm {
+        let scale = max_norm / norm;
+
+        for (weight_grad, bias_grad) in grads.iter_mut() {
+            *weight_grad *= scale;
+            *bias_grad *= scale;
+        }
+    }
+}
+
+/// Add Gaussian noise with standard deviation std to every gradient
+pub fn add_gaussian_noise(grads: &mut Gradients, std: f64, rng: &mut impl Rng) {
+    for (weight_grad, bias_grad) in grads.iter_mut() {
+        Zip::from(weight_grad).for_each(|x| *x += std * standard_normal(rng));
+        Zip::from(bias_grad).for_each(|x| *x += std * standard_normal(rng));
+    }
+}
+
+/// Compute the (epsilon, delta) privacy guarantee of training with DP-SGD for the given number of epochs,
+/// using the Renyi DP accountant of the sampled Gaussian mechanism (Mironov et al. 2019)
+/// The accountant assumes that each batch is sampled uniformly, so it's an approximation for sequential batches
+pub fn compute_privacy_budget(
+    epochs: usize,
+    batch_size: usize,
+    n_samples: usize,
+    config: &DPConfig,
+) -> (f64, f64) {
+    if config.noise_multiplier <= 0f64 {
+        return (f64::INFINITY, config.delta);
+    }
+
+    let sampling_rate = (batch_size as f64 / n_samples as f64).min(1f64);
+    let steps = (epochs * n_samples.div_ceil(batch_size)) as f64;
+    let epsilon = RDP_ORDERS
+        .map(|order| {
+            let rdp = sampled_gaussian_rdp(sampling_rate, config.noise_multiplier, order);
+
+            // Conversion from RDP to (epsilon, delta)-DP
+            steps * rdp + (1f64 / config.delta).ln() / (order - 1) as f64
+        })
+        .fold(f64::INFINITY, f64::min);
+
+    (epsilon, config.delta)
+}
+
+/// The RDP of a single step of the sampled Gaussian mechanism for an integer order alpha:
+/// log(sum_k C(alpha, k) * (1 - q)^(alpha - k) * q^k * exp((k^2 - k) / (2 * sigma^2))) / (alpha - 1)
+fn sampled_gaussian_rdp(sampling_rate: f64, noise_multiplier: f64, order: u32) -> f64 {
+    let alpha = order as f64;
+    let mut log_binomial = 0f64;
+    // The log of each term of the sum
+    let log_terms: Vec<f64> = (0..=order)
+        .map(|k| {
+            let k = k as f64;
+
+            if k > 0f64 {
+                log_binomial += (alpha - k + 1f64).ln() - k.ln();
+            }
+
+            log_binomial
+                + log_power(1f64 - sampling_rate, alpha - k)
+                + log_power(sampling_rate, k)
+                + (k * k - k) / (2f64 * noise_multiplier * noise_multiplier)
+        })
+        .collect();
+    let max = log_terms.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let log_sum = max + log_terms.iter().map(|x| (x - max).exp()).sum::<f64>().ln();
+
+    log_sum / (alpha - 1f64)
+}
+
+/// log(base^exponent), where 0^0 = 1
+fn log_power(base: f64, exponent: f64) -> f64 {
+    if exponent == 0f64 {
+        0f64
+    } else {
+        exponent * base.ln()
+    }
+}
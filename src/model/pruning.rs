@@ -0,0 +1,126 @@
+use super::neural_net::NeuralNet;
+use crate::error::{NeuralNetError, Result};
+use ndarray::{Array2, Axis};
+
+/// Gradual magnitude pruning (Zhu & Gupta 2018). Every frequency steps between begin_step and end_step the net is
+/// pruned to a sparsity that grows from initial_sparsity to final_sparsity along a cubic, so that most of the
+/// weights are pruned early while the net can still recover, and the last ones slowly
+#[derive(Clone, Debug)]
+pub struct PruningScheduler {
+    pub initial_sparsity: f64,
+    pub final_sparsity: f64,
+    pub begin_step: usize,
+    pub end_step: usize,
+    pub frequency: usize,
+    step: usize, // The current step
+}
+
+impl PruningScheduler {
+    pub fn new(
+        initial_sparsity: f64,
+        final_sparsity: f64,
+        begin_step: usize,
+        end_step: usize,
+        frequency: usize,
+    ) -> PruningScheduler {
+        PruningScheduler {
+            initial_sparsity,
+            final_sparsity,
+            begin_step,
+            end_step: end_step.max(begin_step),
+            frequency: frequency.max(1),
+            step: 0,
+        }
+    }
+
+    /// The target sparsity at a step between begin_step and end_step
+    pub fn sparsity_at(&self, step: usize) -> f64 {
+        let length = (self.end_step - self.begin_step).max(1) as f64;
+        let progress = (step.saturating_sub(self.begin_step) as f64 / length).min(1f64);
+
+        self.final_sparsity
+            - (self.final_sparsity - self.initial_sparsity) * (1f64 - progress).powi(3)
+    }
+
+    /// Advance to the next step, and return the sparsity to prune the net to if it's a pruning step
+    /// The end step is always a pruning step, so that the net reaches the final sparsity
+    pub fn step(&mut self) -> Option<f64> {
+        let step = self.step;
+        self.step += 1;
+
+        if step < self.begin_step || step > self.end_step {
+            return None;
+        }
+
+        let is_pruning_step =
+            (step - self.begin_step).is_multiple_of(self.frequency) || step == self.end_step;
+
+        is_pruning_step.then(|| self.sparsity_at(step))
+    }
+}
+
+/// The number of weights of each layer that aren't zero
+pub fn count_nonzero_weights(model: &NeuralNet) -> Vec<usize> {
+    model
+        .layers
+        .iter()
+        .map(|layer| layer.weights().iter().filter(|&&x| x != 0f64).count())
+        .collect()
+}
+
+/// A copy of the net for inference without the neurons of the hidden layers whose incoming weights have an L2 norm
+/// below the threshold (each layer keeps at least its neuron with the largest norm)
+pub fn prune_neurons_auto(model: &NeuralNet, l2_threshold: f64) -> NeuralNet {
+    let mut pruned = model.snapshot();
+
+    for layer_idx in 0..pruned.layers.len().saturating_sub(1) {
+        let norms = pruned.neuron_norms(layer_idx).unwrap();
+        let largest = norms.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let keep: Vec<usize> = (0..norms.len())
+            .filter(|&idx| norms[idx] >= l2_threshold.min(largest))
+            .collect();
+
+        pruned.remove_neurons(layer_idx, &keep).unwrap();
+    }
+
+    pruned
+}
+
+/// The cosine similarity between the incoming weights of every pair of neurons of a layer (the columns of its weight
+/// matrix). Neurons whose weights are almost parallel compute nearly the same feature, so one of them is redundant
+/// A neuron with zero weights has a similarity of 0 to every neuron
+pub fn neuron_redundancy_matrix(model: &NeuralNet, layer_idx: usize) -> Result<Array2<f64>> {
+    let layer = model
+        .layers
+        .get(layer_idx)
+        .ok_or(NeuralNetError::InvalidLayer {
+            index: layer_idx,
+            num_layers: model.layers.len(),
+        })?;
+    let weights = layer.weights();
+    let norms = weights
+        .map_axis(Axis(0), |column| column.dot(&column).sqrt())
+        .mapv(|norm| if norm > 0f64 { norm.recip() } else { 0f64 });
+    let normalized = &weights * &norms;
+
+    Ok(normalized.t().dot(&normalized))
+}
+
+/// The pairs of neurons of a layer (i < j) whose weights have a cosine similarity above the threshold, sorted from
+/// the most similar pair
+pub fn redundant_neuron_pairs(
+    model: &NeuralNet,
+    layer_idx: usize,
+    threshold: f64,
+) -> Result<Vec<(usize, usize, f64)>> {
+    let similarities = neuron_redundancy_matrix(model, layer_idx)?;
+    let mut pairs: Vec<(usize, usize, f64)> = similarities
+        .indexed_iter()
+        .filter(|&((i, j), &similarity)| i < j && similarity > threshold)
+        .map(|((i, j), &similarity)| (i, j, similarity))
+        .collect();
+
+    pairs.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    Ok(pairs)
+}
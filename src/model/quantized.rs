@@ -0,0 +1,245 @@
+use crate::error::Result;
+use crate::parsing::Dataset;
+use clap::ValueEnum;
+use json::object;
+use ndarray::{Array1, Array2, ArrayView2};
+use std::fs::File;
+use std::io::Write;
+
+use super::loss::softmax_rows;
+use super::metrics::accuracy;
+use super::neural_net::{activation, ActivationFunction, NeuralNet};
+use super::Model;
+
+/// The largest magnitude of a quantized value. We use a symmetric range, so -128 is never used
+const QUANTIZED_MAX: f64 = 127f64;
+
+/// The number of int8 values (from -128 to 127) the activations are quantized to
+const ACTIVATION_LEVELS: f64 = 255f64;
+
+/// The affine int8 quantization of the inputs of a layer: x_q = round(x / scale) + zero_point
+#[derive(Clone, Copy, Debug)]
+pub struct ActivationQuantization {
+    pub scale: f64,
+    pub zero_point: i32,
+}
+
+impl ActivationQuantization {
+    /// Map the range of the calibration activations to [-128, 127]. The range is extended to include 0, so that
+    /// zero activations (e.g. of ReLU) are quantized exactly
+    pub fn from_range(min: f64, max: f64) -> ActivationQuantization {
+        let (min, max) = (min.min(0f64), max.max(0f64));
+        // A range of only zeros can use any scale
+        let scale = if max > min {
+            (max - min) / ACTIVATION_LEVELS
+        } else {
+            1f64
+        };
+
+        ActivationQuantization {
+            scale,
+            zero_point: -128 - (min / scale).round() as i32,
+        }
+    }
+
+    /// Quantize the activations to int8 and dequantize them back, clipping the values outside the calibrated range
+    pub fn fake_quantize(&self, x: &Array2<f64>) -> Array2<f64> {
+        x.mapv(|x| {
+            let q = ((x / self.scale).round() as i32 + self.zero_point).clamp(-128, 127);
+
+            (q - self.zero_point) as f64 * self.scale
+        })
+    }
+}
+
+/// A neural net whose weights and biases are quantized to int8, used for inference only
+pub struct QuantizedNeuralNet {
+    pub layers: Vec<(Array2<i8>, Array1<i8>, f64)>, // Each layer holds the quantized weights, biases and their scale
+    pub activation_function: ActivationFunction,
+    pub activations: Vec<ActivationQuantization>, // The quantization of the inputs of each layer. Empty if only the weights are quantized
+}
+
+impl QuantizedNeuralNet {
+    /// Quantize each layer symmetrically: scale = max(|W|, |b|) / 127 and W_q = round(W / scale)
+    /// The biases share the scale of the weights, which is why they're included in the max (otherwise they could be clipped)
+    pub fn from_net(net: &NeuralNet) -> QuantizedNeuralNet {
+        let layers = net
+            .layers
+            .iter()
+            .map(|layer| {
+                let (w, b) = (layer.weights(), layer.biases());
+                let max = w
+                    .iter()
+                    .chain(b.iter())
+                    .fold(0f64, |acc, x| acc.max(x.abs()));
+                // An all-zero layer can use any scale
+                let scale = if max > 0f64 {
+                    max / QUANTIZED_MAX
+                } else {
+                    1f64
+                };
+                let quantize = |x: &f64| (x / scale).round() as i8;
+
+                (w.map(quantize), b.map(quantize), scale)
+            })
+            .collect();
+
+        QuantizedNeuralNet {
+            layers,
+            activation_function: net.activation_function.clone(),
+            activations: vec![],
+        }
+    }
+
+    /// Quantize the inputs of each layer as well, with the ranges (min, max) of its inputs on calibration data
+    pub fn with_activation_ranges(mut self, ranges: &[(f64, f64)]) -> QuantizedNeuralNet {
+        self.activations = ranges
+            .iter()
+            .map(|&(min, max)| ActivationQuantization::from_range(min, max))
+            .collect();
+
+        self
+    }
+
+    /// Predict the probabilities for a set of instances
+    /// Each layer is dequantized to f64 on the fly, so the quantized model is never stored in full precision
+    /// If the activations are quantized, the input of each layer is quantized to int8 and dequantized before it
+    pub fn predict(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        let mut output = inputs.to_owned();
+
+        for (i, (w, b, scale)) in self.layers.iter().enumerate() {
+            if let Some(quantization) = self.activations.get(i) {
+                output = quantization.fake_quantize(&output);
+            }
+
+            let weights = w.mapv(|x| x as f64 * scale);
+            let biases = b.mapv(|x| x as f64 * scale);
+
+            output = output.dot(&weights) + biases;
+
+            // The output layer isn't activated
+            if i != self.layers.len() - 1 {
+                output.mapv_inplace(|x| activation(&self.activation_function, x));
+            }
+        }
+
+        softmax_rows(&output)
+    }
+
+    /// Write the quantized model in JSON format. The keys are the same as those of NeuralNet::save,
+    /// with the scale of each layer stored in s0, s1, etc. and the scale and zero point of its inputs (if they're
+    /// quantized) in as0, az0, etc.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut data = object! {};
+        let mut file = File::create(path)?;
+
+        for (i, (w, b, scale)) in self.layers.iter().enumerate() {
+            let w: Vec<i8> = w.iter().copied().collect();
+            let b: Vec<i8> = b.iter().copied().collect();
+
+            data[format!("W{}", i)] = w.into();
+            data[format!("b{}", i)] = b.into();
+            data[format!("s{}", i)] = (*scale).into();
+        }
+
+        for (i, quantization) in self.activations.iter().enumerate() {
+            data[format!("as{}", i)] = quantization.scale.into();
+            data[format!("az{}", i)] = quantization.zero_point.into();
+        }
+
+        if let Some(name) = self.activation_function.to_possible_value() {
+            data["activation"] = name.get_name().into();
+        }
+
+        file.write_all(data.dump().as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Return the accuracies of the original and the quantized models on the dataset
+pub fn compare_accuracy(
+    orig: &NeuralNet,
+    quant: &QuantizedNeuralNet,
+    dataset: &Dataset,
+) -> (f64, f64) {
+    let inputs = dataset.data.view();
+
+    (
+        accuracy(&orig.predict(&inputs), &dataset.target),
+        accuracy(&quant.predict(&inputs), &dataset.target),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::neural_net::{NeuralNetBuilder, Verbosity};
+    use ndarray::array;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn fake_quantization_is_within_half_a_step() {
+        let quantization = ActivationQuantization::from_range(-1f64, 3f64);
+        let x = Array2::from_shape_fn((1, 101), |(_, col)| -1f64 + 0.04 * col as f64);
+        let quantized = quantization.fake_quantize(&x);
+
+        assert!((quantization.scale - 4f64 / 255f64).abs() < 1e-12);
+        assert!(x
+            .iter()
+            .zip(quantized.iter())
+            .all(|(x, q)| (x - q).abs() <= quantization.scale / 2f64 + 1e-12));
+
+        // 0 is quantized exactly, and the values outside the range are clipped to it
+        let quantized = quantization.fake_quantize(&array![[0f64, -10f64, 10f64]]);
+
+        assert_eq!(quantized[[0, 0]], 0f64);
+        assert!((quantized[[0, 1]] + 1f64).abs() <= quantization.scale);
+        assert!((quantized[[0, 2]] - 3f64).abs() <= quantization.scale);
+
+        // A range of only positive values (e.g. after ReLU) still includes 0
+        let quantization = ActivationQuantization::from_range(2f64, 5f64);
+
+        assert_eq!(quantization.zero_point, -128);
+        assert_eq!(quantization.fake_quantize(&array![[0f64]])[[0, 0]], 0f64);
+    }
+
+    #[test]
+    fn static_quantization_keeps_the_accuracy() {
+        // The class of each instance is the feature with the largest value
+        let mut rng = StdRng::seed_from_u64(0);
+        let data = Array2::from_shape_fn((2000, 4), |_| rng.gen_range(-1f64..1f64));
+        let mut target = Array2::zeros((2000, 4));
+
+        for (row, instance) in data.rows().into_iter().enumerate() {
+            let class = (0..4)
+                .max_by(|&a, &b| instance[a].total_cmp(&instance[b]))
+                .unwrap();
+            target[[row, class]] = 1f64;
+        }
+
+        let (train, test) = Dataset { data, target }.split(0.25, Some(0));
+        let mut net = NeuralNetBuilder::new(vec![4, 32, 4])
+            .num_epochs(Some(20))
+            .batch_size(32)
+            .learning_rate(0.05)
+            .seed(1)
+            .verbosity(Verbosity::Silent)
+            .build();
+
+        net.fit(&train, None);
+
+        let quantized = net.quantize_activations(&train.data);
+        let (accuracy, quantized_accuracy) = compare_accuracy(&net, &quantized, &test);
+
+        assert_eq!(quantized.activations.len(), 2);
+        assert!(accuracy > 0.85, "accuracy {}", accuracy);
+        assert!(
+            accuracy - quantized_accuracy < 0.01,
+            "accuracy {} before quantization, {} after",
+            accuracy,
+            quantized_accuracy
+        );
+    }
+}
@@ -0,0 +1,358 @@
+/// Learning rate schedules. The scheduler is stepped once per mini-batch, and returns the LR of that batch
+pub trait LRScheduler: Send + Sync {
+    /// Return the LR of the current step and advance to the next step
+    fn step(&mut self) -> f64;
+}
+
+/// Polynomial decay from initial_lr to end_lr over decay_steps steps
+/// With power = 1 this is linear decay. If cycle is set, the decay restarts after decay_steps steps,
+/// and the length of each cycle is twice the length of the previous one
+pub struct PolynomialDecay {
+    pub initial_lr: f64,
+    pub end_lr: f64,
+    pub decay_steps: usize,
+    pub power: f64,
+    pub cycle: bool,
+    step: usize,              // The current step
+    cycle_start: usize,       // The step at which the current cycle started
+    cycle_decay_steps: usize, // The length of the current cycle
+}
+
+impl PolynomialDecay {
+    pub fn new(
+        initial_lr: f64,
+        end_lr: f64,
+        decay_steps: usize,
+        power: f64,
+        cycle: bool,
+    ) -> PolynomialDecay {
+        PolynomialDecay {
+            initial_lr,
+            end_lr,
+            decay_steps,
+            power,
+            cycle,
+            step: 0,
+            cycle_start: 0,
+            cycle_decay_steps: decay_steps,
+        }
+    }
+
+    /// Calculate the LR after s steps into a decay of decay_steps steps
+    /// After decay_steps steps, the LR stays at end_lr
+    pub fn lr_at(&self, s: usize, decay_steps: usize) -> f64 {
+        let remaining = (1f64 - s as f64 / decay_steps as f64).max(0f64);
+
+        (self.initial_lr - self.end_lr) * remaining.powf(self.power) + self.end_lr
+    }
+}
+
+impl LRScheduler for PolynomialDecay {
+    fn step(&mut self) -> f64 {
+        if self.cycle && self.step - self.cycle_start >= self.cycle_decay_steps {
+            self.cycle_start = self.step;
+            self.cycle_decay_steps *= 2;
+        }
+
+        let lr = self.lr_at(self.step - self.cycle_start, self.cycle_decay_steps);
+        self.step += 1;
+
+        lr
+    }
+}
+
+/// Cosine annealing with warm restarts (SGDR, Loshchilov & Hutter 2017)
+/// Within each cycle the LR follows a cosine from eta_max down to eta_min. The first cycle lasts t_0 steps,
+/// and each cycle is t_mult times longer than the previous one
+pub struct SGDRScheduler {
+    pub t_0: usize,
+    pub t_mult: usize,
+    pub eta_min: f64,
+    pub eta_max: f64,
+    t_cur: usize,         // The length of the current cycle
+    step_in_cycle: usize, // The step within the current cycle
+}
+
+impl SGDRScheduler {
+    pub fn new(t_0: usize, t_mult: usize, eta_min: f64, eta_max: f64) -> SGDRScheduler {
+        SGDRScheduler {
+            t_0,
+            t_mult,
+            eta_min,
+            eta_max,
+            t_cur: t_0,
+            step_in_cycle: 0,
+        }
+    }
+
+    /// The length of the current cycle
+    pub fn cycle_length(&self) -> usize {
+        self.t_cur
+    }
+}
+
+impl LRScheduler for SGDRScheduler {
+    fn step(&mut self) -> f64 {
+        if self.step_in_cycle >= self.t_cur {
+            self.step_in_cycle = 0;
+            self.t_cur *= self.t_mult;
+        }
+
+        let lr = cosine_annealing(self.eta_min, self.eta_max, self.step_in_cycle, self.t_cur);
+        self.step_in_cycle += 1;
+
+        lr
+    }
+}
+
+/// SGDR whose maximal LR decays exponentially: cycle k anneals from eta_max_0 * eta_max_decay^k down to eta_min
+/// Like SGDR, the first cycle lasts t_0 steps and each cycle is t_mult times longer than the previous one
+pub struct CosineWarmRestartExp {
+    pub t_0: usize,
+    pub t_mult: usize,
+    pub eta_max_0: f64,
+    pub eta_max_decay: f64,
+    pub eta_min: f64,
+    k: usize,           // The number of restarts so far
+    t_cur: usize,       // The step within the current cycle
+    t_i: usize,         // The length of the current cycle
+    t_cur_total: usize, // The total number of steps taken
+}
+
+impl CosineWarmRestartExp {
+    pub fn new(
+        t_0: usize,
+        t_mult: usize,
+        eta_max_0: f64,
+        eta_max_decay: f64,
+        eta_min: f64,
+    ) -> CosineWarmRestartExp {
+        CosineWarmRestartExp {
+            t_0,
+            t_mult,
+            eta_max_0,
+            eta_max_decay,
+            eta_min,
+            k: 0,
+            t_cur: 0,
+            t_i: t_0.max(1),
+            t_cur_total: 0,
+        }
+    }
+
+    /// The number of restarts so far
+    pub fn cycle(&self) -> usize {
+        self.k
+    }
+
+    /// The total number of steps taken
+    pub fn total_steps(&self) -> usize {
+        self.t_cur_total
+    }
+}
+
+impl LRScheduler for CosineWarmRestartExp {
+    fn step(&mut self) -> f64 {
+        if self.t_cur >= self.t_i {
+            self.k += 1;
+            self.t_cur = 0;
+            self.t_i *= self.t_mult.max(1);
+        }
+
+        let eta_max = self.eta_max_0 * self.eta_max_decay.powi(self.k as i32);
+        let lr = cosine_annealing(self.eta_min, eta_max, self.t_cur, self.t_i);
+        self.t_cur += 1;
+        self.t_cur_total += 1;
+
+        lr
+    }
+}
+
+/// Cosine decay with restarts (TensorFlow's CosineDecayRestarts). Within each cycle the LR follows a cosine down to
+/// min_lr. The first cycle lasts first_decay_steps steps, and after each restart the length of the cycle is
+/// multiplied by t_mul and the LR it starts from by m_mul
+pub struct CosineDecayRestarts {
+    pub initial_lr: f64,
+    pub min_lr: f64,
+    pub first_decay_steps: usize,
+    pub t_mul: f64,
+    pub m_mul: f64,
+    step: usize,         // The total number of steps taken
+    cycle: usize,        // The number of restarts so far
+    cycle_start: usize,  // The step at which the current cycle started
+    cycle_length: usize, // The length of the current cycle
+}
+
+impl CosineDecayRestarts {
+    pub fn new(
+        initial_lr: f64,
+        min_lr: f64,
+        first_decay_steps: usize,
+        t_mul: f64,
+        m_mul: f64,
+    ) -> CosineDecayRestarts {
+        CosineDecayRestarts {
+            initial_lr,
+            min_lr,
+            first_decay_steps,
+            t_mul,
+            m_mul,
+            step: 0,
+            cycle: 0,
+            cycle_start: 0,
+            cycle_length: first_decay_steps.max(1),
+        }
+    }
+
+    /// The number of restarts so far
+    pub fn cycle(&self) -> usize {
+        self.cycle
+    }
+}
+
+impl LRScheduler for CosineDecayRestarts {
+    fn step(&mut self) -> f64 {
+        if self.step - self.cycle_start >= self.cycle_length {
+            self.cycle += 1;
+            self.cycle_start = self.step;
+            self.cycle_length = ((self.cycle_length as f64 * self.t_mul).round() as usize).max(1);
+        }
+
+        let max_lr = self.initial_lr * self.m_mul.powi(self.cycle as i32);
+        let lr = cosine_annealing(
+            self.min_lr,
+            max_lr,
+            self.step - self.cycle_start,
+            self.cycle_length,
+        );
+        self.step += 1;
+
+        lr
+    }
+}
+
+/// A linear warm-up from 0 to eta_max during the first warmup_steps steps, followed by a cosine annealing
+/// to eta_min which ends after total_steps steps. The LR stays at eta_min afterwards
+pub struct CosineWithWarmup {
+    pub warmup_steps: usize,
+    pub total_steps: usize,
+    pub eta_min: f64,
+    pub eta_max: f64,
+    step: usize,
+}
+
+impl CosineWithWarmup {
+    pub fn new(
+        warmup_steps: usize,
+        total_steps: usize,
+        eta_min: f64,
+        eta_max: f64,
+    ) -> CosineWithWarmup {
+        CosineWithWarmup {
+            warmup_steps,
+            total_steps,
+            eta_min,
+            eta_max,
+            step: 0,
+        }
+    }
+}
+
+impl LRScheduler for CosineWithWarmup {
+    fn step(&mut self) -> f64 {
+        let lr = if self.step < self.warmup_steps {
+            self.eta_max * self.step as f64 / self.warmup_steps as f64
+        } else if self.step < self.total_steps {
+            cosine_annealing(
+                self.eta_min,
+                self.eta_max,
+                self.step - self.warmup_steps,
+                self.total_steps - self.warmup_steps,
+            )
+        } else {
+            self.eta_min
+        };
+        self.step += 1;
+
+        lr
+    }
+}
+
+/// The LR after step steps of a cosine annealing from eta_max to eta_min that lasts for length steps
+fn cosine_annealing(eta_min: f64, eta_max: f64, step: usize, length: usize) -> f64 {
+    let progress = step as f64 / length as f64;
+
+    eta_min + 0.5 * (eta_max - eta_min) * (1f64 + (std::f64::consts::PI * progress).cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn polynomial_decay_boundaries() {
+        let scheduler = PolynomialDecay::new(0.1, 0.01, 100, 2f64, false);
+
+        assert_close(scheduler.lr_at(0, 100), 0.1);
+        assert_close(scheduler.lr_at(50, 100), 0.09 * 0.25 + 0.01);
+        assert_close(scheduler.lr_at(100, 100), 0.01);
+        assert_close(scheduler.lr_at(250, 100), 0.01);
+    }
+
+    #[test]
+    fn polynomial_decay_stays_at_end_lr_without_cycling() {
+        let mut scheduler = PolynomialDecay::new(0.1, 0.01, 10, 1f64, false);
+        let lrs: Vec<f64> = (0..30).map(|_| scheduler.step()).collect();
+
+        assert_close(lrs[0], 0.1);
+        assert!(lrs.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(lrs[10..].iter().all(|&lr| (lr - 0.01).abs() < 1e-12));
+    }
+
+    #[test]
+    fn polynomial_decay_cycle_doubles_the_decay_steps() {
+        let mut scheduler = PolynomialDecay::new(0.1, 0.01, 10, 1f64, true);
+        let lrs: Vec<f64> = (0..40).map(|_| scheduler.step()).collect();
+        let restarts: Vec<usize> = (0..lrs.len())
+            .filter(|&step| (lrs[step] - 0.1).abs() < 1e-12)
+            .collect();
+
+        // Cycles of 10, 20 and then 40 steps
+        assert_eq!(restarts, vec![0, 10, 30]);
+        assert_close(lrs[20], 0.1 - 0.09 * 0.5);
+    }
+
+    #[test]
+    fn sgdr_cycle_lengths_double() {
+        let mut scheduler = SGDRScheduler::new(5, 2, 0f64, 1f64);
+        let mut restarts = vec![];
+        let mut lengths = vec![];
+
+        for step in 0..40 {
+            let lr = scheduler.step();
+
+            if (lr - 1f64).abs() < 1e-12 {
+                restarts.push(step);
+                lengths.push(scheduler.cycle_length());
+            }
+        }
+
+        assert_eq!(restarts, vec![0, 5, 15, 35]);
+        assert_eq!(lengths, vec![5, 10, 20, 40]);
+    }
+
+    #[test]
+    fn sgdr_anneals_to_eta_min_within_a_cycle() {
+        let mut scheduler = SGDRScheduler::new(4, 1, 0.1, 1f64);
+        let lrs: Vec<f64> = (0..8).map(|_| scheduler.step()).collect();
+
+        assert!(lrs[..4].windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(lrs[3] > 0.1);
+        assert_close(lrs[4], 1f64);
+        assert_close(lrs[2], 0.1 + 0.45);
+    }
+}
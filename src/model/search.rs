@@ -0,0 +1,368 @@
+use crate::error::{NeuralNetError, Result};
+use crate::parsing::Dataset;
+use clap::ValueEnum;
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rayon::prelude::*;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::Read;
+
+use super::metrics::accuracy;
+use super::neural_net::{ActivationFunction, NeuralNetBuilder, Verbosity};
+use super::Model;
+
+/// The hyperparams grid search goes over. Every combination is trained for num_epochs epochs
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GridSearchConfig {
+    pub learning_rates: Vec<f64>,
+    pub batch_sizes: Vec<usize>,
+    pub layer_structures: Vec<Vec<usize>>,
+    #[serde(deserialize_with = "deserialize_activations")]
+    pub activations: Vec<ActivationFunction>,
+    pub num_epochs: usize,
+}
+
+impl Default for GridSearchConfig {
+    fn default() -> GridSearchConfig {
+        GridSearchConfig {
+            learning_rates: vec![],
+            batch_sizes: vec![],
+            layer_structures: vec![],
+            activations: vec![],
+            num_epochs: 10,
+        }
+    }
+}
+
+/// Deserialize the activations by the names the command line takes them by, e.g. "re-lu"
+fn deserialize_activations<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<ActivationFunction>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| ActivationFunction::from_str(name, true).map_err(de::Error::custom))
+        .collect()
+}
+
+/// The validation accuracy of a single combination of hyperparams
+pub struct GridSearchResult {
+    pub learning_rate: f64,
+    pub batch_size: usize,
+    pub layer_structure: Vec<usize>,
+    pub activation: ActivationFunction,
+    pub val_accuracy: f64,
+}
+
+impl GridSearchConfig {
+    /// Parse a TOML config file, e.g.
+    /// learning_rates = [0.1, 0.01]
+    /// layer_structures = [[784, 100, 10], [784, 300, 10]]
+    /// activations = ["re-lu", "tanh"]
+    pub fn from_file(path: &str) -> Result<GridSearchConfig> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+
+        GridSearchConfig::parse(&contents)
+    }
+
+    /// Parse the contents of a config file. Keys that are missing keep their defaults (no values and 10 epochs)
+    pub fn parse(contents: &str) -> Result<GridSearchConfig> {
+        toml::from_str(contents).map_err(|err| NeuralNetError::Parse(err.to_string()))
+    }
+}
+
+/// Train a network for every combination of hyperparams in the grid, and evaluate it on the validation set
+/// The combinations are trained in parallel. Returns the results sorted by descending validation accuracy
+pub fn grid_search(
+    param_grid: GridSearchConfig,
+    dataset: &Dataset,
+    val_dataset: &Dataset,
+) -> Vec<GridSearchResult> {
+    let mut combinations = vec![];
+
+    for learning_rate in &param_grid.learning_rates {
+        for batch_size in &param_grid.batch_sizes {
+            for layer_structure in &param_grid.layer_structures {
+                for activation in &param_grid.activations {
+                    combinations.push((*learning_rate, *batch_size, layer_structure, activation));
+                }
+            }
+        }
+    }
+
+    let mut results: Vec<GridSearchResult> = combinations
+        .par_iter()
+        .map(|(learning_rate, batch_size, layer_structure, activation)| {
+            let mut net = NeuralNetBuilder::new(layer_structure.to_vec())
+                .num_epochs(Some(param_grid.num_epochs))
+                .learning_rate(*learning_rate)
+                .batch_size(*batch_size)
+                .activation_function((*activation).clone())
+                // The models are trained in parallel, so their logs would be interleaved
+                .verbosity(Verbosity::Silent)
+                .build();
+
+            net.fit(dataset, Some(val_dataset));
+
+            GridSearchResult {
+                learning_rate: *learning_rate,
+                batch_size: *batch_size,
+                layer_structure: layer_structure.to_vec(),
+                activation: (*activation).clone(),
+                val_accuracy: accuracy(&net.predict(&val_dataset.data.view()), &val_dataset.target),
+            }
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.val_accuracy.total_cmp(&a.val_accuracy));
+
+    results
+}
+
+/// The distributions random search samples the hyperparams from
+/// The LR is sampled log-uniformly from lr_log_range, and the others are sampled uniformly (inclusive ranges)
+pub struct RandomSearchConfig {
+    pub lr_log_range: (f64, f64),
+    pub batch_size_range: (usize, usize),
+    pub dropout_range: (f64, f64),
+    pub layer_structure: Vec<usize>,
+    pub activation: ActivationFunction,
+    pub num_epochs: usize,
+    pub val_fraction: f64, // Fraction of the dataset held out to compute the validation accuracy
+}
+
+/// The validation accuracy of a single sampled configuration
+pub struct SearchResult {
+    pub learning_rate: f64,
+    pub batch_size: usize,
+    pub dropout_rate: f64,
+    pub val_accuracy: f64,
+}
+
+/// Train networks with n_iter randomly sampled configurations, and evaluate them on a held-out part of the dataset
+/// The configurations are trained in parallel. Returns the 5 best configurations, sorted by descending validation accuracy
+/// The configurations, the held-out part and the seeds of the networks are all sampled from rng
+pub fn random_search(
+    param_distributions: RandomSearchConfig,
+    n_iter: usize,
+    dataset: &Dataset,
+    rng: &mut StdRng,
+) -> Vec<SearchResult> {
+    const NUM_RESULTS: usize = 5;
+
+    let (train, validation) = dataset.split(param_distributions.val_fraction, Some(rng.gen()));
+    let (min_lr, max_lr) = param_distributions.lr_log_range;
+    let log_lr = Uniform::new_inclusive(min_lr.ln(), max_lr.ln());
+    let batch_size = Uniform::new_inclusive(
+        param_distributions.batch_size_range.0,
+        param_distributions.batch_size_range.1,
+    );
+    let dropout = Uniform::new_inclusive(
+        param_distributions.dropout_range.0,
+        param_distributions.dropout_range.1,
+    );
+    let samples: Vec<(f64, usize, f64, u64)> = (0..n_iter)
+        .map(|_| {
+            (
+                log_lr.sample(rng).exp(),
+                batch_size.sample(rng),
+                dropout.sample(rng),
+                rng.gen(),
+            )
+        })
+        .collect();
+
+    let mut results: Vec<SearchResult> = samples
+        .par_iter()
+        .map(|(learning_rate, batch_size, dropout_rate, seed)| {
+            let mut net = NeuralNetBuilder::new(param_distributions.layer_structure.clone())
+                .num_epochs(Some(param_distributions.num_epochs))
+                .learning_rate(*learning_rate)
+                .batch_size(*batch_size)
+                .dropout_rate(*dropout_rate)
+                .activation_function(param_distributions.activation.clone())
+                .verbosity(Verbosity::Silent)
+                .seed(*seed)
+                .build();
+
+            net.fit(&train, Some(&validation));
+
+            SearchResult {
+                learning_rate: *learning_rate,
+                batch_size: *batch_size,
+                dropout_rate: *dropout_rate,
+                val_accuracy: accuracy(&net.predict(&validation.data.view()), &validation.target),
+            }
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.val_accuracy.total_cmp(&a.val_accuracy));
+    results.truncate(NUM_RESULTS);
+
+    results
+}
+
+/// Train networks that differ from the builder only in the value of a single hyperparam (learning_rate, batch_size,
+/// dropout or num_epochs), and evaluate them on a held-out fifth of the dataset, which is the same for all values
+/// The networks are trained in parallel. Returns the (value, validation accuracy) of each value, in their order
+pub fn hyperparameter_sensitivity(
+    builder: &NeuralNetBuilder,
+    dataset: &Dataset,
+    param: &str,
+    values: &[f64],
+) -> Result<Vec<(f64, f64)>> {
+    const VAL_FRACTION: f64 = 0.2;
+    const SPLIT_SEED: u64 = 0;
+
+    let with_value = |builder: NeuralNetBuilder, value: f64| match param {
+        "learning_rate" => Ok(builder.learning_rate(value)),
+        "batch_size" => Ok(builder.batch_size(value as usize)),
+        "dropout" => Ok(builder.dropout_rate(value)),
+        "num_epochs" => Ok(builder.num_epochs(Some(value as usize))),
+        param => Err(NeuralNetError::Parse(format!(
+            "Unknown hyperparam {}",
+            param
+        ))),
+    };
+    let builders = values
+        .iter()
+        .map(|&value| with_value(builder.clone().verbosity(Verbosity::Silent), value))
+        .collect::<Result<Vec<_>>>()?;
+    let (train, validation) = dataset.stratified_split(VAL_FRACTION, SPLIT_SEED);
+    let accuracies: Vec<f64> = builders
+        .par_iter()
+        .map(|builder| {
+            let mut net = builder.build();
+            net.fit(&train, Some(&validation));
+
+            accuracy(&net.predict(&validation.data.view()), &validation.target)
+        })
+        .collect();
+
+    Ok(values.iter().copied().zip(accuracies).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn grid_search_config_parses_every_key() {
+        let config = GridSearchConfig::parse(
+            "# A comment\n\
+             learning_rates = [0.1, 0.01,] # A trailing comma and comment\n\
+             \n\
+             batch_sizes = [32]\n\
+             layer_structures = [[4, 8, 2], [4, 2]]\n\
+             activations = [\"re-lu\", 'tanh']\n\
+             num_epochs = 3\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.learning_rates, vec![0.1, 0.01]);
+        assert_eq!(config.batch_sizes, vec![32]);
+        assert_eq!(config.layer_structures, vec![vec![4, 8, 2], vec![4, 2]]);
+        assert!(matches!(
+            config.activations[..],
+            [ActivationFunction::ReLU, ActivationFunction::Tanh]
+        ));
+        assert_eq!(config.num_epochs, 3);
+    }
+
+    #[test]
+    fn grid_search_config_defaults_missing_keys() {
+        let config = GridSearchConfig::parse("learning_rates = [0.1]").unwrap();
+
+        assert!(config.batch_sizes.is_empty());
+        assert_eq!(config.num_epochs, 10);
+    }
+
+    #[test]
+    fn grid_search_config_rejects_invalid_lines() {
+        let invalid = [
+            "learning_rates [0.1]",      // No =
+            "momentum = [0.9]",          // Unknown key
+            "batch_sizes = [0.5]",       // Not a usize
+            "activations = [\"swish\"]", // Unknown activation
+            "num_epochs = -1",           // Negative
+            "learning_rates = [0.1",     // Unclosed array
+        ];
+
+        for contents in invalid {
+            assert!(
+                GridSearchConfig::parse(contents).is_err(),
+                "{} should be rejected",
+                contents
+            );
+        }
+    }
+
+    #[test]
+    fn grid_search_trains_every_combination() {
+        // The class of each instance is the sign of its only feature
+        let records = (0..64)
+            .map(|i| {
+                let x = i as f64 / 32.0 - 1.0;
+                (vec![x], (x > 0.0) as usize)
+            })
+            .collect();
+        let dataset = Dataset::from_records(records, 2).unwrap();
+        let config = GridSearchConfig::parse(
+            "learning_rates = [0.0, 0.5]\n\
+             batch_sizes = [8, 16]\n\
+             layer_structures = [[1, 2]]\n\
+             activations = [\"re-lu\"]\n\
+             num_epochs = 2",
+        )
+        .unwrap();
+
+        let results = grid_search(config, &dataset, &dataset);
+
+        assert_eq!(results.len(), 4);
+        assert!(results
+            .windows(2)
+            .all(|pair| pair[0].val_accuracy >= pair[1].val_accuracy));
+    }
+
+    #[test]
+    fn random_search_is_reproducible_with_the_same_rng() {
+        let records = (0..64)
+            .map(|i| {
+                let x = i as f64 / 32.0 - 1.0;
+                (vec![x], (x > 0.0) as usize)
+            })
+            .collect();
+        let dataset = Dataset::from_records(records, 2).unwrap();
+        let search = || {
+            let config = RandomSearchConfig {
+                lr_log_range: (1e-3, 1e-1),
+                batch_size_range: (4, 16),
+                dropout_range: (0f64, 0.5),
+                layer_structure: vec![1, 4, 2],
+                activation: ActivationFunction::ReLU,
+                num_epochs: 2,
+                val_fraction: 0.25,
+            };
+            let mut rng = StdRng::seed_from_u64(42);
+
+            random_search(config, 6, &dataset, &mut rng)
+                .into_iter()
+                .map(|r| {
+                    (
+                        r.learning_rate,
+                        r.batch_size,
+                        r.dropout_rate,
+                        r.val_accuracy,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(), search());
+    }
+}
@@ -0,0 +1,51 @@
+use ndarray::Array2;
+
+use super::neural_net::{Gradients, NeuralNet};
+
+/// The intermediate values of a forward pass, which backprop needs
+pub(super) struct ForwardRecord {
+    pub hidden: Vec<Array2<f64>>,
+    pub hidden_linear: Vec<Array2<f64>>,
+    pub dropout_masks: Vec<Array2<f64>>,
+}
+
+/// Records the forward passes run inside NeuralNet::with_tape, so that the gradients can be computed
+/// later and applied manually (e.g. in custom training loops)
+#[derive(Default)]
+pub struct GradientTape {
+    pub enabled: bool,
+    record: Option<ForwardRecord>, // The last recorded forward pass
+}
+
+impl GradientTape {
+    pub fn new() -> GradientTape {
+        GradientTape {
+            enabled: true,
+            record: None,
+        }
+    }
+
+    pub(super) fn record(&mut self, record: ForwardRecord) {
+        if self.enabled {
+            self.record = Some(record);
+        }
+    }
+
+    /// Compute the gradients WRT the weights and biases of each layer of the last recorded forward pass
+    /// A scalar loss doesn't determine the gradients by itself, so this takes the gradient of the loss
+    /// WRT the outputs of the network (e.g. from LossFunction::gradient)
+    pub fn gradient(&self, output_grad: &Array2<f64>, params: &NeuralNet) -> Gradients {
+        let record = self
+            .record
+            .as_ref()
+            .expect("The tape hasn't recorded any forward pass");
+        let (grads, _) = params.backward(
+            &record.hidden,
+            &record.hidden_linear,
+            &record.dropout_masks,
+            output_grad.clone(),
+        );
+
+        grads
+    }
+}
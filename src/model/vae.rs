@@ -0,0 +1,156 @@
+use ndarray::{concatenate, s, Array2, ArrayView2, Axis};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::f64::consts::LN_2;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::history::TrainingHistory;
+use super::loss::LossFunction;
+use super::neural_net::{NeuralNet, Task, Verbosity};
+use super::noise::standard_normal;
+use super::Model;
+use crate::parsing::Dataset;
+
+/// A variational autoencoder (Kingma & Welling 2013)
+/// The encoder outputs the mean and the log-variance of the latent distribution of each instance (2 * latent_dim
+/// outputs), and the decoder maps latent vectors to the probabilities of the features, which should be in [0, 1]
+pub struct VAE {
+    pub encoder: NeuralNet,
+    pub decoder: NeuralNet,
+    pub latent_dim: usize,
+    rng: Mutex<StdRng>, // Samples eps in the reparametrization. Behind a mutex like the RNG of NeuralNet
+}
+
+impl VAE {
+    /// The reconstruction loss is the binary cross-entropy of the decoder, so its loss function and task are overridden
+    pub fn new(encoder: NeuralNet, mut decoder: NeuralNet, latent_dim: usize) -> VAE {
+        decoder.loss_function = LossFunction::BinaryCrossEntropy;
+        decoder.task = Task::Multilabel;
+
+        VAE {
+            encoder,
+            decoder,
+            latent_dim,
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+
+    pub fn with_seed(self, seed: u64) -> VAE {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+
+        self
+    }
+
+    /// Split the outputs of the encoder into the means and the log-variances
+    fn split_latent(&self, outputs: &Array2<f64>) -> (Array2<f64>, Array2<f64>) {
+        (
+            outputs.slice(s![.., ..self.latent_dim]).to_owned(),
+            outputs.slice(s![.., self.latent_dim..]).to_owned(),
+        )
+    }
+
+    /// Return the mean and the log-variance of the latent distribution of each instance
+    pub fn encode(&self, inputs: &ArrayView2<f64>) -> (Array2<f64>, Array2<f64>) {
+        self.split_latent(&self.encoder.logits(inputs))
+    }
+
+    /// Return the probabilities of the features of the instances with the given latent vectors
+    pub fn decode(&self, z: &ArrayView2<f64>) -> Array2<f64> {
+        self.decoder.predict(z)
+    }
+
+    /// Encode and decode the instances, using the mean of their latent distributions
+    pub fn reconstruct(&self, inputs: &ArrayView2<f64>) -> Array2<f64> {
+        let (mu, _) = self.encode(inputs);
+
+        self.decode(&mu.view())
+    }
+
+    /// The mean reconstruction loss of the instances in bits, using the mean of their latent distributions
+    pub fn reconstruction_loss(&self, inputs: &ArrayView2<f64>) -> f64 {
+        let (mu, _) = self.encode(inputs);
+        let logits = self.decoder.logits(&mu.view());
+
+        self.decoder.loss_function.loss(&logits, inputs, &mu.view())
+    }
+
+    /// Train the VAE to minimize the negative ELBO: reconstruction loss + beta * KL(q(z|x) || N(0, I))
+    /// The batch size is the batch size of the encoder, and each net is updated with its own LR and optimizer
+    /// The history holds the mean loss of the batches in each epoch (in bits)
+    pub fn train_vae(&mut self, dataset: &Dataset, n_epochs: usize, beta: f64) -> TrainingHistory {
+        let mut history = TrainingHistory::new();
+        let start = Instant::now();
+
+        for epoch in 0..n_epochs {
+            let mut total_loss = 0f64;
+            let mut num_batches = 0;
+
+            for batch in dataset
+                .data
+                .axis_chunks_iter(Axis(0), self.encoder.batch_size)
+            {
+                total_loss += self.train_batch(&batch, beta);
+                num_batches += 1;
+            }
+
+            history.train_losses.push(total_loss / num_batches as f64);
+
+            if self.encoder.verbosity >= Verbosity::Epoch {
+                eprintln!(
+                    "[Epoch {}/{}] loss={:.4} elapsed={:.1}s",
+                    epoch + 1,
+                    n_epochs,
+                    history.train_losses.last().unwrap(),
+                    start.elapsed().as_secs_f64()
+                );
+            }
+        }
+
+        history
+    }
+
+    /// Perform a GD step of the encoder and the decoder on a batch. Returns the loss of the batch before the step
+    fn train_batch(&mut self, batch: &ArrayView2<f64>, beta: f64) -> f64 {
+        let (e_hidden, e_hidden_linear, e_dropout_masks) = self.encoder.forward(batch, true);
+        let (mu, log_var) = self.split_latent(e_hidden.last().unwrap());
+        let std = log_var.mapv(|x| (x / 2f64).exp());
+        let eps = {
+            let mut rng = self.rng.lock().unwrap();
+
+            Array2::from_shape_simple_fn(mu.dim(), || standard_normal(&mut *rng))
+        };
+        // The reparametrization trick: z is a differentiable function of mu and log_var
+        let z = &mu + &eps * &std;
+
+        let (d_hidden, d_hidden_linear, d_dropout_masks) = self.decoder.forward(&z.view(), true);
+        let d_logits = d_hidden.last().unwrap();
+        let reconstruction_loss = self.decoder.loss_function.loss(d_logits, batch, &z.view());
+        let d_grad = self
+            .decoder
+            .loss_function
+            .gradient(d_logits, batch, &z.view());
+        let (d_grads, z_grad) =
+            self.decoder
+                .backward(&d_hidden, &d_hidden_linear, &d_dropout_masks, d_grad);
+
+        // KL = -0.5 * sum(1 + log_var - mu^2 - exp(log_var)), averaged over the batch and measured in bits like the
+        // reconstruction loss. Like the loss gradients, its gradients are per instance
+        let kl = -0.5 * (1f64 + &log_var - &mu * &mu - log_var.mapv(f64::exp)).sum()
+            / (batch.nrows() as f64 * LN_2);
+        let mu_grad = &z_grad + &(beta * &mu);
+        let log_var_grad =
+            &z_grad * &eps * &std / 2f64 + beta * 0.5 * (log_var.mapv(f64::exp) - 1f64);
+        let (e_grads, _) = self.encoder.backward(
+            &e_hidden,
+            &e_hidden_linear,
+            &e_dropout_masks,
+            concatenate![Axis(1), mu_grad, log_var_grad],
+        );
+
+        self.decoder.apply_gradients(&d_grads);
+        self.encoder.apply_gradients(&e_grads);
+
+        reconstruction_loss + beta * kl
+    }
+}
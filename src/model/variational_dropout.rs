@@ -0,0 +1,97 @@
+use ndarray::{Array2, ArrayView2, Zip};
+use rand::Rng;
+
+use super::neural_net::sigmoid;
+use super::noise::standard_normal;
+
+// Constants of the approximation of the KL divergence from Molchanov et al. (2017)
+const K1: f64 = 0.63576;
+const K2: f64 = 1.87320;
+const K3: f64 = 1.48695;
+/// log(alpha) is clipped to this range to keep the noise and the KL approximation stable
+const LOG_ALPHA_RANGE: (f64, f64) = (-8f64, 8f64);
+/// The initial log(alpha) of every weight, so that training starts with almost no noise
+const INITIAL_LOG_ALPHA: f64 = -8f64;
+
+/// Sparse variational dropout (Molchanov et al. 2017) of a weight matrix. During training, each weight w
+/// is multiplied by 1 + sqrt(alpha) * epsilon where epsilon ~ N(0, 1), and alpha is learned per weight
+/// Weights with a large alpha are pure noise, so they can be pruned after training
+#[derive(Clone, Debug)]
+pub struct VariationalDropoutLayer {
+    pub log_alpha: Array2<f64>, // log(alpha) of each weight. Has the same shape as the weight matrix
+}
+
+impl VariationalDropoutLayer {
+    /// Construct the layer for a weight matrix of the given shape
+    pub fn new(dim: (usize, usize)) -> VariationalDropoutLayer {
+        VariationalDropoutLayer {
+            log_alpha: Array2::from_elem(dim, INITIAL_LOG_ALPHA),
+        }
+    }
+
+    /// Sample the standard normal noise epsilon of each weight
+    pub fn sample_epsilon(&self, rng: &mut impl Rng) -> Array2<f64> {
+        Array2::from_shape_simple_fn(self.log_alpha.dim(), || standard_normal(rng))
+    }
+
+    /// The multiplicative noise 1 + sqrt(alpha) * epsilon of each weight
+    pub fn noise(&self, epsilon: &Array2<f64>) -> Array2<f64> {
+        Zip::from(&self.log_alpha)
+            .and(epsilon)
+            .map_collect(|log_alpha, eps| 1f64 + (0.5 * log_alpha).exp() * eps)
+    }
+
+    /// The approximate KL(q(w) || p(w)) summed over the weights, where p is the log-uniform prior:
+    /// KL ~= k1 - k1 * sigmoid(k2 + k3 * log(alpha)) + 0.5 * log(1 + 1 / alpha)
+    pub fn kl(&self) -> f64 {
+        self.log_alpha
+            .iter()
+            .map(|log_alpha| {
+                K1 - K1 * sigmoid(K2 + K3 * log_alpha) + 0.5 * (-log_alpha).exp().ln_1p()
+            })
+            .sum()
+    }
+
+    /// The gradient of the KL divergence WRT log(alpha). It's always negative, so the KL pushes alpha up,
+    /// and only weights that reduce the loss keep a small alpha
+    pub fn kl_gradient(&self) -> Array2<f64> {
+        self.log_alpha.mapv(|log_alpha| {
+            let s = sigmoid(K2 + K3 * log_alpha);
+
+            -K1 * K3 * s * (1f64 - s) - 0.5 * sigmoid(-log_alpha)
+        })
+    }
+
+    /// Compute the gradients WRT the weights and log(alpha) from the gradient WRT the noisy weights
+    /// weights are the noise-free weights, and epsilon is the noise sampled for the forward pass
+    pub fn gradients(
+        &self,
+        weights: &ArrayView2<f64>,
+        noisy_weight_grad: &Array2<f64>,
+        epsilon: &Array2<f64>,
+    ) -> (Array2<f64>, Array2<f64>) {
+        let weight_grad = noisy_weight_grad * &self.noise(epsilon);
+        // d(w * sqrt(alpha) * eps) / d(log(alpha)) = 0.5 * w * sqrt(alpha) * eps
+        let log_alpha_grad = Zip::from(noisy_weight_grad)
+            .and(weights)
+            .and(epsilon)
+            .and(&self.log_alpha)
+            .map_collect(|grad, w, eps, log_alpha| grad * 0.5 * w * (0.5 * log_alpha).exp() * eps);
+
+        (weight_grad, log_alpha_grad)
+    }
+
+    /// Perform a GD step on log(alpha), clipping it to the supported range
+    pub fn update(&mut self, log_alpha_grad: &Array2<f64>, learning_rate: f64) {
+        self.log_alpha.scaled_add(-learning_rate, log_alpha_grad);
+        self.log_alpha
+            .mapv_inplace(|log_alpha| log_alpha.clamp(LOG_ALPHA_RANGE.0, LOG_ALPHA_RANGE.1));
+    }
+}
+
+/// Variational dropout of all of the layers of a network
+#[derive(Clone, Debug)]
+pub struct VariationalDropout {
+    pub layers: Vec<VariationalDropoutLayer>,
+    pub kl_weight: f64, // The weight of the KL term in the objective, usually 1 / (the size of the training set)
+}
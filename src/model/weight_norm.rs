@@ -0,0 +1,44 @@
+use ndarray::{Array1, Array2, ArrayView2, Axis};
+
+/// A weight matrix reparametrized as W = g * v / ||v|| (Salimans & Kingma 2016), where the norms are of the
+/// columns of v. This decouples the magnitude of the weights of each output unit (g) from their direction (v)
+#[derive(Clone, Debug)]
+pub struct WeightNormLayer {
+    pub g: Array1<f64>, // The magnitude of the weights of each output unit
+    pub v: Array2<f64>, // The direction of the weights of each output unit (a column per unit)
+}
+
+impl WeightNormLayer {
+    /// The L2 norm of each column of v
+    fn column_norms(&self) -> Array1<f64> {
+        self.v
+            .map_axis(Axis(0), |col| col.dot(&col).sqrt())
+            .mapv(|norm| norm.max(f64::MIN_POSITIVE))
+    }
+
+    /// The effective weight matrix W = g * v / ||v||
+    pub fn weights(&self) -> Array2<f64> {
+        &self.v * &(&self.g / &self.column_norms())
+    }
+
+    /// Compute the gradients WRT g and v from the gradient WRT the effective weights:
+    /// dL/dg = dL/dW . v / ||v|| and dL/dv = g * (dL/dW / ||v|| - v * (dL/dW . v) / ||v||^3)
+    pub fn gradients(&self, weight_grad: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+        let norms = self.column_norms();
+        // dL/dW . v for each column
+        let dots = (weight_grad * &self.v).sum_axis(Axis(0));
+        let g_grad = &dots / &norms;
+        let v_grad =
+            (weight_grad / &norms - &self.v * &(&dots / &norms.mapv(|n| n.powi(3)))) * &self.g;
+
+        (g_grad, v_grad)
+    }
+}
+
+/// Decompose a weight matrix into its weight normalization parameters, so that the effective weights don't change
+pub fn init_weight_norm_from_dense(w: &ArrayView2<f64>) -> WeightNormLayer {
+    WeightNormLayer {
+        g: w.map_axis(Axis(0), |col| col.dot(&col).sqrt()),
+        v: w.to_owned(),
+    }
+}
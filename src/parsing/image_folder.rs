@@ -0,0 +1,390 @@
+use super::Dataset;
+use crate::error::{NeuralNetError, Result};
+use ndarray::Array2;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Parse a dataset of images stored in a directory with a subdirectory for each class (e.g. root/cat/*.png)
+/// The classes are sorted lexicographically and one-hot encoded. Each image is converted to grayscale,
+/// resized to target_size x target_size, and flattened to target_size^2 features in [0, 1]
+/// PNG, JPEG and binary PGM/PPM images are supported. Images in other formats (GIF, BMP, TIFF and WebP) return an
+/// error, and files that aren't images are skipped
+pub fn parse_image_folder(root_dir: &str, target_size: usize) -> Result<Dataset> {
+    let mut class_dirs: Vec<PathBuf> = fs::read_dir(root_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    class_dirs.retain(|path| path.is_dir());
+    class_dirs.sort();
+
+    let mut rows = vec![];
+    let mut classes = vec![];
+
+    for (class, class_dir) in class_dirs.iter().enumerate() {
+        let mut files: Vec<PathBuf> = fs::read_dir(class_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        files.retain(|path| path.is_file());
+        files.sort();
+
+        for file in files {
+            if let Some((width, height, pixels)) = load_image(&file)? {
+                rows.extend(resize(&pixels, width, height, target_size));
+                classes.push(class);
+            }
+        }
+    }
+
+    let n_features = target_size * target_size;
+    let data = Array2::from_shape_vec((classes.len(), n_features), rows).unwrap();
+    let mut target = Array2::zeros((classes.len(), class_dirs.len()));
+
+    for (i, class) in classes.into_iter().enumerate() {
+        target[[i, class]] = 1f64;
+    }
+
+    Ok(Dataset { data, target })
+}
+
+/// Load an image as grayscale according to its extension. Returns None for files that aren't images, and an error for
+/// images in unsupported formats
+fn load_image(path: &Path) -> Result<Option<(usize, usize, Vec<f64>)>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    let path_str = path.to_string_lossy();
+
+    match extension.as_deref() {
+        Some("png" | "jpg" | "jpeg") => load_grayscale(path).map(Some),
+        Some("pgm") | Some("ppm") => load_netpbm_grayscale(&path_str).map(Some),
+        Some(format @ ("gif" | "bmp" | "tif" | "tiff" | "webp")) => {
+            Err(NeuralNetError::Parse(format!(
+                "{}: {} images aren't supported, convert them to PNG",
+                path_str,
+                format.to_uppercase()
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Decode a PNG or JPEG image with the image crate and convert it to grayscale
+/// Returns the width, the height and the intensity of each pixel in [0, 1] in row-major order
+/// The alpha channel is ignored
+fn load_grayscale(path: &Path) -> Result<(usize, usize, Vec<f64>)> {
+    let image = image::open(path)
+        .map_err(|err| NeuralNetError::Parse(format!("{}: {}", path.display(), err)))?;
+    // 16 bits per channel keep the precision of 16-bit PNGs, and convert 8-bit channels exactly
+    let image = image.to_rgb16();
+    let pixels = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b] = pixel.0.map(|channel| channel as f64 / 65535f64);
+
+            luminance(r, g, b)
+        })
+        .collect();
+
+    Ok((image.width() as usize, image.height() as usize, pixels))
+}
+
+/// The perceived brightness of a color (ITU-R BT.601)
+fn luminance(r: f64, g: f64, b: f64) -> f64 {
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// Read a binary PGM (P5) or PPM (P6) image and convert it to grayscale
+/// Returns the width, the height and the intensity of each pixel in [0, 1] in row-major order
+fn load_netpbm_grayscale(path: &str) -> Result<(usize, usize, Vec<f64>)> {
+    let bytes = fs::read(path)?;
+    let malformed = |msg: &str| NeuralNetError::Parse(format!("{}: {}", path, msg));
+
+    // The header is the magic number, the width, the height and the max value, separated by whitespace
+    // Comments start with # and last until the end of the line
+    let mut fields = vec![];
+    let mut pos = 0;
+
+    while fields.len() < 4 && pos < bytes.len() {
+        if bytes[pos] == b'#' {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+        } else if bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        } else {
+            let start = pos;
+
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+
+            fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+        }
+    }
+
+    // A single whitespace character separates the header from the pixels
+    pos += 1;
+
+    if fields.len() < 4 {
+        return Err(malformed("Truncated header"));
+    }
+
+    let channels = match fields[0].as_str() {
+        "P5" => 1,
+        "P6" => 3,
+        _ => return Err(malformed("Only binary PGM and PPM images are supported")),
+    };
+    let parse = |field: &str| {
+        field
+            .parse::<usize>()
+            .map_err(|_| malformed("Invalid header"))
+    };
+    let (width, height, max_value) = (parse(&fields[1])?, parse(&fields[2])?, parse(&fields[3])?);
+
+    if max_value == 0 || max_value > 65535 {
+        return Err(malformed("Invalid max value"));
+    }
+
+    // Samples are 2 bytes (big-endian) if the max value doesn't fit in a byte
+    let bytes_per_sample = if max_value > 255 { 2 } else { 1 };
+    let n_bytes = width * height * channels * bytes_per_sample;
+    let data = bytes
+        .get(pos..pos + n_bytes)
+        .ok_or_else(|| malformed("Truncated image data"))?;
+    let samples: Vec<f64> = data
+        .chunks_exact(bytes_per_sample)
+        .map(|sample| match sample {
+            [value] => *value as f64 / max_value as f64,
+            _ => u16::from_be_bytes([sample[0], sample[1]]) as f64 / max_value as f64,
+        })
+        .collect();
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|pixel| match pixel {
+            [gray] => *gray,
+            _ => luminance(pixel[0], pixel[1], pixel[2]),
+        })
+        .collect();
+
+    Ok((width, height, pixels))
+}
+
+/// Resize a grayscale image to size x size. Each pixel is the mean of the source pixels it covers
+/// (the nearest source pixel when upscaling)
+fn resize(pixels: &[f64], width: usize, height: usize, size: usize) -> Vec<f64> {
+    let mut resized = Vec::with_capacity(size * size);
+
+    for y in 0..size {
+        let y0 = y * height / size;
+        let y1 = ((y + 1) * height / size).max(y0 + 1);
+
+        for x in 0..size {
+            let x0 = x * width / size;
+            let x1 = ((x + 1) * width / size).max(x0 + 1);
+            let sum: f64 = (y0..y1)
+                .flat_map(|sy| (x0..x1).map(move |sx| pixels[sy * width + sx]))
+                .sum();
+
+            resized.push(sum / ((y1 - y0) * (x1 - x0)) as f64);
+        }
+    }
+
+    resized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory in the temp dir that's unique to this test process, with a file for each (path, contents)
+    fn temp_dir_with(name: &str, files: &[(&str, Vec<u8>)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+
+        for (path, contents) in files {
+            let path = root.join(path);
+
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        root
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_a_folder_per_class() {
+        // A 2x2 PGM with a comment in its header, and a 1x1 16-bit PPM
+        let pgm = [b"P5\n# A comment\n2 2\n255\n".as_slice(), &[0, 255, 255, 0]].concat();
+        let ppm = [b"P6 1 1 65535\n".as_slice(), &[0xff, 0xff, 0, 0, 0, 0]].concat();
+        let root = temp_dir_with(
+            "parses_a_folder_per_class",
+            &[
+                ("b/rgb.png", fixture("rgb8.png")),
+                ("a/gray.pgm", pgm),
+                ("a/red.ppm", ppm),
+                ("a/notes.txt", b"not an image".to_vec()),
+            ],
+        );
+
+        let dataset = parse_image_folder(root.to_str().unwrap(), 1);
+        fs::remove_dir_all(&root).unwrap();
+        let dataset = dataset.unwrap();
+
+        // The classes are sorted, and the text file is skipped
+        assert_eq!(
+            dataset.target,
+            ndarray::array![[1f64, 0f64], [1f64, 0f64], [0f64, 1f64]]
+        );
+        // Resizing to 1x1 averages the pixels
+        let expected = [0.5, 0.299, (0.299 + 0.587 + 0.114 + 1f64) / 4f64];
+        for (x, expected) in dataset.data.iter().zip(expected) {
+            assert!((x - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn unsupported_formats_are_errors() {
+        for name in ["anim.gif", "bitmap.bmp", "scan.tiff", "web.webp"] {
+            let root = temp_dir_with(
+                "unsupported_formats_are_errors",
+                &[(&format!("a/{}", name), vec![0xff, 0xd8, 0xff])],
+            );
+
+            let result = parse_image_folder(root.to_str().unwrap(), 4);
+            fs::remove_dir_all(&root).unwrap();
+
+            assert!(result.is_err(), "{} should be rejected", name);
+        }
+    }
+
+    fn fixture_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-12, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn decodes_png_images_to_grayscale() {
+        // A 4x5 grayscale image whose rows use the None, Sub, Up, Average and Paeth filters
+        let (width, height, pixels) = load_grayscale(&fixture_path("gray8_filters.png")).unwrap();
+        let expected: Vec<f64> = (0..5)
+            .flat_map(|y| (0..4).map(move |x| ((x * 40 + y * 30) % 256) as f64 / 255f64))
+            .collect();
+
+        assert_eq!((width, height), (4, 5));
+        assert_close(&pixels, &expected);
+
+        // Red, green, blue and white pixels
+        let (_, _, pixels) = load_grayscale(&fixture_path("rgb8.png")).unwrap();
+        assert_close(&pixels, &[0.299, 0.587, 0.114, 1f64]);
+
+        // A palette of black, white and red
+        let (_, _, pixels) = load_grayscale(&fixture_path("palette.png")).unwrap();
+        assert_close(&pixels, &[0f64, 1f64, 0.299]);
+
+        // Black with full alpha, and white with no alpha
+        let (_, _, pixels) = load_grayscale(&fixture_path("gray16_alpha.png")).unwrap();
+        assert_close(&pixels, &[0f64, 1f64]);
+
+        // Interlaced and 1-bit images
+        let (_, _, pixels) = load_grayscale(&fixture_path("interlaced.png")).unwrap();
+        assert_close(&pixels, &[7f64 / 255f64]);
+        let (_, _, pixels) = load_grayscale(&fixture_path("gray1.png")).unwrap();
+        assert_close(&pixels, &[1f64, 0f64, 1f64, 0f64, 1f64, 0f64, 1f64, 0f64]);
+    }
+
+    #[test]
+    fn decodes_jpeg_images_to_grayscale() {
+        let root = temp_dir_with("decodes_jpeg_images_to_grayscale", &[]);
+        let path = root.join("gray.jpg");
+        fs::create_dir_all(&root).unwrap();
+        image::GrayImage::from_pixel(8, 8, image::Luma([100]))
+            .save(&path)
+            .unwrap();
+
+        let result = load_grayscale(&path);
+        fs::remove_dir_all(&root).unwrap();
+        let (width, height, pixels) = result.unwrap();
+
+        // JPEG is lossy, but a flat image survives it
+        assert_eq!((width, height), (8, 8));
+        assert!(pixels
+            .iter()
+            .all(|pixel| (pixel - 100f64 / 255f64).abs() < 0.01));
+    }
+
+    #[test]
+    fn malformed_png_images_are_errors() {
+        let bytes = fixture("gray8_filters.png");
+        let mut bad_zlib = bytes.clone();
+        // Zeroing the first byte of the zlib header makes the image data invalid
+        let idat = bytes.windows(4).position(|w| w == b"IDAT").unwrap();
+        bad_zlib[idat + 4] = 0;
+        let root = temp_dir_with(
+            "malformed_png_images_are_errors",
+            &[
+                ("signature.png", bytes[..4].to_vec()), // Cut off in the signature
+                ("chunk.png", bytes[..bytes.len() / 2].to_vec()), // Cut off in a chunk
+                ("header.png", bytes[..33].to_vec()),   // Only the signature and the IHDR chunk
+                ("zlib.png", bad_zlib),
+            ],
+        );
+
+        for name in ["signature.png", "chunk.png", "header.png", "zlib.png"] {
+            let result = load_grayscale(&root.join(name));
+
+            assert!(result.is_err(), "{} should be rejected", name);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn malformed_netpbm_images_are_errors() {
+        let root = temp_dir_with(
+            "malformed_netpbm_images_are_errors",
+            &[
+                ("ascii.pgm", b"P2 1 1 255\n0\n".to_vec()),
+                ("truncated_header.pgm", b"P5 1".to_vec()),
+                ("truncated_data.pgm", b"P5 2 2 255\n\x00\x01".to_vec()),
+                ("zero_max.pgm", b"P5 1 1 0\n\x00".to_vec()),
+            ],
+        );
+
+        for name in [
+            "ascii.pgm",
+            "truncated_header.pgm",
+            "truncated_data.pgm",
+            "zero_max.pgm",
+        ] {
+            let result = load_netpbm_grayscale(root.join(name).to_str().unwrap());
+
+            assert!(result.is_err(), "{} should be rejected", name);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn resize_averages_when_downscaling_and_repeats_when_upscaling() {
+        let pixels = [0f64, 1f64, 2f64, 3f64];
+
+        assert_eq!(resize(&pixels, 2, 2, 1), vec![1.5]);
+        assert_eq!(resize(&pixels, 2, 2, 4)[..4], [0f64, 0f64, 1f64, 1f64]);
+    }
+}
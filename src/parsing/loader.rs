@@ -0,0 +1,202 @@
+use super::Dataset;
+use ndarray::{s, Array2};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// A source of training batches (input, target). An epoch ends when next_batch returns None
+pub trait DataSource {
+    fn next_batch(&mut self) -> Option<(Array2<f64>, Array2<f64>)>;
+
+    /// Start a new epoch
+    fn reset(&mut self);
+}
+
+/// Loads the instances start..end of a dataset as a batch (input, target)
+pub type LoadBatchFn = Arc<dyn Fn(usize, usize) -> (Array2<f64>, Array2<f64>) + Send + Sync>;
+
+/// Loads the batches of a dataset in background threads, so that the next batches are ready while the current
+/// one is being trained on. Worker i loads batches i, i + n_workers, ..., and each worker keeps up to
+/// prefetch_batches / n_workers batches ready. The batches are returned in order
+/// The workers are plain threads rather than a rayon pool: a worker blocks on its channel while its batches wait to
+/// be trained on, which would hold threads of the pool that par_iter shares
+pub struct DataLoader {
+    pub n_samples: usize,
+    pub batch_size: usize,
+    pub n_workers: usize,
+    pub prefetch_batches: usize,
+    load_batch: LoadBatchFn,
+    receivers: Vec<Receiver<(Array2<f64>, Array2<f64>)>>, // The batches loaded by each worker
+    workers: Vec<JoinHandle<()>>,
+    next_batch_idx: usize,
+}
+
+impl DataLoader {
+    /// Create a loader of the batches of an in-memory dataset and start loading the first epoch
+    pub fn new(
+        dataset: Arc<Dataset>,
+        batch_size: usize,
+        n_workers: usize,
+        prefetch_batches: usize,
+    ) -> DataLoader {
+        let n_samples = dataset.data.nrows();
+        let load_batch: LoadBatchFn = Arc::new(move |start, end| {
+            (
+                dataset.data.slice(s![start..end, ..]).to_owned(),
+                dataset.target.slice(s![start..end, ..]).to_owned(),
+            )
+        });
+
+        DataLoader::from_fn(
+            n_samples,
+            batch_size,
+            n_workers,
+            prefetch_batches,
+            load_batch,
+        )
+    }
+
+    /// Create a loader of n_samples instances whose batches are loaded with load_batch (e.g. read from disk), and
+    /// start loading the first epoch. The workers call load_batch concurrently
+    pub fn from_fn(
+        n_samples: usize,
+        batch_size: usize,
+        n_workers: usize,
+        prefetch_batches: usize,
+        load_batch: LoadBatchFn,
+    ) -> DataLoader {
+        let mut loader = DataLoader {
+            n_samples,
+            batch_size: batch_size.max(1),
+            n_workers: n_workers.max(1),
+            prefetch_batches,
+            load_batch,
+            receivers: vec![],
+            workers: vec![],
+            next_batch_idx: 0,
+        };
+
+        loader.reset();
+
+        loader
+    }
+
+    pub fn num_batches(&self) -> usize {
+        self.n_samples.div_ceil(self.batch_size)
+    }
+
+    /// Stop the workers of the current epoch. A worker stops once its channel is closed
+    fn stop_workers(&mut self) {
+        self.receivers.clear();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl DataSource for DataLoader {
+    fn next_batch(&mut self) -> Option<(Array2<f64>, Array2<f64>)> {
+        if self.next_batch_idx == self.num_batches() {
+            return None;
+        }
+
+        let batch = self.receivers[self.next_batch_idx % self.n_workers]
+            .recv()
+            .expect("A data loader worker stopped early");
+        self.next_batch_idx += 1;
+
+        Some(batch)
+    }
+
+    fn reset(&mut self) {
+        self.stop_workers();
+        self.next_batch_idx = 0;
+
+        let num_batches = self.num_batches();
+        let capacity = (self.prefetch_batches / self.n_workers).max(1);
+
+        for worker_idx in 0..self.n_workers {
+            let (sender, receiver) = sync_channel(capacity);
+            let load_batch = Arc::clone(&self.load_batch);
+            let (n_samples, batch_size, n_workers) =
+                (self.n_samples, self.batch_size, self.n_workers);
+
+            self.workers.push(thread::spawn(move || {
+                for batch_idx in (worker_idx..num_batches).step_by(n_workers) {
+                    let start = batch_idx * batch_size;
+                    let batch = load_batch(start, (start + batch_size).min(n_samples));
+
+                    // The loader was reset or dropped
+                    if sender.send(batch).is_err() {
+                        return;
+                    }
+                }
+            }));
+            self.receivers.push(receiver);
+        }
+    }
+}
+
+impl Drop for DataLoader {
+    fn drop(&mut self) {
+        self.stop_workers();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array;
+    use std::time::Duration;
+
+    #[test]
+    fn batches_are_returned_in_order_every_epoch() {
+        let data = Array::from_shape_fn((10, 2), |(row, col)| (row * 2 + col) as f64);
+        let target = Array::from_shape_fn((10, 1), |(row, _)| row as f64);
+        let dataset = Arc::new(Dataset {
+            data: data.clone(),
+            target: target.clone(),
+        });
+        let mut loader = DataLoader::new(dataset, 3, 3, 2);
+
+        assert_eq!(loader.num_batches(), 4);
+
+        for _ in 0..2 {
+            loader.reset();
+
+            let mut start = 0;
+
+            while let Some((input_batch, target_batch)) = loader.next_batch() {
+                let end = (start + 3).min(10);
+
+                assert_eq!(input_batch, data.slice(s![start..end, ..]));
+                assert_eq!(target_batch, target.slice(s![start..end, ..]));
+                start = end;
+            }
+
+            assert_eq!(start, 10);
+        }
+    }
+
+    #[test]
+    fn slow_batches_are_returned_in_order() {
+        // The later batches are loaded faster, so the workers finish them out of order
+        let load_batch: LoadBatchFn = Arc::new(|start, end| {
+            thread::sleep(Duration::from_millis(10 - start as u64));
+
+            (
+                Array2::from_elem((end - start, 1), start as f64),
+                Array2::zeros((end - start, 1)),
+            )
+        });
+        let mut loader = DataLoader::from_fn(9, 2, 4, 4, load_batch);
+        let mut starts = vec![];
+
+        while let Some((input_batch, _)) = loader.next_batch() {
+            starts.push(input_batch[[0, 0]]);
+        }
+
+        assert_eq!(starts, vec![0f64, 2f64, 4f64, 6f64, 8f64]);
+    }
+}
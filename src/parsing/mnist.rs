@@ -43,19 +43,13 @@ fn parse_dataset_line(line: &str) -> Option<(Vec<f64>, f64)> {
     }
 }
 
-// Return matrix that represents the dataset
-pub fn parse_dataset(path: &str) -> Dataset {
-    let file = File::open(path);
+/// Build a dataset from parsed (pixels, label) records
+fn records_to_dataset(records: impl Iterator<Item = (Vec<f64>, f64)>) -> Dataset {
     let mut data = Array::zeros((0, NUM_FEATURES));
     let mut target = Array::zeros((0, NUM_CLASSES));
-    let mut contents = String::new();
 
-    file.unwrap().read_to_string(&mut contents).unwrap();
-
-    for line in contents.lines().skip(1).take_while(|x| !x.is_empty()) {
-        let line = parse_dataset_line(line).unwrap();
-        let pixels = line.0;
-        let label = line.1 as usize;
+    for (pixels, label) in records {
+        let label = label as usize;
         // Construct one-hot encoding for the label
         let one_hot_target: Vec<f64> = (0..NUM_CLASSES)
             .map(|idx| if idx == label { 1f64 } else { 0f64 })
@@ -67,3 +61,29 @@ pub fn parse_dataset(path: &str) -> Dataset {
 
     Dataset { data, target }
 }
+
+// Return matrix that represents the dataset
+pub fn parse_dataset(path: &str) -> Dataset {
+    let file = File::open(path);
+    let mut contents = String::new();
+
+    file.unwrap().read_to_string(&mut contents).unwrap();
+
+    records_to_dataset(
+        contents
+            .lines()
+            .skip(1)
+            .take_while(|x| !x.is_empty())
+            .map(|line| parse_dataset_line(line).unwrap()),
+    )
+}
+
+/// Parse a batch of dataset lines (e.g. read from stdin)
+/// Lines that aren't valid records, such as the CSV header, are skipped
+pub fn parse_lines<S: AsRef<str>>(lines: &[S]) -> Dataset {
+    records_to_dataset(
+        lines
+            .iter()
+            .filter_map(|line| parse_dataset_line(line.as_ref())),
+    )
+}
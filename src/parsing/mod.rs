@@ -1,8 +1,426 @@
-use ndarray::Array2;
+use crate::error::{NeuralNetError, Result};
+use ndarray::{concatenate, s, Array1, Array2, ArrayView1, Axis};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use sampler::{
+    BalancedBatchIter, BalancedBatchSampler, BatchIter, StratifiedBatchIter, WeightedSampler,
+};
+use std::fs::File;
+use std::io::Read;
 
+pub mod image_folder;
+pub mod loader;
 pub mod mnist;
+pub mod npy;
+pub mod onnx;
+pub mod parquet;
+pub mod sampler;
+pub mod streaming;
+pub mod uci;
 
+#[derive(Clone, Default)]
 pub struct Dataset {
     pub data: Array2<f64>,
     pub target: Array2<f64>,
 }
+
+/// The format of a CSV dataset whose lines hold the class followed by the features: <class>,<x0>,<x1>,...
+#[derive(Clone, Debug)]
+pub struct CsvConfig {
+    pub separator: char,
+    /// Whether the first line is a header
+    pub has_header: bool,
+    pub num_classes: usize,
+    /// The features are divided by this (e.g. 255 for pixels)
+    pub scale: f64,
+}
+
+impl Default for CsvConfig {
+    /// The format of the MNIST CSV datasets
+    fn default() -> CsvConfig {
+        CsvConfig {
+            separator: ',',
+            has_header: true,
+            num_classes: 10,
+            scale: 255f64,
+        }
+    }
+}
+
+impl CsvConfig {
+    /// Parse a line of the dataset into its (scaled) features and its class
+    pub fn parse_record(&self, line: &str) -> std::result::Result<(Vec<f64>, usize), String> {
+        let mut values = line.split(self.separator).map(str::trim);
+        let class = values
+            .next()
+            .and_then(|class| class.parse::<usize>().ok())
+            .filter(|&class| class < self.num_classes)
+            .ok_or_else(|| format!("Invalid class, expected one of 0..{}", self.num_classes))?;
+        let features = values
+            .map(|x| x.parse::<f64>().map(|x| x / self.scale))
+            .collect::<std::result::Result<Vec<f64>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok((features, class))
+    }
+}
+
+/// Pairs of instances labeled 1 if they are similar and 0 otherwise (used for metric learning)
+#[derive(Clone, Default)]
+pub struct PairedDataset {
+    pub x1: Array2<f64>,
+    pub x2: Array2<f64>,
+    pub labels: Array1<f64>,
+}
+
+/// Triplets of instances where the anchor and the positive have the same class
+/// and the negative has a different class (used for metric learning)
+#[derive(Clone, Default)]
+pub struct TripletDataset {
+    pub anchors: Array2<f64>,
+    pub positives: Array2<f64>,
+    pub negatives: Array2<f64>,
+}
+
+impl Dataset {
+    /// Parse a multi-label dataset from a CSV file with a header line
+    /// Each line holds n_labels binary labels, followed by the features: <label0>,...,<feature0>,...
+    pub fn from_multilabel_csv(path: &str, n_labels: usize) -> Result<Dataset> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+
+        let mut rows = vec![];
+
+        for (line_idx, line) in contents.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let values = line
+                .split(',')
+                .map(|x| x.trim().parse::<f64>())
+                .collect::<std::result::Result<Vec<f64>, _>>()
+                .map_err(|e| NeuralNetError::Parse(format!("Line {}: {}", line_idx + 1, e)))?;
+
+            if values.len() <= n_labels {
+                return Err(NeuralNetError::Parse(format!(
+                    "Line {} has {} values, but there are {} labels",
+                    line_idx + 1,
+                    values.len(),
+                    n_labels
+                )));
+            }
+
+            rows.push(values);
+        }
+
+        let num_features = rows.first().map_or(0, |row| row.len() - n_labels);
+        let mut data = Array2::zeros((0, num_features));
+        let mut target = Array2::zeros((0, n_labels));
+
+        for row in rows {
+            let instance = ArrayView1::from(&row[n_labels..]);
+
+            data.push_row(instance)
+                .map_err(|_| NeuralNetError::ShapeMismatch {
+                    expected: vec![num_features],
+                    actual: vec![instance.len()],
+                })?;
+            target.push_row(ArrayView1::from(&row[..n_labels])).unwrap();
+        }
+
+        Ok(Dataset { data, target })
+    }
+
+    /// Construct a dataset from (features, class) records, one-hot encoding the classes
+    pub fn from_records(records: Vec<(Vec<f64>, usize)>, num_classes: usize) -> Result<Dataset> {
+        let num_features = records.first().map_or(0, |(features, _)| features.len());
+        let mut data = Array2::zeros((0, num_features));
+        let mut target = Array2::zeros((records.len(), num_classes));
+
+        for (idx, (features, class)) in records.into_iter().enumerate() {
+            data.push_row(ArrayView1::from(&features)).map_err(|_| {
+                NeuralNetError::ShapeMismatch {
+                    expected: vec![num_features],
+                    actual: vec![features.len()],
+                }
+            })?;
+            target[[idx, class]] = 1f64;
+        }
+
+        Ok(Dataset { data, target })
+    }
+
+    /// Weight each instance by 1 / (the number of instances of its class), so that all the classes are sampled equally
+    pub fn compute_sample_weights(&self) -> Vec<f64> {
+        let labels = self.labels();
+        let mut counts = vec![0usize; self.target.ncols()];
+
+        for &label in labels.iter() {
+            counts[label] += 1;
+        }
+
+        labels
+            .into_iter()
+            .map(|label| (counts[label] as f64).recip())
+            .collect()
+    }
+
+    /// Weight each class by n_samples / (n_classes * the number of instances of the class), so that all the classes
+    /// contribute the same total weight to the loss. Classes that don't appear get a weight of 0
+    pub fn auto_class_weights(&self) -> Array1<f64> {
+        let n_classes = self.target.ncols() as f64;
+        let n_samples = self.target.nrows() as f64;
+
+        self.target.sum_axis(Axis(0)).mapv(|count| {
+            if count > 0f64 {
+                n_samples / (n_classes * count)
+            } else {
+                0f64
+            }
+        })
+    }
+
+    /// The (class, number of instances, fraction of the instances) of each class, sorted from the most frequent class
+    pub fn class_distribution(&self) -> Vec<(usize, usize, f64)> {
+        let mut counts = vec![0usize; self.target.ncols()];
+
+        for label in self.labels() {
+            counts[label] += 1;
+        }
+
+        let n_samples = self.target.nrows().max(1) as f64;
+        let mut distribution: Vec<(usize, usize, f64)> = counts
+            .into_iter()
+            .enumerate()
+            .map(|(class, count)| (class, count, count as f64 / n_samples))
+            .collect();
+        distribution.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        distribution
+    }
+
+    /// Whether the most frequent class has at most threshold times the instances of the least frequent one
+    /// A dataset in which some class doesn't appear is never balanced
+    pub fn is_balanced(&self, threshold: f64) -> bool {
+        let distribution = self.class_distribution();
+
+        match (distribution.first(), distribution.last()) {
+            (Some(most), Some(least)) => most.1 as f64 <= threshold * least.1 as f64,
+            _ => true,
+        }
+    }
+
+    /// Iterate over an epoch of batches sampled with replacement according to the weights of the sampler
+    pub fn weighted_batch_iter<'a>(
+        &'a self,
+        batch_size: usize,
+        sampler: &WeightedSampler,
+        rng: &'a mut StdRng,
+    ) -> BatchIter<'a> {
+        BatchIter::new(self, batch_size, sampler, rng)
+    }
+
+    /// Iterate over an epoch of batches holding the same number of instances of each class
+    pub fn balanced_batch_iter<'a>(
+        &'a self,
+        sampler: &BalancedBatchSampler,
+        rng: &'a mut StdRng,
+    ) -> BalancedBatchIter<'a> {
+        BalancedBatchIter::new(self, sampler, rng)
+    }
+
+    /// Iterate over an epoch of batches of instance indices whose classes are as balanced as possible
+    /// Each instance is drawn once. The batches can be constructed with select
+    pub fn stratified_batch_iter(&self, batch_size: usize, seed: u64) -> StratifiedBatchIter {
+        StratifiedBatchIter::new(self, batch_size, StdRng::seed_from_u64(seed))
+    }
+
+    /// Construct a dataset from a subset of the instances of this dataset
+    pub fn select(&self, indices: &[usize]) -> Dataset {
+        Dataset {
+            data: self.data.select(Axis(0), indices),
+            target: self.target.select(Axis(0), indices),
+        }
+    }
+
+    /// The class of each instance, i.e. the index of the largest element of its target
+    pub fn labels(&self) -> Vec<usize> {
+        self.target
+            .axis_iter(Axis(0))
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map_or(0, |(idx, _)| idx)
+            })
+            .collect()
+    }
+
+    /// Randomly split the dataset into a training set and a validation set holding val_fraction of the instances
+    /// The split is stratified: val_fraction of the instances of each class go to the validation set,
+    /// so both sets preserve the class proportions. If a seed is provided the split is reproducible
+    pub fn split(&self, val_fraction: f64, seed: Option<u64>) -> (Dataset, Dataset) {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut class_indices = vec![vec![]; self.target.ncols()];
+        let mut train_indices = vec![];
+        let mut val_indices = vec![];
+
+        for (idx, label) in self.labels().into_iter().enumerate() {
+            class_indices[label].push(idx);
+        }
+
+        for indices in class_indices.iter_mut() {
+            let num_val = (val_fraction * indices.len() as f64).round() as usize;
+
+            indices.shuffle(&mut rng);
+            val_indices.extend_from_slice(&indices[..num_val]);
+            train_indices.extend_from_slice(&indices[num_val..]);
+        }
+
+        // Don't leave the instances grouped by class
+        train_indices.shuffle(&mut rng);
+        val_indices.shuffle(&mut rng);
+
+        (self.select(&train_indices), self.select(&val_indices))
+    }
+
+    /// A reproducible stratified split into a training set and a validation set holding val_fraction of the instances
+    pub fn stratified_split(&self, val_fraction: f64, seed: u64) -> (Dataset, Dataset) {
+        self.split(val_fraction, Some(seed))
+    }
+}
+
+impl PairedDataset {
+    /// Stack the pairs in [start, end) into a single batch: the first instances of the pairs followed by the second ones
+    /// The targets are the labels of the pairs as a column
+    pub fn stack(&self, start: usize, end: usize) -> (Array2<f64>, Array2<f64>) {
+        let inputs = concatenate![
+            Axis(0),
+            self.x1.slice(s![start..end, ..]),
+            self.x2.slice(s![start..end, ..])
+        ];
+        let targets = self
+            .labels
+            .slice(s![start..end])
+            .to_owned()
+            .insert_axis(Axis(1));
+
+        (inputs, targets)
+    }
+
+    /// Split the pairs into stacked batches of batch_size pairs
+    pub fn stacked_batches(&self, batch_size: usize) -> Vec<(Array2<f64>, Array2<f64>)> {
+        (0..self.labels.len())
+            .step_by(batch_size)
+            .map(|start| self.stack(start, (start + batch_size).min(self.labels.len())))
+            .collect()
+    }
+}
+
+impl TripletDataset {
+    /// Construct triplets from the rows of data given (anchor, positive, negative) row indices
+    pub fn from_indices(data: &Array2<f64>, indices: &[(usize, usize, usize)]) -> TripletDataset {
+        let select = |f: fn(&(usize, usize, usize)) -> usize| {
+            data.select(Axis(0), &indices.iter().map(f).collect::<Vec<_>>())
+        };
+
+        TripletDataset {
+            anchors: select(|t| t.0),
+            positives: select(|t| t.1),
+            negatives: select(|t| t.2),
+        }
+    }
+
+    /// Stack the triplets in [start, end) into a single batch: the anchors, then the positives, then the negatives
+    /// The triplets don't have targets, so the targets are an empty column
+    pub fn stack(&self, start: usize, end: usize) -> (Array2<f64>, Array2<f64>) {
+        let inputs = concatenate![
+            Axis(0),
+            self.anchors.slice(s![start..end, ..]),
+            self.positives.slice(s![start..end, ..]),
+            self.negatives.slice(s![start..end, ..])
+        ];
+
+        (inputs, Array2::zeros((end - start, 0)))
+    }
+
+    /// Split the triplets into stacked batches of batch_size triplets
+    pub fn stacked_batches(&self, batch_size: usize) -> Vec<(Array2<f64>, Array2<f64>)> {
+        let n = self.anchors.nrows();
+
+        (0..n)
+            .step_by(batch_size)
+            .map(|start| self.stack(start, (start + batch_size).min(n)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Imbalanced classes of 600, 300 and 100 instances, whose only feature is the index of the instance
+    fn imbalanced_dataset() -> Dataset {
+        let data = Array2::from_shape_fn((1000, 1), |(row, _)| row as f64);
+        let target = Array2::from_shape_fn((1000, 3), |(row, col)| {
+            let class = match row % 10 {
+                0..=5 => 0,
+                6..=8 => 1,
+                _ => 2,
+            };
+
+            (class == col) as u8 as f64
+        });
+
+        Dataset { data, target }
+    }
+
+    fn class_proportions(dataset: &Dataset) -> Vec<f64> {
+        let counts = dataset.target.sum_axis(Axis(0));
+
+        counts
+            .iter()
+            .map(|count| count / dataset.data.nrows() as f64)
+            .collect()
+    }
+
+    #[test]
+    fn stratified_split_keeps_the_class_proportions() {
+        let dataset = imbalanced_dataset();
+        let (train, validation) = dataset.stratified_split(0.2, 5);
+        let proportions = class_proportions(&dataset);
+
+        assert_eq!(validation.data.nrows(), 200);
+
+        for split in [&train, &validation] {
+            for (split_proportion, proportion) in
+                class_proportions(split).iter().zip(proportions.iter())
+            {
+                assert!((split_proportion - proportion).abs() <= 0.01);
+            }
+        }
+
+        // Every instance is in exactly one of the splits
+        let mut indices: Vec<usize> = train
+            .data
+            .iter()
+            .chain(validation.data.iter())
+            .map(|&idx| idx as usize)
+            .collect();
+        indices.sort();
+
+        assert_eq!(indices, (0..1000).collect::<Vec<_>>());
+
+        // The same seed gives the same split
+        let (_, same_validation) = dataset.stratified_split(0.2, 5);
+        let (_, other_validation) = dataset.stratified_split(0.2, 6);
+
+        assert_eq!(same_validation.data, validation.data);
+        assert_ne!(other_validation.data, validation.data);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::error::{NeuralNetError, Result};
+use ndarray::{Array1, Array2};
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// Every .npy file starts with this magic string
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+/// The header (magic, version, header length and the descriptor) is padded to a multiple of this
+const NPY_ALIGNMENT: usize = 64;
+
+/// Save a matrix as a version 1.0 .npy file of little-endian float64s in C order
+/// The file format is described here https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html
+pub fn save_npy_f64_2d(path: &str, array: &Array2<f64>) -> Result<()> {
+    // iter() goes over the elements in logical (row-major) order regardless of the memory layout
+    let data: Vec<u8> = array.iter().flat_map(|x| x.to_le_bytes()).collect();
+
+    write_npy(path, "<f8", &[array.nrows(), array.ncols()], &data)
+}
+
+/// Write a version 1.0 .npy file of C-order elements of type descr, whose bytes are data
+fn write_npy(path: &str, descr: &str, shape: &[usize], data: &[u8]) -> Result<()> {
+    let mut file = File::create(path)?;
+    // Like Python tuples, shapes with a single dimension have a trailing comma, e.g. (3,)
+    let dims: Vec<String> = shape.iter().map(usize::to_string).collect();
+    let shape = match shape.len() {
+        1 => format!("({},)", dims[0]),
+        _ => format!("({})", dims.join(", ")),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // Magic (6 bytes), version (2 bytes), header length (2 bytes), header, and a terminating newline
+    let unpadded_len = NPY_MAGIC.len() + 4 + header.len() + 1;
+    let padding = (NPY_ALIGNMENT - unpadded_len % NPY_ALIGNMENT) % NPY_ALIGNMENT;
+
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    file.write_all(NPY_MAGIC)?;
+    file.write_all(&[1, 0])?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    file.write_all(data)?;
+
+    Ok(())
+}
+
+/// Read a .npy file of little-endian float64s, float32s or int64s in C order, converting the elements to float64s
+/// Returns the shape and the elements in row-major order
+fn load_npy_f64(path: &str) -> Result<(Vec<usize>, Vec<f64>)> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    let malformed = |msg: &str| NeuralNetError::Parse(format!("{}: {}", path, msg));
+
+    if !bytes.starts_with(NPY_MAGIC) || bytes.len() < NPY_MAGIC.len() + 2 {
+        return Err(malformed("Not a .npy file"));
+    }
+
+    // Version 1.0 has a 2-byte header length, and later versions have a 4-byte header length
+    let major_version = bytes[NPY_MAGIC.len()];
+    let len_start = NPY_MAGIC.len() + 2;
+    let (header_len, header_start) = match major_version {
+        1 if bytes.len() >= len_start + 2 => (
+            u16::from_le_bytes([bytes[len_start], bytes[len_start + 1]]) as usize,
+            len_start + 2,
+        ),
+        2 | 3 if bytes.len() >= len_start + 4 => (
+            u32::from_le_bytes(bytes[len_start..len_start + 4].try_into().unwrap()) as usize,
+            len_start + 4,
+        ),
+        _ => return Err(malformed("Unsupported version")),
+    };
+    let data_start = header_start + header_len;
+    let header = bytes
+        .get(header_start..data_start)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or_else(|| malformed("Invalid header"))?;
+
+    let (element_size, decode): (usize, fn(&[u8]) -> f64) = match header_value(header, "descr") {
+        Some("'<f8'") => (8, |bytes| f64::from_le_bytes(bytes.try_into().unwrap())),
+        Some("'<f4'") => (4, |bytes| {
+            f32::from_le_bytes(bytes.try_into().unwrap()) as f64
+        }),
+        Some("'<i8'") => (8, |bytes| {
+            i64::from_le_bytes(bytes.try_into().unwrap()) as f64
+        }),
+        _ => {
+            return Err(malformed(
+                "Only little-endian float64, float32 and int64 arrays are supported",
+            ))
+        }
+    };
+
+    if header_value(header, "fortran_order") != Some("False") {
+        return Err(malformed("Only C-order arrays are supported"));
+    }
+
+    let shape = header_value(header, "shape")
+        .and_then(parse_shape)
+        .ok_or_else(|| malformed("Invalid shape"))?;
+    let num_elements: usize = shape.iter().product();
+    let data = &bytes[data_start..];
+
+    if data.len() != num_elements * element_size {
+        return Err(malformed("The size of the data doesn't match the shape"));
+    }
+
+    let elements = data.chunks_exact(element_size).map(decode).collect();
+
+    Ok((shape, elements))
+}
+
+/// Find the value of a key in the header dict, e.g. 'shape': (2, 3), -> (2, 3)
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let rest = &header[header.find(&format!("'{}':", key))? + key.len() + 3..];
+    let rest = rest.trim_start();
+    // The shape is a tuple, which contains commas itself
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+
+    Some(rest[..end].trim())
+}
+
+/// Parse a shape tuple such as (2, 3), (3,) or ()
+fn parse_shape(shape: &str) -> Option<Vec<usize>> {
+    shape
+        .strip_prefix('(')?
+        .strip_suffix(')')?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().ok())
+        .collect()
+}
+
+/// Load a vector from a .npy file of float64s (or float32s or int64s, which are converted)
+pub fn load_npy_f64_1d(path: &str) -> Result<Array1<f64>> {
+    match load_npy_f64(path)? {
+        (shape, elements) if shape.len() == 1 => Ok(Array1::from_vec(elements)),
+        (shape, _) => Err(NeuralNetError::Parse(format!(
+            "{}: Expected a 1-D array, got shape {:?}",
+            path, shape
+        ))),
+    }
+}
+
+/// Load a matrix from a .npy file of float64s (or float32s or int64s, which are converted) in C order
+pub fn load_npy_f64_2d(path: &str) -> Result<Array2<f64>> {
+    match load_npy_f64(path)? {
+        (shape, elements) if shape.len() == 2 => {
+            Ok(Array2::from_shape_vec((shape[0], shape[1]), elements).unwrap())
+        }
+        (shape, _) => Err(NeuralNetError::Parse(format!(
+            "{}: Expected a 2-D array, got shape {:?}",
+            path, shape
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    /// A path in the temp dir that's unique to this test process
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("{}_{}.npy", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn f8_round_trip() {
+        let path = temp_path("f8_round_trip");
+        let array = array![[1.5f64, -2f64, 3.25f64], [0f64, 1e-300, f64::MAX]];
+
+        save_npy_f64_2d(&path, &array).unwrap();
+        let loaded = load_npy_f64_2d(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), array);
+    }
+
+    #[test]
+    fn header_is_aligned() {
+        let path = temp_path("header_is_aligned");
+
+        save_npy_f64_2d(&path, &array![[1f64]]).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The data starts right after the header, which ends with a newline
+        assert_eq!((bytes.len() - 8) % NPY_ALIGNMENT, 0);
+        assert_eq!(bytes[bytes.len() - 9], b'\n');
+    }
+
+    #[test]
+    fn f4_round_trip() {
+        let path = temp_path("f4_round_trip");
+        let elements = [0.5f32, -1.25f32, 3f32, 1e-3f32];
+        let data: Vec<u8> = elements.iter().flat_map(|x| x.to_le_bytes()).collect();
+
+        write_npy(&path, "<f4", &[2, 2], &data).unwrap();
+        let loaded = load_npy_f64_2d(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = Array2::from_shape_vec((2, 2), elements.map(f64::from).to_vec()).unwrap();
+        assert_eq!(loaded.unwrap(), expected);
+    }
+
+    #[test]
+    fn i8_round_trip() {
+        let path = temp_path("i8_round_trip");
+        let elements = [7i64, -3i64, 0i64];
+        let data: Vec<u8> = elements.iter().flat_map(|x| x.to_le_bytes()).collect();
+
+        write_npy(&path, "<i8", &[3], &data).unwrap();
+        let loaded = load_npy_f64_1d(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), array![7f64, -3f64, 0f64]);
+    }
+
+    #[test]
+    fn bad_files_are_errors() {
+        let path = temp_path("bad_files_are_errors");
+        let f8_data: Vec<u8> = [1f64, 2f64].iter().flat_map(|x| x.to_le_bytes()).collect();
+        let load_bytes = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            load_npy_f64_1d(&path)
+        };
+
+        // Not a .npy file, and one that's cut off in the header length
+        assert!(load_bytes(b"PK\x03\x04 not numpy").is_err());
+        assert!(load_bytes(b"\x93NUMPY\x01\x00\x10").is_err());
+        // A header length that goes past the end of the file
+        assert!(load_bytes(b"\x93NUMPY\x01\x00\xff\x00{'descr': '<f8'").is_err());
+
+        let write_and_load = |descr: &str, shape: &[usize], data: &[u8]| {
+            write_npy(&path, descr, shape, data).unwrap();
+            load_npy_f64_1d(&path)
+        };
+
+        assert!(write_and_load(">f8", &[2], &f8_data).is_err()); // Big-endian
+        assert!(write_and_load("<c16", &[1], &f8_data).is_err()); // Complex
+        assert!(write_and_load("<f8", &[3], &f8_data).is_err()); // Truncated data
+        assert!(write_and_load("<f8", &[1, 2], &f8_data).is_err()); // Not 1-D
+        assert!(write_and_load("<f8", &[2], &f8_data).is_ok());
+
+        // A Fortran-order array
+        let mut bytes = std::fs::read(&path).unwrap();
+        let header_start = NPY_MAGIC.len() + 4;
+        let header = String::from_utf8(bytes[header_start..bytes.len() - f8_data.len()].to_vec())
+            .unwrap()
+            .replace("False", "True ");
+        bytes.splice(
+            header_start..bytes.len() - f8_data.len(),
+            header.into_bytes(),
+        );
+
+        assert!(load_bytes(&bytes).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
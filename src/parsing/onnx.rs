@@ -0,0 +1,410 @@
+use crate::error::{NeuralNetError, Result};
+use ndarray::{ArrayD, IxDyn};
+use prost::Message;
+use proto::attribute_proto::AttributeType;
+use proto::tensor_proto::DataType;
+use proto::tensor_shape_proto::{dimension, Dimension};
+use proto::type_proto;
+use std::collections::HashMap;
+use std::fs;
+
+/// The messages of proto/onnx.proto, generated by build.rs
+#[allow(clippy::all)]
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/onnx.rs"));
+}
+
+// The versions written by write_onnx_graph
+const IR_VERSION: i64 = 7;
+const OPSET_VERSION: i64 = 13;
+
+type ParseResult<T> = std::result::Result<T, String>;
+
+/// A node of an ONNX graph, e.g. a MatMul
+#[derive(Clone, Debug, Default)]
+pub struct OnnxNode {
+    pub op_type: String,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub int_attributes: HashMap<String, i64>,
+    pub float_attributes: HashMap<String, f64>,
+}
+
+/// A graph input or output. A dimension is None if it isn't fixed (e.g. the batch size)
+#[derive(Clone, Debug, Default)]
+pub struct OnnxValueInfo {
+    pub name: String,
+    pub shape: Vec<Option<usize>>,
+}
+
+/// The computation graph of an ONNX model. The initializers are the constant tensors (e.g. the weights) by name
+#[derive(Clone, Debug, Default)]
+pub struct OnnxGraph {
+    pub nodes: Vec<OnnxNode>,
+    pub initializers: HashMap<String, ArrayD<f64>>,
+    pub inputs: Vec<OnnxValueInfo>,
+    pub outputs: Vec<OnnxValueInfo>,
+}
+
+/// Parse the graph of an ONNX model file
+pub fn parse_onnx_graph(path: &str) -> Result<OnnxGraph> {
+    let bytes = fs::read(path)?;
+    let malformed = |msg: String| NeuralNetError::Parse(format!("{}: {}", path, msg));
+    let model =
+        proto::ModelProto::decode(bytes.as_slice()).map_err(|e| malformed(e.to_string()))?;
+    let graph = model
+        .graph
+        .ok_or_else(|| malformed("The model has no graph".to_string()))?;
+
+    read_graph(graph).map_err(malformed)
+}
+
+fn read_graph(graph: proto::GraphProto) -> ParseResult<OnnxGraph> {
+    Ok(OnnxGraph {
+        nodes: graph.node.into_iter().map(read_node).collect(),
+        initializers: graph
+            .initializer
+            .iter()
+            .map(read_tensor)
+            .collect::<ParseResult<_>>()?,
+        inputs: graph.input.iter().map(read_value_info).collect(),
+        outputs: graph.output.iter().map(read_value_info).collect(),
+    })
+}
+
+/// Keep the integer and float attributes of a node (e.g. the transB of a Gemm), and skip the others
+fn read_node(node: proto::NodeProto) -> OnnxNode {
+    let mut int_attributes = HashMap::new();
+    let mut float_attributes = HashMap::new();
+
+    for attribute in node.attribute {
+        match attribute.r#type() {
+            AttributeType::Int => {
+                int_attributes.insert(attribute.name, attribute.i);
+            }
+            AttributeType::Float => {
+                float_attributes.insert(attribute.name, attribute.f as f64);
+            }
+            _ => {}
+        }
+    }
+
+    OnnxNode {
+        op_type: node.op_type,
+        inputs: node.input,
+        outputs: node.output,
+        int_attributes,
+        float_attributes,
+    }
+}
+
+/// Read a float or double tensor, stored either in its typed data field or as raw little-endian bytes
+fn read_tensor(tensor: &proto::TensorProto) -> ParseResult<(String, ArrayD<f64>)> {
+    let raw_data = &tensor.raw_data;
+    let values = match DataType::try_from(tensor.data_type) {
+        Ok(DataType::Float) if !raw_data.is_empty() => raw_data
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()) as f64)
+            .collect(),
+        Ok(DataType::Double) if !raw_data.is_empty() => raw_data
+            .chunks_exact(8)
+            .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect(),
+        Ok(DataType::Float) => tensor.float_data.iter().map(|&x| x as f64).collect(),
+        Ok(DataType::Double) => tensor.double_data.clone(),
+        _ => {
+            return Err(format!(
+                "Unsupported data type {} of {}",
+                tensor.data_type, tensor.name
+            ))
+        }
+    };
+
+    let shape: Vec<usize> = tensor.dims.iter().map(|&dim| dim as usize).collect();
+    let array = ArrayD::from_shape_vec(IxDyn(&shape), values)
+        .map_err(|e| format!("The data of {} doesn't match its shape: {}", tensor.name, e))?;
+
+    Ok((tensor.name.clone(), array))
+}
+
+/// Read the name and the shape of a graph input or output (ValueInfoProto -> TypeProto -> Tensor -> Shape)
+fn read_value_info(info: &proto::ValueInfoProto) -> OnnxValueInfo {
+    let tensor_type = info
+        .r#type
+        .as_ref()
+        .and_then(|type_proto| type_proto.value.as_ref());
+    let dims = match tensor_type {
+        Some(type_proto::Value::TensorType(tensor_type)) => tensor_type
+            .shape
+            .as_ref()
+            .map_or(&[][..], |shape| shape.dim.as_slice()),
+        _ => &[],
+    };
+
+    OnnxValueInfo {
+        name: info.name.clone(),
+        // The size of a dimension is None if it's symbolic
+        shape: dims
+            .iter()
+            .map(|dim| match dim.value {
+                Some(dimension::Value::DimValue(size)) => Some(size as usize),
+                _ => None,
+            })
+            .collect(),
+    }
+}
+
+/// Write a graph as an ONNX model file. The initializers are written as double tensors, in the order of their names
+pub fn write_onnx_graph(path: &str, graph: &OnnxGraph) -> Result<()> {
+    let mut initializers: Vec<_> = graph.initializers.iter().collect();
+    initializers.sort_by_key(|(name, _)| *name);
+
+    // The IR version, the producer, the opset of the default domain and the graph
+    let model = proto::ModelProto {
+        ir_version: IR_VERSION,
+        producer_name: "rust_neuralnet".to_string(),
+        opset_import: vec![proto::OperatorSetIdProto {
+            domain: String::new(),
+            version: OPSET_VERSION,
+        }],
+        graph: Some(proto::GraphProto {
+            node: graph.nodes.iter().map(encode_node).collect(),
+            name: "graph".to_string(),
+            initializer: initializers
+                .into_iter()
+                .map(|(name, tensor)| encode_tensor(name, tensor))
+                .collect(),
+            input: graph.inputs.iter().map(encode_value_info).collect(),
+            output: graph.outputs.iter().map(encode_value_info).collect(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    fs::write(path, model.encode_to_vec())?;
+
+    Ok(())
+}
+
+fn encode_node(node: &OnnxNode) -> proto::NodeProto {
+    let mut int_attributes: Vec<_> = node.int_attributes.iter().collect();
+    int_attributes.sort();
+    let mut float_attributes: Vec<_> = node.float_attributes.iter().collect();
+    float_attributes.sort_by_key(|(name, _)| *name);
+
+    let ints = int_attributes
+        .into_iter()
+        .map(|(name, &value)| proto::AttributeProto {
+            name: name.clone(),
+            r#type: AttributeType::Int as i32,
+            i: value,
+            ..Default::default()
+        });
+    let floats = float_attributes
+        .into_iter()
+        .map(|(name, &value)| proto::AttributeProto {
+            name: name.clone(),
+            r#type: AttributeType::Float as i32,
+            f: value as f32,
+            ..Default::default()
+        });
+
+    proto::NodeProto {
+        input: node.inputs.clone(),
+        output: node.outputs.clone(),
+        op_type: node.op_type.clone(),
+        attribute: ints.chain(floats).collect(),
+        ..Default::default()
+    }
+}
+
+fn encode_tensor(name: &str, tensor: &ArrayD<f64>) -> proto::TensorProto {
+    proto::TensorProto {
+        dims: tensor.shape().iter().map(|&dim| dim as i64).collect(),
+        data_type: DataType::Double as i32,
+        name: name.to_string(),
+        raw_data: tensor.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        ..Default::default()
+    }
+}
+
+/// A graph input or output as a double tensor, with a symbolic dimension for each unknown one
+fn encode_value_info(info: &OnnxValueInfo) -> proto::ValueInfoProto {
+    let dim = info
+        .shape
+        .iter()
+        .map(|dim| Dimension {
+            value: Some(match dim {
+                Some(size) => dimension::Value::DimValue(*size as i64),
+                None => dimension::Value::DimParam("N".to_string()),
+            }),
+            ..Default::default()
+        })
+        .collect();
+    let tensor_type = type_proto::Tensor {
+        elem_type: DataType::Double as i32,
+        shape: Some(proto::TensorShapeProto { dim }),
+    };
+
+    proto::ValueInfoProto {
+        name: info.name.clone(),
+        r#type: Some(proto::TypeProto {
+            value: Some(type_proto::Value::TensorType(tensor_type)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("onnx_parsing_{}_{}.onnx", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn example_graph() -> OnnxGraph {
+        let mut node = OnnxNode {
+            op_type: "Gemm".to_string(),
+            inputs: vec!["x".to_string(), "W".to_string(), "b".to_string()],
+            outputs: vec!["y".to_string()],
+            ..OnnxNode::default()
+        };
+        node.int_attributes.insert("transB".to_string(), 1);
+        node.float_attributes.insert("alpha".to_string(), 0.5);
+
+        let mut graph = OnnxGraph::default();
+        graph.nodes.push(node);
+        graph.initializers.insert(
+            "W".to_string(),
+            Array::from_shape_fn((3, 2), |(i, j)| i as f64 - 0.1 * j as f64).into_dyn(),
+        );
+        graph.initializers.insert(
+            "b".to_string(),
+            Array::from_vec(vec![1e-300, -7.25, 3f64]).into_dyn(),
+        );
+        graph.inputs.push(OnnxValueInfo {
+            name: "x".to_string(),
+            shape: vec![None, Some(2)],
+        });
+        graph.outputs.push(OnnxValueInfo {
+            name: "y".to_string(),
+            shape: vec![None, Some(3)],
+        });
+
+        graph
+    }
+
+    #[test]
+    fn written_graphs_are_parsed_back() {
+        let path = temp_path("round_trip");
+        let graph = example_graph();
+
+        write_onnx_graph(&path, &graph).unwrap();
+
+        let parsed = parse_onnx_graph(&path).unwrap();
+        let node = &parsed.nodes[0];
+
+        assert_eq!(node.op_type, "Gemm");
+        assert_eq!(node.inputs, graph.nodes[0].inputs);
+        assert_eq!(node.outputs, graph.nodes[0].outputs);
+        assert_eq!(node.int_attributes, graph.nodes[0].int_attributes);
+        assert_eq!(node.float_attributes, graph.nodes[0].float_attributes);
+        assert_eq!(parsed.initializers, graph.initializers);
+        assert_eq!(parsed.inputs[0].name, "x");
+        assert_eq!(parsed.inputs[0].shape, vec![None, Some(2)]);
+        assert_eq!(parsed.outputs[0].shape, vec![None, Some(3)]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_protobufs_are_errors() {
+        let path = temp_path("malformed");
+        let invalid: [&[u8]; 4] = [
+            &[0x08, 0x80], // A key whose value is a truncated varint
+            &[
+                0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ], // A varint of 77 bits
+            &[0x3a, 0xff, 0x01], // A graph whose length is past the end of the file
+            &[0x0b],       // Wire type 3 is the deprecated start of a group
+        ];
+
+        for bytes in invalid {
+            fs::write(&path, bytes).unwrap();
+            assert!(parse_onnx_graph(&path).is_err(), "{:?}", bytes);
+        }
+
+        // A valid model without a graph
+        fs::write(&path, [0x08, 0x07]).unwrap();
+        assert!(parse_onnx_graph(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let path = temp_path("truncated");
+
+        write_onnx_graph(&path, &example_graph()).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+
+        // The fields are written in the order of their numbers, so only the opset import (field 8) comes after the
+        // graph (field 7), and every shorter prefix cuts the graph off
+        let opset = proto::OperatorSetIdProto {
+            domain: String::new(),
+            version: OPSET_VERSION,
+        };
+        // The opset import is preceded by its key and its length, a byte each
+        let opset_len = opset.encoded_len() + 2;
+
+        for len in 0..bytes.len() - opset_len {
+            fs::write(&path, &bytes[..len]).unwrap();
+            assert!(
+                parse_onnx_graph(&path).is_err(),
+                "a prefix of {} bytes",
+                len
+            );
+        }
+
+        // Removing a byte shifts the fields, which may still parse but mustn't panic
+        for idx in 0..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted.remove(idx);
+            fs::write(&path, &corrupted).unwrap();
+            let _ = parse_onnx_graph(&path);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tensors_must_match_their_shape() {
+        let tensor = proto::TensorProto {
+            dims: vec![2, 2],
+            data_type: DataType::Double as i32,
+            raw_data: vec![0u8; 24],
+            ..Default::default()
+        };
+
+        assert!(read_tensor(&tensor).is_err());
+
+        let tensor = proto::TensorProto {
+            dims: vec![3],
+            data_type: DataType::Float as i32,
+            float_data: vec![1f32, 2f32, 3f32],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            read_tensor(&tensor).unwrap().1.into_raw_vec(),
+            vec![1f64, 2f64, 3f64]
+        );
+    }
+}
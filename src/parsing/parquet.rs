@@ -0,0 +1,325 @@
+use super::Dataset;
+use crate::error::{NeuralNetError, Result};
+use ::parquet::file::reader::{FileReader, SerializedFileReader};
+use ::parquet::record::Field;
+use ndarray::Array2;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Parse a dataset from a Parquet file. The feature columns (integers or floats) become the data matrix,
+/// and the target column is one-hot encoded: integer targets are the indices of the classes, and other targets
+/// are mapped to classes in sorted order. If feature_columns is empty, all of the columns except the target are used
+/// Nulls in the features are replaced with the mean of their column
+/// The file is decoded with the parquet crate. Flat schemas with uncompressed, Snappy or gzip compressed columns are
+/// supported
+pub fn parse_parquet(path: &str, target_column: &str, feature_columns: &[&str]) -> Result<Dataset> {
+    let malformed = |msg: String| NeuralNetError::Parse(format!("{}: {}", path, msg));
+    let reader =
+        SerializedFileReader::new(File::open(path)?).map_err(|err| malformed(err.to_string()))?;
+    let schema = reader.metadata().file_metadata().schema_descr_ptr();
+
+    if schema
+        .columns()
+        .iter()
+        .any(|column| column.path().parts().len() > 1)
+    {
+        return Err(malformed("Nested schemas aren't supported".to_string()));
+    }
+
+    let leaf_names: Vec<&str> = schema
+        .columns()
+        .iter()
+        .map(|column| column.name())
+        .collect();
+    let feature_columns: Vec<&str> = if feature_columns.is_empty() {
+        leaf_names
+            .iter()
+            .copied()
+            .filter(|name| *name != target_column)
+            .collect()
+    } else {
+        feature_columns.to_vec()
+    };
+
+    if let Some(column) = feature_columns
+        .iter()
+        .chain([&target_column])
+        .find(|column| !leaf_names.contains(column))
+    {
+        return Err(malformed(format!("No column named {}", column)));
+    }
+
+    // The parquet crate panics on some corrupted pages instead of returning an error
+    let columns = panic::catch_unwind(AssertUnwindSafe(|| read_columns(&reader, &leaf_names)))
+        .unwrap_or_else(|_| Err("Corrupted column data".to_string()))
+        .map_err(malformed)?;
+    let mut features = vec![];
+
+    for column in &feature_columns {
+        let numbers = columns[*column]
+            .iter()
+            .map(|value| match value {
+                Some(Value::Number(x)) => Ok(Some(*x)),
+                None => Ok(None),
+                Some(Value::String(_)) => {
+                    Err(format!("The feature column {} isn't numeric", column))
+                }
+            })
+            .collect::<ParseResult<Vec<Option<f64>>>>()
+            .map_err(malformed)?;
+        let present: Vec<f64> = numbers.iter().flatten().copied().collect();
+        let mean = if present.is_empty() {
+            0f64
+        } else {
+            present.iter().sum::<f64>() / present.len() as f64
+        };
+
+        features.push(
+            numbers
+                .into_iter()
+                .map(|x| x.unwrap_or(mean))
+                .collect::<Vec<f64>>(),
+        );
+    }
+
+    let targets = columns[target_column]
+        .iter()
+        .cloned()
+        .collect::<Option<Vec<Value>>>()
+        .ok_or_else(|| malformed(format!("The target column {} has nulls", target_column)))?;
+    let n_rows = targets.len();
+    let data = Array2::from_shape_fn((n_rows, features.len()), |(i, j)| features[j][i]);
+    let classes = target_classes(&targets);
+    let n_classes = classes.iter().max().map_or(0, |max| max + 1);
+    let mut target = Array2::zeros((n_rows, n_classes));
+
+    for (i, class) in classes.into_iter().enumerate() {
+        target[[i, class]] = 1f64;
+    }
+
+    Ok(Dataset { data, target })
+}
+
+type ParseResult<T> = std::result::Result<T, String>;
+
+/// A value of a column
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+enum Value {
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    /// The value of a field of a row, or None if it's null
+    fn from_field(field: &Field) -> ParseResult<Option<Value>> {
+        let number = match field {
+            Field::Null => return Ok(None),
+            Field::Str(string) => return Ok(Some(Value::String(string.clone()))),
+            Field::Bytes(bytes) => {
+                let string = String::from_utf8_lossy(bytes.data()).into_owned();
+
+                return Ok(Some(Value::String(string)));
+            }
+            Field::Bool(x) => *x as u8 as f64,
+            Field::Byte(x) => *x as f64,
+            Field::Short(x) => *x as f64,
+            Field::Int(x) => *x as f64,
+            Field::Long(x) => *x as f64,
+            Field::UByte(x) => *x as f64,
+            Field::UShort(x) => *x as f64,
+            Field::UInt(x) => *x as f64,
+            Field::ULong(x) => *x as f64,
+            Field::Float(x) => *x as f64,
+            Field::Double(x) => *x,
+            field => return Err(format!("Unsupported value {}", field)),
+        };
+
+        Ok(Some(Value::Number(number)))
+    }
+}
+
+/// Read all of the values of each column, across the row groups
+fn read_columns(
+    reader: &SerializedFileReader<File>,
+    names: &[&str],
+) -> ParseResult<HashMap<String, Vec<Option<Value>>>> {
+    let mut columns: HashMap<String, Vec<Option<Value>>> = names
+        .iter()
+        .map(|name| (name.to_string(), vec![]))
+        .collect();
+
+    for row in reader.get_row_iter(None).map_err(|err| err.to_string())? {
+        for (name, field) in row.map_err(|err| err.to_string())?.get_column_iter() {
+            columns
+                .entry(name.clone())
+                .or_default()
+                .push(Value::from_field(field)?);
+        }
+    }
+
+    Ok(columns)
+}
+
+/// Map the targets to class indices. Non-negative integers are used as is, and other values are sorted
+fn target_classes(targets: &[Value]) -> Vec<usize> {
+    let indices: Option<Vec<usize>> = targets
+        .iter()
+        .map(|value| match value {
+            Value::Number(x) if *x >= 0f64 && x.fract() == 0f64 => Some(*x as usize),
+            _ => None,
+        })
+        .collect();
+
+    if let Some(indices) = indices {
+        return indices;
+    }
+
+    let mut distinct = targets.to_vec();
+    distinct.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    distinct.dedup();
+
+    targets
+        .iter()
+        .map(|value| distinct.iter().position(|v| v == value).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+    use std::fs;
+    use std::path::Path;
+
+    /// The path of a Parquet file in tests/fixtures, which were written with tests/fixtures/make_parquet.py
+    fn fixture(name: &str) -> String {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The features of the fixtures, with the nulls replaced with the means of their columns
+    fn expected_data() -> Array2<f64> {
+        let i64_mean = (10 + 30 + 40 + 60 + 70 + 80) as f64 / 6f64;
+        let f64_mean = -(1 + 2 + 4 + 5 + 6 + 7) as f64 / 6f64;
+
+        array![
+            [1f64, 10f64, 0.5, -1f64],
+            [2f64, i64_mean, 1.5, -2f64],
+            [3f64, 30f64, 2.5, f64_mean],
+            [4f64, 40f64, 3.5, -4f64],
+            [5f64, i64_mean, 4.5, -5f64],
+            [6f64, 60f64, 5.5, -6f64],
+            [7f64, 70f64, 6.5, -7f64],
+            [8f64, 80f64, 7.5, f64_mean],
+        ]
+    }
+
+    /// The labels cat, dog, cat, bird, dog, dog, cat, bird, whose classes are in sorted order (bird, cat, dog)
+    fn expected_target() -> Array2<f64> {
+        let classes = [1, 2, 1, 0, 2, 2, 1, 0];
+        let mut target = Array2::zeros((8, 3));
+
+        for (i, class) in classes.into_iter().enumerate() {
+            target[[i, class]] = 1f64;
+        }
+
+        target
+    }
+
+    fn assert_dataset(path: &str) {
+        let dataset = parse_parquet(&fixture(path), "label", &[]).unwrap();
+
+        assert!((&dataset.data - &expected_data())
+            .iter()
+            .all(|x| x.abs() < 1e-12));
+        assert_eq!(dataset.target, expected_target());
+    }
+
+    #[test]
+    fn reads_plain_uncompressed_columns_with_nulls() {
+        assert_dataset("plain.parquet");
+    }
+
+    #[test]
+    fn reads_gzip_compressed_dictionary_pages() {
+        assert_dataset("gzip_dictionary.parquet");
+    }
+
+    #[test]
+    fn reads_snappy_compressed_dictionary_pages() {
+        assert_dataset("snappy.parquet");
+    }
+
+    #[test]
+    fn selects_feature_columns_and_integer_targets() {
+        let dataset = parse_parquet(&fixture("plain.parquet"), "i32", &["f32"]).unwrap();
+
+        assert_eq!(
+            dataset.data.column(0),
+            array![0.5, 1.5, 2.5, 3.5, 4.5, 5.5, 6.5, 7.5]
+        );
+        // Integer targets are the indices of the classes
+        assert_eq!(dataset.target.dim(), (8, 9));
+        assert!((0..8).all(|i| dataset.target[[i, i + 1]] == 1f64));
+    }
+
+    #[test]
+    fn invalid_columns_are_errors() {
+        let path = fixture("plain.parquet");
+
+        assert!(parse_parquet(&path, "missing", &[]).is_err());
+        // The target can't have nulls, and the features must be numeric
+        assert!(parse_parquet(&path, "i64", &["i32"]).is_err());
+        assert!(parse_parquet(&path, "i32", &["label"]).is_err());
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let bytes = fs::read(fixture("gzip_dictionary.parquet")).unwrap();
+        let path = std::env::temp_dir().join(format!("malformed_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap();
+        let check = |contents: &[u8]| {
+            fs::write(path, contents).unwrap();
+
+            parse_parquet(path, "label", &[]).is_err()
+        };
+
+        // Not a Parquet file, and a footer length that's past the start of the file
+        assert!(check(b"PAR1PAR1"));
+        assert!(check(&[b"PAR1".as_slice(), &[0xff; 4], b"PAR1"].concat()));
+
+        // Every prefix of the file is missing its footer
+        for len in 0..bytes.len() {
+            assert!(
+                check(&bytes[..len]),
+                "Truncating to {} bytes should fail",
+                len
+            );
+        }
+
+        // Removing a byte shifts the pages away from their offsets, which must not panic
+        for idx in 0..bytes.len() {
+            let mut shifted = bytes.clone();
+            shifted.remove(idx);
+
+            check(&shifted);
+        }
+
+        // Corrupting any byte must not panic, even if the file still happens to parse
+        for idx in 0..bytes.len() {
+            for value in [0x00, 0x7f, 0xff] {
+                let mut corrupted = bytes.clone();
+                corrupted[idx] = value;
+
+                check(&corrupted);
+            }
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+use super::Dataset;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+/// Samples instances of a dataset with replacement, with probabilities proportional to their weights
+pub struct WeightedSampler {
+    pub weights: Vec<f64>,
+}
+
+impl WeightedSampler {
+    pub fn new(weights: Vec<f64>) -> WeightedSampler {
+        WeightedSampler { weights }
+    }
+}
+
+/// Iterates over batches drawn by a WeightedSampler. An epoch has as many batches as iterating over the dataset in order
+pub struct BatchIter<'a> {
+    dataset: &'a Dataset,
+    distribution: WeightedIndex<f64>,
+    rng: &'a mut StdRng,
+    batch_size: usize,
+    num_batches: usize,
+}
+
+impl<'a> BatchIter<'a> {
+    pub(super) fn new(
+        dataset: &'a Dataset,
+        batch_size: usize,
+        sampler: &WeightedSampler,
+        rng: &'a mut StdRng,
+    ) -> BatchIter<'a> {
+        let distribution = WeightedIndex::new(&sampler.weights)
+            .expect("The sample weights must be non-negative and not all zero");
+
+        BatchIter {
+            dataset,
+            distribution,
+            rng,
+            batch_size,
+            num_batches: dataset.data.nrows().div_ceil(batch_size),
+        }
+    }
+}
+
+impl Iterator for BatchIter<'_> {
+    type Item = Dataset;
+
+    fn next(&mut self) -> Option<Dataset> {
+        if self.num_batches == 0 {
+            return None;
+        }
+
+        self.num_batches -= 1;
+
+        let indices: Vec<usize> = (0..self.batch_size)
+            .map(|_| self.distribution.sample(self.rng))
+            .collect();
+
+        Some(self.dataset.select(&indices))
+    }
+}
+
+/// Draws batches with exactly samples_per_class instances of each class, regardless of the class frequencies
+pub struct BalancedBatchSampler {
+    pub samples_per_class: usize,
+}
+
+impl BalancedBatchSampler {
+    pub fn new(samples_per_class: usize) -> BalancedBatchSampler {
+        BalancedBatchSampler { samples_per_class }
+    }
+}
+
+/// Iterates over batches drawn by a BalancedBatchSampler
+/// Each class has a shuffled pool of its instances that is reshuffled when it runs out, so instances
+/// are only repeated within a batch if their class has fewer than samples_per_class instances
+/// Classes without any instances are skipped. An epoch holds enough batches to cover the dataset once
+pub struct BalancedBatchIter<'a> {
+    dataset: &'a Dataset,
+    rng: &'a mut StdRng,
+    samples_per_class: usize,
+    pools: Vec<Vec<usize>>, // The instances of each class
+    positions: Vec<usize>,  // The position of the next instance in the pool of each class
+    num_batches: usize,
+}
+
+impl<'a> BalancedBatchIter<'a> {
+    pub(super) fn new(
+        dataset: &'a Dataset,
+        sampler: &BalancedBatchSampler,
+        rng: &'a mut StdRng,
+    ) -> BalancedBatchIter<'a> {
+        let mut pools = vec![vec![]; dataset.target.ncols()];
+
+        for (idx, label) in dataset.labels().into_iter().enumerate() {
+            pools[label].push(idx);
+        }
+
+        pools.retain(|pool| !pool.is_empty());
+
+        for pool in pools.iter_mut() {
+            pool.shuffle(rng);
+        }
+
+        let batch_size = (pools.len() * sampler.samples_per_class).max(1);
+
+        BalancedBatchIter {
+            dataset,
+            rng,
+            samples_per_class: sampler.samples_per_class,
+            positions: vec![0; pools.len()],
+            pools,
+            num_batches: dataset.data.nrows().div_ceil(batch_size),
+        }
+    }
+}
+
+impl Iterator for BalancedBatchIter<'_> {
+    type Item = Dataset;
+
+    fn next(&mut self) -> Option<Dataset> {
+        if self.num_batches == 0 {
+            return None;
+        }
+
+        self.num_batches -= 1;
+
+        let mut indices = Vec::with_capacity(self.pools.len() * self.samples_per_class);
+
+        for (pool, position) in self.pools.iter_mut().zip(self.positions.iter_mut()) {
+            for _ in 0..self.samples_per_class {
+                if *position == pool.len() {
+                    pool.shuffle(self.rng);
+                    *position = 0;
+                }
+
+                indices.push(pool[*position]);
+                *position += 1;
+            }
+        }
+
+        // Don't leave the instances of the batch grouped by class
+        indices.shuffle(self.rng);
+
+        Some(self.dataset.select(&indices))
+    }
+}
+
+/// Iterates over an epoch of batches of instance indices in which the classes are as balanced as possible
+/// Unlike BalancedBatchIter, instances are drawn without replacement: each batch gets batch_size / n_classes
+/// instances of each class, and the rest of the batch (and the share of the classes that ran out) goes to the
+/// classes with the most instances left. The epoch ends when all the instances were drawn
+pub struct StratifiedBatchIter {
+    class_indices: Vec<Vec<usize>>, // The instances of each class that weren't drawn yet, in a shuffled order
+    batch_size: usize,
+    rng: StdRng,
+}
+
+impl StratifiedBatchIter {
+    pub(super) fn new(
+        dataset: &Dataset,
+        batch_size: usize,
+        mut rng: StdRng,
+    ) -> StratifiedBatchIter {
+        let mut class_indices = vec![vec![]; dataset.target.ncols()];
+
+        for (idx, label) in dataset.labels().into_iter().enumerate() {
+            class_indices[label].push(idx);
+        }
+
+        class_indices.retain(|pool| !pool.is_empty());
+
+        for pool in class_indices.iter_mut() {
+            pool.shuffle(&mut rng);
+        }
+
+        StratifiedBatchIter {
+            class_indices,
+            batch_size: batch_size.max(1),
+            rng,
+        }
+    }
+}
+
+impl Iterator for StratifiedBatchIter {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        let num_remaining: usize = self.class_indices.iter().map(Vec::len).sum();
+
+        if num_remaining == 0 {
+            return None;
+        }
+
+        let mut counts = vec![0usize; self.class_indices.len()];
+        let mut left = self.batch_size.min(num_remaining);
+
+        while left > 0 {
+            // The classes that can still contribute to the batch, with the most instances left first
+            let mut open: Vec<usize> = (0..counts.len())
+                .filter(|&class| counts[class] < self.class_indices[class].len())
+                .collect();
+            open.sort_by_key(|&class| {
+                std::cmp::Reverse(self.class_indices[class].len() - counts[class])
+            });
+
+            let share = (left / open.len()).max(1);
+
+            for class in open {
+                let take = share
+                    .min(self.class_indices[class].len() - counts[class])
+                    .min(left);
+                counts[class] += take;
+                left -= take;
+
+                if left == 0 {
+                    break;
+                }
+            }
+        }
+
+        let mut indices = Vec::with_capacity(self.batch_size);
+
+        for (pool, count) in self.class_indices.iter_mut().zip(counts) {
+            indices.extend(pool.drain(pool.len() - count..));
+        }
+
+        // Don't leave the instances of the batch grouped by class
+        indices.shuffle(&mut self.rng);
+
+        Some(indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    /// An imbalanced dataset with 50, 10 and 2 instances of its classes, whose only feature is its index
+    fn imbalanced_dataset() -> Dataset {
+        let records = (0..62)
+            .map(|idx| {
+                let class = match idx {
+                    0..=49 => 0,
+                    50..=59 => 1,
+                    _ => 2,
+                };
+
+                (vec![idx as f64], class)
+            })
+            .collect();
+
+        Dataset::from_records(records, 3).unwrap()
+    }
+
+    #[test]
+    fn balanced_batches_have_samples_per_class_of_each_class() {
+        let dataset = imbalanced_dataset();
+        let sampler = BalancedBatchSampler::new(4);
+        let mut rng = StdRng::seed_from_u64(0);
+        let batches: Vec<Dataset> = dataset.balanced_batch_iter(&sampler, &mut rng).collect();
+
+        // Enough batches of 3 * 4 instances to cover the 62 instances
+        assert_eq!(batches.len(), 6);
+
+        for batch in &batches {
+            let labels = batch.labels();
+
+            assert_eq!(batch.data.nrows(), 3 * 4);
+            for class in 0..3 {
+                assert_eq!(labels.iter().filter(|&&label| label == class).count(), 4);
+            }
+        }
+    }
+
+    #[test]
+    fn balanced_batches_use_the_whole_pool_before_repeating() {
+        let dataset = imbalanced_dataset();
+        let sampler = BalancedBatchSampler::new(5);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut batches = dataset.balanced_batch_iter(&sampler, &mut rng);
+        // The 10 instances of class 1 fill exactly two batches
+        let mut drawn: Vec<usize> = batches
+            .by_ref()
+            .take(2)
+            .flat_map(|batch| {
+                batch
+                    .data
+                    .column(0)
+                    .iter()
+                    .map(|&x| x as usize)
+                    .filter(|idx| (50..60).contains(idx))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        drawn.sort_unstable();
+        assert_eq!(drawn, (50..60).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,114 @@
+use super::loader::DataSource;
+use super::{CsvConfig, Dataset};
+use crate::error::{NeuralNetError, Result};
+use ndarray::Array2;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+
+/// A CSV dataset that is read from disk a batch at a time, so it doesn't have to fit in memory
+/// The byte offset of every instance is indexed once when it's opened, so any range of instances can be read
+pub struct StreamingCsvDataset {
+    pub path: String,
+    pub config: CsvConfig,
+    pub n_samples: usize,
+    pub batch_size: usize, // The size of the batches returned by next_batch
+    offsets: Vec<u64>,     // The byte offset of each instance
+    reader: BufReader<File>,
+    next_sample: usize,
+}
+
+impl StreamingCsvDataset {
+    pub fn new(path: &str, config: CsvConfig, batch_size: usize) -> Result<StreamingCsvDataset> {
+        let mut offsets = StreamingCsvDataset::build_index(path)?;
+
+        if config.has_header && !offsets.is_empty() {
+            offsets.remove(0);
+        }
+
+        Ok(StreamingCsvDataset {
+            path: path.to_string(),
+            config,
+            n_samples: offsets.len(),
+            batch_size: batch_size.max(1),
+            offsets,
+            reader: BufReader::new(File::open(path)?),
+            next_sample: 0,
+        })
+    }
+
+    /// Return the byte offset of each non-empty line of a file, in a single pass
+    pub fn build_index(path: &str) -> Result<Vec<u64>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut offsets = vec![];
+        let mut offset = 0u64;
+        let mut line = vec![];
+
+        loop {
+            line.clear();
+
+            let num_read = reader.read_until(b'\n', &mut line)?;
+
+            if num_read == 0 {
+                break;
+            }
+
+            if !line.trim_ascii().is_empty() {
+                offsets.push(offset);
+            }
+
+            offset += num_read as u64;
+        }
+
+        Ok(offsets)
+    }
+
+    /// Read and parse the instances start..end
+    pub fn get_batch(&mut self, start: usize, end: usize) -> Result<Dataset> {
+        let mut records = Vec::with_capacity(end - start);
+        let mut line = String::new();
+
+        if start < end {
+            self.reader.seek(SeekFrom::Start(self.offsets[start]))?;
+        }
+
+        for sample in start..end {
+            // The index skips empty lines, so they are skipped here as well
+            loop {
+                line.clear();
+                self.reader.read_line(&mut line)?;
+
+                if !line.trim().is_empty() {
+                    break;
+                }
+            }
+
+            let record = self.config.parse_record(&line).map_err(|msg| {
+                NeuralNetError::Parse(format!("Instance {}: {}", sample + 1, msg))
+            })?;
+            records.push(record);
+        }
+
+        Dataset::from_records(records, self.config.num_classes)
+    }
+}
+
+impl DataSource for StreamingCsvDataset {
+    fn next_batch(&mut self) -> Option<(Array2<f64>, Array2<f64>)> {
+        if self.next_sample == self.n_samples {
+            return None;
+        }
+
+        let start = self.next_sample;
+        let end = (start + self.batch_size).min(self.n_samples);
+        let batch = self
+            .get_batch(start, end)
+            .expect("Failed to read a batch of the streaming dataset");
+        self.next_sample = end;
+
+        Some((batch.data, batch.target))
+    }
+
+    fn reset(&mut self) {
+        self.next_sample = 0;
+    }
+}
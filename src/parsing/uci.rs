@@ -0,0 +1,138 @@
+use super::Dataset;
+use crate::error::{NeuralNetError, Result};
+use ndarray::Array2;
+use std::fs;
+
+/// Where the class is stored in each line of a UCI dataset
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TargetPosition {
+    First,
+    #[default]
+    Last,
+}
+
+/// The format of a UCI dataset
+#[derive(Clone, Debug)]
+pub struct UciConfig {
+    /// Separates the values of a line. If it's whitespace, any run of whitespace separates the values
+    pub separator: char,
+    pub target_col_position: TargetPosition,
+    /// The classes in the order of the one-hot encoding. If empty, the distinct classes are used in sorted order
+    pub class_labels: Vec<String>,
+    /// Marks a missing feature (e.g. "?"), which is replaced with the mean of its column
+    pub missing_value_str: Option<String>,
+    /// Min-max scale each feature to [0, 1]
+    pub normalize: bool,
+}
+
+impl Default for UciConfig {
+    fn default() -> UciConfig {
+        UciConfig {
+            separator: ',',
+            target_col_position: TargetPosition::Last,
+            class_labels: vec![],
+            missing_value_str: Some("?".to_string()),
+            normalize: false,
+        }
+    }
+}
+
+/// Parse a dataset in the format of the UCI ML repository (e.g. Iris, Wine): lines of numeric features
+/// with a string class label as the first or last value, and no header
+pub fn parse_uci(path: &str, config: UciConfig) -> Result<Dataset> {
+    let contents = fs::read_to_string(path)?;
+    let malformed = |line_idx: usize, msg: String| {
+        NeuralNetError::Parse(format!("Line {}: {}", line_idx + 1, msg))
+    };
+    let mut rows: Vec<Vec<Option<f64>>> = vec![];
+    let mut labels = vec![];
+
+    for (line_idx, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut values: Vec<&str> = if config.separator.is_whitespace() {
+            line.split_whitespace().collect()
+        } else {
+            line.split(config.separator).map(str::trim).collect()
+        };
+        let label = match config.target_col_position {
+            TargetPosition::First => values.remove(0),
+            TargetPosition::Last => values.pop().unwrap(),
+        };
+        let features = values
+            .into_iter()
+            .map(|value| {
+                if config.missing_value_str.as_deref() == Some(value) {
+                    Ok(None)
+                } else {
+                    value
+                        .parse::<f64>()
+                        .map(Some)
+                        .map_err(|e| malformed(line_idx, format!("{}: {}", value, e)))
+                }
+            })
+            .collect::<Result<Vec<Option<f64>>>>()?;
+
+        if let Some(first) = rows.first() {
+            if first.len() != features.len() {
+                return Err(malformed(
+                    line_idx,
+                    format!("Expected {} features, got {}", first.len(), features.len()),
+                ));
+            }
+        }
+
+        rows.push(features);
+        labels.push((line_idx, label.to_string()));
+    }
+
+    let class_labels = if config.class_labels.is_empty() {
+        let mut distinct: Vec<String> = labels.iter().map(|(_, label)| label.clone()).collect();
+        distinct.sort();
+        distinct.dedup();
+
+        distinct
+    } else {
+        config.class_labels
+    };
+    let num_features = rows.first().map_or(0, |row| row.len());
+    let mut data = Array2::zeros((rows.len(), num_features));
+    let mut target = Array2::zeros((rows.len(), class_labels.len()));
+
+    for (i, (line_idx, label)) in labels.iter().enumerate() {
+        let class = class_labels
+            .iter()
+            .position(|class| class == label)
+            .ok_or_else(|| malformed(*line_idx, format!("Unknown class {}", label)))?;
+
+        target[[i, class]] = 1f64;
+    }
+
+    for j in 0..num_features {
+        let present: Vec<f64> = rows.iter().filter_map(|row| row[j]).collect();
+        let mean = if present.is_empty() {
+            0f64
+        } else {
+            present.iter().sum::<f64>() / present.len() as f64
+        };
+
+        for (i, row) in rows.iter().enumerate() {
+            data[[i, j]] = row[j].unwrap_or(mean);
+        }
+    }
+
+    if config.normalize {
+        for mut column in data.columns_mut() {
+            let min = column.fold(f64::INFINITY, |a, &b| a.min(b));
+            let max = column.fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+            // Constant features are mapped to 0
+            let range = if max > min { max - min } else { 1f64 };
+
+            column.mapv_inplace(|x| (x - min) / range);
+        }
+    }
+
+    Ok(Dataset { data, target })
+}
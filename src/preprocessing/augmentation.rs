@@ -0,0 +1,51 @@
+use super::Transform;
+use crate::error::{NeuralNetError, Result};
+use crate::model::noise::{GaussianNoiseLayer, NoiseLayer};
+use json::{object, JsonValue};
+use ndarray::Array2;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::Mutex;
+
+/// Augments instances by adding zero-mean Gaussian noise with standard deviation std to their features
+/// Every call to transform samples new noise
+pub struct GaussianNoise {
+    pub std: f64,
+    rng: Mutex<StdRng>,
+}
+
+impl GaussianNoise {
+    pub fn new(std: f64, seed: u64) -> GaussianNoise {
+        GaussianNoise {
+            std,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    /// The state of the RNG isn't serialized, so the deserialized noise is seeded randomly
+    pub(super) fn from_json(value: &JsonValue) -> Result<GaussianNoise> {
+        let std = value["std"]
+            .as_f64()
+            .ok_or_else(|| NeuralNetError::Parse("Missing std".to_string()))?;
+
+        Ok(GaussianNoise {
+            std,
+            rng: Mutex::new(StdRng::from_entropy()),
+        })
+    }
+}
+
+impl Transform for GaussianNoise {
+    fn transform(&self, data: &Array2<f64>) -> Array2<f64> {
+        let noise = NoiseLayer::Gaussian(GaussianNoiseLayer { std: self.std });
+
+        data + &noise.sample(data.dim(), &mut *self.rng.lock().unwrap())
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            type: "gaussian_noise",
+            std: self.std,
+        }
+    }
+}
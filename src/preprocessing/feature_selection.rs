@@ -0,0 +1,21 @@
+use ndarray::{Array2, Axis};
+
+/// Remove the features whose variance across the samples is at most threshold, e.g. constant pixels at the
+/// border of MNIST images (a threshold of 0 removes exactly the constant features)
+/// Returns the filtered data and the indices of the retained features, to apply to new data with apply_feature_selection
+pub fn variance_threshold_filter(data: &Array2<f64>, threshold: f64) -> (Array2<f64>, Vec<usize>) {
+    let selected: Vec<usize> = data
+        .var_axis(Axis(0), 0f64)
+        .iter()
+        .enumerate()
+        .filter(|(_, &variance)| variance > threshold)
+        .map(|(idx, _)| idx)
+        .collect();
+
+    (apply_feature_selection(data, &selected), selected)
+}
+
+/// Keep only the selected features (columns) of the data
+pub fn apply_feature_selection(data: &Array2<f64>, selected_indices: &[usize]) -> Array2<f64> {
+    data.select(Axis(1), selected_indices)
+}
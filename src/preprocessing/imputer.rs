@@ -0,0 +1,159 @@
+use super::{parse_json_array, Transform};
+use crate::error::Result;
+use json::{object, JsonValue};
+use ndarray::{Array1, Array2};
+
+/// Replaces the missing values of each feature with the mean of the values that aren't missing
+pub struct MeanImputer {
+    pub feature_means: Array1<f64>,
+    pub missing_value: f64, // The value that marks a missing feature (can be NaN)
+}
+
+/// Replaces the missing values of each feature with the median of the values that aren't missing
+pub struct MedianImputer {
+    pub feature_medians: Array1<f64>,
+    pub missing_value: f64,
+}
+
+impl MeanImputer {
+    /// Compute the mean of each feature of the data (one instance per row), excluding the missing values
+    /// Features that are always missing get a mean of 0
+    pub fn fit(data: &Array2<f64>, missing_value: f64) -> MeanImputer {
+        let feature_means = fit_columns(data, missing_value, |present| {
+            present.iter().sum::<f64>() / present.len() as f64
+        });
+
+        MeanImputer {
+            feature_means,
+            missing_value,
+        }
+    }
+
+    /// Replace the missing values of the data with the means of their features
+    pub fn transform(&self, data: &Array2<f64>, missing_value: f64) -> Array2<f64> {
+        impute(data, missing_value, &self.feature_means)
+    }
+
+    pub(super) fn from_json(value: &JsonValue) -> Result<MeanImputer> {
+        Ok(MeanImputer {
+            feature_means: parse_json_array(value, "feature_means")?,
+            missing_value: parse_missing_value(value),
+        })
+    }
+}
+
+impl MedianImputer {
+    /// Compute the median of each feature of the data (one instance per row), excluding the missing values
+    /// Features that are always missing get a median of 0
+    pub fn fit(data: &Array2<f64>, missing_value: f64) -> MedianImputer {
+        let feature_medians = fit_columns(data, missing_value, |present| {
+            present.sort_by(f64::total_cmp);
+            let mid = present.len() / 2;
+
+            if present.len() % 2 == 0 {
+                (present[mid - 1] + present[mid]) / 2f64
+            } else {
+                present[mid]
+            }
+        });
+
+        MedianImputer {
+            feature_medians,
+            missing_value,
+        }
+    }
+
+    /// Replace the missing values of the data with the medians of their features
+    pub fn transform(&self, data: &Array2<f64>, missing_value: f64) -> Array2<f64> {
+        impute(data, missing_value, &self.feature_medians)
+    }
+
+    pub(super) fn from_json(value: &JsonValue) -> Result<MedianImputer> {
+        Ok(MedianImputer {
+            feature_medians: parse_json_array(value, "feature_medians")?,
+            missing_value: parse_missing_value(value),
+        })
+    }
+}
+
+impl Transform for MeanImputer {
+    fn transform(&self, data: &Array2<f64>) -> Array2<f64> {
+        MeanImputer::transform(self, data, self.missing_value)
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            type: "mean_imputer",
+            feature_means: self.feature_means.to_vec(),
+            missing_value: self.missing_value,
+        }
+    }
+}
+
+impl Transform for MedianImputer {
+    fn transform(&self, data: &Array2<f64>) -> Array2<f64> {
+        MedianImputer::transform(self, data, self.missing_value)
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            type: "median_imputer",
+            feature_medians: self.feature_medians.to_vec(),
+            missing_value: self.missing_value,
+        }
+    }
+}
+
+/// NaN never equals itself, so it's compared separately
+fn is_missing(x: f64, missing_value: f64) -> bool {
+    if missing_value.is_nan() {
+        x.is_nan()
+    } else {
+        x == missing_value
+    }
+}
+
+/// Compute a statistic of the values of each column that aren't missing
+fn fit_columns(
+    data: &Array2<f64>,
+    missing_value: f64,
+    statistic: impl Fn(&mut Vec<f64>) -> f64,
+) -> Array1<f64> {
+    data.columns()
+        .into_iter()
+        .map(|column| {
+            let mut present: Vec<f64> = column
+                .iter()
+                .copied()
+                .filter(|x| !is_missing(*x, missing_value))
+                .collect();
+
+            if present.is_empty() {
+                0f64
+            } else {
+                statistic(&mut present)
+            }
+        })
+        .collect()
+}
+
+fn impute(data: &Array2<f64>, missing_value: f64, fill: &Array1<f64>) -> Array2<f64> {
+    let mut imputed = data.clone();
+
+    for (mut column, fill) in imputed.columns_mut().into_iter().zip(fill.iter()) {
+        column.mapv_inplace(|x| {
+            if is_missing(x, missing_value) {
+                *fill
+            } else {
+                x
+            }
+        });
+    }
+
+    imputed
+}
+
+/// A NaN missing value is saved as null
+fn parse_missing_value(value: &JsonValue) -> f64 {
+    value["missing_value"].as_f64().unwrap_or(f64::NAN)
+}
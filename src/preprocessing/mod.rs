@@ -0,0 +1,49 @@
+use crate::error::{NeuralNetError, Result};
+use augmentation::GaussianNoise;
+use imputer::{MeanImputer, MedianImputer};
+use json::JsonValue;
+use ndarray::{Array1, Array2};
+use pca::PCA;
+use scaler::StandardScaler;
+
+pub mod augmentation;
+pub mod feature_selection;
+pub mod imputer;
+pub mod pca;
+pub mod scaler;
+pub mod sequence;
+pub mod timeseries;
+
+/// A fitted preprocessing step that maps instances (rows) to new features
+pub trait Transform: Send + Sync {
+    fn transform(&self, data: &Array2<f64>) -> Array2<f64>;
+
+    /// Serialize the transform, with a "type" key that transform_from_json uses to deserialize it
+    fn to_json(&self) -> JsonValue;
+}
+
+/// Deserialize a transform serialized with Transform::to_json
+pub fn transform_from_json(value: &JsonValue) -> Result<Box<dyn Transform>> {
+    match value["type"].as_str() {
+        Some("standard_scaler") => Ok(Box::new(StandardScaler::from_json(value)?)),
+        Some("pca") => Ok(Box::new(PCA::from_json(value)?)),
+        Some("gaussian_noise") => Ok(Box::new(GaussianNoise::from_json(value)?)),
+        Some("mean_imputer") => Ok(Box::new(MeanImputer::from_json(value)?)),
+        Some("median_imputer") => Ok(Box::new(MedianImputer::from_json(value)?)),
+        other => Err(NeuralNetError::Parse(format!(
+            "Unknown transform type {:?}",
+            other
+        ))),
+    }
+}
+
+/// Read the numeric array value[key]
+fn parse_json_array(value: &JsonValue, key: &str) -> Result<Array1<f64>> {
+    value[key]
+        .members()
+        .map(|x| {
+            x.as_f64()
+                .ok_or_else(|| NeuralNetError::Parse(format!("Non-numeric value in {}", key)))
+        })
+        .collect()
+}
@@ -0,0 +1,283 @@
+use super::{parse_json_array, Transform};
+use crate::error::{NeuralNetError, Result};
+use json::{object, JsonValue};
+use ndarray::{Array1, Array2, Axis};
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+const MAX_ITERATIONS: usize = 1000;
+const TOLERANCE: f64 = 1e-10;
+
+/// Principal component analysis - projects the data onto the directions of largest variance
+pub struct PCA {
+    pub n_components: usize,
+    pub components: Array2<f64>, // Each row is a principal component (a unit vector), by decreasing variance
+    pub explained_variance: Array1<f64>, // The variance of the data along each component
+    pub mean: Array1<f64>,       // Subtracted from the data before projecting it
+    total_variance: f64,
+}
+
+impl PCA {
+    /// Find the top n_components principal components of the data (one instance per row)
+    /// The components are the top eigenvectors of the covariance matrix, found using orthogonal iteration
+    /// They're computed here rather than with ndarray-linalg, which would link the crate (and the C library built
+    /// from it) against a LAPACK implementation for this one decomposition. The covariance is only
+    /// n_features x n_features, and orthogonal iteration only needs matrix products
+    pub fn fit(data: &Array2<f64>, n_components: usize) -> PCA {
+        let n_features = data.ncols();
+        let n_components = n_components.min(n_features);
+        let mean = data.mean_axis(Axis(0)).unwrap();
+        let centered = data - &mean;
+        let covariance = centered.t().dot(&centered) / (data.nrows().max(2) - 1) as f64;
+        let total_variance = covariance.diag().sum();
+
+        // Start from a random basis, so that it isn't orthogonal to any of the components
+        let mut rng = StdRng::seed_from_u64(0);
+        let uniform = Uniform::new(-1f64, 1f64);
+        let mut basis = orthonormalize(Array2::from_shape_fn((n_features, n_components), |_| {
+            uniform.sample(&mut rng)
+        }));
+
+        for _ in 0..MAX_ITERATIONS {
+            let next = orthonormalize(covariance.dot(&basis));
+            let change = (&next - &basis).mapv(|x| x * x).sum();
+
+            basis = next;
+
+            if change < TOLERANCE {
+                break;
+            }
+        }
+
+        // The variance along each component is its Rayleigh quotient
+        let variances = (&basis * &covariance.dot(&basis)).sum_axis(Axis(0));
+        let mut order: Vec<usize> = (0..n_components).collect();
+        order.sort_by(|&a, &b| variances[b].total_cmp(&variances[a]));
+
+        PCA {
+            n_components,
+            components: basis.select(Axis(1), &order).reversed_axes(),
+            explained_variance: variances.select(Axis(0), &order),
+            mean,
+            total_variance,
+        }
+    }
+
+    pub(super) fn from_json(value: &JsonValue) -> Result<PCA> {
+        let explained_variance = parse_json_array(value, "explained_variance")?;
+        let mean = parse_json_array(value, "mean")?;
+        let n_components = explained_variance.len();
+        let components = parse_json_array(value, "components")?
+            .into_shape((n_components, mean.len()))
+            .map_err(|e| NeuralNetError::Parse(e.to_string()))?;
+        let total_variance = value["total_variance"]
+            .as_f64()
+            .ok_or_else(|| NeuralNetError::Parse("Missing total_variance".to_string()))?;
+
+        Ok(PCA {
+            n_components,
+            components,
+            explained_variance,
+            mean,
+            total_variance,
+        })
+    }
+
+    /// The fraction of the total variance of the data along each component
+    pub fn explained_variance_ratio(&self) -> Array1<f64> {
+        &self.explained_variance / self.total_variance
+    }
+}
+
+impl Transform for PCA {
+    /// Project the data onto the principal components
+    fn transform(&self, data: &Array2<f64>) -> Array2<f64> {
+        (data - &self.mean).dot(&self.components.t())
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            type: "pca",
+            components: self.components.iter().copied().collect::<Vec<f64>>(),
+            explained_variance: self.explained_variance.to_vec(),
+            mean: self.mean.to_vec(),
+            total_variance: self.total_variance,
+        }
+    }
+}
+
+/// Orthonormalize the columns of a matrix using (modified) Gram-Schmidt
+fn orthonormalize(mut matrix: Array2<f64>) -> Array2<f64> {
+    for i in 0..matrix.ncols() {
+        for j in 0..i {
+            let prev = matrix.column(j).to_owned();
+            let projection = matrix.column(i).dot(&prev);
+
+            matrix.column_mut(i).scaled_add(-projection, &prev);
+        }
+
+        let norm = matrix.column(i).dot(&matrix.column(i)).sqrt();
+
+        if norm > 0f64 {
+            matrix.column_mut(i).mapv_inplace(|x| x / norm);
+        }
+    }
+
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    /// The rows of the SVD of the centered data: orthonormal directions with standard deviations 3, 2 and 1
+    fn directions() -> Array2<f64> {
+        array![
+            [1f64, 1f64, 0f64],
+            [1f64, -1f64, 1f64],
+            [1f64, -1f64, -2f64],
+        ] / array![[2f64.sqrt()], [3f64.sqrt()], [6f64.sqrt()]]
+    }
+
+    /// Every combination of signs of (3, 2, 1) along the directions, shifted by a mean. The coordinates along the
+    /// directions are uncorrelated, so the data is U * diag(3, 2, 1) * directions + mean for an orthogonal U
+    fn data() -> Array2<f64> {
+        let mut data = Array2::zeros((0, 3));
+
+        for signs in 0..8 {
+            let sign = |bit: usize| if signs & (1 << bit) == 0 { 1f64 } else { -1f64 };
+            let coords = array![3f64 * sign(0), 2f64 * sign(1), sign(2)];
+
+            data.push_row(coords.dot(&directions()).view()).unwrap();
+        }
+
+        data + array![5f64, -1f64, 2f64]
+    }
+
+    #[test]
+    fn directions_are_orthonormal() {
+        let directions = directions();
+
+        assert!((directions.dot(&directions.t()) - Array2::<f64>::eye(3))
+            .iter()
+            .all(|x| x.abs() < 1e-12));
+    }
+
+    #[test]
+    fn components_match_the_svd() {
+        let pca = PCA::fit(&data(), 3);
+
+        assert!((&pca.mean - &array![5f64, -1f64, 2f64])
+            .iter()
+            .all(|x| x.abs() < 1e-12));
+        // The components are only unique up to their sign. Orthogonal iteration stops once the basis changes by less
+        // than sqrt(TOLERANCE) per iteration, so they're only accurate to a few digits
+        for (component, direction) in pca.components.rows().into_iter().zip(directions().rows()) {
+            let sign = component.dot(&direction).signum();
+
+            assert!((&component - &(&direction * sign))
+                .iter()
+                .all(|x| x.abs() < 1e-5));
+        }
+
+        // The explained variances are the squared singular values divided by n - 1
+        let expected = array![9f64, 4f64, 1f64] * 8f64 / 7f64;
+
+        assert!((&pca.explained_variance - &expected)
+            .iter()
+            .all(|x| x.abs() < 1e-8));
+        assert!(
+            (pca.explained_variance_ratio() - array![9f64, 4f64, 1f64] / 14f64)
+                .iter()
+                .all(|x| x.abs() < 1e-8)
+        );
+    }
+
+    #[test]
+    fn components_are_orthonormal() {
+        let pca = PCA::fit(&data(), 2);
+        let gram = pca.components.dot(&pca.components.t());
+
+        assert_eq!(pca.components.dim(), (2, 3));
+        assert!((gram - Array2::<f64>::eye(2))
+            .iter()
+            .all(|x| x.abs() < 1e-12));
+    }
+
+    #[test]
+    fn transform_projects_onto_the_components() {
+        let data = data();
+        let pca = PCA::fit(&data, 3);
+        let projected = pca.transform(&data);
+
+        // Each instance is (+-3, +-2, +-1) along the components
+        for row in projected.rows() {
+            assert!(row
+                .iter()
+                .zip([3f64, 2f64, 1f64])
+                .all(|(x, expected)| (x.abs() - expected).abs() < 1e-4));
+        }
+    }
+
+    #[test]
+    fn components_match_the_closed_form_eigenvectors() {
+        // Correlated 2D data with no structure built in
+        let mut rng = StdRng::seed_from_u64(1);
+        let uniform = Uniform::new(-1f64, 1f64);
+        let data = Array2::from_shape_fn((200, 2), |_| uniform.sample(&mut rng))
+            .dot(&array![[2f64, 0.5], [0.3, 1f64]]);
+        let pca = PCA::fit(&data, 2);
+
+        // The eigendecomposition of a symmetric 2x2 matrix [[a, b], [b, c]]: the first eigenvector is at the angle
+        // atan2(2b, a - c) / 2, and the eigenvalues are (a + c) / 2 +- sqrt(((a - c) / 2)^2 + b^2)
+        let centered = &data - &data.mean_axis(Axis(0)).unwrap();
+        let covariance = centered.t().dot(&centered) / 199f64;
+        let (a, b, c) = (covariance[[0, 0]], covariance[[0, 1]], covariance[[1, 1]]);
+        let angle = (2f64 * b).atan2(a - c) / 2f64;
+        let radius = ((a - c) * (a - c) / 4f64 + b * b).sqrt();
+        let expected_components = array![[angle.cos(), angle.sin()], [-angle.sin(), angle.cos()]];
+        let expected_variances = array![(a + c) / 2f64 + radius, (a + c) / 2f64 - radius];
+
+        for (component, expected) in pca
+            .components
+            .rows()
+            .into_iter()
+            .zip(expected_components.rows())
+        {
+            let sign = component.dot(&expected).signum();
+
+            assert!((&component - &(&expected * sign))
+                .iter()
+                .all(|x| x.abs() < 1e-5));
+        }
+
+        assert!((&pca.explained_variance - &expected_variances)
+            .iter()
+            .all(|x| x.abs() < 1e-8));
+    }
+
+    #[test]
+    fn components_reconstruct_the_covariance() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let uniform = Uniform::new(-1f64, 1f64);
+        let mixing = Array2::from_shape_fn((5, 5), |_| uniform.sample(&mut rng));
+        let data = Array2::from_shape_fn((500, 5), |_| uniform.sample(&mut rng)).dot(&mixing);
+        let pca = PCA::fit(&data, 5);
+
+        // With all of the components, the covariance is components^T * diag(explained_variance) * components
+        // Like the components, the reconstruction is only accurate to a few digits
+        let centered = &data - &pca.mean;
+        let covariance = centered.t().dot(&centered) / 499f64;
+        let reconstructed = pca
+            .components
+            .t()
+            .dot(&Array2::from_diag(&pca.explained_variance))
+            .dot(&pca.components);
+
+        assert!((&reconstructed - &covariance)
+            .iter()
+            .all(|x| x.abs() < 1e-5));
+    }
+}
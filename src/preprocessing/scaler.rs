@@ -0,0 +1,43 @@
+use super::{parse_json_array, Transform};
+use crate::error::Result;
+use json::{object, JsonValue};
+use ndarray::{Array1, Array2, Axis};
+
+/// Standardizes each feature to zero mean and unit variance
+pub struct StandardScaler {
+    pub mean: Array1<f64>,
+    pub std: Array1<f64>, // Constant features have a std of 1, so they are only centered
+}
+
+impl StandardScaler {
+    /// Compute the mean and the standard deviation of each feature of the data (one instance per row)
+    pub fn fit(data: &Array2<f64>) -> StandardScaler {
+        let mean = data.mean_axis(Axis(0)).unwrap();
+        let std = data
+            .std_axis(Axis(0), 0f64)
+            .mapv(|std| if std > 0f64 { std } else { 1f64 });
+
+        StandardScaler { mean, std }
+    }
+
+    pub(super) fn from_json(value: &JsonValue) -> Result<StandardScaler> {
+        Ok(StandardScaler {
+            mean: parse_json_array(value, "mean")?,
+            std: parse_json_array(value, "std")?,
+        })
+    }
+}
+
+impl Transform for StandardScaler {
+    fn transform(&self, data: &Array2<f64>) -> Array2<f64> {
+        (data - &self.mean) / &self.std
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            type: "standard_scaler",
+            mean: self.mean.to_vec(),
+            std: self.std.to_vec(),
+        }
+    }
+}
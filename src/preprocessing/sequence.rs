@@ -0,0 +1,47 @@
+use ndarray::{s, Array1, Array2};
+
+/// Which end of a sequence is padded or truncated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TruncatingMode {
+    Pre,  // Pad or truncate at the beginning of the sequence
+    Post, // Pad or truncate at the end of the sequence
+}
+
+/// Pad or truncate 1D sequences to the same length, and stack them into a matrix (one sequence per row)
+/// If max_len is None, the sequences are padded to the length of the longest sequence
+pub fn pad_sequences(
+    sequences: Vec<Array1<f64>>,
+    max_len: Option<usize>,
+    padding_value: f64,
+    truncating: TruncatingMode,
+) -> Array2<f64> {
+    let max_len =
+        max_len.unwrap_or_else(|| sequence_lengths(&sequences).into_iter().max().unwrap_or(0));
+    let mut padded = Array2::from_elem((sequences.len(), max_len), padding_value);
+
+    for (mut row, sequence) in padded.rows_mut().into_iter().zip(sequences.iter()) {
+        let len = sequence.len().min(max_len);
+
+        match truncating {
+            // Keep the end of the sequence, and place it at the end of the row
+            TruncatingMode::Pre => row
+                .slice_mut(s![max_len - len..])
+                .assign(&sequence.slice(s![sequence.len() - len..])),
+            // Keep the beginning of the sequence, and place it at the beginning of the row
+            TruncatingMode::Post => row.slice_mut(s![..len]).assign(&sequence.slice(s![..len])),
+        }
+    }
+
+    padded
+}
+
+/// The length of each sequence
+pub fn sequence_lengths(sequences: &[Array1<f64>]) -> Vec<usize> {
+    sequences.iter().map(|sequence| sequence.len()).collect()
+}
+
+/// Construct a mask of the valid (non-padding) positions of sequences padded at the end
+/// Row i is true in its first lengths[i] positions (capped at max_len) and false afterwards
+pub fn mask_from_lengths(lengths: &[usize], max_len: usize) -> Array2<bool> {
+    Array2::from_shape_fn((lengths.len(), max_len), |(i, j)| j < lengths[i])
+}
@@ -0,0 +1,45 @@
+use crate::parsing::Dataset;
+use ndarray::{s, Array1, Array2};
+
+/// Build a classification dataset from a multivariate time series using sliding windows
+/// data holds one time step per row, and targets holds the class of each time step
+/// Each instance is a window of window_size steps flattened to window_size * n_features features
+/// (step after step), and its target is the one-hot encoding of the class at the last step of the window
+pub fn build_windows(
+    data: &Array2<f64>,
+    targets: &Array1<usize>,
+    window_size: usize,
+    stride: usize,
+) -> Dataset {
+    assert!(stride > 0, "The stride of the windows must be positive");
+
+    let n_timesteps = data.nrows();
+    let n_features = data.ncols();
+    let n_classes = targets.iter().max().map_or(0, |max| max + 1);
+    // The last window must end at or before the last time step
+    let n_windows = if window_size == 0 || n_timesteps < window_size {
+        0
+    } else {
+        (n_timesteps - window_size) / stride + 1
+    };
+    let mut windows = Array2::zeros((n_windows, window_size * n_features));
+    let mut one_hot = Array2::zeros((n_windows, n_classes));
+
+    for i in 0..n_windows {
+        let start = i * stride;
+        let end = start + window_size;
+        let window = data.slice(s![start..end, ..]);
+
+        // iter() goes over the window in logical (row-major) order, i.e. step after step
+        for (dst, src) in windows.row_mut(i).iter_mut().zip(window.iter()) {
+            *dst = *src;
+        }
+
+        one_hot[[i, targets[end - 1]]] = 1f64;
+    }
+
+    Dataset {
+        data: windows,
+        target: one_hot,
+    }
+}
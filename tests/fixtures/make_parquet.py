@@ -0,0 +1,245 @@
+"""Write the Parquet fixtures of the tests of src/parsing/parquet.rs
+
+Every fixture holds the same 8 rows in 2 row groups of 4 rows:
+    i32    (int32, required):   1, 2, 3, 4, 5, 6, 7, 8
+    i64    (int64, optional):   10, null, 30, 40, null, 60, 70, 80
+    f32    (float, required):   0.5, 1.5, 2.5, 3.5, 4.5, 5.5, 6.5, 7.5
+    f64    (double, optional):  -1.0, -2.0, null, -4.0, -5.0, -6.0, -7.0, null
+    label  (byte array):        "cat", "dog", "cat", "bird", "dog", "dog", "cat", "bird"
+
+The first row group has v1 data pages and the second has v2 data pages. The fixtures differ in the compression codec
+and in the encoding of the label column:
+    plain.parquet:           uncompressed, plain labels
+    gzip_dictionary.parquet: gzip, dictionary-encoded labels
+    snappy.parquet:          Snappy, dictionary-encoded labels
+
+Run with: python3 make_parquet.py (only the standard library is needed)
+"""
+
+import gzip
+import struct
+
+ROWS = {
+    "i32": [1, 2, 3, 4, 5, 6, 7, 8],
+    "i64": [10, None, 30, 40, None, 60, 70, 80],
+    "f32": [0.5, 1.5, 2.5, 3.5, 4.5, 5.5, 6.5, 7.5],
+    "f64": [-1.0, -2.0, None, -4.0, -5.0, -6.0, -7.0, None],
+    "label": ["cat", "dog", "cat", "bird", "dog", "dog", "cat", "bird"],
+}
+# (name, physical type, optional)
+COLUMNS = [("i32", 1, False), ("i64", 2, True), ("f32", 4, False), ("f64", 5, True), ("label", 6, False)]
+ROW_GROUP_SIZE = 4
+
+
+# The Thrift compact protocol
+def varint(n):
+    out = bytearray()
+    while True:
+        byte = n & 0x7F
+        n >>= 7
+        if n:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def zigzag(n):
+    return varint((n << 1) ^ (n >> 63))
+
+
+def struct_(fields):
+    """fields is a list of (id, type, value) with increasing ids"""
+    out = bytearray()
+    last = 0
+    for field_id, field_type, value in fields:
+        delta = field_id - last
+        if field_type == "bool":
+            out.append((delta << 4) | (1 if value else 2))
+        else:
+            type_id, encoded = encode(field_type, value)
+            out.append((delta << 4) | type_id)
+            out += encoded
+        last = field_id
+    out.append(0)
+    return bytes(out)
+
+
+def encode(field_type, value):
+    if field_type == "i32":
+        return 5, zigzag(value)
+    if field_type == "i64":
+        return 6, zigzag(value)
+    if field_type == "string":
+        data = value.encode()
+        return 8, varint(len(data)) + data
+    if field_type == "struct":
+        return 12, value
+    if field_type.startswith("list:"):
+        element_type = field_type[5:]
+        encoded = [encode(element_type, item) for item in value]
+        type_id = {"i32": 5, "string": 8, "struct": 12}[element_type]
+        return 9, bytes([(len(value) << 4) | type_id]) + b"".join(e for _, e in encoded)
+    raise ValueError(field_type)
+
+
+# The RLE/bit-packing hybrid encoding
+def rle_run(value, count, bit_width):
+    return varint(count << 1) + value.to_bytes((bit_width + 7) // 8, "little")
+
+
+def bit_packed(values, bit_width):
+    values = values + [0] * (-len(values) % 8)
+    bits = 0
+    for i, value in enumerate(values):
+        bits |= value << (i * bit_width)
+    return varint((len(values) // 8) << 1 | 1) + bits.to_bytes(len(values) * bit_width // 8, "little")
+
+
+def encode_levels(levels, use_rle):
+    """The definition levels as a single bit-packed run, or as RLE runs"""
+    if not use_rle:
+        return bit_packed(levels, 1)
+    out = b""
+    start = 0
+    for i in range(1, len(levels) + 1):
+        if i == len(levels) or levels[i] != levels[start]:
+            out += rle_run(levels[start], i - start, 1)
+            start = i
+    return out
+
+
+def plain(physical_type, values):
+    if physical_type == 1:
+        return b"".join(struct.pack("<i", v) for v in values)
+    if physical_type == 2:
+        return b"".join(struct.pack("<q", v) for v in values)
+    if physical_type == 4:
+        return b"".join(struct.pack("<f", v) for v in values)
+    if physical_type == 5:
+        return b"".join(struct.pack("<d", v) for v in values)
+    return b"".join(struct.pack("<I", len(v.encode())) + v.encode() for v in values)
+
+
+# Compression
+def snappy(data):
+    """A Snappy block with a literal of the first half of the data (at most 60 bytes) and copies for the rest"""
+    out = bytearray(varint(len(data)))
+    literal_len = min(max(len(data) // 2, 1), 60)
+    out.append((literal_len - 1) << 2)
+    out += data[:literal_len]
+    pos = literal_len
+    while pos < len(data):
+        # Copy with a 2-byte offset of a single byte that's equal to the next one, or a 1-byte literal
+        match = data.rfind(data[pos : pos + 1], 0, pos)
+        if match >= 0:
+            out.append(0b10)
+            out += struct.pack("<H", pos - match)
+        else:
+            out.append(0)
+            out += data[pos : pos + 1]
+        pos += 1
+    return bytes(out)
+
+
+def compress(codec, data):
+    if codec == 0:
+        return data
+    if codec == 1:
+        return snappy(data)
+    return gzip.compress(data, mtime=0)
+
+
+def page(header_fields, compressed, uncompressed_size):
+    fields = [(1, "i32", header_fields[0]), (2, "i32", uncompressed_size), (3, "i32", len(compressed))]
+    return struct_(fields + header_fields[1]) + compressed
+
+
+def write(path, codec, dictionary_labels):
+    body = bytearray(b"PAR1")
+    row_groups = []
+    labels = sorted(set(ROWS["label"]))
+
+    for group in range(len(ROWS["i32"]) // ROW_GROUP_SIZE):
+        columns = []
+        rows = slice(group * ROW_GROUP_SIZE, (group + 1) * ROW_GROUP_SIZE)
+        for name, physical_type, optional in COLUMNS:
+            values = ROWS[name][rows]
+            present = [v for v in values if v is not None]
+            start = len(body)
+            dictionary_offset = None
+            encoding = 0
+
+            if name == "label" and dictionary_labels:
+                dictionary_offset = start
+                data = plain(physical_type, labels)
+                compressed = compress(codec, data)
+                body += page((2, [(7, "struct", struct_([(1, "i32", len(labels)), (2, "i32", 0)]))]), compressed, len(data))
+                indices = [labels.index(v) for v in present]
+                # A bit width of 2, the indices bit-packed in the first row group and RLE in the second
+                encoded = bytes([2]) + (bit_packed(indices, 2) if group == 0 else b"".join(rle_run(i, 1, 2) for i in indices))
+                encoding = 8
+            else:
+                encoded = plain(physical_type, present)
+
+            data_offset = len(body)
+            levels = encode_levels([0 if v is None else 1 for v in values], use_rle=group == 1) if optional else b""
+
+            if group == 0:
+                # v1: the levels are prefixed with their length, and compressed with the values
+                data = (struct.pack("<I", len(levels)) + levels if optional else b"") + encoded
+                compressed = compress(codec, data)
+                header = struct_([(1, "i32", len(values)), (2, "i32", encoding), (3, "i32", 3), (4, "i32", 3)])
+                body += page((0, [(5, "struct", header)]), compressed, len(data))
+            else:
+                # v2: the levels aren't compressed, and their length is in the header
+                compressed = compress(codec, encoded)
+                header = struct_(
+                    [
+                        (1, "i32", len(values)),
+                        (2, "i32", len(values) - len(present)),
+                        (3, "i32", len(values)),
+                        (4, "i32", encoding),
+                        (5, "i32", len(levels)),
+                        (6, "i32", 0),
+                        (7, "bool", codec != 0),
+                    ]
+                )
+                body += page((3, [(8, "struct", header)]), levels + compressed, len(levels) + len(encoded))
+
+            metadata = [
+                (1, "i32", physical_type),
+                (2, "list:i32", [encoding, 0] if encoding else [0]),
+                (3, "list:string", [name]),
+                (4, "i32", codec),
+                (5, "i64", len(values)),
+                (6, "i64", len(body) - start),
+                (7, "i64", len(body) - start),
+                (9, "i64", data_offset),
+            ]
+            if dictionary_offset is not None:
+                metadata.append((11, "i64", dictionary_offset))
+            columns.append(struct_([(2, "i64", start), (3, "struct", struct_(metadata))]))
+        row_groups.append(struct_([(1, "list:struct", columns), (2, "i64", 0), (3, "i64", ROW_GROUP_SIZE)]))
+
+    schema = [struct_([(4, "string", "schema"), (5, "i32", len(COLUMNS))])]
+    for name, physical_type, optional in COLUMNS:
+        schema.append(struct_([(1, "i32", physical_type), (3, "i32", 1 if optional else 0), (4, "string", name)]))
+    footer = struct_(
+        [
+            (1, "i32", 1),
+            (2, "list:struct", schema),
+            (3, "i64", len(ROWS["i32"])),
+            (4, "list:struct", row_groups),
+        ]
+    )
+    body += footer + struct.pack("<I", len(footer)) + b"PAR1"
+
+    with open(path, "wb") as f:
+        f.write(body)
+
+
+if __name__ == "__main__":
+    write("plain.parquet", 0, False)
+    write("gzip_dictionary.parquet", 2, True)
+    write("snappy.parquet", 1, True)